@@ -0,0 +1,1634 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::encoder::{condition_code, encode};
+use crate::instruction::{
+    Condition, ControlRegister, Direction, IndexRegister, Instructions, Registers, Size, Target,
+};
+use crate::memory::Memory;
+
+// Contiguous run of assembled bytes
+pub struct Chunk {
+    pub address: u32,
+    pub data: Vec<u8>,
+}
+
+pub struct Program {
+    pub chunks: Vec<Chunk>,
+    pub symbols: HashMap<String, u32>,
+    // Every assembled instruction with its address
+    pub listing: Vec<(u32, Instructions)>,
+    // Operand of the END directive, if any
+    pub start: Option<u32>,
+}
+
+impl Program {
+    pub fn load<M: Memory + ?Sized>(&self, mem: &mut M) -> Result<(), &'static str> {
+        for chunk in &self.chunks {
+            for (offset, byte) in chunk.data.iter().enumerate() {
+                mem.write_at_address_byte(chunk.address.wrapping_add(offset as u32), *byte)
+                    .map_err(|_| "Program does not fit into memory")?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone)]
+enum Expr {
+    Number(i64),
+    Symbol(String),
+    // Location counter, written as *
+    Location,
+    Negate(Box<Expr>),
+    Complement(Box<Expr>),
+    Binary(Operator, Box<Expr>, Box<Expr>),
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Operator {
+    Or,
+    Xor,
+    And,
+    ShiftLeft,
+    ShiftRight,
+    Add,
+    Subtract,
+    Multiply,
+    Divide,
+    Modulo,
+}
+
+enum Operand {
+    DataRegister(Registers),
+    AddressRegister(Registers),
+    Indirect(Registers),
+    PostInc(Registers),
+    PreDec(Registers),
+    Displacement(Expr, Registers),
+    Index(Expr, Registers, IndexRegister),
+    PCDisplacement(Expr),
+    PCIndex(Expr, IndexRegister),
+    Absolute(Expr, Option<Size>),
+    Immediate(Expr),
+    // Bit mask of D0-D7 (bits 0-7) and A0-A7 (bits 8-15)
+    RegisterList(u16),
+    Ccr,
+    Sr,
+    Usp,
+    Vbr,
+}
+
+enum DataItem {
+    Bytes(Vec<u8>),
+    Value(Expr),
+}
+
+enum Kind {
+    Instruction {
+        mnemonic: String,
+        size: Option<Size>,
+        operands: Vec<Operand>,
+        // Branches only: whether the 8-bit displacement form was chosen in pass one
+        short: bool,
+    },
+    Data(Size, Vec<DataItem>),
+}
+
+struct Statement {
+    line: usize,
+    address: u32,
+    kind: Kind,
+}
+
+// EQU whose value depended on symbols not yet defined in pass one
+struct Equate {
+    line: usize,
+    address: u32,
+    name: String,
+    value: Expr,
+}
+
+struct Assembler {
+    symbols: HashMap<String, i64>,
+    // Symbols defined with EQU, which are plain numbers rather than addresses
+    equates: HashSet<String>,
+    statements: Vec<Statement>,
+    deferred: Vec<Equate>,
+    start: Option<(usize, u32, Expr)>,
+    chunks: Vec<Chunk>,
+    listing: Vec<(u32, Instructions)>,
+    final_pass: bool,
+}
+
+// Assembles Motorola syntax source in two passes: the first parses every line,
+// sizes it and defines the labels, the second evaluates operands and encodes
+pub fn assemble(source: &str) -> Result<Program, String> {
+    let mut assembler = Assembler {
+        symbols: HashMap::new(),
+        equates: HashSet::new(),
+        statements: Vec::new(),
+        deferred: Vec::new(),
+        start: None,
+        chunks: Vec::new(),
+        listing: Vec::new(),
+        final_pass: false,
+    };
+    assembler.first_pass(source)?;
+    assembler.resolve_equates()?;
+    assembler.final_pass = true;
+    assembler.second_pass()?;
+
+    let start = match assembler.start.take() {
+        Some((line, address, expr)) => Some(
+            assembler
+                .value(&expr, address)
+                .map_err(|e| format!("line {}: {}", line, e))? as u32,
+        ),
+        None => None,
+    };
+    Ok(Program {
+        chunks: assembler.chunks,
+        symbols: assembler
+            .symbols
+            .into_iter()
+            .map(|(name, value)| (name, value as u32))
+            .collect(),
+        listing: assembler.listing,
+        start,
+    })
+}
+
+impl Assembler {
+    fn first_pass(&mut self, source: &str) -> Result<(), String> {
+        let mut location: u32 = 0;
+        let mut scope = String::new();
+        for (number, text) in source.lines().enumerate() {
+            let line = number + 1;
+            let fail = |e: String| format!("line {}: {}", line, e);
+            let (label, op, operands) = split_line(text);
+            let (mnemonic, size) = match op {
+                Some(op) => split_size(op).map_err(fail)?,
+                None => (String::new(), None),
+            };
+
+            if mnemonic == "equ" || mnemonic == "=" {
+                let name = label.ok_or_else(|| fail("EQU without a label".to_string()))?;
+                let name = qualify(name, &scope);
+                let value = parse_expression(operands, &scope).map_err(fail)?;
+                self.equates.insert(name.clone());
+                match self.evaluate(&value, location).map_err(fail)? {
+                    Some(value) => self.define(&name, value).map_err(fail)?,
+                    None => self.deferred.push(Equate {
+                        line,
+                        address: location,
+                        name,
+                        value,
+                    }),
+                }
+                continue;
+            }
+
+            let label = label.map(|label| {
+                if !is_local(label) {
+                    scope = label.to_string();
+                }
+                qualify(label, &scope)
+            });
+
+            match mnemonic.as_str() {
+                "" => {}
+                "org" => {
+                    let expr = parse_expression(operands, &scope).map_err(fail)?;
+                    location = self
+                        .evaluate(&expr, location)
+                        .map_err(fail)?
+                        .ok_or_else(|| fail("ORG needs a value known in pass one".to_string()))?
+                        as u32;
+                }
+                "even" => location = location.wrapping_add(1) & !1,
+                "end" => {
+                    if !operands.is_empty() {
+                        let expr = parse_expression(operands, &scope).map_err(fail)?;
+                        self.start = Some((line, location, expr));
+                    }
+                }
+                "dc" | "ds" => {
+                    let size = size.unwrap_or(Size::Word);
+                    if size != Size::Byte {
+                        location = location.wrapping_add(1) & !1;
+                    }
+                    if mnemonic == "ds" {
+                        let expr = parse_expression(operands, &scope).map_err(fail)?;
+                        let count =
+                            self.evaluate(&expr, location)
+                                .map_err(fail)?
+                                .ok_or_else(|| {
+                                    fail("DS needs a count known in pass one".to_string())
+                                })?;
+                        let length = u32::try_from(count)
+                            .ok()
+                            .and_then(|count| count.checked_mul(size_bytes(size)))
+                            .ok_or_else(|| fail(format!("Bad DS count {}", count)))?;
+                        if let Some(label) = &label {
+                            self.define(label, location as i64).map_err(fail)?;
+                        }
+                        location = location.wrapping_add(length);
+                        continue;
+                    }
+                    let items = parse_data(operands, size, &scope).map_err(fail)?;
+                    let length: u32 = items
+                        .iter()
+                        .map(|item| match item {
+                            DataItem::Bytes(bytes) => bytes.len() as u32,
+                            DataItem::Value(_) => size_bytes(size),
+                        })
+                        .sum();
+                    self.statements.push(Statement {
+                        line,
+                        address: location,
+                        kind: Kind::Data(size, items),
+                    });
+                    if let Some(label) = &label {
+                        self.define(label, location as i64).map_err(fail)?;
+                    }
+                    location = location.wrapping_add(length);
+                    continue;
+                }
+                _ => {
+                    if !location.is_multiple_of(2) {
+                        return Err(fail("Instruction at odd address".to_string()));
+                    }
+                    let operands = split_operands(operands)
+                        .iter()
+                        .map(|operand| parse_operand(operand, &scope))
+                        .collect::<Result<Vec<_>, _>>()
+                        .map_err(fail)?;
+                    if let Some(label) = &label {
+                        self.define(label, location as i64).map_err(fail)?;
+                    }
+                    let short = match branch_condition(&mnemonic) {
+                        Some(_) => self
+                            .choose_short_branch(size, &operands, location)
+                            .map_err(fail)?,
+                        None => false,
+                    };
+                    let length = if branch_condition(&mnemonic).is_some() {
+                        if short {
+                            2
+                        } else {
+                            4
+                        }
+                    } else {
+                        let ins = self
+                            .build(&mnemonic, size, &operands, location)
+                            .map_err(fail)?;
+                        2 * encode(&ins).map_err(|e| fail(e.to_string()))?.len() as u32
+                    };
+                    self.statements.push(Statement {
+                        line,
+                        address: location,
+                        kind: Kind::Instruction {
+                            mnemonic,
+                            size,
+                            operands,
+                            short,
+                        },
+                    });
+                    location = location.wrapping_add(length);
+                    continue;
+                }
+            }
+            if let Some(label) = &label {
+                self.define(label, location as i64).map_err(fail)?;
+            }
+            if mnemonic == "end" {
+                break;
+            }
+        }
+        Ok(())
+    }
+
+    fn resolve_equates(&mut self) -> Result<(), String> {
+        while !self.deferred.is_empty() {
+            let pending = self.deferred.len();
+            let mut unresolved = Vec::new();
+            for equate in std::mem::take(&mut self.deferred) {
+                match self.evaluate(&equate.value, equate.address)? {
+                    Some(value) => {
+                        self.define(&equate.name, value)
+                            .map_err(|e| format!("line {}: {}", equate.line, e))?;
+                    }
+                    None => unresolved.push(equate),
+                }
+            }
+            if unresolved.len() == pending {
+                let equate = &unresolved[0];
+                return Err(format!(
+                    "line {}: Cannot resolve {}",
+                    equate.line, equate.name
+                ));
+            }
+            self.deferred = unresolved;
+        }
+        Ok(())
+    }
+
+    fn second_pass(&mut self) -> Result<(), String> {
+        for statement in std::mem::take(&mut self.statements) {
+            let fail = |e: String| format!("line {}: {}", statement.line, e);
+            let address = statement.address;
+            let bytes = match &statement.kind {
+                Kind::Instruction {
+                    mnemonic,
+                    size,
+                    operands,
+                    short,
+                } => {
+                    let ins = self
+                        .build(mnemonic, *size, operands, address)
+                        .map_err(fail)?;
+                    let words = match &ins {
+                        Instructions::Bcc(_, disp)
+                        | Instructions::BRA(disp)
+                        | Instructions::BSR(disp)
+                            if *short =>
+                        {
+                            if *disp == 0 || !(-128..=127).contains(disp) {
+                                return Err(fail("Short branch out of range".to_string()));
+                            }
+                            encode(&ins).map_err(|e| fail(e.to_string()))?
+                        }
+                        Instructions::Bcc(_, disp)
+                        | Instructions::BRA(disp)
+                        | Instructions::BSR(disp) => {
+                            if !(-32768..=32767).contains(disp) {
+                                return Err(fail("Branch out of range".to_string()));
+                            }
+                            let cc = match &ins {
+                                Instructions::Bcc(cond, _) => condition_code(cond),
+                                Instructions::BSR(_) => 1,
+                                _ => 0,
+                            };
+                            vec![0x6000 | cc << 8, *disp as u16]
+                        }
+                        _ => encode(&ins).map_err(|e| fail(e.to_string()))?,
+                    };
+                    self.listing.push((address, ins));
+                    words
+                        .iter()
+                        .flat_map(|word| word.to_be_bytes())
+                        .collect::<Vec<u8>>()
+                }
+                Kind::Data(size, items) => {
+                    let mut bytes = Vec::new();
+                    for item in items {
+                        match item {
+                            DataItem::Bytes(data) => bytes.extend(data),
+                            DataItem::Value(expr) => {
+                                let value = self.value(expr, address).map_err(fail)?;
+                                let value = truncate(value, *size).map_err(fail)?;
+                                let width = size_bytes(*size) as usize;
+                                bytes.extend(&value.to_be_bytes()[4 - width..]);
+                            }
+                        }
+                    }
+                    bytes
+                }
+            };
+            self.emit(address, &bytes);
+        }
+        Ok(())
+    }
+
+    fn emit(&mut self, address: u32, bytes: &[u8]) {
+        match self.chunks.last_mut() {
+            Some(chunk) if chunk.address.wrapping_add(chunk.data.len() as u32) == address => {
+                chunk.data.extend_from_slice(bytes)
+            }
+            _ => self.chunks.push(Chunk {
+                address,
+                data: bytes.to_vec(),
+            }),
+        }
+    }
+
+    fn define(&mut self, name: &str, value: i64) -> Result<(), String> {
+        if self.symbols.insert(name.to_string(), value).is_some() {
+            return Err(format!("Symbol {} redefined", name));
+        }
+        Ok(())
+    }
+
+    // Value of an expression, or None while it depends on undefined symbols
+    fn evaluate(&self, expr: &Expr, location: u32) -> Result<Option<i64>, String> {
+        let binary = |a: &Expr, b: &Expr| -> Result<Option<(i64, i64)>, String> {
+            match (self.evaluate(a, location)?, self.evaluate(b, location)?) {
+                (Some(a), Some(b)) => Ok(Some((a, b))),
+                _ => Ok(None),
+            }
+        };
+        Ok(match expr {
+            Expr::Number(value) => Some(*value),
+            Expr::Symbol(name) => self.symbols.get(name).copied(),
+            Expr::Location => Some(location as i64),
+            Expr::Negate(e) => self.evaluate(e, location)?.map(|v| v.wrapping_neg()),
+            Expr::Complement(e) => self.evaluate(e, location)?.map(|v| !v),
+            Expr::Binary(op, a, b) => match binary(a, b)? {
+                None => None,
+                Some((a, b)) => Some(match op {
+                    Operator::Or => a | b,
+                    Operator::Xor => a ^ b,
+                    Operator::And => a & b,
+                    Operator::ShiftLeft => a.wrapping_shl(b as u32),
+                    Operator::ShiftRight => a.wrapping_shr(b as u32),
+                    Operator::Add => a.wrapping_add(b),
+                    Operator::Subtract => a.wrapping_sub(b),
+                    Operator::Multiply => a.wrapping_mul(b),
+                    Operator::Divide | Operator::Modulo if b == 0 => {
+                        return Err("Division by zero".to_string())
+                    }
+                    Operator::Divide => a / b,
+                    Operator::Modulo => a % b,
+                }),
+            },
+        })
+    }
+
+    // Value of an operand expression; in pass one anything unresolved sizes as 1
+    fn value(&self, expr: &Expr, location: u32) -> Result<i64, String> {
+        match self.evaluate(expr, location)? {
+            Some(value) => Ok(value),
+            None if !self.final_pass => Ok(1),
+            None => Err(format!("Undefined symbol in {}", describe(expr))),
+        }
+    }
+
+    // Whether an expression refers to a location rather than being a plain number
+    fn is_address(&self, expr: &Expr) -> bool {
+        match expr {
+            Expr::Number(_) => false,
+            Expr::Symbol(name) => !self.equates.contains(name),
+            Expr::Location => true,
+            Expr::Negate(e) | Expr::Complement(e) => self.is_address(e),
+            Expr::Binary(_, a, b) => self.is_address(a) || self.is_address(b),
+        }
+    }
+
+    fn choose_short_branch(
+        &self,
+        size: Option<Size>,
+        operands: &[Operand],
+        location: u32,
+    ) -> Result<bool, String> {
+        match (size, operands) {
+            (Some(Size::Byte), _) => Ok(true),
+            (Some(Size::Word), _) => Ok(false),
+            (Some(Size::Long), _) => Err("Long branches not available on the 68000".to_string()),
+            (None, [Operand::Absolute(expr, None)]) => Ok(match self.evaluate(expr, location)? {
+                Some(target) => {
+                    let disp = target - (location as i64 + 2);
+                    disp != 0 && (-128..=127).contains(&disp)
+                }
+                None => false,
+            }),
+            _ => Ok(false),
+        }
+    }
+
+    // Converts a parsed operand into an instruction target; base is the
+    // address PC-relative displacements are measured from
+    fn target(&self, operand: &Operand, size: Size, base: u32) -> Result<Target, String> {
+        let address = base.wrapping_sub(2);
+        // The displacement is one extension word whatever its value, so pass
+        // one leaves it at 0 and pass two range checks it against the labels
+        let pc_relative = |expr: &Expr| -> Result<i32, String> {
+            if !self.final_pass {
+                return Ok(0);
+            }
+            let value = self.value(expr, address)?;
+            Ok(if self.is_address(expr) {
+                (value - base as i64) as i32
+            } else {
+                value as i32
+            })
+        };
+        Ok(match operand {
+            Operand::DataRegister(reg) => Target::DnDirect(*reg),
+            Operand::AddressRegister(reg) => Target::AnDirect(*reg),
+            Operand::Indirect(reg) => Target::AnIndirect(*reg),
+            Operand::PostInc(reg) => Target::AnIndirectPostInc(*reg),
+            Operand::PreDec(reg) => Target::AnIndirectPreDec(*reg),
+            Operand::Displacement(expr, reg) => {
+                Target::AnIndirectDisplacement(*reg, self.value(expr, address)? as i32)
+            }
+            Operand::Index(expr, reg, index) => {
+                Target::AnIndirectIndex(self.value(expr, address)? as i32, *reg, *index)
+            }
+            Operand::PCDisplacement(expr) => {
+                Target::PCIndirectDisplacement(pc_relative(expr)?, Registers::PC)
+            }
+            Operand::PCIndex(expr, index) => {
+                Target::PCIndirectIndex(pc_relative(expr)?, Registers::PC, *index)
+            }
+            Operand::Absolute(expr, Some(Size::Word)) => {
+                let value = self.value(expr, address)?;
+                match value {
+                    -0x8000..=0x7FFF => Target::AbsoluteShortAddress(value as i32),
+                    0x8000..=0xFFFF => Target::AbsoluteShortAddress(value as i16 as i32),
+                    0xFFFF_8000..=0xFFFF_FFFF => Target::AbsoluteShortAddress(value as i32),
+                    _ => return Err("Address out of range for absolute short".to_string()),
+                }
+            }
+            Operand::Absolute(expr, _) => {
+                let value = truncate(self.value(expr, address)?, Size::Long)?;
+                Target::AbsoluteLongAddress(value >> 16, value & 0xFFFF)
+            }
+            Operand::Immediate(expr) => {
+                Target::Immediate(truncate(self.value(expr, address)?, size)?)
+            }
+            _ => return Err("Invalid operand".to_string()),
+        })
+    }
+
+    fn immediate(&self, operand: &Operand, size: Size, address: u32) -> Result<u32, String> {
+        match operand {
+            Operand::Immediate(expr) => truncate(self.value(expr, address)?, size),
+            _ => Err("Expected immediate data".to_string()),
+        }
+    }
+
+    fn build(
+        &self,
+        mnemonic: &str,
+        size: Option<Size>,
+        operands: &[Operand],
+        address: u32,
+    ) -> Result<Instructions, String> {
+        let sz = size.unwrap_or(Size::Word);
+        let base = address.wrapping_add(2);
+        let expect = |count: usize| -> Result<(), String> {
+            if operands.len() == count {
+                Ok(())
+            } else {
+                Err(format!("{} expects {} operand(s)", mnemonic, count))
+            }
+        };
+        let target = |index: usize, size: Size| self.target(&operands[index], size, base);
+        let data_register = |index: usize| match &operands[index] {
+            Operand::DataRegister(reg) => Ok(*reg),
+            _ => Err("Expected a data register".to_string()),
+        };
+        let address_register = |index: usize| match &operands[index] {
+            Operand::AddressRegister(reg) => Ok(*reg),
+            _ => Err("Expected an address register".to_string()),
+        };
+
+        if let Some(cond) = branch_condition(mnemonic) {
+            expect(1)?;
+            let disp = match &operands[0] {
+                Operand::Absolute(expr, None) => (self.value(expr, address)? - base as i64) as i32,
+                _ => return Err("Expected a branch target".to_string()),
+            };
+            return Ok(match mnemonic {
+                "bra" => Instructions::BRA(disp),
+                "bsr" => Instructions::BSR(disp),
+                _ => Instructions::Bcc(cond, disp),
+            });
+        }
+        let decrement = if mnemonic == "dbra" { "dbf" } else { mnemonic };
+        if let Some(cond) = decrement.strip_prefix("db").and_then(condition) {
+            expect(2)?;
+            let disp = match &operands[1] {
+                Operand::Absolute(expr, None) => self.value(expr, address)? - base as i64,
+                _ => return Err("Expected a branch target".to_string()),
+            };
+            if !(-32768..=32767).contains(&disp) {
+                return Err("Branch out of range".to_string());
+            }
+            return Ok(Instructions::DBcc(cond, data_register(0)?, disp as i16));
+        }
+        if let Some(cond) = mnemonic.strip_prefix('s').and_then(condition) {
+            expect(1)?;
+            return Ok(Instructions::Scc(cond, target(0, Size::Byte)?));
+        }
+
+        Ok(match mnemonic {
+            "abcd" | "sbcd" | "addx" | "subx" => {
+                expect(2)?;
+                let (src, dst) = (target(0, sz)?, target(1, sz)?);
+                match mnemonic {
+                    "abcd" => Instructions::ABCD(src, dst),
+                    "sbcd" => Instructions::SBCD(src, dst),
+                    "addx" => Instructions::ADDX(src, dst, sz),
+                    _ => Instructions::SUBX(src, dst, sz),
+                }
+            }
+            "add" | "sub" | "and" | "or" | "eor" | "cmp" => {
+                expect(2)?;
+                match (&operands[0], &operands[1]) {
+                    (Operand::Immediate(_), Operand::Ccr | Operand::Sr)
+                        if mnemonic != "add" && mnemonic != "sub" && mnemonic != "cmp" =>
+                    {
+                        let immediate = match mnemonic {
+                            "and" => "andi",
+                            "or" => "ori",
+                            _ => "eori",
+                        };
+                        self.build(immediate, size, operands, address)?
+                    }
+                    (_, Operand::AddressRegister(reg))
+                        if mnemonic != "and" && mnemonic != "or" && mnemonic != "eor" =>
+                    {
+                        let src = target(0, sz)?;
+                        match mnemonic {
+                            "add" => Instructions::ADDA(src, *reg, sz),
+                            "sub" => Instructions::SUBA(src, *reg, sz),
+                            _ => Instructions::CMPA(src, *reg, sz),
+                        }
+                    }
+                    (Operand::Immediate(_), _) => {
+                        let immediate = format!("{}i", mnemonic);
+                        self.build(&immediate, size, operands, address)?
+                    }
+                    _ => {
+                        let (src, dst) = (target(0, sz)?, target(1, sz)?);
+                        match mnemonic {
+                            "add" => Instructions::ADD(src, dst, sz),
+                            "sub" => Instructions::SUB(src, dst, sz),
+                            "and" => Instructions::AND(src, dst, sz),
+                            "or" => Instructions::OR(src, dst, sz),
+                            "eor" => Instructions::EOR(src, dst, sz),
+                            _ => Instructions::CMP(src, dst, sz),
+                        }
+                    }
+                }
+            }
+            "adda" | "suba" | "cmpa" => {
+                expect(2)?;
+                let (src, reg) = (target(0, sz)?, address_register(1)?);
+                match mnemonic {
+                    "adda" => Instructions::ADDA(src, reg, sz),
+                    "suba" => Instructions::SUBA(src, reg, sz),
+                    _ => Instructions::CMPA(src, reg, sz),
+                }
+            }
+            "addi" | "subi" | "andi" | "ori" | "eori" | "cmpi" => {
+                expect(2)?;
+                match (mnemonic, &operands[1]) {
+                    ("andi", Operand::Ccr) => {
+                        Instructions::ANDItoCCR(
+                            self.immediate(&operands[0], Size::Byte, address)? as u8
+                        )
+                    }
+                    ("ori", Operand::Ccr) => {
+                        Instructions::ORItoCCR(self.immediate(&operands[0], Size::Byte, address)?)
+                    }
+                    ("eori", Operand::Ccr) => {
+                        Instructions::EORtoCCR(
+                            self.immediate(&operands[0], Size::Byte, address)? as u8
+                        )
+                    }
+                    ("andi", Operand::Sr) => {
+                        Instructions::ANDItoSR(
+                            self.immediate(&operands[0], Size::Word, address)? as u16
+                        )
+                    }
+                    ("ori", Operand::Sr) => {
+                        Instructions::ORItoSR(self.immediate(&operands[0], Size::Word, address)?)
+                    }
+                    ("eori", Operand::Sr) => {
+                        Instructions::EORtoSR(
+                            self.immediate(&operands[0], Size::Word, address)? as u16
+                        )
+                    }
+                    _ => {
+                        let imm = self.immediate(&operands[0], sz, address)?;
+                        let immediate_words = if sz == Size::Long { 4 } else { 2 };
+                        let dst = self.target(&operands[1], sz, base + immediate_words)?;
+                        match mnemonic {
+                            "addi" => Instructions::ADDI(imm, dst, sz),
+                            "subi" => Instructions::SUBI(imm, dst, sz),
+                            "andi" => Instructions::ANDI(imm, dst, sz),
+                            "ori" => Instructions::ORI(imm, dst, sz),
+                            "eori" => Instructions::EORI(imm, dst, sz),
+                            _ => Instructions::CMPI(imm, dst, sz),
+                        }
+                    }
+                }
+            }
+            "addq" | "subq" => {
+                expect(2)?;
+                let imm = self.immediate(&operands[0], Size::Long, address)?;
+                let dst = target(1, sz)?;
+                match mnemonic {
+                    "addq" => Instructions::ADDQ(imm, dst, sz),
+                    _ => Instructions::SUBQ(imm, dst, sz),
+                }
+            }
+            "asl" | "asr" | "lsl" | "lsr" | "rol" | "ror" | "roxl" | "roxr" => {
+                let (count, dst) = match operands.len() {
+                    1 => (Target::Immediate(1), target(0, sz)?),
+                    2 => {
+                        let count = match &operands[0] {
+                            Operand::Immediate(expr) => {
+                                Target::Immediate(self.value(expr, address)? as u32)
+                            }
+                            Operand::DataRegister(reg) => Target::DnDirect(*reg),
+                            _ => return Err("Shift count must be immediate or Dn".to_string()),
+                        };
+                        (count, target(1, sz)?)
+                    }
+                    _ => return Err(format!("{} expects 1 or 2 operands", mnemonic)),
+                };
+                match mnemonic {
+                    "asl" => Instructions::ASL(count, dst, sz),
+                    "asr" => Instructions::ASR(count, dst, sz),
+                    "lsl" => Instructions::LSL(count, dst, sz),
+                    "lsr" => Instructions::LSR(count, dst, sz),
+                    "rol" => Instructions::ROL(count, dst, sz),
+                    "ror" => Instructions::ROR(count, dst, sz),
+                    "roxl" => Instructions::ROXL(count, dst, sz),
+                    _ => Instructions::ROXR(count, dst, sz),
+                }
+            }
+            "btst" | "bchg" | "bclr" | "bset" => {
+                expect(2)?;
+                let sz = match &operands[1] {
+                    Operand::DataRegister(_) => Size::Long,
+                    _ => Size::Byte,
+                };
+                let (src, dst) = match &operands[0] {
+                    Operand::Immediate(expr) => (
+                        Target::Immediate(truncate(self.value(expr, address)?, Size::Byte)?),
+                        self.target(&operands[1], sz, base.wrapping_add(2))?,
+                    ),
+                    _ => (target(0, sz)?, target(1, sz)?),
+                };
+                match mnemonic {
+                    "btst" => Instructions::BTST(src, dst, sz),
+                    "bchg" => Instructions::BCHG(src, dst, sz),
+                    "bclr" => Instructions::BCLR(src, dst, sz),
+                    _ => Instructions::BSET(src, dst, sz),
+                }
+            }
+            "chk" => {
+                expect(2)?;
+                Instructions::CHK(target(0, Size::Word)?, data_register(1)?, Size::Word)
+            }
+            "clr" | "neg" | "negx" | "not" | "tst" => {
+                expect(1)?;
+                let dst = target(0, sz)?;
+                match mnemonic {
+                    "clr" => Instructions::CLR(dst, sz),
+                    "neg" => Instructions::NEG(dst, sz),
+                    "negx" => Instructions::NEGX(dst, sz),
+                    "not" => Instructions::NOT(dst, sz),
+                    _ => Instructions::TST(dst, sz),
+                }
+            }
+            "cmpm" => {
+                expect(2)?;
+                Instructions::CMPM(target(0, sz)?, target(1, sz)?, sz)
+            }
+            "divs" | "divu" | "muls" | "mulu" => {
+                expect(2)?;
+                let (src, reg) = (target(0, Size::Word)?, data_register(1)?);
+                match mnemonic {
+                    "divs" => Instructions::DIVSW(src, reg, Size::Word),
+                    "divu" => Instructions::DIVUW(src, reg, Size::Word),
+                    "muls" => Instructions::MULSW(src, reg),
+                    _ => Instructions::MULUW(src, reg),
+                }
+            }
+            "exg" => {
+                expect(2)?;
+                Instructions::EXG(target(0, Size::Long)?, target(1, Size::Long)?)
+            }
+            "ext" => {
+                expect(1)?;
+                match sz {
+                    Size::Long => Instructions::EXT(data_register(0)?, Size::Word, Size::Long),
+                    _ => Instructions::EXT(data_register(0)?, Size::Byte, Size::Word),
+                }
+            }
+            "illegal" | "nop" | "reset" | "rte" | "rtr" | "rts" | "trapv" => {
+                expect(0)?;
+                match mnemonic {
+                    "illegal" => Instructions::ILLEGAL,
+                    "nop" => Instructions::NOP,
+                    "reset" => Instructions::RESET,
+                    "rte" => Instructions::RTE,
+                    "rtr" => Instructions::RTR,
+                    "rts" => Instructions::RTS,
+                    _ => Instructions::TRAPV,
+                }
+            }
+            "jmp" | "jsr" | "pea" => {
+                expect(1)?;
+                let dst = target(0, Size::Long)?;
+                match mnemonic {
+                    "jmp" => Instructions::JMP(dst),
+                    "jsr" => Instructions::JSR(dst),
+                    _ => Instructions::PEA(dst),
+                }
+            }
+            "lea" => {
+                expect(2)?;
+                Instructions::LEA(target(0, Size::Long)?, address_register(1)?)
+            }
+            "link" => {
+                expect(2)?;
+                let disp = match &operands[1] {
+                    Operand::Immediate(expr) => self.value(expr, address)?,
+                    _ => return Err("Expected immediate displacement".to_string()),
+                };
+                if !(-32768..=32767).contains(&disp) {
+                    return Err("Displacement out of range".to_string());
+                }
+                Instructions::LINK(address_register(0)?, disp as i32)
+            }
+            "unlk" => {
+                expect(1)?;
+                Instructions::UNLK(address_register(0)?)
+            }
+            "move" | "movea" => {
+                expect(2)?;
+                match (&operands[0], &operands[1]) {
+                    (Operand::Sr, _) => Instructions::MOVEfromSR(target(1, Size::Word)?),
+                    (Operand::Ccr, _) => Instructions::MOVEfromCCR(target(1, Size::Word)?),
+                    (_, Operand::Sr) => Instructions::MOVEtoSR(target(0, Size::Word)?),
+                    (_, Operand::Ccr) => Instructions::MOVEtoCCR(target(0, Size::Word)?),
+                    (Operand::Usp, Operand::AddressRegister(reg)) => {
+                        Instructions::MOVEUSP(Target::AnDirect(*reg), Direction::MemoryToRegister)
+                    }
+                    (Operand::AddressRegister(reg), Operand::Usp) => {
+                        Instructions::MOVEUSP(Target::AnDirect(*reg), Direction::RegisterToMemory)
+                    }
+                    (_, Operand::AddressRegister(reg)) => {
+                        Instructions::MOVEA(target(0, sz)?, *reg, sz)
+                    }
+                    _ => {
+                        let src = target(0, sz)?;
+                        let src_words = encode(&Instructions::MOVE(
+                            src.clone(),
+                            Target::DnDirect(Registers::D0),
+                            sz,
+                        ))
+                        .map_err(|e| e.to_string())?
+                        .len() as u32
+                            - 1;
+                        let dst =
+                            self.target(&operands[1], sz, base.wrapping_add(2 * src_words))?;
+                        Instructions::MOVE(src, dst, sz)
+                    }
+                }
+            }
+            "moveq" => {
+                expect(2)?;
+                let imm = match &operands[0] {
+                    Operand::Immediate(expr) => self.value(expr, address)?,
+                    _ => return Err("Expected immediate data".to_string()),
+                };
+                if !(-128..=127).contains(&imm) {
+                    return Err("MOVEQ data out of range".to_string());
+                }
+                Instructions::MOVEQ(imm as u8, data_register(1)?)
+            }
+            "movem" => {
+                expect(2)?;
+                let sz = match sz {
+                    Size::Long => Size::Long,
+                    _ => Size::Word,
+                };
+                let (mask, memory, direction) =
+                    match (register_list(&operands[0]), register_list(&operands[1])) {
+                        (Some(mask), None) => (mask, &operands[1], Direction::RegisterToMemory),
+                        (None, Some(mask)) => (mask, &operands[0], Direction::MemoryToRegister),
+                        _ => return Err("MOVEM needs a register list and an address".to_string()),
+                    };
+                let mask = match memory {
+                    Operand::PreDec(_) => mask.reverse_bits(),
+                    _ => mask,
+                };
+                Instructions::MOVEM(
+                    self.target(memory, sz, base.wrapping_add(2))?,
+                    sz,
+                    mask as i16,
+                    direction,
+                )
+            }
+            "movep" => {
+                expect(2)?;
+                let sz = match sz {
+                    Size::Long => Size::Long,
+                    _ => Size::Word,
+                };
+                let peripheral = |operand: &Operand| match operand {
+                    Operand::Indirect(reg) => Ok((*reg, 0)),
+                    Operand::Displacement(expr, reg) => {
+                        let disp = self.value(expr, address)?;
+                        if !(-32768..=32767).contains(&disp) {
+                            return Err("Displacement out of range".to_string());
+                        }
+                        Ok((*reg, disp))
+                    }
+                    _ => Err("Expected d16(An)".to_string()),
+                };
+                match (&operands[0], &operands[1]) {
+                    (Operand::DataRegister(data), operand) => {
+                        let (reg, disp) = peripheral(operand)?;
+                        Instructions::MOVEP(
+                            *data,
+                            reg,
+                            disp as i16,
+                            sz,
+                            Direction::RegisterToMemory,
+                        )
+                    }
+                    (operand, Operand::DataRegister(data)) => {
+                        let (reg, disp) = peripheral(operand)?;
+                        Instructions::MOVEP(
+                            *data,
+                            reg,
+                            disp as i16,
+                            sz,
+                            Direction::MemoryToRegister,
+                        )
+                    }
+                    _ => return Err("MOVEP needs a data register".to_string()),
+                }
+            }
+            "movec" => {
+                expect(2)?;
+                match (&operands[0], &operands[1]) {
+                    (Operand::Vbr, _) => Instructions::MOVEC(
+                        target(1, Size::Long)?,
+                        ControlRegister::VBR,
+                        Direction::MemoryToRegister,
+                    ),
+                    (_, Operand::Vbr) => Instructions::MOVEC(
+                        target(0, Size::Long)?,
+                        ControlRegister::VBR,
+                        Direction::RegisterToMemory,
+                    ),
+                    _ => return Err("MOVEC needs a control register".to_string()),
+                }
+            }
+            "nbcd" | "tas" => {
+                expect(1)?;
+                let dst = target(0, Size::Byte)?;
+                match mnemonic {
+                    "nbcd" => Instructions::NBCD(dst),
+                    _ => Instructions::TAS(dst),
+                }
+            }
+            "swap" => {
+                expect(1)?;
+                Instructions::SWAP(data_register(0)?)
+            }
+            "trap" => {
+                expect(1)?;
+                Instructions::TRAP(self.immediate(&operands[0], Size::Byte, address)? as u8)
+            }
+            "stop" | "rtd" => {
+                expect(1)?;
+                let imm = self.immediate(&operands[0], Size::Word, address)?;
+                match mnemonic {
+                    "stop" => Instructions::STOP(imm as u16),
+                    _ => Instructions::RTD(imm as i16),
+                }
+            }
+            _ => return Err(format!("Unknown instruction {}", mnemonic)),
+        })
+    }
+}
+
+fn size_bytes(size: Size) -> u32 {
+    match size {
+        Size::Byte => 1,
+        Size::Word => 2,
+        Size::Long => 4,
+    }
+}
+
+// Range checks a value for the given size and returns its two's complement bits
+fn truncate(value: i64, size: Size) -> Result<u32, String> {
+    let (min, max, mask) = match size {
+        Size::Byte => (-0x80, 0xFF, 0xFF),
+        Size::Word => (-0x8000, 0xFFFF, 0xFFFF),
+        Size::Long => (-0x8000_0000, 0xFFFF_FFFF, 0xFFFF_FFFF),
+    };
+    if value < min || value > max {
+        return Err(format!("Value {} out of range", value));
+    }
+    Ok((value & mask) as u32)
+}
+
+fn condition(name: &str) -> Option<Condition> {
+    Some(match name {
+        "t" => Condition::True,
+        "f" => Condition::False,
+        "hi" => Condition::High,
+        "ls" => Condition::Low,
+        "cc" | "hs" => Condition::CarryClear,
+        "cs" | "lo" => Condition::CarrySet,
+        "ne" => Condition::Neq,
+        "eq" => Condition::Equal,
+        "vc" => Condition::OverflowClear,
+        "vs" => Condition::OverflowSet,
+        "pl" => Condition::Plus,
+        "mi" => Condition::Minus,
+        "ge" => Condition::Geq,
+        "lt" => Condition::Lt,
+        "gt" => Condition::Gt,
+        "le" => Condition::Leq,
+        _ => return None,
+    })
+}
+
+// Condition of a Bcc, BRA or BSR mnemonic
+fn branch_condition(mnemonic: &str) -> Option<Condition> {
+    match mnemonic {
+        "bra" => Some(Condition::True),
+        "bsr" => Some(Condition::False),
+        _ => match mnemonic.strip_prefix('b').and_then(condition) {
+            Some(Condition::True | Condition::False) => None,
+            cond => cond,
+        },
+    }
+}
+
+fn register_list(operand: &Operand) -> Option<u16> {
+    match operand {
+        Operand::RegisterList(mask) => Some(*mask),
+        Operand::DataRegister(reg) => Some(1 << reg.number()?),
+        Operand::AddressRegister(reg) => Some(1 << (reg.number()? + 8)),
+        _ => None,
+    }
+}
+
+fn is_local(name: &str) -> bool {
+    name.starts_with('.') || name.ends_with('$')
+}
+
+// Local labels (.name or name$) belong to the last global label before them
+fn qualify(name: &str, scope: &str) -> String {
+    if is_local(name) {
+        format!("{}{}", scope, name)
+    } else {
+        name.to_string()
+    }
+}
+
+fn describe(expr: &Expr) -> String {
+    match expr {
+        Expr::Symbol(name) => name.clone(),
+        Expr::Negate(e) | Expr::Complement(e) => describe(e),
+        Expr::Binary(_, a, b) => format!("{} {}", describe(a), describe(b)),
+        _ => String::new(),
+    }
+    .trim()
+    .to_string()
+}
+
+// Splits a source line into label, operation and operand field
+fn split_line(text: &str) -> (Option<&str>, Option<&str>, &str) {
+    if text.starts_with('*') {
+        return (None, None, "");
+    }
+    let mut quote = None;
+    let mut end = text.len();
+    for (i, c) in text.char_indices() {
+        match (quote, c) {
+            (None, '\'' | '"') => quote = Some(c),
+            (Some(q), _) if q == c => quote = None,
+            (None, ';') => {
+                end = i;
+                break;
+            }
+            _ => {}
+        }
+    }
+    let text = text[..end].trim_end();
+
+    let mut rest = text;
+    let mut label = None;
+    if !text.starts_with(char::is_whitespace) && !text.is_empty() {
+        let split = text
+            .find(|c: char| c.is_whitespace() || c == ':')
+            .unwrap_or(text.len());
+        label = Some(&text[..split]);
+        rest = text[split..].trim_start_matches(':');
+    } else {
+        let trimmed = rest.trim_start();
+        let split = trimmed.find(char::is_whitespace).unwrap_or(trimmed.len());
+        if trimmed[..split].ends_with(':') {
+            label = Some(&trimmed[..split - 1]);
+            rest = &trimmed[split..];
+        }
+    }
+
+    let rest = rest.trim();
+    if rest.is_empty() {
+        return (label, None, "");
+    }
+    let split = rest.find(char::is_whitespace).unwrap_or(rest.len());
+    (label, Some(&rest[..split]), rest[split..].trim())
+}
+
+// Lower-cases a mnemonic and separates its size suffix
+fn split_size(op: &str) -> Result<(String, Option<Size>), String> {
+    let op = op.to_lowercase();
+    match op.split_once('.') {
+        None => Ok((op, None)),
+        Some((mnemonic, suffix)) => {
+            let size = match suffix {
+                "b" | "s" => Size::Byte,
+                "w" => Size::Word,
+                "l" => Size::Long,
+                _ => return Err(format!("Unknown size .{}", suffix)),
+            };
+            Ok((mnemonic.to_string(), Some(size)))
+        }
+    }
+}
+
+// Splits an operand field on commas outside of parentheses and quotes
+fn split_operands(text: &str) -> Vec<&str> {
+    let mut operands = Vec::new();
+    let mut depth = 0;
+    let mut quote = None;
+    let mut start = 0;
+    for (i, c) in text.char_indices() {
+        match (quote, c) {
+            (None, '\'' | '"') => quote = Some(c),
+            (Some(q), _) if q == c => quote = None,
+            (None, '(') => depth += 1,
+            (None, ')') => depth -= 1,
+            (None, ',') if depth == 0 => {
+                operands.push(text[start..i].trim());
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    if !text.trim().is_empty() {
+        operands.push(text[start..].trim());
+    }
+    operands
+}
+
+fn parse_data(text: &str, size: Size, scope: &str) -> Result<Vec<DataItem>, String> {
+    split_operands(text)
+        .into_iter()
+        .map(|item| {
+            let quoted = item.len() >= 2
+                && (item.starts_with('\'') && item.ends_with('\'')
+                    || item.starts_with('"') && item.ends_with('"'));
+            if quoted && size == Size::Byte {
+                let quote = &item[..1];
+                let text = item[1..item.len() - 1].replace(&quote.repeat(2), quote);
+                Ok(DataItem::Bytes(text.into_bytes()))
+            } else {
+                Ok(DataItem::Value(parse_expression(item, scope)?))
+            }
+        })
+        .collect()
+}
+
+fn register(name: &str) -> Option<Registers> {
+    let name = name.trim().to_lowercase();
+    if name == "sp" {
+        return Some(Registers::SP);
+    }
+    let mut chars = name.chars();
+    let bank = chars.next()?;
+    let number = chars.as_str().parse::<u16>().ok().filter(|n| *n < 8)?;
+    match bank {
+        'd' => Some(Registers::data(number)),
+        'a' => Some(Registers::address(number)),
+        _ => None,
+    }
+}
+
+fn index_register(text: &str) -> Result<IndexRegister, String> {
+    let text = text.trim().to_lowercase();
+    let (text, scale) = match text.split_once('*') {
+        Some((text, scale)) => (
+            text.to_string(),
+            scale
+                .trim()
+                .parse::<u8>()
+                .map_err(|_| format!("Invalid scale {}", scale))?,
+        ),
+        None => (text.clone(), 1),
+    };
+    let (name, size) = match text.split_once('.') {
+        Some((name, "w")) => (name, Size::Word),
+        Some((name, "l")) => (name, Size::Long),
+        Some(_) => return Err(format!("Invalid index register {}", text)),
+        None => (text.as_str(), Size::Word),
+    };
+    let register = register(name).ok_or_else(|| format!("Invalid index register {}", name))?;
+    Ok(IndexRegister {
+        register,
+        size,
+        scale,
+    })
+}
+
+// Parses register lists such as d0-d3/a0/a5-a6 into a D0-A7 bit mask
+fn parse_register_list(text: &str) -> Option<u16> {
+    if !text.contains('/') && !text.contains('-') {
+        return None;
+    }
+    let index = |name: &str| {
+        let reg = register(name)?;
+        Some(reg.number()? + if reg.is_address() { 8 } else { 0 })
+    };
+    let mut mask = 0u16;
+    for part in text.split('/') {
+        match part.split_once('-') {
+            Some((from, to)) => {
+                let (from, to) = (index(from)?, index(to)?);
+                if from > to {
+                    return None;
+                }
+                for n in from..=to {
+                    mask |= 1 << n;
+                }
+            }
+            None => mask |= 1 << index(part)?,
+        }
+    }
+    Some(mask)
+}
+
+fn parse_operand(text: &str, scope: &str) -> Result<Operand, String> {
+    let lower = text.to_lowercase();
+    if let Some(expr) = text.strip_prefix('#') {
+        return Ok(Operand::Immediate(parse_expression(expr, scope)?));
+    }
+    match lower.as_str() {
+        "ccr" => return Ok(Operand::Ccr),
+        "sr" => return Ok(Operand::Sr),
+        "usp" => return Ok(Operand::Usp),
+        "vbr" => return Ok(Operand::Vbr),
+        _ => {}
+    }
+    if let Some(reg) = register(text) {
+        return Ok(if reg.is_address() {
+            Operand::AddressRegister(reg)
+        } else {
+            Operand::DataRegister(reg)
+        });
+    }
+    if let Some(mask) = parse_register_list(text) {
+        return Ok(Operand::RegisterList(mask));
+    }
+    let address = |name: &str| register(name).filter(|reg| reg.is_address());
+    if let Some(inner) = lower.strip_prefix("-(").and_then(|s| s.strip_suffix(')')) {
+        if let Some(reg) = address(inner) {
+            return Ok(Operand::PreDec(reg));
+        }
+    }
+    if let Some(inner) = lower.strip_prefix('(').and_then(|s| s.strip_suffix(")+")) {
+        if let Some(reg) = address(inner) {
+            return Ok(Operand::PostInc(reg));
+        }
+    }
+    if text.ends_with(')') {
+        if let Some(open) = matching_open(text) {
+            let prefix = text[..open].trim();
+            let inner = &text[open + 1..text.len() - 1];
+            let parts = split_operands(inner);
+            let is_base =
+                |part: &str| part.trim().eq_ignore_ascii_case("pc") || address(part).is_some();
+            let (disp, base, index) = match parts.as_slice() {
+                [base] if is_base(base) => (prefix, *base, None),
+                [base, index] if is_base(base) => (prefix, *base, Some(*index)),
+                [disp, base] if prefix.is_empty() && is_base(base) => (*disp, *base, None),
+                [disp, base, index] if prefix.is_empty() && is_base(base) => {
+                    (*disp, *base, Some(*index))
+                }
+                _ => ("", "", None),
+            };
+            if !base.is_empty() {
+                let pc = base.trim().eq_ignore_ascii_case("pc");
+                let disp = if disp.is_empty() {
+                    None
+                } else {
+                    Some(parse_expression(disp, scope)?)
+                };
+                let index = index.map(index_register).transpose()?;
+                return Ok(match (pc, disp, index) {
+                    (false, None, None) => Operand::Indirect(address(base).unwrap()),
+                    (false, Some(disp), None) => {
+                        Operand::Displacement(disp, address(base).unwrap())
+                    }
+                    (false, disp, Some(index)) => Operand::Index(
+                        disp.unwrap_or(Expr::Number(0)),
+                        address(base).unwrap(),
+                        index,
+                    ),
+                    (true, disp, None) => Operand::PCDisplacement(disp.unwrap_or(Expr::Number(0))),
+                    (true, disp, Some(index)) => {
+                        Operand::PCIndex(disp.unwrap_or(Expr::Number(0)), index)
+                    }
+                });
+            }
+        }
+    }
+    if lower.ends_with(".w") || lower.ends_with(".l") {
+        let size = if lower.ends_with(".w") {
+            Size::Word
+        } else {
+            Size::Long
+        };
+        let expr = parse_expression(&text[..text.len() - 2], scope)?;
+        return Ok(Operand::Absolute(expr, Some(size)));
+    }
+    Ok(Operand::Absolute(parse_expression(text, scope)?, None))
+}
+
+// Index of the parenthesis that opens the group closed by the last character
+fn matching_open(text: &str) -> Option<usize> {
+    let mut depth = 0;
+    for (i, c) in text.char_indices().rev() {
+        match c {
+            ')' => depth += 1,
+            '(' => {
+                depth -= 1;
+                if depth == 0 {
+                    return Some(i);
+                }
+            }
+            _ => {}
+        }
+    }
+    None
+}
+
+fn parse_expression(text: &str, scope: &str) -> Result<Expr, String> {
+    let mut parser = ExprParser {
+        chars: text.chars().collect(),
+        position: 0,
+        scope,
+    };
+    let expr = parser.binary(0)?;
+    parser.skip_whitespace();
+    if parser.position != parser.chars.len() {
+        return Err(format!("Invalid expression {}", text.trim()));
+    }
+    Ok(expr)
+}
+
+struct ExprParser<'a> {
+    chars: Vec<char>,
+    position: usize,
+    scope: &'a str,
+}
+
+impl ExprParser<'_> {
+    fn skip_whitespace(&mut self) {
+        while self.peek().is_some_and(char::is_whitespace) {
+            self.position += 1;
+        }
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.position).copied()
+    }
+
+    // Binary operator at the current position with its precedence (0 binds loosest)
+    fn operator(&self) -> Option<(Operator, usize, usize)> {
+        let next = self.chars.get(self.position + 1).copied();
+        Some(match (self.peek()?, next) {
+            ('|' | '!', _) => (Operator::Or, 0, 1),
+            ('^', _) => (Operator::Xor, 1, 1),
+            ('&', _) => (Operator::And, 2, 1),
+            ('<', Some('<')) => (Operator::ShiftLeft, 3, 2),
+            ('>', Some('>')) => (Operator::ShiftRight, 3, 2),
+            ('+', _) => (Operator::Add, 4, 1),
+            ('-', _) => (Operator::Subtract, 4, 1),
+            ('*', _) => (Operator::Multiply, 5, 1),
+            ('/', _) => (Operator::Divide, 5, 1),
+            ('%', _) => (Operator::Modulo, 5, 1),
+            _ => return None,
+        })
+    }
+
+    fn binary(&mut self, precedence: usize) -> Result<Expr, String> {
+        if precedence > 5 {
+            return self.unary();
+        }
+        let mut left = self.binary(precedence + 1)?;
+        loop {
+            self.skip_whitespace();
+            match self.operator() {
+                Some((op, level, width)) if level == precedence => {
+                    self.position += width;
+                    let right = self.binary(precedence + 1)?;
+                    left = Expr::Binary(op, Box::new(left), Box::new(right));
+                }
+                _ => return Ok(left),
+            }
+        }
+    }
+
+    fn unary(&mut self) -> Result<Expr, String> {
+        self.skip_whitespace();
+        match self.peek() {
+            Some('-') => {
+                self.position += 1;
+                Ok(Expr::Negate(Box::new(self.unary()?)))
+            }
+            Some('~') => {
+                self.position += 1;
+                Ok(Expr::Complement(Box::new(self.unary()?)))
+            }
+            Some('+') => {
+                self.position += 1;
+                self.unary()
+            }
+            _ => self.primary(),
+        }
+    }
+
+    fn primary(&mut self) -> Result<Expr, String> {
+        let c = self.peek().ok_or("Missing operand in expression")?;
+        match c {
+            '(' => {
+                self.position += 1;
+                let expr = self.binary(0)?;
+                self.skip_whitespace();
+                if self.peek() != Some(')') {
+                    return Err("Missing )".to_string());
+                }
+                self.position += 1;
+                Ok(expr)
+            }
+            '*' => {
+                self.position += 1;
+                Ok(Expr::Location)
+            }
+            '$' => self.number(16, 1),
+            '%' => self.number(2, 1),
+            '@' => self.number(8, 1),
+            '0' if matches!(self.chars.get(self.position + 1), Some('x' | 'X')) => {
+                self.number(16, 2)
+            }
+            '\'' | '"' => self.character(c),
+            _ if c.is_ascii_digit() => {
+                let mut end = self.position;
+                while self.chars.get(end).is_some_and(|c| c.is_ascii_digit()) {
+                    end += 1;
+                }
+                if self.chars.get(end) == Some(&'$') {
+                    self.symbol()
+                } else {
+                    self.number(10, 0)
+                }
+            }
+            _ if c.is_alphabetic() || c == '_' || c == '.' => self.symbol(),
+            _ => Err(format!("Unexpected {} in expression", c)),
+        }
+    }
+
+    fn number(&mut self, radix: u32, prefix: usize) -> Result<Expr, String> {
+        self.position += prefix;
+        let start = self.position;
+        while self.peek().is_some_and(|c| c.is_digit(radix)) {
+            self.position += 1;
+        }
+        let digits: String = self.chars[start..self.position].iter().collect();
+        i64::from_str_radix(&digits, radix)
+            .map(Expr::Number)
+            .map_err(|_| format!("Invalid number {}", digits))
+    }
+
+    // Character constant, packed big-endian: 'AB' is $4142
+    fn character(&mut self, quote: char) -> Result<Expr, String> {
+        self.position += 1;
+        let mut value: i64 = 0;
+        loop {
+            match self.peek() {
+                None => return Err("Unterminated character constant".to_string()),
+                Some(c) if c == quote => {
+                    self.position += 1;
+                    if self.peek() != Some(quote) {
+                        return Ok(Expr::Number(value));
+                    }
+                    value = value << 8 | quote as i64;
+                }
+                Some(c) => value = value << 8 | (c as i64 & 0xFF),
+            }
+            self.position += 1;
+        }
+    }
+
+    fn symbol(&mut self) -> Result<Expr, String> {
+        let start = self.position;
+        while self
+            .peek()
+            .is_some_and(|c| c.is_alphanumeric() || c == '_' || c == '.' || c == '$')
+        {
+            self.position += 1;
+        }
+        let name: String = self.chars[start..self.position].iter().collect();
+        Ok(Expr::Symbol(qualify(&name, self.scope)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::assemble;
+
+    #[test]
+    fn forward_pc_relative_reference() {
+        let program = assemble(" org $10000\n lea data(pc),a0\n nop\ndata dc.w 1\n").unwrap();
+        assert_eq!(
+            program.chunks[0].data,
+            [0x41, 0xFA, 0x00, 0x04, 0x4E, 0x71, 0x00, 0x01]
+        );
+    }
+
+    #[test]
+    fn pc_relative_out_of_range() {
+        let error = assemble(" org $10000\n lea data(pc),a0\n org $20000\ndata dc.w 1\n");
+        assert!(error.is_err());
+    }
+
+    #[test]
+    fn top_of_the_address_space() {
+        let program = assemble(" org $FFFFFFFE\n nop\n").unwrap();
+        assert_eq!(program.chunks[0].address, 0xFFFF_FFFE);
+        assert_eq!(program.chunks[0].data, [0x4E, 0x71]);
+    }
+
+    #[test]
+    fn negative_ds_count() {
+        assert!(assemble(" ds.b -1\n").is_err());
+    }
+
+    #[test]
+    fn local_labels_belong_to_the_last_global_label() {
+        let source = "\
+first moveq #1,d0
+.loop dbra d0,.loop
+1$ bra.s 1$
+second moveq #2,d0
+.loop dbra d0,.loop
+1$ bra.s first.loop
+";
+        let program = assemble(source).unwrap();
+        let symbols = &program.symbols;
+        assert_eq!(symbols["first.loop"], 2);
+        assert_eq!(symbols["first1$"], 6);
+        assert_eq!(symbols["second.loop"], 10);
+        assert_eq!(symbols["second1$"], 14);
+        assert_eq!(
+            program.chunks[0].data[10..],
+            [0x51, 0xC8, 0xFF, 0xFE, 0x60, 0xF2]
+        );
+    }
+
+    #[test]
+    fn equates_and_expressions() {
+        let source = "\
+count equ end-start
+mask = ~0&$FF
+ org $1000
+start dc.w count,mask,1+2*3,(1+2)*3,1<<4|1,7%4,-2,%101,@17
+ dc.l *
+end
+";
+        let program = assemble(source).unwrap();
+        assert_eq!(program.symbols["count"], 22);
+        assert_eq!(
+            program.chunks[0].data,
+            [
+                0x00, 0x16, 0x00, 0xFF, 0x00, 0x07, 0x00, 0x09, 0x00, 0x11, 0x00, 0x03, 0xFF, 0xFE,
+                0x00, 0x05, 0x00, 0x0F, 0x00, 0x00, 0x10, 0x12
+            ]
+        );
+    }
+
+    #[test]
+    fn dc_strings_and_alignment() {
+        let program = assemble(" dc.b 'Hi',0,\"A\"\n dc.w 1\n").unwrap();
+        assert_eq!(program.chunks[0].data, b"Hi\0A\0\x01");
+    }
+
+    #[test]
+    fn displacements_are_range_checked() {
+        assert!(assemble(" movep.w d0,$12345(a0)\n").is_err());
+        assert!(assemble(" movep.w $8000(a0),d0\n").is_err());
+        assert!(assemble(" link a6,#$8000\n").is_err());
+        let program = assemble(" movep.w d0,-$8000(a0)\n link a6,#-4\n").unwrap();
+        assert_eq!(
+            program.chunks[0].data,
+            [0x01, 0x88, 0x80, 0x00, 0x4E, 0x56, 0xFF, 0xFC]
+        );
+    }
+}
@@ -0,0 +1,231 @@
+use crate::cpu::Registers;
+use crate::decoder::disassemble;
+use crate::instruction::Instructions;
+use crate::memory::Memory;
+
+// Deepest backtrace walked, in case a corrupt stack links back on itself
+const MAX_FRAMES: usize = 256;
+
+// A subroutine call that has not returned yet
+struct Call {
+    call_site: u32,
+    return_address: u32,
+    // Where the return address was pushed
+    stack_pointer: u32,
+}
+
+// Shadow stack of the calls in progress, kept from JSR/BSR and the return
+// instructions, for code that does not keep an A6 frame chain
+pub struct CallStack {
+    calls: Vec<Call>,
+}
+
+impl CallStack {
+    pub fn new() -> Self {
+        Self { calls: Vec::new() }
+    }
+
+    pub fn clear(&mut self) {
+        self.calls.clear();
+    }
+
+    // Whether the instruction starting with opcode is a call or a return,
+    // so only those need decoding for record
+    pub fn tracks(opcode: u16) -> bool {
+        opcode & 0xFFC0 == 0x4E80
+            || opcode & 0xFF00 == 0x6100
+            || matches!(opcode, 0x4E73 | 0x4E74 | 0x4E75 | 0x4E77)
+    }
+
+    // Follows ins at pc, which ends at next, left the PC at after and the
+    // stack pointer at sp
+    pub fn record(&mut self, pc: u32, ins: &Instructions, next: u32, after: u32, sp: u32) {
+        match ins {
+            Instructions::JSR(_) | Instructions::BSR(_) => {
+                // Calls whose return address was at or below the new one were
+                // abandoned, by longjmp or a discarded stack
+                self.calls.retain(|call| call.stack_pointer > sp);
+                self.calls.push(Call {
+                    call_site: pc,
+                    return_address: next,
+                    stack_pointer: sp,
+                });
+            }
+            Instructions::RTS | Instructions::RTD(_) | Instructions::RTR | Instructions::RTE => {
+                if let Some(depth) = self
+                    .calls
+                    .iter()
+                    .rposition(|call| call.return_address == after)
+                {
+                    self.calls.truncate(depth);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    // The PC followed by the call site of each active subroutine call,
+    // innermost first. Frames come from the A6 chain built by LINK; calls
+    // made since the innermost LINK, and whole stacks without a valid chain,
+    // come from the shadow stack.
+    pub fn frames<M: Memory + ?Sized>(&self, mem: &mut M, registers: &Registers) -> Vec<u32> {
+        let (pc, sp) = (registers.PC, registers.SP);
+        let mut frames = vec![pc];
+        let mut calls: Vec<(u32, u32)> = self
+            .calls
+            .iter()
+            .rev()
+            .filter(|call| call.stack_pointer >= sp)
+            .map(|call| (call.stack_pointer, call.call_site))
+            .collect();
+        // Before LINK and after UNLK the return address is on top of the
+        // stack, whether or not the call was seen
+        let at_edge = matches!(
+            disassemble(mem, pc).0,
+            Instructions::LINK(..) | Instructions::RTS
+        );
+        if at_edge && calls.first().is_none_or(|(at, _)| *at != sp) {
+            let site = mem
+                .read_at_address_long(sp)
+                .and_then(|return_address| call_site(mem, return_address));
+            if let Some(site) = site {
+                calls.insert(0, (sp, site));
+            }
+        }
+        let calls = calls.into_iter();
+        let chain = frame_chain(mem, registers.A6, sp);
+        match chain.first() {
+            Some((frame_pointer, _)) => {
+                let linked = frame_pointer.wrapping_add(4);
+                frames.extend(
+                    calls
+                        .take_while(|(at, _)| *at < linked)
+                        .map(|(_, site)| site),
+                );
+                frames.extend(chain.iter().map(|(_, site)| *site));
+            }
+            None => frames.extend(calls.map(|(_, site)| site)),
+        }
+        frames.truncate(MAX_FRAMES);
+        frames
+    }
+}
+
+impl Default for CallStack {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// Frame pointers and call sites along the chain of saved A6 values starting
+// at frame_pointer. Each LINK frame holds the caller's A6 with the return
+// address above it; the walk stops at the first frame that is not on the
+// stack or does not return just past a JSR or BSR.
+fn frame_chain<M: Memory + ?Sized>(
+    mem: &mut M,
+    mut frame_pointer: u32,
+    sp: u32,
+) -> Vec<(u32, u32)> {
+    let mut chain = Vec::new();
+    while chain.len() < MAX_FRAMES && frame_pointer >= sp && frame_pointer.is_multiple_of(2) {
+        let saved = mem.read_at_address_long(frame_pointer);
+        let return_address = mem.read_at_address_long(frame_pointer.wrapping_add(4));
+        let (Some(saved), Some(return_address)) = (saved, return_address) else {
+            break;
+        };
+        let Some(call_site) = call_site(mem, return_address) else {
+            break;
+        };
+        chain.push((frame_pointer, call_site));
+        // Frames are further up the stack the further out they are
+        if saved <= frame_pointer {
+            break;
+        }
+        frame_pointer = saved;
+    }
+    chain
+}
+
+// Address of the JSR or BSR that return_address follows, if there is one
+fn call_site<M: Memory + ?Sized>(mem: &mut M, return_address: u32) -> Option<u32> {
+    [2, 4, 6].iter().find_map(|length| {
+        let address = return_address.checked_sub(*length)?;
+        match disassemble(mem, address) {
+            (Instructions::JSR(_) | Instructions::BSR(_), next) if next == return_address => {
+                Some(address)
+            }
+            _ => None,
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::CallStack;
+    use crate::assembler::assemble;
+    use crate::cpu::CPU;
+    use crate::decoder::disassemble;
+
+    // main calls f1, which builds an A6 frame and calls f2, which has no
+    // frame and calls f3
+    const PROGRAM: &str = concat!(
+        " org $1000\n",
+        "main jsr f1\n",
+        " bra.s *\n",
+        "f1 link a6,#-4\n",
+        " bsr.s f2\n",
+        " unlk a6\n",
+        " rts\n",
+        "f2 bsr.s f3\n",
+        " rts\n",
+        "f3 nop\n",
+        " rts\n",
+    );
+
+    // Runs PROGRAM until the PC reaches f3, following its calls in a
+    // CallStack
+    fn run_to_f3() -> (CPU, CallStack, u32) {
+        let program = assemble(PROGRAM).unwrap();
+        let mut cpu = CPU::with_memory(0x10000);
+        program.load(&mut cpu.memory_bus.memory).unwrap();
+        cpu.set_sr(0x2700);
+        cpu.registers.PC = 0x1000;
+        cpu.registers.SP = 0x8000;
+        let mut calls = CallStack::new();
+        while cpu.registers.PC != program.symbols["f3"] {
+            let pc = cpu.registers.PC;
+            let (ins, next) = disassemble(&mut cpu.memory_bus.memory, pc);
+            cpu.step().unwrap();
+            calls.record(pc, &ins, next, cpu.registers.PC, cpu.registers.SP);
+        }
+        (cpu, calls, program.symbols["f3"])
+    }
+
+    #[test]
+    fn shadow_stack_fills_in_calls_below_the_frame_chain() {
+        let (mut cpu, calls, f3) = run_to_f3();
+        let frames = calls.frames(&mut cpu.memory_bus.memory, &cpu.registers);
+        // f3, the BSR in f2, the BSR in f1 and the JSR in main
+        assert_eq!(frames, [f3, 0x1012, 0x100C, 0x1000]);
+    }
+
+    #[test]
+    fn frame_chain_alone_skips_frameless_calls() {
+        let (mut cpu, _, f3) = run_to_f3();
+        let frames = CallStack::new().frames(&mut cpu.memory_bus.memory, &cpu.registers);
+        assert_eq!(frames, [f3, 0x1000]);
+    }
+
+    #[test]
+    fn garbage_stack_at_the_top_of_memory() {
+        let mut cpu = CPU::with_memory(0x10000);
+        cpu.memory_bus.memory.fill(0xFF);
+        // The frame at A6 returns to $FFFFFFFF, so the call site is looked
+        // for just below the top of the address space
+        cpu.registers.SP = 0xFF00;
+        cpu.registers.A6 = 0xFF00;
+        cpu.registers.PC = 0x1000;
+        let frames = CallStack::new().frames(&mut cpu.memory_bus.memory, &cpu.registers);
+        assert_eq!(frames, [0x1000]);
+    }
+}
@@ -0,0 +1,15 @@
+pub mod rosco;
+
+use crate::cpu::CPU;
+
+// Boards a firmware image can be booted on
+pub const BOARDS: [&str; 1] = ["rosco_m68k"];
+
+// Builds board name around the firmware image rom and resets it, with its
+// first serial port on stdio
+pub fn boot(name: &str, rom: &[u8]) -> Result<CPU, String> {
+    match name {
+        "rosco_m68k" => rosco::boot(rom),
+        _ => Err(format!("Unknown board {}, use {}", name, BOARDS.join(", "))),
+    }
+}
@@ -0,0 +1,116 @@
+use crate::cpu::{StatusRegister, CPU};
+use crate::device::console::Console;
+use crate::device::duart::Duart;
+use crate::device::Mapping;
+use crate::loader::binary;
+use crate::memory::Memory;
+
+// Memory map of the rosco_m68k boards with the 68681 DUART (r1.2 and r2):
+// onboard RAM from 0, the ROM sockets and then the I/O space
+pub const RAM_SIZE: u32 = 0x10_0000;
+pub const ROM_BASE: u32 = 0xE0_0000;
+pub const ROM_SIZE: u32 = 0x10_0000;
+// The DUART's registers sit on the odd bytes from here
+pub const DUART_BASE: u32 = 0xF0_0001;
+const DUART_STRIDE: u32 = 2;
+// The DUART interrupts on IPL 4 and supplies its own vector
+pub const DUART_LEVEL: u8 = 4;
+// The CPU runs from a 10 MHz oscillator
+pub const CLOCK: u32 = 10_000_000;
+// The 68000's 24-bit address space
+const ADDRESS_SPACE: u32 = 0x100_0000;
+
+// Builds the board around the firmware image rom, with DUART channel A,
+// the one the firmware talks on, on stdio
+pub fn boot(rom: &[u8]) -> Result<CPU, String> {
+    build(rom, Console::open("stdio")?, Console::open("none")?)
+}
+
+// Builds the board with DUART channels A and B on the given consoles and
+// resets it. Everything above the onboard RAM ignores writes: the ROM, and
+// the expansion and I/O space with nothing fitted, where the real board
+// would time out with a bus error.
+pub fn build(rom: &[u8], channel_a: Console, channel_b: Console) -> Result<CPU, String> {
+    if rom.len() > ROM_SIZE as usize {
+        return Err(format!(
+            "ROM image is {} bytes but the sockets hold {}",
+            rom.len(),
+            ROM_SIZE
+        ));
+    }
+    if rom.len() < 8 {
+        return Err("ROM image too short for reset vectors".to_string());
+    }
+    let mut cpu = CPU::with_memory(ADDRESS_SPACE as usize);
+    cpu.clock = CLOCK;
+    binary::load(rom, &mut cpu.memory_bus.memory, ROM_BASE)?;
+    cpu.memory_bus.read_only.push(RAM_SIZE..ADDRESS_SPACE);
+    let duart = Duart::new(channel_a, channel_b, cpu.clock);
+    cpu.memory_bus.map(Mapping::new(
+        DUART_BASE,
+        DUART_STRIDE,
+        DUART_LEVEL,
+        Box::new(duart),
+    ))?;
+    reset(&mut cpu)?;
+    Ok(cpu)
+}
+
+// Pulses RESET and restarts the CPU in supervisor mode with interrupts
+// masked. The glue logic decodes the ROM at address 0 for the first four
+// bus cycles after reset, so the initial SSP and PC come from the start of
+// the ROM while the RAM at 0 is left alone.
+pub fn reset(cpu: &mut CPU) -> Result<(), String> {
+    cpu.memory_bus.reset();
+    let bus = &cpu.memory_bus;
+    let (ssp, pc) = bus
+        .read_at_address_long(ROM_BASE)
+        .zip(bus.read_at_address_long(ROM_BASE + 4))
+        .ok_or("No ROM for reset vectors")?;
+    cpu.registers.SP = ssp;
+    cpu.registers.PC = pc;
+    cpu.registers.SR = StatusRegister::from_word(0x2700);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn board(rom: &[u8]) -> Result<CPU, String> {
+        build(rom, Console::open("none")?, Console::open("none")?)
+    }
+
+    #[test]
+    fn reset_takes_ssp_and_pc_from_the_rom() {
+        let rom = [0x00, 0x10, 0x00, 0x00, 0x00, 0xE0, 0x00, 0x08, 0x4E, 0x71];
+        let mut cpu = board(&rom).unwrap();
+        assert_eq!(cpu.registers.SP, RAM_SIZE);
+        assert_eq!(cpu.registers.PC, ROM_BASE + 8);
+        assert_eq!(cpu.registers.SR.to_word(), 0x2700);
+        // The RAM at 0 is left alone, and the ROM ignores writes
+        let bus = &mut cpu.memory_bus;
+        assert_eq!(bus.read_at_address_long(0), Some(0));
+        bus.write_at_address_long(ROM_BASE, 0).unwrap();
+        assert_eq!(bus.read_at_address_long(ROM_BASE), Some(RAM_SIZE));
+        // The DUART's IVR holds its reset vector
+        assert_eq!(
+            bus.read_at_address_byte(DUART_BASE + 12 * DUART_STRIDE),
+            Some(0x0F)
+        );
+
+        cpu.registers.PC = 0;
+        cpu.registers.SP = 0;
+        reset(&mut cpu).unwrap();
+        assert_eq!(
+            (cpu.registers.SP, cpu.registers.PC),
+            (RAM_SIZE, ROM_BASE + 8)
+        );
+    }
+
+    #[test]
+    fn rom_must_hold_the_vectors_and_fit_the_sockets() {
+        assert!(board(&[0; 7]).is_err());
+        assert!(board(&vec![0; ROM_SIZE as usize + 1]).is_err());
+    }
+}
@@ -0,0 +1,332 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::Write as _;
+
+use crate::cpu::StatusRegister;
+use crate::decoder::disassemble_range;
+use crate::instruction::Instructions;
+use crate::loader::dwarf::LineTable;
+use crate::memory::Memory;
+use crate::symbols::SymbolTable;
+
+// Furthest a function is decoded past its symbol when no symbol follows
+const MAX_FUNCTION: u32 = 0x1_0000;
+
+// How often a conditional instruction went each way: branched (or set its
+// byte, for Scc) or fell through
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Branch {
+    pub taken: u64,
+    pub not_taken: u64,
+}
+
+// Executed addresses and conditional instruction outcomes
+pub struct Coverage {
+    executed: BTreeMap<u32, u64>,
+    branches: BTreeMap<u32, Branch>,
+}
+
+impl Coverage {
+    pub fn new() -> Self {
+        Self {
+            executed: BTreeMap::new(),
+            branches: BTreeMap::new(),
+        }
+    }
+
+    // Counts ins at pc, which ends at next, left the PC at after and ran
+    // with the flags in sr
+    pub fn record(
+        &mut self,
+        pc: u32,
+        ins: &Instructions,
+        next: u32,
+        after: u32,
+        sr: &StatusRegister,
+    ) {
+        *self.executed.entry(pc).or_default() += 1;
+        let taken = match ins {
+            Instructions::Bcc(..) | Instructions::DBcc(..) => after != next,
+            Instructions::Scc(condition, _) => sr.condition(*condition),
+            _ => return,
+        };
+        let branch = self.branches.entry(pc).or_default();
+        if taken {
+            branch.taken += 1;
+        } else {
+            branch.not_taken += 1;
+        }
+    }
+
+    // Instruction and branch coverage of each function entered, by symbol
+    pub fn report<M: Memory + ?Sized>(&self, mem: &mut M, symbols: &SymbolTable) -> String {
+        let (branches, taken) = self.directions(self.branches.keys().copied());
+        let mut text = format!(
+            "{} instructions executed, {} of {} directions of {} conditional instructions taken\n",
+            self.executed.len(),
+            taken,
+            branches * 2,
+            branches
+        );
+
+        let starts: BTreeSet<u32> = symbols.iter().map(|(_, address)| address).collect();
+        for (n, start) in starts.iter().enumerate() {
+            let end = starts
+                .iter()
+                .nth(n + 1)
+                .copied()
+                .unwrap_or(start.saturating_add(MAX_FUNCTION));
+            if self.executed.range(start..&end).next().is_none() {
+                continue;
+            }
+            let code = disassemble_range(mem, *start, end, symbols);
+            let executed = code
+                .iter()
+                .filter(|(address, _, _)| self.executed.contains_key(address))
+                .count();
+            let conditional = code
+                .iter()
+                .filter(|(_, _, ins)| is_conditional(ins))
+                .map(|(address, _, _)| *address);
+            let (branches, taken) = self.directions(conditional);
+            let _ = writeln!(
+                text,
+                "{:6.2}%  {}/{} instructions  {}/{} branches  {}",
+                executed as f64 * 100.0 / code.len().max(1) as f64,
+                executed,
+                code.len(),
+                taken,
+                branches * 2,
+                symbols.describe(*start)
+            );
+        }
+        text
+    }
+
+    // Line, branch and function coverage in lcov's tracefile format, for the
+    // source files in lines
+    pub fn lcov<M: Memory + ?Sized>(
+        &self,
+        mem: &mut M,
+        lines: &LineTable,
+        symbols: &SymbolTable,
+        test: &str,
+    ) -> String {
+        // Executions of each statement start and the conditional
+        // instructions on each line, per file
+        let mut files: BTreeMap<usize, BTreeMap<u32, u64>> = BTreeMap::new();
+        let mut branches: BTreeMap<usize, BTreeMap<u32, Vec<u32>>> = BTreeMap::new();
+        for (n, row) in lines.rows.iter().enumerate() {
+            let repeated = n > 0 && lines.rows[n - 1] == *row;
+            if row.line == 0 || repeated {
+                continue;
+            }
+            let count = self.executed.get(&row.address).copied().unwrap_or(0);
+            *files
+                .entry(row.file)
+                .or_default()
+                .entry(row.line)
+                .or_default() += count;
+            let end = lines
+                .rows
+                .get(n + 1)
+                .map_or(row.address, |next| next.address)
+                .min(row.address.saturating_add(MAX_FUNCTION));
+            for (address, _, ins) in disassemble_range(mem, row.address, end, symbols) {
+                if is_conditional(&ins) {
+                    let line = branches.entry(row.file).or_default();
+                    line.entry(row.line).or_default().push(address);
+                }
+            }
+        }
+
+        let mut text = String::new();
+        for (file, line_counts) in &files {
+            let functions: BTreeSet<(u32, &str, u32)> = symbols
+                .iter()
+                .filter_map(|(name, address)| match lines.lookup(address) {
+                    Some((path, line)) if path == lines.files[*file] => Some((line, name, address)),
+                    _ => None,
+                })
+                .collect();
+            let no_branches = BTreeMap::new();
+            let file_branches = branches.get(file).unwrap_or(&no_branches);
+            self.tracefile(
+                &mut text,
+                test,
+                &lines.files[*file],
+                &functions,
+                file_branches,
+                line_counts,
+            );
+        }
+        text
+    }
+
+    // lcov output for a program without line tables: one record for image,
+    // with instruction addresses standing in for line numbers and a function
+    // for each symbol
+    pub fn lcov_symbols<M: Memory + ?Sized>(
+        &self,
+        mem: &mut M,
+        symbols: &SymbolTable,
+        image: &str,
+        test: &str,
+    ) -> String {
+        let mut line_counts: BTreeMap<u32, u64> = self.executed.clone();
+        let mut branches: BTreeMap<u32, Vec<u32>> = self
+            .branches
+            .keys()
+            .map(|address| (*address, vec![*address]))
+            .collect();
+        let starts: BTreeSet<u32> = symbols.iter().map(|(_, address)| address).collect();
+        for (n, start) in starts.iter().enumerate() {
+            let end = starts
+                .iter()
+                .nth(n + 1)
+                .copied()
+                .unwrap_or(start.saturating_add(MAX_FUNCTION));
+            for (address, _, ins) in disassemble_range(mem, *start, end, symbols) {
+                line_counts.entry(address).or_default();
+                if is_conditional(&ins) {
+                    branches.insert(address, vec![address]);
+                }
+            }
+        }
+        let functions: BTreeSet<(u32, &str, u32)> = symbols
+            .iter()
+            .map(|(name, address)| (address, name, address))
+            .collect();
+        let mut text = String::new();
+        self.tracefile(&mut text, test, image, &functions, &branches, &line_counts);
+        text
+    }
+
+    // One lcov record: functions as (line, name, address), the conditional
+    // instructions on each line and the executions of each line
+    fn tracefile(
+        &self,
+        text: &mut String,
+        test: &str,
+        file: &str,
+        functions: &BTreeSet<(u32, &str, u32)>,
+        branches: &BTreeMap<u32, Vec<u32>>,
+        line_counts: &BTreeMap<u32, u64>,
+    ) {
+        let _ = writeln!(text, "TN:{}\nSF:{}", test, file);
+
+        for (line, name, _) in functions {
+            let _ = writeln!(text, "FN:{},{}", line, name);
+        }
+        let mut functions_hit = 0;
+        for (_, name, address) in functions {
+            let count = self.executed.get(address).copied().unwrap_or(0);
+            functions_hit += (count > 0) as usize;
+            let _ = writeln!(text, "FNDA:{},{}", count, name);
+        }
+        let _ = writeln!(text, "FNF:{}\nFNH:{}", functions.len(), functions_hit);
+
+        let (mut found, mut hit) = (0, 0);
+        for (line, addresses) in branches {
+            for (block, address) in addresses.iter().enumerate() {
+                let outcome = self.branches.get(address);
+                for (direction, count) in [
+                    outcome.map(|branch| branch.taken),
+                    outcome.map(|branch| branch.not_taken),
+                ]
+                .iter()
+                .enumerate()
+                {
+                    found += 1;
+                    let count = match count {
+                        Some(count) => {
+                            hit += (*count > 0) as usize;
+                            count.to_string()
+                        }
+                        None => "-".to_string(),
+                    };
+                    let _ = writeln!(text, "BRDA:{},{},{},{}", line, block, direction, count);
+                }
+            }
+        }
+        let _ = writeln!(text, "BRF:{}\nBRH:{}", found, hit);
+
+        for (line, count) in line_counts {
+            let _ = writeln!(text, "DA:{},{}", line, count);
+        }
+        let lines_hit = line_counts.values().filter(|count| **count > 0).count();
+        let _ = writeln!(text, "LF:{}\nLH:{}", line_counts.len(), lines_hit);
+        text.push_str("end_of_record\n");
+    }
+
+    // Number of conditional instructions at addresses, and how many of
+    // their directions were taken
+    fn directions(&self, addresses: impl Iterator<Item = u32>) -> (usize, usize) {
+        addresses.fold((0, 0), |(count, taken), address| {
+            let hit = self.branches.get(&address).map_or(0, |branch| {
+                (branch.taken > 0) as usize + (branch.not_taken > 0) as usize
+            });
+            (count + 1, taken + hit)
+        })
+    }
+}
+
+impl Default for Coverage {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn is_conditional(ins: &Instructions) -> bool {
+    matches!(
+        ins,
+        Instructions::Bcc(..) | Instructions::DBcc(..) | Instructions::Scc(..)
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Coverage;
+    use crate::assembler::assemble;
+    use crate::cpu::StatusRegister;
+    use crate::symbols::SymbolTable;
+
+    #[test]
+    fn lcov_without_line_tables_uses_symbols_and_addresses() {
+        let program = assemble(" org $100\nstart tst.w d0\n beq.s done\n nop\ndone rts\n").unwrap();
+        let mut memory = vec![0u8; 0x200];
+        program.load(&mut memory[..]).unwrap();
+        let mut symbols = SymbolTable::new();
+        symbols.insert("start", 0x100);
+        symbols.insert("unused", 0x180);
+
+        // tst and beq run with Z set, so the branch is taken past the nop
+        let mut coverage = Coverage::new();
+        let sr = StatusRegister::from_word(0x0004);
+        let ins = |n: usize| &program.listing[n].1;
+        coverage.record(0x100, ins(0), 0x102, 0x102, &sr);
+        coverage.record(0x102, ins(1), 0x104, 0x106, &sr);
+        coverage.record(0x106, ins(3), 0x108, 0x200, &sr);
+
+        let text = coverage.lcov_symbols(&mut memory[..], &symbols, "rom.bin", "test");
+        let expected = "\
+TN:test
+SF:rom.bin
+FN:256,start
+FN:384,unused
+FNDA:1,start
+FNDA:0,unused
+FNF:2
+FNH:1
+BRDA:258,0,0,1
+BRDA:258,0,1,0
+BRF:2
+BRH:1
+DA:256,1
+DA:258,1
+DA:260,0
+DA:262,1
+";
+        assert!(text.starts_with(expected), "{}", text);
+        assert!(text.ends_with("end_of_record\n"));
+    }
+}
@@ -1,80 +1,242 @@
-use crate::memory::{Memory, MemoryIter, MEMORY_CAPACITY};
+use std::cell::RefCell;
+use std::fmt;
+use std::ops::Range;
 
-pub trait Processor<M: Memory + ?Sized> {
+use crate::device::{self, Mapping};
+use crate::instruction::{Condition, Instructions};
+use crate::memory::{Memory, MEMORY_CAPACITY};
+
+// Clock rate of a CPU that no board has set up
+pub const DEFAULT_CLOCK: u32 = 8_000_000;
+
+pub trait Processor {
     fn init(&mut self);
-    fn run(&mut self);
-    fn fetch(&mut self, mem_iter: &MemoryIter<'_, M>) -> u16;
-    fn execute(&mut self);
+    // Runs until an error stops the CPU and returns it
+    fn run(&mut self) -> String;
+    fn fetch(&mut self) -> Option<u16>;
+    fn execute(&mut self) -> Result<Instructions, String>;
 }
 
-enum CPUState {
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CPUState {
     Fetching,
     Decoding,
     Executing,
+    // Halted after a double fault
     Halting,
+    // Waiting for an interrupt after STOP
+    Stopped,
+}
+
+impl CPUState {
+    // Number standing for the state in save state files
+    pub fn code(self) -> u8 {
+        match self {
+            CPUState::Fetching => 0,
+            CPUState::Decoding => 1,
+            CPUState::Executing => 2,
+            CPUState::Halting => 3,
+            CPUState::Stopped => 4,
+        }
+    }
+
+    pub fn from_code(code: u8) -> Option<Self> {
+        Some(match code {
+            0 => CPUState::Fetching,
+            1 => CPUState::Decoding,
+            2 => CPUState::Executing,
+            3 => CPUState::Halting,
+            4 => CPUState::Stopped,
+            _ => return None,
+        })
+    }
 }
 
 pub struct CPU {
     pub registers: Registers,
     pub state: CPUState,
     pub memory_bus: MemoryBus<Box<[u8]>>,
+    // Clock cycles executed, from the 68000 timing tables with no wait states
+    pub cycles: u64,
+    // Clock rate in Hz, which devices use to turn cycles into time
+    pub clock: u32,
+    // Vector base register; zero on the 68000, settable with MOVEC
+    pub vbr: u32,
+    // Level 7 was taken and the request has not dropped since; level 7
+    // interrupts are edge triggered
+    pub(crate) nmi_taken: bool,
+}
+
+pub struct MemoryBus<M: Memory + ?Sized> {
+    // Record data accesses made through the bus, for watchpoints
+    pub recording: bool,
+    accesses: RefCell<Vec<Access>>,
+    // Peripherals, which take precedence over memory at their addresses
+    pub devices: Vec<Mapping>,
+    // Memory the bus ignores writes to, such as ROM
+    pub read_only: Vec<Range<u32>>,
+    pub memory: M,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AccessKind {
+    Read,
+    Write,
 }
 
-struct MemoryBus<M: Memory + ?Sized> {
-    memory: M,
+// One data access made through the memory bus
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Access {
+    pub kind: AccessKind,
+    pub address: u32,
+    // Bytes accessed: 1, 2 or 4
+    pub size: u8,
+    // Value before the access, the same as new for reads
+    pub old: u32,
+    pub new: u32,
 }
 
 #[allow(non_snake_case)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct Registers {
     /* Data Registers */
-    D0: u32,
-    D1: u32,
-    D2: u32,
-    D3: u32,
-    D4: u32,
-    D5: u32,
-    D6: u32,
-    D7: u32,
+    pub D0: u32,
+    pub D1: u32,
+    pub D2: u32,
+    pub D3: u32,
+    pub D4: u32,
+    pub D5: u32,
+    pub D6: u32,
+    pub D7: u32,
     /* Address Registers */
-    A0: u32,
-    A1: u32,
-    A2: u32,
-    A3: u32,
-    A4: u32,
-    A5: u32,
-    A6: u32,
+    pub A0: u32,
+    pub A1: u32,
+    pub A2: u32,
+    pub A3: u32,
+    pub A4: u32,
+    pub A5: u32,
+    pub A6: u32,
     // Stack Pointer (either SSP or USP)
-    SP: u32,
+    pub SP: u32,
+    // The other stack pointer: USP in supervisor mode, SSP in user mode
+    pub ALT_SP: u32,
     // Program Counter
-    PC: u32,
+    pub PC: u32,
     // Status Register (including CCR)
-    SR: StatusRegister,
+    pub SR: StatusRegister,
 }
 
-struct StatusRegister {
-    trace_mode: bool,
-    supervisor_state: bool,
-    interrupt_mask: u8,
-    extend: bool,
-    negative: bool,
-    zero: bool,
-    overflow: bool,
-    carry: bool,
+#[derive(Debug, Clone, PartialEq)]
+pub struct StatusRegister {
+    pub(crate) trace_mode: bool,
+    pub(crate) supervisor_state: bool,
+    pub(crate) interrupt_mask: u8,
+    pub(crate) extend: bool,
+    pub(crate) negative: bool,
+    pub(crate) zero: bool,
+    pub(crate) overflow: bool,
+    pub(crate) carry: bool,
 }
 
 impl CPU {
-    fn new() -> Self {
+    pub fn new() -> Self {
+        Self::with_memory(MEMORY_CAPACITY)
+    }
+
+    // A CPU with size bytes of memory from address 0
+    pub fn with_memory(size: usize) -> Self {
         Self {
             registers: Registers::new(),
-            state: CPUState::Halting,
+            state: CPUState::Fetching,
             memory_bus: MemoryBus {
-                memory: Box::new([0; MEMORY_CAPACITY]),
+                recording: false,
+                accesses: RefCell::new(Vec::new()),
+                devices: Vec::new(),
+                read_only: Vec::new(),
+                memory: vec![0; size].into_boxed_slice(),
             },
+            cycles: 0,
+            clock: DEFAULT_CLOCK,
+            vbr: 0,
+            nmi_taken: false,
         }
     }
+
+    // Level of the interrupt the CPU would take before its next instruction:
+    // one above the SR mask, or a new level 7 request
+    pub fn pending_interrupt(&mut self) -> Option<u8> {
+        let level = self.memory_bus.interrupt_level();
+        if level < 7 {
+            self.nmi_taken = false;
+        }
+        let mask = self.registers.SR.interrupt_mask;
+        (level > mask || (level == 7 && !self.nmi_taken)).then_some(level)
+    }
+}
+
+impl Default for CPU {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Registers {
+    // Registers numbered in GDB's m68k order: D0-D7, A0-A7, SR, PC
+    pub const COUNT: usize = 18;
+    pub const SR: usize = 16;
+
+    pub fn get(&self, n: usize) -> u32 {
+        match n {
+            0 => self.D0,
+            1 => self.D1,
+            2 => self.D2,
+            3 => self.D3,
+            4 => self.D4,
+            5 => self.D5,
+            6 => self.D6,
+            7 => self.D7,
+            8 => self.A0,
+            9 => self.A1,
+            10 => self.A2,
+            11 => self.A3,
+            12 => self.A4,
+            13 => self.A5,
+            14 => self.A6,
+            15 => self.SP,
+            16 => self.SR.to_word() as u32,
+            _ => self.PC,
+        }
+    }
+
+    // A raw write that leaves ALT_SP alone, for restoring saved registers;
+    // CPU::set_sr is the way to change modes
+    pub fn set(&mut self, n: usize, value: u32) {
+        let register = match n {
+            0 => &mut self.D0,
+            1 => &mut self.D1,
+            2 => &mut self.D2,
+            3 => &mut self.D3,
+            4 => &mut self.D4,
+            5 => &mut self.D5,
+            6 => &mut self.D6,
+            7 => &mut self.D7,
+            8 => &mut self.A0,
+            9 => &mut self.A1,
+            10 => &mut self.A2,
+            11 => &mut self.A3,
+            12 => &mut self.A4,
+            13 => &mut self.A5,
+            14 => &mut self.A6,
+            15 => &mut self.SP,
+            16 => {
+                self.SR = StatusRegister::from_word(value as u16);
+                return;
+            }
+            _ => &mut self.PC,
+        };
+        *register = value;
+    }
+
     fn new() -> Self {
         Self {
             D0: 0,
@@ -93,6 +255,7 @@ impl Registers {
             A5: 0,
             A6: 0,
             SP: 0,
+            ALT_SP: 0,
             PC: 0,
             SR: StatusRegister::new(),
         }
@@ -114,35 +277,280 @@ impl StatusRegister {
     }
 }
 
-impl<M: Memory + ?Sized> Processor<M> for CPU {
+impl<M: Memory + ?Sized> MemoryBus<M> {
+    // Accesses recorded since the last call
+    pub fn take_accesses(&mut self) -> Vec<Access> {
+        std::mem::take(self.accesses.get_mut())
+    }
+
+    // Adds a device, refusing one that overlaps a device already mapped
+    pub fn map(&mut self, mapping: Mapping) -> Result<(), String> {
+        let (start, span) = (mapping.base, mapping.span());
+        let end = start.wrapping_add(span.saturating_sub(1));
+        if let Some(other) = self.devices.iter().find(|other| {
+            other.contains(start) || other.contains(end) || mapping.contains(other.base)
+        }) {
+            return Err(format!(
+                "${:08X} overlaps the {} at ${:08X}",
+                start,
+                other.device.borrow().name(),
+                other.base
+            ));
+        }
+        self.devices.push(mapping);
+        Ok(())
+    }
+
+    // Advances every device by cycles CPU clock cycles
+    pub fn tick(&mut self, cycles: u32) {
+        for mapping in &self.devices {
+            mapping.device.borrow_mut().tick(cycles);
+        }
+    }
+
+    // The RESET line
+    pub fn reset(&mut self) {
+        for mapping in &self.devices {
+            mapping.device.borrow_mut().reset();
+        }
+    }
+
+    // Level on the IPL lines
+    pub fn interrupt_level(&self) -> u8 {
+        device::interrupt_level(&self.devices)
+    }
+
+    // Vector for an interrupt acknowledge cycle at level
+    pub fn acknowledge(&mut self, level: u8) -> u8 {
+        device::acknowledge(&self.devices, level)
+    }
+
+    // Whether any of size bytes from address belongs to a device
+    fn on_device(&self, address: u32, size: u32) -> bool {
+        !self.devices.is_empty()
+            && (0..size).any(|n| self.device_at(address.wrapping_add(n)).is_some())
+    }
+
+    // Whether a write of size bytes from address needs handling byte by byte
+    fn decoded_write(&self, address: u32, size: u32) -> bool {
+        self.on_device(address, size)
+            || (0..size).any(|n| self.is_read_only(address.wrapping_add(n)))
+    }
+
+    fn is_read_only(&self, address: u32) -> bool {
+        self.read_only.iter().any(|range| range.contains(&address))
+    }
+
+    fn device_at(&self, address: u32) -> Option<&Mapping> {
+        self.devices
+            .iter()
+            .find(|mapping| mapping.contains(address))
+    }
+
+    // Byte by byte access for accesses that touch a device or, for writes,
+    // read-only memory
+    fn read_bytes(&self, address: u32, size: u32) -> Option<u32> {
+        (0..size).try_fold(0, |value, n| {
+            let at = address.wrapping_add(n);
+            let byte = match self.device_at(at) {
+                Some(mapping) => mapping.read(at),
+                None => self.memory.read_at_address_byte(at)?,
+            };
+            Some(value << 8 | byte as u32)
+        })
+    }
+
+    fn write_bytes(&mut self, address: u32, size: u32, value: u32) -> Result<(), ()> {
+        for n in 0..size {
+            let at = address.wrapping_add(n);
+            let byte = (value >> (8 * (size - 1 - n))) as u8;
+            match self.device_at(at) {
+                Some(mapping) => mapping.write(at, byte),
+                None if self.is_read_only(at) => {}
+                None => self
+                    .memory
+                    .write_at_address_byte(at, byte)
+                    .map_err(|_| ())?,
+            }
+        }
+        Ok(())
+    }
+
+    fn record(&self, kind: AccessKind, address: u32, size: u8, old: u32, new: u32) {
+        if self.recording {
+            self.accesses.borrow_mut().push(Access {
+                kind,
+                address,
+                size,
+                old,
+                new,
+            });
+        }
+    }
+}
+
+impl<M: Memory + ?Sized> Memory for MemoryBus<M> {
+    fn read_at_address_byte(&self, address: u32) -> Option<u8> {
+        let value = if self.on_device(address, 1) {
+            self.read_bytes(address, 1)? as u8
+        } else {
+            self.memory.read_at_address_byte(address)?
+        };
+        self.record(AccessKind::Read, address, 1, value as u32, value as u32);
+        Some(value)
+    }
+    fn read_at_address_word(&self, address: u32) -> Option<u16> {
+        let value = if self.on_device(address, 2) {
+            self.read_bytes(address, 2)? as u16
+        } else {
+            self.memory.read_at_address_word(address)?
+        };
+        self.record(AccessKind::Read, address, 2, value as u32, value as u32);
+        Some(value)
+    }
+    fn read_at_address_long(&self, address: u32) -> Option<u32> {
+        let value = if self.on_device(address, 4) {
+            self.read_bytes(address, 4)?
+        } else {
+            self.memory.read_at_address_long(address)?
+        };
+        self.record(AccessKind::Read, address, 4, value, value);
+        Some(value)
+    }
+
+    fn write_at_address_byte(&mut self, address: u32, data: u8) -> Result<(), &str> {
+        let old = self.memory.read_at_address_byte(address).unwrap_or(0);
+        let written = if self.decoded_write(address, 1) {
+            self.write_bytes(address, 1, data as u32)
+        } else {
+            self.memory
+                .write_at_address_byte(address, data)
+                .map_err(|_| ())
+        };
+        if written.is_err() {
+            return Err("Address out of Bounds");
+        }
+        self.record(AccessKind::Write, address, 1, old as u32, data as u32);
+        Ok(())
+    }
+    fn write_at_address_word(&mut self, address: u32, data: u16) -> Result<(), &str> {
+        let old = self.memory.read_at_address_word(address).unwrap_or(0);
+        let written = if self.decoded_write(address, 2) {
+            self.write_bytes(address, 2, data as u32)
+        } else {
+            self.memory
+                .write_at_address_word(address, data)
+                .map_err(|_| ())
+        };
+        if written.is_err() {
+            return Err("Address out of Bounds");
+        }
+        self.record(AccessKind::Write, address, 2, old as u32, data as u32);
+        Ok(())
+    }
+    fn write_at_address_long(&mut self, address: u32, data: u32) -> Result<(), &str> {
+        let old = self.memory.read_at_address_long(address).unwrap_or(0);
+        let written = if self.decoded_write(address, 4) {
+            self.write_bytes(address, 4, data)
+        } else {
+            self.memory
+                .write_at_address_long(address, data)
+                .map_err(|_| ())
+        };
+        if written.is_err() {
+            return Err("Address out of Bounds");
+        }
+        self.record(AccessKind::Write, address, 4, old, data);
+        Ok(())
+    }
+}
+
+impl StatusRegister {
+    // SR as the word MOVE from SR stores
+    pub fn to_word(&self) -> u16 {
+        (self.trace_mode as u16) << 15
+            | (self.supervisor_state as u16) << 13
+            | (self.interrupt_mask as u16 & 7) << 8
+            | (self.extend as u16) << 4
+            | (self.negative as u16) << 3
+            | (self.zero as u16) << 2
+            | (self.overflow as u16) << 1
+            | self.carry as u16
+    }
+
+    // Whether the flags satisfy a Bcc, DBcc or Scc condition
+    pub fn condition(&self, condition: Condition) -> bool {
+        let (n, z, v, c) = (self.negative, self.zero, self.overflow, self.carry);
+        match condition {
+            Condition::True => true,
+            Condition::False => false,
+            Condition::CarryClear => !c,
+            Condition::CarrySet => c,
+            Condition::Equal => z,
+            Condition::Neq => !z,
+            Condition::Geq => n == v,
+            Condition::Lt => n != v,
+            Condition::Gt => !z && n == v,
+            Condition::Leq => z || n != v,
+            Condition::High => !c && !z,
+            Condition::Low => c || z,
+            Condition::Minus => n,
+            Condition::Plus => !n,
+            Condition::OverflowClear => !v,
+            Condition::OverflowSet => v,
+        }
+    }
+
+    pub fn from_word(word: u16) -> Self {
+        Self {
+            trace_mode: word & 0x8000 != 0,
+            supervisor_state: word & 0x2000 != 0,
+            interrupt_mask: (word >> 8 & 7) as u8,
+            extend: word & 0x10 != 0,
+            negative: word & 0x08 != 0,
+            zero: word & 0x04 != 0,
+            overflow: word & 0x02 != 0,
+            carry: word & 0x01 != 0,
+        }
+    }
+}
+
+// Flags spelled out, a dash for each clear one: T S, interrupt mask, X N Z V C
+impl fmt::Display for StatusRegister {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let flag = |set: bool, name: char| if set { name } else { '-' };
+        write!(
+            f,
+            "{}{} {} {}{}{}{}{}",
+            flag(self.trace_mode, 'T'),
+            flag(self.supervisor_state, 'S'),
+            self.interrupt_mask,
+            flag(self.extend, 'X'),
+            flag(self.negative, 'N'),
+            flag(self.zero, 'Z'),
+            flag(self.overflow, 'V'),
+            flag(self.carry, 'C')
+        )
+    }
+}
+
+impl Processor for CPU {
     fn init(&mut self) {
         self.state = CPUState::Fetching;
     }
-    fn run(&mut self) {
-        let mut ins: u16;
-        //        let mut dec_ins: DecodedInstruction;
-        let mut mem_iter = self.memory_bus.memory.iter(0);
+    fn run(&mut self) -> String {
         loop {
-            match self.state {
-                CPUState::Fetching => {
-                    ins = self.fetch(&mem_iter);
-                    self.state = CPUState::Decoding;
-                }
-                CPUState::Decoding => {
-                    //                  dec_ins = self.decode(ins);
-                }
-                CPUState::Executing => {} //self.execute(dec_ins),
-                CPUState::Halting => {}
+            if let Err(e) = self.execute() {
+                return e;
             }
         }
     }
-    fn fetch(&mut self, mem_iter: &MemoryIter<'_, M>) -> u16 {
-        match mem_iter.next() {
-            Some(value) => value,
-            None => panic!("Iterator has hit memory limit"),
-        }
+    fn fetch(&mut self) -> Option<u16> {
+        self.memory_bus
+            .memory
+            .read_at_address_word(self.registers.PC)
     }
-    fn execute(&mut self) {
-        todo!();
+    fn execute(&mut self) -> Result<Instructions, String> {
+        self.step()
     }
 }
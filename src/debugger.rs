@@ -0,0 +1,880 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::{self, IsTerminal, Write};
+
+use crate::backtrace::CallStack;
+use crate::board;
+use crate::coverage::Coverage;
+use crate::cpu::{Access, AccessKind, CPUState, StatusRegister, CPU};
+use crate::decoder::disassemble;
+use crate::instruction::Instructions;
+use crate::loader::dwarf::LineTable;
+use crate::loader::{binary, elf, hunk, ihex, srecord, tos};
+use crate::memory::Memory;
+use crate::profiler::Profiler;
+use crate::recorder::Recorder;
+use crate::symbols::SymbolTable;
+use crate::trace::Tracer;
+use crate::trap::linux::Linux;
+use crate::trap::{TrapAction, TrapHandler};
+
+mod breakpoints;
+mod devices;
+mod inspect;
+mod profiling;
+mod recording;
+
+pub use breakpoints::{Condition, WatchKind, Watchpoint};
+
+// Where TOS programs get their basepage, above the exception vectors and a
+// stand-in for the system variables
+const TPA_START: u32 = 0x1_0000;
+
+const HELP: &str = "\
+load FILE [BASE] [ARGS...]  load an ELF, PRG, hunk, S-record, Intel HEX or raw image
+load-split EVEN ODD [BASE]  load a raw image from a pair of byte-wide ROMs
+board NAME ROM [ODD]        build board NAME (rosco_m68k) around firmware ROM,
+                            or the split pair ROM and ODD, and reset it, its
+                            serial port on stdio
+registers | r               show registers and decoded status flags
+set REG VALUE               set D0-D7, A0-A7, SP, PC, SR or CCR
+step | s [N]                execute N instructions
+next | n                    step over subroutine calls and traps
+continue | c                run until a breakpoint or the program stops
+break | b ADDR [if COND]    set a breakpoint
+watch ADDR [LEN] [if COND]  stop when LEN bytes (default 4) at ADDR are written
+rwatch | awatch ...         the same for reads, or for any access
+condition ADDR [COND]       set or clear the condition at ADDR
+ignore ADDR N               let the next N hits at ADDR pass
+delete | d [ADDR]           remove the breakpoint and watchpoints at ADDR, or all
+breakpoints | bl            list breakpoints and watchpoints with hit counts
+x[/NF] ADDR                 examine N units in format F: b w l c s i
+modify | m[/F] ADDR VALUES  store values, F one of b w l
+dump FILE START END [ENTRY] write memory from START up to END to FILE as
+                            S-records starting at ENTRY (default the PC)
+disassemble | dis [ADDR] [N] list instructions, around the PC by default
+backtrace | bt [N]          show the N innermost subroutine calls in progress
+symbols [PATTERN]           list symbols
+save FILE                   write registers and memory to a save state file
+restore FILE                return to the state saved in FILE
+record [on|off]             record execution so it can be stepped backwards
+reverse-step | rs [N]       undo N instructions
+reverse-continue | rc       run backwards to a breakpoint or watchpoint
+last-write ADDR             find the latest recorded write to ADDR
+profile on|off|reset        count executions and cycles per instruction and
+                            function
+profile [N]                 show the N hottest functions, instructions and loops
+profile folded|callgrind F  write folded stacks or callgrind output to file F
+coverage on|off|reset       track executed instructions and branch directions
+coverage                    show coverage of each function entered
+coverage lcov FILE          write line and branch coverage in lcov format
+device TYPE ADDR [CON] [IPL] map an acia (MC6850), duart (MC68681), mfp
+                            (MC68901) or pit (MC68230) at ADDR with consoles
+                            CON (stdio, pty, tcp:PORT or none, comma
+                            separated per channel) and its IRQ on IPL
+device                      list mapped devices
+trace FILE [full|mame]      log executed instructions to FILE; trace off stops
+history                     list previous commands, rerun one with !N or !!
+source FILE                 run commands from a file
+quit | q                    leave the debugger
+
+Addresses and values are numbers ($hex, 0xhex, %binary, decimal),
+register names, symbols or memory ([ADDR] long, [ADDR].w, [ADDR].b),
+joined by + and -. Conditions compare two values with == != < <= > >=
+(unsigned), or are a single value that must be nonzero.";
+
+// Steps between checks for an interrupt request while running
+const INTERRUPT_POLL: u32 = 4096;
+
+// Why running stopped
+pub enum Stop {
+    Breakpoint(u32),
+    Watchpoint(WatchKind, Access),
+    Interrupted,
+    // Stepping backwards reached the oldest recorded instruction
+    HistoryStart,
+    Exited(i32),
+    Fault(String),
+}
+
+pub struct Debugger {
+    pub cpu: CPU,
+    pub symbols: SymbolTable,
+    // Source lines of the loaded ELF program
+    pub lines: LineTable,
+    // Path of the loaded program or firmware image
+    pub image: Option<String>,
+    pub breakpoints: BTreeMap<u32, Condition>,
+    pub watchpoints: Vec<Watchpoint>,
+    // High-level TRAP services for the loaded program
+    pub trap_handler: Option<Box<dyn TrapHandler>>,
+    // Log of executed instructions
+    pub tracer: Option<Tracer>,
+    // Execution history for reverse stepping, while recording
+    pub recorder: Option<Recorder>,
+    // Execution counts, while profiling
+    pub profiler: Option<Profiler>,
+    // Executed addresses and branch outcomes, while measuring coverage
+    pub coverage: Option<Coverage>,
+    // Subroutine calls in progress, for backtraces
+    pub calls: CallStack,
+    // Run Linux executables as user-mode processes instead of bare images
+    pub linux: bool,
+    history: Vec<String>,
+    // Exit status of the program, to become the process exit status
+    pub exit_status: Option<i32>,
+    // Whether the last reported stop was a fault, which fails the process
+    pub faulted: bool,
+    quit: bool,
+}
+
+impl Debugger {
+    pub fn new(cpu: CPU) -> Self {
+        Self {
+            cpu,
+            symbols: SymbolTable::new(),
+            lines: LineTable::default(),
+            image: None,
+            breakpoints: BTreeMap::new(),
+            watchpoints: Vec::new(),
+            trap_handler: None,
+            tracer: None,
+            recorder: None,
+            profiler: None,
+            coverage: None,
+            calls: CallStack::new(),
+            linux: false,
+            history: Vec::new(),
+            exit_status: None,
+            faulted: false,
+            quit: false,
+        }
+    }
+
+    // Reads commands until quit or end of input, prompting on a terminal. An
+    // empty line repeats the previous command.
+    pub fn repl(&mut self) {
+        let interactive = io::stdin().is_terminal();
+        while !self.quit {
+            if interactive {
+                print!("(rs68000) ");
+                let _ = io::stdout().flush();
+            }
+            // Locked only while reading, as programs read stdin too
+            let mut line = String::new();
+            if !matches!(io::stdin().read_line(&mut line), Ok(1..)) {
+                break;
+            }
+            let line = match line.trim() {
+                "" => match self.history.last() {
+                    Some(last) if interactive => last.clone(),
+                    _ => continue,
+                },
+                line => line.to_string(),
+            };
+            self.command(&line);
+        }
+    }
+
+    // Runs every command in a script file, stopping at the first failure
+    pub fn source(&mut self, path: &str) -> Result<(), String> {
+        let script = fs::read_to_string(path).map_err(|e| format!("{}: {}", path, e))?;
+        for line in script.lines().map(str::trim) {
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            if !self.command(line) || self.quit {
+                break;
+            }
+        }
+        Ok(())
+    }
+
+    // Records and executes one command line, reporting errors; false if the
+    // command failed
+    pub fn command(&mut self, line: &str) -> bool {
+        let line = match self.recall(line) {
+            Ok(line) => line,
+            Err(e) => {
+                println!("{}", e);
+                return false;
+            }
+        };
+        self.history.push(line.clone());
+        let result = self.execute(&line);
+        self.flush_trace();
+        match result {
+            Ok(()) => true,
+            Err(e) => {
+                println!("{}", e);
+                false
+            }
+        }
+    }
+
+    // Writes out buffered trace lines, so the log is complete between commands
+    pub fn flush_trace(&mut self) {
+        if let Some(Err(e)) = self.tracer.as_mut().map(Tracer::flush) {
+            println!("Trace stopped: {}", e);
+            self.tracer = None;
+        }
+    }
+
+    // Expands !! and !N history references
+    fn recall(&self, line: &str) -> Result<String, String> {
+        let Some(reference) = line.strip_prefix('!') else {
+            return Ok(line.to_string());
+        };
+        let entry = if reference == "!" {
+            self.history.last()
+        } else {
+            reference
+                .parse::<usize>()
+                .ok()
+                .and_then(|n| self.history.get(n.wrapping_sub(1)))
+        };
+        entry
+            .cloned()
+            .ok_or_else(|| format!("No history entry {}", line))
+    }
+
+    fn execute(&mut self, line: &str) -> Result<(), String> {
+        let mut words = line.split_whitespace();
+        let Some(command) = words.next() else {
+            return Ok(());
+        };
+        let args: Vec<&str> = words.collect();
+        let (command, format) = match command.split_once('/') {
+            Some((command, format)) => (command, Some(format)),
+            None => (command, None),
+        };
+
+        match command {
+            "help" | "h" | "?" => println!("{}", HELP),
+            "load" => {
+                let path = args.first().ok_or("Usage: load FILE [BASE] [ARGS...]")?;
+                let base = args.get(1).map(|base| self.value(base)).transpose()?;
+                let program_args = args.get(2..).unwrap_or_default();
+                self.load(path, base, program_args)?;
+                println!("Loaded {}, PC ${:08X}", path, self.cpu.registers.PC);
+            }
+            "load-split" => {
+                let [even, odd, ref base @ ..] = args[..] else {
+                    return Err("Usage: load-split EVEN ODD [BASE]".to_string());
+                };
+                let base = base.first().map(|base| self.value(base)).transpose()?;
+                self.load_split(even, odd, base)?;
+                println!(
+                    "Loaded {} and {}, PC ${:08X}",
+                    even, odd, self.cpu.registers.PC
+                );
+            }
+            "board" => {
+                let (name, path, odd) = match args[..] {
+                    [name, path] => (name, path, None),
+                    [name, path, odd] => (name, path, Some(odd)),
+                    _ => return Err("Usage: board NAME ROM [ODD]".to_string()),
+                };
+                self.boot(name, path, odd)?;
+                println!(
+                    "Booted {} on {}, PC ${:08X}",
+                    path, name, self.cpu.registers.PC
+                );
+            }
+            "registers" | "r" => self.print_registers(),
+            "set" => {
+                let [register, value] = args[..] else {
+                    return Err("Usage: set REG VALUE".to_string());
+                };
+                let value = self.value(value)?;
+                self.set_register(register, value)?;
+            }
+            "step" | "s" => {
+                let count = match args.first() {
+                    Some(count) => self.value(count)?,
+                    None => 1,
+                };
+                for _ in 0..count {
+                    if let Some(stop) = self.step_one() {
+                        self.report(stop);
+                        return Ok(());
+                    }
+                }
+                self.print_location();
+            }
+            "next" | "n" => {
+                let pc = self.cpu.registers.PC;
+                let (ins, next) = disassemble(&mut self.cpu.memory_bus.memory, pc);
+                let stop = match ins {
+                    Instructions::JSR(_) | Instructions::BSR(_) | Instructions::TRAP(_) => {
+                        Some(self.run(Some(next), &mut || false))
+                    }
+                    _ => self.step_one(),
+                };
+                match stop {
+                    Some(Stop::Breakpoint(address)) if address == next => self.print_location(),
+                    Some(stop) => self.report(stop),
+                    None => self.print_location(),
+                }
+            }
+            "continue" | "c" => {
+                let stop = self.run(None, &mut || false);
+                self.report(stop);
+            }
+            "break" | "b" => self.break_command(&args)?,
+            "watch" | "rwatch" | "awatch" => self.watch_command(command, &args)?,
+            "condition" => self.condition_command(&args)?,
+            "ignore" => self.ignore_command(&args)?,
+            "delete" | "d" => self.delete_command(&args)?,
+            "breakpoints" | "bl" => self.list_breakpoints(),
+            "x" => self.examine_command(&args, format)?,
+            "modify" | "m" => self.modify_command(&args, format)?,
+            "dump" => self.dump_command(&args)?,
+            "disassemble" | "dis" => self.disassemble_command(&args)?,
+            "backtrace" | "bt" => self.backtrace_command(&args)?,
+            "symbols" => self.symbols_command(&args)?,
+            "history" => {
+                for (n, line) in self.history.iter().enumerate() {
+                    println!("{:4}  {}", n + 1, line);
+                }
+            }
+            "save" => self.save_command(&args)?,
+            "restore" => self.restore_command(&args)?,
+            "record" => self.record_command(&args)?,
+            "reverse-step" | "rs" => self.reverse_step_command(&args)?,
+            "reverse-continue" | "rc" => {
+                let stop = self.reverse_run(&mut || false);
+                self.report(stop);
+            }
+            "last-write" => self.last_write_command(&args)?,
+            "profile" => self.profile_command(&args)?,
+            "coverage" => self.coverage_command(&args)?,
+            "device" => self.device_command(&args)?,
+            "trace" => self.trace_command(&args)?,
+            "source" => self.source(args.first().ok_or("Usage: source FILE")?)?,
+            "quit" | "q" => self.quit = true,
+            _ => return Err(format!("Unknown command {}, try help", command)),
+        }
+        Ok(())
+    }
+
+    // Loads a program, picking the format from its contents. Raw images load
+    // at base and start there; without a base they load at 0 and start from
+    // their reset vectors.
+    pub fn load(&mut self, path: &str, base: Option<u32>, args: &[&str]) -> Result<(), String> {
+        let data = fs::read(path).map_err(|e| format!("{}: {}", path, e))?;
+        let cpu = &mut self.cpu;
+        let mut argv = vec![path];
+        argv.extend_from_slice(args);
+
+        let symbols = if data.starts_with(b"\x7FELF") {
+            if self.linux {
+                let env: Vec<String> = std::env::vars()
+                    .map(|(k, v)| format!("{}={}", k, v))
+                    .collect();
+                let env: Vec<&str> = env.iter().map(String::as_str).collect();
+                let (linux, elf) = Linux::load(&data, cpu, &argv, &env)?;
+                self.trap_handler = Some(Box::new(linux));
+                self.lines = elf.lines;
+                elf.symbols
+            } else {
+                let elf = elf::load_cpu(&data, cpu)?;
+                self.lines = elf.lines;
+                elf.symbols
+            }
+        } else if data.starts_with(&[0x60, 0x1A]) {
+            let top = cpu.memory_bus.memory.len() as u32;
+            let basepage = base.unwrap_or(TPA_START);
+            tos::load_cpu(&data, cpu, basepage, top, &args.join(" "))?.symbols
+        } else if data.starts_with(&[0x00, 0x00, 0x03, 0xF3]) {
+            hunk::load_cpu(&data, cpu, base.unwrap_or(TPA_START), &args.join(" "))?.symbols
+        } else if let Some(text) = text_image(&data) {
+            if text.starts_with('S') {
+                srecord::load_cpu(text, cpu)?;
+            } else {
+                ihex::load_cpu(text, cpu, base.unwrap_or(0))?;
+            }
+            SymbolTable::new()
+        } else {
+            match base {
+                Some(base) => {
+                    binary::load(&data, &mut cpu.memory_bus.memory, base)?;
+                    cpu.registers.PC = base;
+                }
+                None => binary::load_cpu(&data, cpu, 0)?,
+            }
+            SymbolTable::new()
+        };
+        self.symbols.extend(&symbols);
+        self.image = Some(path.to_string());
+        self.restart();
+        Ok(())
+    }
+
+    // Loads a raw image from the even and odd chips of a split ROM pair, at
+    // base and starting there, or at 0 starting from its reset vectors
+    pub fn load_split(&mut self, even: &str, odd: &str, base: Option<u32>) -> Result<(), String> {
+        self.image = Some(even.to_string());
+        let even = fs::read(even).map_err(|e| format!("{}: {}", even, e))?;
+        let odd = fs::read(odd).map_err(|e| format!("{}: {}", odd, e))?;
+        let cpu = &mut self.cpu;
+        match base {
+            Some(base) => {
+                binary::load_split(&even, &odd, &mut cpu.memory_bus.memory, base)?;
+                cpu.registers.PC = base;
+            }
+            None => binary::load_split_cpu(&even, &odd, cpu, 0)?,
+        }
+        self.restart();
+        Ok(())
+    }
+
+    // Replaces the CPU with board name built around the firmware image at
+    // path, or the split ROM pair at path and odd, fresh from reset
+    pub fn boot(&mut self, name: &str, path: &str, odd: Option<&str>) -> Result<(), String> {
+        let mut rom = fs::read(path).map_err(|e| format!("{}: {}", path, e))?;
+        if let Some(odd) = odd {
+            let odd = fs::read(odd).map_err(|e| format!("{}: {}", odd, e))?;
+            rom = binary::interleave(&rom, &odd)?;
+        }
+        self.cpu = board::boot(name, &rom)?;
+        self.image = Some(path.to_string());
+        self.restart();
+        Ok(())
+    }
+
+    // Forgets the state of the program that ran before a load
+    fn restart(&mut self) {
+        self.exit_status = None;
+        self.calls.clear();
+        if self.recorder.is_some() {
+            self.recorder = Some(Recorder::new());
+        }
+    }
+
+    // Runs until a breakpoint, the until address, the program stopping or
+    // interrupt returning true; interrupt is polled every few thousand steps
+    pub fn run(&mut self, until: Option<u32>, interrupt: &mut dyn FnMut() -> bool) -> Stop {
+        for steps in 1u32.. {
+            if let Some(stop) = self.step_one() {
+                return stop;
+            }
+            let pc = self.cpu.registers.PC;
+            if until == Some(pc) || self.hit_breakpoint(pc) {
+                return Stop::Breakpoint(pc);
+            }
+            if steps.is_multiple_of(INTERRUPT_POLL) && interrupt() {
+                return Stop::Interrupted;
+            }
+        }
+        Stop::Interrupted
+    }
+
+    // Executes one instruction, tracing it and checking watchpoints against
+    // the memory it accessed
+    pub fn step_one(&mut self) -> Option<Stop> {
+        if let Some(status) = self.exit_status {
+            return Some(Stop::Exited(status));
+        }
+        if let Some(level) = self.cpu.pending_interrupt() {
+            return self.take_interrupt(level);
+        }
+        let pc = self.cpu.registers.PC;
+        if self.cpu.state == CPUState::Stopped && self.cpu.memory_bus.devices.is_empty() {
+            return Some(Stop::Fault(format!(
+                "Stopped at ${:08X} with no device to interrupt it",
+                pc
+            )));
+        }
+        let opcode = self.cpu.memory_bus.memory.read_at_address_word(pc);
+        let observed = self.tracer.is_some()
+            || self.profiler.is_some()
+            || self.coverage.is_some()
+            || opcode.is_some_and(CallStack::tracks);
+        let decoded = observed.then(|| {
+            let mem = &mut self.cpu.memory_bus.memory;
+            let (ins, next) = disassemble(mem, pc);
+            let words: Vec<u16> = (pc..next)
+                .step_by(2)
+                .filter_map(|at| mem.read_at_address_word(at))
+                .collect();
+            (ins, next, words)
+        });
+        let before =
+            (decoded.is_some() || self.recorder.is_some()).then(|| self.cpu.registers.clone());
+        self.cpu.memory_bus.recording = !self.watchpoints.is_empty() || before.is_some();
+        let (cycles, state) = (self.cpu.cycles, self.cpu.state);
+        let stop = self.execute_one(pc);
+        let accesses = self.cpu.memory_bus.take_accesses();
+        self.tick_devices(cycles);
+        if let (Some(recorder), Some(before)) = (self.recorder.as_mut(), &before) {
+            recorder.record(before, cycles, state, &self.cpu, &accesses);
+        }
+        if let Some(before) = before.filter(|_| !matches!(stop, Some(Stop::Fault(_)))) {
+            if let Some((ins, next, _)) = &decoded {
+                let after = &self.cpu.registers;
+                self.calls.record(pc, ins, *next, after.PC, after.SP);
+            }
+            if let (Some(profiler), Some((ins, next, _))) = (self.profiler.as_mut(), &decoded) {
+                let taken = self.cpu.cycles - cycles;
+                profiler.record(pc, ins, *next, self.cpu.registers.PC, taken);
+            }
+            if let (Some(coverage), Some((ins, next, _))) = (self.coverage.as_mut(), &decoded) {
+                coverage.record(pc, ins, *next, self.cpu.registers.PC, &before.SR);
+            }
+            if let (Some(tracer), Some((ins, _, words))) = (self.tracer.as_mut(), decoded) {
+                let after = &self.cpu.registers;
+                if let Err(e) = tracer.record(pc, &words, &ins, &before, after, &accesses) {
+                    self.tracer = None;
+                    return Some(Stop::Fault(format!("Trace stopped: {}", e)));
+                }
+            }
+        }
+        if stop.is_some() {
+            return stop;
+        }
+        self.check_watchpoints(&accesses)
+    }
+
+    // Takes an interrupt in place of the next instruction, recording its
+    // exception processing like an instruction
+    fn take_interrupt(&mut self, level: u8) -> Option<Stop> {
+        let before = self.recorder.is_some().then(|| self.cpu.registers.clone());
+        self.cpu.memory_bus.recording = !self.watchpoints.is_empty() || before.is_some();
+        let (cycles, state) = (self.cpu.cycles, self.cpu.state);
+        let result = self.cpu.interrupt(level);
+        let accesses = self.cpu.memory_bus.take_accesses();
+        self.tick_devices(cycles);
+        if let (Some(recorder), Some(before)) = (self.recorder.as_mut(), before) {
+            recorder.record(&before, cycles, state, &self.cpu, &accesses);
+        }
+        if let Err(e) = result {
+            return Some(Stop::Fault(e));
+        }
+        self.check_watchpoints(&accesses)
+    }
+
+    // Advances devices by the cycles the CPU has run since it was at cycles
+    fn tick_devices(&mut self, cycles: u64) {
+        let elapsed = self.cpu.cycles.saturating_sub(cycles);
+        self.cpu
+            .memory_bus
+            .tick(elapsed.min(u32::MAX as u64) as u32);
+    }
+
+    // Executes the instruction at pc, handing TRAPs and opcodes that do not
+    // decode to the trap handler first
+    fn execute_one(&mut self, pc: u32) -> Option<Stop> {
+        let mem = &mut self.cpu.memory_bus.memory;
+        let opcode = mem.read_at_address_word(pc).unwrap_or(0);
+        let (ins, next) = disassemble(mem, pc);
+        if let Some(handler) = self.trap_handler.as_mut() {
+            self.cpu.registers.PC = next;
+            let action = match ins {
+                Instructions::TRAP(vector) => Some(handler.trap(&mut self.cpu, vector)),
+                Instructions::NotImplemented => handler.illegal(&mut self.cpu, opcode),
+                _ => None,
+            };
+            match action {
+                Some(TrapAction::Handled) => return None,
+                Some(TrapAction::Exit(status)) => {
+                    self.exit_status = Some(status);
+                    return Some(Stop::Exited(status));
+                }
+                Some(TrapAction::Exception) | None => self.cpu.registers.PC = pc,
+            }
+        }
+        self.cpu.step().err().map(Stop::Fault)
+    }
+
+    fn report(&mut self, stop: Stop) {
+        self.faulted = matches!(stop, Stop::Fault(_));
+        match stop {
+            Stop::Breakpoint(address) => {
+                match self.breakpoints.get(&address) {
+                    Some(condition) => println!(
+                        "Breakpoint at {}, hit {} times",
+                        self.describe(address),
+                        condition.hits
+                    ),
+                    None => println!("Stopped at {}", self.describe(address)),
+                }
+                self.print_location();
+            }
+            Stop::Watchpoint(kind, access) => {
+                let width = access.size as usize * 2;
+                let verb = match access.kind {
+                    AccessKind::Read => "read",
+                    AccessKind::Write => "write",
+                };
+                println!(
+                    "{:?} watchpoint: {} at {}, old ${:0width$X} new ${:0width$X}",
+                    kind,
+                    verb,
+                    self.describe(access.address),
+                    access.old,
+                    access.new,
+                    width = width
+                );
+                self.print_location();
+            }
+            Stop::Interrupted => {
+                println!("Interrupted");
+                self.print_location();
+            }
+            Stop::HistoryStart => {
+                println!("Reached the start of the recorded history");
+                self.print_location();
+            }
+            Stop::Exited(status) => println!("Program exited with status {}", status),
+            Stop::Fault(message) => {
+                println!("{}", message);
+                self.print_location();
+            }
+        }
+    }
+
+    fn print_location(&mut self) {
+        self.disassemble(self.cpu.registers.PC, 1);
+    }
+
+    fn print_registers(&mut self) {
+        let r = &self.cpu.registers;
+        let data = [r.D0, r.D1, r.D2, r.D3, r.D4, r.D5, r.D6, r.D7];
+        let address = [r.A0, r.A1, r.A2, r.A3, r.A4, r.A5, r.A6, r.SP];
+        for (prefix, values) in [('D', data), ('A', address)] {
+            for (row, chunk) in values.chunks(4).enumerate() {
+                let line: Vec<String> = chunk
+                    .iter()
+                    .enumerate()
+                    .map(|(i, v)| format!("{}{} {:08X}", prefix, row * 4 + i, v))
+                    .collect();
+                println!("{}", line.join("  "));
+            }
+        }
+        println!("PC {:08X}  SR {:04X}  {}", r.PC, r.SR.to_word(), r.SR);
+        self.print_location();
+    }
+
+    fn set_register(&mut self, name: &str, value: u32) -> Result<(), String> {
+        let name = name.to_ascii_lowercase();
+        if name == "sr" {
+            // Through set_sr, so that changing S swaps the stack pointers
+            self.cpu.set_sr(value as u16);
+            return Ok(());
+        }
+        let r = &mut self.cpu.registers;
+        match name.as_str() {
+            "pc" => r.PC = value,
+            "ccr" => {
+                r.SR = StatusRegister::from_word((r.SR.to_word() & 0xFF00) | (value as u16 & 0xFF))
+            }
+            name => *register(r, name).ok_or_else(|| format!("Unknown register {}", name))? = value,
+        }
+        Ok(())
+    }
+
+    // Evaluates terms joined by + and -: numbers, registers and symbols
+    fn value(&self, text: &str) -> Result<u32, String> {
+        let mut total = 0u32;
+        let mut rest = text;
+        loop {
+            let end = next_sign(rest).unwrap_or(rest.len());
+            let (term, tail) = rest.split_at(end);
+            let term = term.trim();
+            let (negative, term) = match term.strip_prefix('-') {
+                Some(term) => (true, term.trim()),
+                None => (false, term.trim_start_matches('+').trim()),
+            };
+            let value = self.term(term)?;
+            total = if negative {
+                total.wrapping_sub(value)
+            } else {
+                total.wrapping_add(value)
+            };
+            if tail.is_empty() {
+                return Ok(total);
+            }
+            rest = tail;
+        }
+    }
+
+    fn term(&self, term: &str) -> Result<u32, String> {
+        let number = if let Some(hex) = term.strip_prefix('$') {
+            u32::from_str_radix(hex, 16).ok()
+        } else if let Some(hex) = term.strip_prefix("0x") {
+            u32::from_str_radix(hex, 16).ok()
+        } else if let Some(binary) = term.strip_prefix('%') {
+            u32::from_str_radix(binary, 2).ok()
+        } else if term.starts_with(|c: char| c.is_ascii_digit()) {
+            term.parse().ok()
+        } else {
+            None
+        };
+        if let Some(number) = number {
+            return Ok(number);
+        }
+        if let Some(inner) = term.strip_prefix('[') {
+            return self.memory_term(inner);
+        }
+
+        let r = &self.cpu.registers;
+        match term.to_ascii_lowercase().as_str() {
+            "pc" => Ok(r.PC),
+            "sr" => Ok(r.SR.to_word() as u32),
+            "ccr" => Ok(r.SR.to_word() as u32 & 0xFF),
+            name => {
+                let mut registers = self.cpu.registers.clone();
+                register(&mut registers, name)
+                    .map(|value| *value)
+                    .or_else(|| self.symbols.address_of(term))
+                    .ok_or_else(|| format!("Cannot evaluate {}", term))
+            }
+        }
+    }
+
+    // Memory contents for a term [ADDR] with an optional .b, .w or .l size
+    fn memory_term(&self, inner: &str) -> Result<u32, String> {
+        let (address, size) = inner
+            .rsplit_once(']')
+            .ok_or_else(|| format!("Missing ] in [{}", inner))?;
+        let address = self.value(address)?;
+        let mem = &self.cpu.memory_bus.memory;
+        let value = match size {
+            "" | ".l" => mem.read_at_address_long(address),
+            ".w" => mem.read_at_address_word(address).map(u32::from),
+            ".b" => mem.read_at_address_byte(address).map(u32::from),
+            _ => return Err(format!("Size must be one of .b .w .l, not {}", size)),
+        };
+        value.ok_or_else(|| format!("${:08X} out of bounds", address))
+    }
+
+    // Evaluates a comparison such as d0 == 3 or [a0].w != 0; a single value is
+    // true when nonzero
+    fn condition(&self, text: &str) -> Result<bool, String> {
+        for operator in ["==", "!=", "<=", ">=", "<", ">"] {
+            let Some((left, right)) = text.split_once(operator) else {
+                continue;
+            };
+            let (left, right) = (self.value(left.trim())?, self.value(right.trim())?);
+            return Ok(match operator {
+                "==" => left == right,
+                "!=" => left != right,
+                "<=" => left <= right,
+                ">=" => left >= right,
+                "<" => left < right,
+                _ => left > right,
+            });
+        }
+        Ok(self.value(text.trim())? != 0)
+    }
+
+    fn describe(&self, address: u32) -> String {
+        match self.symbols.lookup(address) {
+            Some(_) => format!("${:08X} <{}>", address, self.symbols.describe(address)),
+            None => format!("${:08X}", address),
+        }
+    }
+}
+
+// Data and address registers by name, A7 being the stack pointer
+fn register<'a>(r: &'a mut crate::cpu::Registers, name: &str) -> Option<&'a mut u32> {
+    Some(match name {
+        "d0" => &mut r.D0,
+        "d1" => &mut r.D1,
+        "d2" => &mut r.D2,
+        "d3" => &mut r.D3,
+        "d4" => &mut r.D4,
+        "d5" => &mut r.D5,
+        "d6" => &mut r.D6,
+        "d7" => &mut r.D7,
+        "a0" => &mut r.A0,
+        "a1" => &mut r.A1,
+        "a2" => &mut r.A2,
+        "a3" => &mut r.A3,
+        "a4" => &mut r.A4,
+        "a5" => &mut r.A5,
+        "a6" => &mut r.A6,
+        "a7" | "sp" => &mut r.SP,
+        _ => return None,
+    })
+}
+
+// Index of the + or - that ends the first term, skipping a leading sign and
+// anything inside brackets
+fn next_sign(text: &str) -> Option<usize> {
+    let mut depth = 0u32;
+    for (i, c) in text.char_indices() {
+        match c {
+            '[' => depth += 1,
+            ']' => depth = depth.saturating_sub(1),
+            '+' | '-' if i > 0 && depth == 0 => return Some(i),
+            _ => {}
+        }
+    }
+    None
+}
+
+// The text of S-record and Intel HEX files, recognised by their first record
+fn text_image(data: &[u8]) -> Option<&str> {
+    let text = std::str::from_utf8(data).ok()?;
+    let first = text.trim_start().as_bytes();
+    match first {
+        [b'S', digit, ..] if digit.is_ascii_digit() => Some(text),
+        [b':', ..] => Some(text),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::Cell;
+    use std::rc::Rc;
+
+    use super::Debugger;
+    use crate::assembler::assemble;
+    use crate::cpu::CPU;
+    use crate::device::{Device, Mapping};
+
+    // Counts the cycles it is advanced by
+    struct Counter(Rc<Cell<u64>>);
+
+    impl Device for Counter {
+        fn name(&self) -> &str {
+            "counter"
+        }
+
+        fn registers(&self) -> u32 {
+            1
+        }
+
+        fn read(&mut self, _register: u32) -> u8 {
+            0
+        }
+
+        fn write(&mut self, _register: u32, _value: u8) {}
+
+        fn tick(&mut self, cycles: u32) {
+            self.0.set(self.0.get() + cycles as u64);
+        }
+    }
+
+    #[test]
+    fn devices_tick_by_instruction_cycles() {
+        let program = assemble(" org $1000\n moveq #3,d0\n divu d0,d1\n nop\n").unwrap();
+        let mut cpu = CPU::with_memory(0x10000);
+        program.load(&mut cpu.memory_bus.memory).unwrap();
+        cpu.registers.PC = 0x1000;
+        let ticks = Rc::new(Cell::new(0));
+        let counter = Box::new(Counter(ticks.clone()));
+        cpu.memory_bus
+            .map(Mapping::new(0xF000, 2, 0, counter))
+            .unwrap();
+        let mut debugger = Debugger::new(cpu);
+        for _ in 0..3 {
+            assert!(debugger.step_one().is_none());
+        }
+        assert!(debugger.cpu.cycles > 30);
+        assert_eq!(ticks.get(), debugger.cpu.cycles);
+    }
+}
@@ -0,0 +1,240 @@
+use super::{Debugger, Stop};
+use crate::cpu::{Access, AccessKind};
+
+// Extra test on a breakpoint or watchpoint: an expression that must hold, and
+// a number of hits to let pass before stopping
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Condition {
+    pub expression: Option<String>,
+    pub ignore: u32,
+    pub hits: u32,
+}
+
+impl Condition {
+    // Counts a hit whose expression held; true once the ignore count is used up
+    fn hit(&mut self) -> bool {
+        self.hits += 1;
+        self.hits > self.ignore
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum WatchKind {
+    Read,
+    Write,
+    Access,
+}
+
+// Data accesses that stop execution when they touch length bytes at address
+#[derive(Debug, Clone, PartialEq)]
+pub struct Watchpoint {
+    pub kind: WatchKind,
+    pub address: u32,
+    pub length: u32,
+    pub condition: Condition,
+}
+
+impl Watchpoint {
+    pub fn new(kind: WatchKind, address: u32, length: u32) -> Self {
+        Self {
+            kind,
+            address,
+            length,
+            condition: Condition::default(),
+        }
+    }
+
+    fn matches(&self, access: &Access) -> bool {
+        let kind = match (self.kind, access.kind) {
+            (WatchKind::Access, _) => true,
+            (WatchKind::Read, kind) => kind == AccessKind::Read,
+            (WatchKind::Write, kind) => kind == AccessKind::Write,
+        };
+        let end = access.address.wrapping_add(access.size as u32);
+        kind && access.address < self.address.wrapping_add(self.length) && end > self.address
+    }
+}
+
+impl Debugger {
+    // Sets a breakpoint, optionally conditional
+    pub(super) fn break_command(&mut self, args: &[&str]) -> Result<(), String> {
+        let (args, expression) = split_condition(args);
+        let [address] = args[..] else {
+            return Err("Usage: break ADDR [if COND]".to_string());
+        };
+        let address = self.value(address)?;
+        let condition = Condition {
+            expression,
+            ..Condition::default()
+        };
+        self.breakpoints.insert(address, condition);
+        println!("Breakpoint at {}", self.describe(address));
+        Ok(())
+    }
+
+    // Sets a watchpoint on writes, reads or any access, as command says
+    pub(super) fn watch_command(&mut self, command: &str, args: &[&str]) -> Result<(), String> {
+        let (args, expression) = split_condition(args);
+        let (address, length) = match args[..] {
+            [address] => (self.value(address)?, 4),
+            [address, length] => (self.value(address)?, self.value(length)?),
+            _ => return Err(format!("Usage: {} ADDR [LEN] [if COND]", command)),
+        };
+        let kind = match command {
+            "watch" => WatchKind::Write,
+            "rwatch" => WatchKind::Read,
+            _ => WatchKind::Access,
+        };
+        let mut watchpoint = Watchpoint::new(kind, address, length.max(1));
+        watchpoint.condition.expression = expression;
+        println!(
+            "{:?} watchpoint at {}, {} bytes",
+            kind,
+            self.describe(address),
+            watchpoint.length
+        );
+        self.watchpoints.push(watchpoint);
+        Ok(())
+    }
+
+    // Replaces or removes the condition of the stops at an address
+    pub(super) fn condition_command(&mut self, args: &[&str]) -> Result<(), String> {
+        let (address, expression) = args.split_first().ok_or("Usage: condition ADDR [COND]")?;
+        let address = self.value(address)?;
+        let expression = (!expression.is_empty()).then(|| expression.join(" "));
+        for condition in self.conditions_at(address)? {
+            condition.expression = expression.clone();
+        }
+        Ok(())
+    }
+
+    // Lets the stops at an address pass a number of further hits
+    pub(super) fn ignore_command(&mut self, args: &[&str]) -> Result<(), String> {
+        let [address, count] = args[..] else {
+            return Err("Usage: ignore ADDR N".to_string());
+        };
+        let (address, count) = (self.value(address)?, self.value(count)?);
+        for condition in self.conditions_at(address)? {
+            condition.ignore = condition.hits + count;
+        }
+        Ok(())
+    }
+
+    // Removes the stops at an address, or all of them
+    pub(super) fn delete_command(&mut self, args: &[&str]) -> Result<(), String> {
+        match args.first() {
+            Some(address) => {
+                let address = self.value(address)?;
+                let watchpoints = self.watchpoints.len();
+                self.watchpoints.retain(|w| w.address != address);
+                if self.breakpoints.remove(&address).is_none()
+                    && self.watchpoints.len() == watchpoints
+                {
+                    return Err(format!("No breakpoint or watchpoint at ${:08X}", address));
+                }
+            }
+            None => {
+                self.breakpoints.clear();
+                self.watchpoints.clear();
+            }
+        }
+        Ok(())
+    }
+
+    // Lists breakpoints and watchpoints with their hit counts
+    pub(super) fn list_breakpoints(&self) {
+        for (address, condition) in &self.breakpoints {
+            println!(
+                "Breakpoint {}{}",
+                self.describe(*address),
+                describe_condition(condition)
+            );
+        }
+        for watchpoint in &self.watchpoints {
+            println!(
+                "{:?} watchpoint {}, {} bytes{}",
+                watchpoint.kind,
+                self.describe(watchpoint.address),
+                watchpoint.length,
+                describe_condition(&watchpoint.condition)
+            );
+        }
+    }
+
+    // The first watchpoint whose condition holds for one of accesses and
+    // whose ignore count is used up
+    pub(super) fn check_watchpoints(&mut self, accesses: &[Access]) -> Option<Stop> {
+        for access in accesses {
+            for n in 0..self.watchpoints.len() {
+                let watchpoint = &self.watchpoints[n];
+                if !watchpoint.matches(access) || !self.holds(&watchpoint.condition) {
+                    continue;
+                }
+                let watchpoint = &mut self.watchpoints[n];
+                if watchpoint.condition.hit() {
+                    return Some(Stop::Watchpoint(watchpoint.kind, *access));
+                }
+            }
+        }
+        None
+    }
+
+    // Whether a breakpoint at pc has its condition hold and its ignore count
+    // used up, counting the hit
+    pub(super) fn hit_breakpoint(&mut self, pc: u32) -> bool {
+        match self.breakpoints.get(&pc) {
+            Some(condition) if self.holds(condition) => {}
+            _ => return false,
+        }
+        self.breakpoints.get_mut(&pc).is_some_and(Condition::hit)
+    }
+
+    // A condition that cannot be evaluated holds, so the stop shows why
+    pub(super) fn holds(&self, condition: &Condition) -> bool {
+        match &condition.expression {
+            Some(expression) => self.condition(expression).unwrap_or(true),
+            None => true,
+        }
+    }
+
+    // Conditions of the breakpoint and watchpoints at address
+    pub(super) fn conditions_at(&mut self, address: u32) -> Result<Vec<&mut Condition>, String> {
+        let conditions: Vec<&mut Condition> = self
+            .breakpoints
+            .get_mut(&address)
+            .into_iter()
+            .chain(
+                self.watchpoints
+                    .iter_mut()
+                    .filter(|w| w.address == address)
+                    .map(|w| &mut w.condition),
+            )
+            .collect();
+        if conditions.is_empty() {
+            return Err(format!("No breakpoint or watchpoint at ${:08X}", address));
+        }
+        Ok(conditions)
+    }
+}
+
+// Command arguments before an "if", and the condition after it
+fn split_condition<'a>(args: &'a [&'a str]) -> (&'a [&'a str], Option<String>) {
+    match args.iter().position(|arg| *arg == "if") {
+        Some(n) => (&args[..n], Some(args[n + 1..].join(" "))),
+        None => (args, None),
+    }
+}
+
+fn describe_condition(condition: &Condition) -> String {
+    let mut text = format!(", hit {} times", condition.hits);
+    if let Some(expression) = &condition.expression {
+        text.push_str(&format!(", if {}", expression));
+    }
+    if condition.ignore > condition.hits {
+        text.push_str(&format!(
+            ", ignoring {} more",
+            condition.ignore - condition.hits
+        ));
+    }
+    text
+}
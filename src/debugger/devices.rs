@@ -0,0 +1,68 @@
+use super::Debugger;
+use crate::device::acia::Acia;
+use crate::device::console::Console;
+use crate::device::duart::Duart;
+use crate::device::mfp::Mfp;
+use crate::device::pit::Pit;
+use crate::device::{Device, Mapping};
+
+// Distance between the registers of 8-bit devices, which sit on one byte
+// lane of the 16-bit data bus
+const DEVICE_STRIDE: u32 = 2;
+// Devices the device command can map
+const DEVICES: [&str; 4] = ["acia", "duart", "mfp", "pit"];
+
+impl Debugger {
+    // Lists mapped devices, or maps a new one with its consoles
+    pub(super) fn device_command(&mut self, args: &[&str]) -> Result<(), String> {
+        match args[..] {
+            [] => {
+                for mapping in &self.cpu.memory_bus.devices {
+                    let device = mapping.device.borrow();
+                    let level = match mapping.level {
+                        0 => String::new(),
+                        level => format!("  IPL {}", level),
+                    };
+                    println!(
+                        "${:08X}  {}  {}{}",
+                        mapping.base,
+                        device.name(),
+                        device.describe(),
+                        level
+                    );
+                }
+            }
+            [kind, address, ref options @ ..] if options.len() <= 2 => {
+                if !DEVICES.contains(&kind) {
+                    return Err(format!("Unknown device {}", kind));
+                }
+                let address = self.value(address)?;
+                let level = match options.get(1) {
+                    Some(level) => self.value(level)?,
+                    None => 0,
+                };
+                if level > 7 {
+                    return Err(format!("No interrupt level {}", level));
+                }
+                // Consoles for each channel, the first on stdio by default
+                let mut consoles = options.first().copied().unwrap_or("stdio").split(',');
+                let mut console = || Console::open(consoles.next().unwrap_or("none"));
+                let device: Box<dyn Device> = match kind {
+                    "acia" => Box::new(Acia::new(console()?)),
+                    "duart" => Box::new(Duart::new(console()?, console()?, self.cpu.clock)),
+                    "mfp" => Box::new(Mfp::new(console()?, self.cpu.clock)),
+                    _ => Box::new(Pit::new()),
+                };
+                let connected = match device.describe() {
+                    host if host.is_empty() => device.name().to_string(),
+                    host => format!("{} on {}", device.name(), host),
+                };
+                let mapping = Mapping::new(address, DEVICE_STRIDE, level as u8, device);
+                self.cpu.memory_bus.map(mapping)?;
+                println!("{}", connected);
+            }
+            _ => return Err("Usage: device [TYPE ADDR [CONSOLE] [IPL]]".to_string()),
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,249 @@
+use std::fs;
+
+use super::Debugger;
+use crate::decoder::{disassemble, instruction_length};
+use crate::instruction::Instructions;
+use crate::loader::srecord;
+use crate::memory::Memory;
+
+// Instructions shown by a bare disassemble command, and how many of them
+// come before the PC
+const DISASSEMBLY_LINES: u32 = 10;
+const DISASSEMBLY_CONTEXT: u32 = 4;
+
+impl Debugger {
+    // Prints memory in the format x/NF gives
+    pub(super) fn examine_command(
+        &mut self,
+        args: &[&str],
+        format: Option<&str>,
+    ) -> Result<(), String> {
+        let address = self.value(args.first().ok_or("Usage: x[/NF] ADDR")?)?;
+        let format = format.unwrap_or("");
+        let digits = format.trim_end_matches(|c: char| c.is_ascii_alphabetic());
+        let count = if digits.is_empty() {
+            1
+        } else {
+            digits.parse().map_err(|_| "Bad count")?
+        };
+        let unit = format[digits.len()..].chars().next().unwrap_or('w');
+        self.examine(address, count, unit)?;
+        Ok(())
+    }
+
+    // Stores values at consecutive addresses, in the size modify/F gives
+    pub(super) fn modify_command(
+        &mut self,
+        args: &[&str],
+        format: Option<&str>,
+    ) -> Result<(), String> {
+        let Some((address, values)) = args.split_first() else {
+            return Err("Usage: modify[/F] ADDR VALUES".to_string());
+        };
+        let mut address = self.value(address)?;
+        let size = match format.unwrap_or("b") {
+            "b" => 1,
+            "w" => 2,
+            "l" => 4,
+            _ => return Err("Format must be one of b w l".to_string()),
+        };
+        for value in values {
+            let value = self.value(value)?;
+            let mem = &mut self.cpu.memory_bus.memory;
+            match size {
+                1 => mem.write_at_address_byte(address, value as u8),
+                2 => mem.write_at_address_word(address, value as u16),
+                _ => mem.write_at_address_long(address, value),
+            }
+            .map_err(|e| format!("${:08X}: {}", address, e))?;
+            address = address.wrapping_add(size);
+        }
+        Ok(())
+    }
+
+    // Lists instructions from an address, or around the PC
+    pub(super) fn disassemble_command(&mut self, args: &[&str]) -> Result<(), String> {
+        let count = match args.get(1) {
+            Some(count) => self.value(count)?,
+            None => DISASSEMBLY_LINES,
+        };
+        let from = match args.first() {
+            Some(address) => self.value(address)?,
+            None => self.backtrack(self.cpu.registers.PC, DISASSEMBLY_CONTEXT),
+        };
+        self.disassemble(from, count);
+        Ok(())
+    }
+
+    // Writes memory from START up to END as S-records, with the PC or ENTRY
+    // as the start address, for the EPROM burner or the cross tools
+    pub(super) fn dump_command(&mut self, args: &[&str]) -> Result<(), String> {
+        let (path, start, end, entry) = match args[..] {
+            [path, start, end] => (path, start, end, None),
+            [path, start, end, entry] => (path, start, end, Some(entry)),
+            _ => return Err("Usage: dump FILE START END [ENTRY]".to_string()),
+        };
+        let (start, end) = (self.value(start)?, self.value(end)?);
+        if end <= start {
+            return Err("END must be above START".to_string());
+        }
+        let entry = match entry {
+            Some(entry) => self.value(entry)?,
+            None => self.cpu.registers.PC,
+        };
+        let text = srecord::write(
+            &self.cpu.memory_bus.memory,
+            &[(start, end)],
+            path,
+            Some(entry),
+        )?;
+        fs::write(path, text).map_err(|e| format!("{}: {}", path, e))?;
+        println!("Wrote ${:X} bytes to {}", end - start, path);
+        Ok(())
+    }
+
+    // Prints the calls in progress, innermost first
+    pub(super) fn backtrace_command(&mut self, args: &[&str]) -> Result<(), String> {
+        let count = match args.first() {
+            Some(count) => self.value(count)? as usize,
+            None => usize::MAX,
+        };
+        let mem = &mut self.cpu.memory_bus.memory;
+        let frames = self.calls.frames(mem, &self.cpu.registers);
+        for (n, pc) in frames.iter().take(count).enumerate() {
+            let source = match self.lines.lookup(*pc) {
+                Some((file, line)) => format!(" at {}:{}", file, line),
+                None => String::new(),
+            };
+            println!("#{:<2} {}{}", n, self.describe(*pc), source);
+        }
+        Ok(())
+    }
+
+    // Lists symbols whose names contain a pattern
+    pub(super) fn symbols_command(&mut self, args: &[&str]) -> Result<(), String> {
+        let pattern = args.first().copied().unwrap_or("");
+        for (name, address) in self.symbols.iter() {
+            if name.contains(pattern) {
+                println!("${:08X} {}", address, name);
+            }
+        }
+        Ok(())
+    }
+
+    pub(super) fn examine(&mut self, address: u32, count: u32, unit: char) -> Result<(), String> {
+        let mem = &mut self.cpu.memory_bus.memory;
+        let size = match unit {
+            'b' | 'c' => 1,
+            'w' => 2,
+            'l' => 4,
+            's' => {
+                let mut at = address;
+                for _ in 0..count {
+                    let text = crate::trap::read_string(mem, at)?;
+                    println!("${:08X}: {:?}", at, text);
+                    at = at.wrapping_add(text.len() as u32 + 1);
+                }
+                return Ok(());
+            }
+            'i' => {
+                self.disassemble(address, count);
+                return Ok(());
+            }
+            _ => return Err("Format must be one of b w l c s i".to_string()),
+        };
+
+        let per_line = 16 / size;
+        for line in 0..count.div_ceil(per_line) {
+            let start = address.wrapping_add(line * 16);
+            let units = per_line.min(count - line * per_line);
+            let mut text = format!("${:08X}:", start);
+            for i in 0..units {
+                let at = start.wrapping_add(i * size);
+                let unit_text = match (unit, size) {
+                    ('c', _) => mem.read_at_address_byte(at).map(|b| {
+                        let c = b as char;
+                        format!(
+                            "{}",
+                            if c.is_ascii_graphic() || c == ' ' {
+                                c
+                            } else {
+                                '.'
+                            }
+                        )
+                    }),
+                    (_, 1) => mem.read_at_address_byte(at).map(|v| format!(" {:02X}", v)),
+                    (_, 2) => mem.read_at_address_word(at).map(|v| format!(" {:04X}", v)),
+                    _ => mem.read_at_address_long(at).map(|v| format!(" {:08X}", v)),
+                };
+                text.push_str(&unit_text.ok_or_else(|| format!("${:08X} out of bounds", at))?);
+            }
+            println!("{}", text);
+        }
+        Ok(())
+    }
+
+    pub(super) fn disassemble(&mut self, from: u32, count: u32) {
+        let pc = self.cpu.registers.PC;
+        let mut address = from;
+        for _ in 0..count {
+            if let Some(name) = self.symbols.name_at(address) {
+                println!("{}:", name);
+            }
+            let mem = &mut self.cpu.memory_bus.memory;
+            let (ins, next) = disassemble(mem, address);
+            let words: Vec<String> = (address..next)
+                .step_by(2)
+                .map(|at| {
+                    mem.read_at_address_word(at)
+                        .map_or("????".to_string(), |w| format!("{:04X}", w))
+                })
+                .collect();
+            let marker = if address == pc { "=>" } else { "  " };
+            let text = match ins {
+                Instructions::NotImplemented => format!("dc.w    ${}", words[0]),
+                ins => ins.at(address).to_string(),
+            };
+            println!(
+                "{} ${:08X}  {:<24} {}",
+                marker,
+                address,
+                words.join(" "),
+                text
+            );
+            address = next;
+        }
+    }
+
+    // Start of a run of up to lines whole instructions that ends exactly at
+    // address, found by trying the furthest candidate starts first
+    pub(super) fn backtrack(&mut self, address: u32, lines: u32) -> u32 {
+        // The longest 68000 instruction is ten bytes
+        for distance in (1..=lines * 5).rev().map(|words| words * 2) {
+            let Some(start) = address.checked_sub(distance) else {
+                continue;
+            };
+            let mut at = start;
+            let mut decoded = 0;
+            let mem = &self.cpu.memory_bus.memory;
+            while at < address {
+                let Some(opcode) = mem.read_at_address_word(at) else {
+                    break;
+                };
+                let next: Vec<u16> = mem
+                    .read_at_address_word(at.wrapping_add(2))
+                    .into_iter()
+                    .collect();
+                let Some(after) = at.checked_add(instruction_length(opcode, &next)) else {
+                    break;
+                };
+                at = after;
+                decoded += 1;
+            }
+            if at == address && decoded <= lines {
+                return start;
+            }
+        }
+        address
+    }
+}
@@ -0,0 +1,92 @@
+use std::fs;
+
+use super::Debugger;
+use crate::coverage::Coverage;
+use crate::profiler::Profiler;
+
+// Entries in each table of a bare profile command
+const PROFILE_LINES: usize = 10;
+
+impl Debugger {
+    // Starts, stops, reports or exports the execution profile
+    pub(super) fn profile_command(&mut self, args: &[&str]) -> Result<(), String> {
+        match args[..] {
+            ["on"] => {
+                if self.profiler.is_none() {
+                    self.profiler = Some(Profiler::new());
+                }
+            }
+            ["off"] => self.profiler = None,
+            ["reset"] => self.profiler = Some(Profiler::new()),
+            ["folded", path] | ["callgrind", path] => {
+                let profiler = self
+                    .profiler
+                    .as_ref()
+                    .ok_or("Not profiling, use profile on")?;
+                let text = match args[0] {
+                    "folded" => profiler.folded(&self.symbols),
+                    _ => profiler.callgrind(&self.symbols),
+                };
+                fs::write(path, text).map_err(|e| format!("{}: {}", path, e))?;
+            }
+            [] | [_] => {
+                let count = match args.first() {
+                    Some(count) => self.value(count)? as usize,
+                    None => PROFILE_LINES,
+                };
+                let profiler = self
+                    .profiler
+                    .as_ref()
+                    .ok_or("Not profiling, use profile on")?;
+                print!("{}", profiler.report(&self.symbols, count));
+            }
+            _ => {
+                return Err(
+                    "Usage: profile [N] | profile on|off|reset | profile folded|callgrind FILE"
+                        .to_string(),
+                )
+            }
+        }
+        Ok(())
+    }
+
+    // Starts, stops, reports or exports code coverage
+    pub(super) fn coverage_command(&mut self, args: &[&str]) -> Result<(), String> {
+        match args[..] {
+            ["on"] => {
+                if self.coverage.is_none() {
+                    self.coverage = Some(Coverage::new());
+                }
+            }
+            ["off"] => self.coverage = None,
+            ["reset"] => self.coverage = Some(Coverage::new()),
+            ["lcov", path] => {
+                let coverage = self
+                    .coverage
+                    .as_ref()
+                    .ok_or("Not measuring coverage, use coverage on")?;
+                let mem = &mut self.cpu.memory_bus.memory;
+                // Without line tables, addresses stand in for source lines
+                let text = if self.lines.is_empty() {
+                    let image = self.image.as_deref().unwrap_or("memory");
+                    coverage.lcov_symbols(mem, &self.symbols, image, "rs68000")
+                } else {
+                    coverage.lcov(mem, &self.lines, &self.symbols, "rs68000")
+                };
+                fs::write(path, text).map_err(|e| format!("{}: {}", path, e))?;
+            }
+            [] => {
+                let coverage = self
+                    .coverage
+                    .as_ref()
+                    .ok_or("Not measuring coverage, use coverage on")?;
+                print!(
+                    "{}",
+                    coverage.report(&mut self.cpu.memory_bus.memory, &self.symbols)
+                );
+            }
+            _ => return Err("Usage: coverage [on|off|reset] | coverage lcov FILE".to_string()),
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,139 @@
+use super::{Debugger, Stop, INTERRUPT_POLL};
+use crate::recorder::Recorder;
+use crate::savestate;
+use crate::trace::{TraceFormat, Tracer};
+
+impl Debugger {
+    // Writes the CPU and memory to a state file
+    pub(super) fn save_command(&mut self, args: &[&str]) -> Result<(), String> {
+        let path = args.first().ok_or("Usage: save FILE")?;
+        savestate::save_file(&self.cpu, path)?;
+        println!("Saved state to {}", path);
+        Ok(())
+    }
+
+    // Reads a state file, starting a fresh history if recording
+    pub(super) fn restore_command(&mut self, args: &[&str]) -> Result<(), String> {
+        let path = args.first().ok_or("Usage: restore FILE")?;
+        savestate::restore_file(&mut self.cpu, path)?;
+        self.exit_status = None;
+        self.calls.clear();
+        if self.recorder.is_some() {
+            self.recorder = Some(Recorder::new());
+        }
+        self.print_location();
+        Ok(())
+    }
+
+    // Starts or stops recording execution history
+    pub(super) fn record_command(&mut self, args: &[&str]) -> Result<(), String> {
+        match args[..] {
+            [] | ["on"] => {
+                if self.recorder.is_none() {
+                    self.recorder = Some(Recorder::new());
+                }
+            }
+            ["off"] => self.recorder = None,
+            _ => return Err("Usage: record [on|off]".to_string()),
+        }
+        Ok(())
+    }
+
+    // Undoes a number of recorded instructions
+    pub(super) fn reverse_step_command(&mut self, args: &[&str]) -> Result<(), String> {
+        let count = match args.first() {
+            Some(count) => self.value(count)? as u64,
+            None => 1,
+        };
+        let recorder = self
+            .recorder
+            .as_mut()
+            .ok_or("Not recording, use record first")?;
+        let available = recorder.position() - recorder.first();
+        recorder.rewind(&mut self.cpu, count);
+        self.exit_status = None;
+        if available < count {
+            self.report(Stop::HistoryStart);
+        } else {
+            self.print_location();
+        }
+        Ok(())
+    }
+
+    // Finds the most recent recorded write to an address
+    pub(super) fn last_write_command(&mut self, args: &[&str]) -> Result<(), String> {
+        let address = self.value(args.first().ok_or("Usage: last-write ADDR")?)?;
+        let recorder = self
+            .recorder
+            .as_ref()
+            .ok_or("Not recording, use record first")?;
+        let write = recorder
+            .last_write(address)
+            .ok_or_else(|| format!("No recorded write to ${:08X}", address))?;
+        let width = write.access.size as usize * 2;
+        println!(
+            "Instruction {} at {} wrote {} bytes at ${:08X}: ${:0width$X} -> ${:0width$X}",
+            write.position,
+            self.describe(write.pc),
+            write.access.size,
+            write.access.address,
+            write.access.old,
+            write.access.new,
+            width = width
+        );
+        Ok(())
+    }
+
+    // Starts logging executed instructions to a file, or stops
+    pub(super) fn trace_command(&mut self, args: &[&str]) -> Result<(), String> {
+        match args[..] {
+            ["off"] => {
+                self.flush_trace();
+                self.tracer = None;
+            }
+            [path] => self.tracer = Some(Tracer::create(path, TraceFormat::Full)?),
+            [path, format] => {
+                self.tracer = Some(Tracer::create(path, TraceFormat::parse(format)?)?)
+            }
+            _ => return Err("Usage: trace FILE [full|mame] | trace off".to_string()),
+        }
+        Ok(())
+    }
+
+    // Undoes one recorded instruction
+    pub fn reverse_step(&mut self) -> Option<Stop> {
+        let Some(recorder) = self.recorder.as_mut() else {
+            return Some(Stop::Fault("Not recording, use record first".to_string()));
+        };
+        if recorder.undo(&mut self.cpu).is_none() {
+            return Some(Stop::HistoryStart);
+        }
+        self.exit_status = None;
+        None
+    }
+
+    // Runs backwards until a breakpoint, a watchpoint on a write being undone,
+    // the start of the history or interrupt returning true
+    pub fn reverse_run(&mut self, interrupt: &mut dyn FnMut() -> bool) -> Stop {
+        for steps in 1u32.. {
+            let Some(recorder) = self.recorder.as_mut() else {
+                return Stop::Fault("Not recording, use record first".to_string());
+            };
+            let Some(writes) = recorder.undo(&mut self.cpu) else {
+                return Stop::HistoryStart;
+            };
+            self.exit_status = None;
+            if let Some(stop) = self.check_watchpoints(&writes) {
+                return stop;
+            }
+            let pc = self.cpu.registers.PC;
+            if self.hit_breakpoint(pc) {
+                return Stop::Breakpoint(pc);
+            }
+            if steps.is_multiple_of(INTERRUPT_POLL) && interrupt() {
+                return Stop::Interrupted;
+            }
+        }
+        Stop::Interrupted
+    }
+}
@@ -1,6 +1,13 @@
 use crate::cpu::CPU;
-use crate::instruction::Instructions;
+use crate::encoder::{
+    ea_kind, EA_ALL, EA_ALTERABLE, EA_AN, EA_CONTROL, EA_CONTROL_ALTERABLE, EA_DATA,
+    EA_DATA_ALTERABLE, EA_IMMEDIATE, EA_MEMORY_ALTERABLE, EA_POSTINC, EA_PREDEC,
+};
+use crate::instruction::{
+    Condition, ControlRegister, Direction, IndexRegister, Instructions, Registers, Size, Target,
+};
 use crate::memory::Memory;
+use crate::symbols::SymbolTable;
 
 pub enum OpCodeType {
     BitManipulation,
@@ -57,92 +64,900 @@ pub enum AddressingMode {
     ImpliedRegister,
 }
 
+// Decoding is driven by the opcode word; extension words are pulled through
+// extension_word, so any type that can supply them gets the full decoder
 pub trait Decoder<M: Memory + ?Sized> {
-    fn decode(&self, ins: u16) -> Instructions;
-    fn decode_bit_manipulation(&mut self, mem: &mut M, ins: u16) -> Instructions;
-    fn decode_mov_byte(&mut self, mem: &mut M, ins: u16) -> Instructions;
-    fn decode_mov_long(&mut self, mem: &mut M, ins: u16) -> Instructions;
-    fn decode_mov_word(&mut self, mem: &mut M, ins: u16) -> Instructions;
-    fn decode_misc(&mut self, mem: &mut M, ins: u16) -> Instructions;
-    fn decode_addq_subq(&mut self, mem: &mut M, ins: u16) -> Instructions;
-    fn decode_branch(&mut self, mem: &mut M, ins: u16) -> Instructions;
-    fn decode_moveq(&mut self, mem: &mut M, ins: u16) -> Instructions;
-    fn decode_or_div(&mut self, mem: &mut M, ins: u16) -> Instructions;
-    fn decode_sub(&mut self, mem: &mut M, ins: u16) -> Instructions;
-    fn decode_reserved(&mut self, mem: &mut M, ins: u16) -> Instructions;
-    fn decode_cmp_eor(&mut self, mem: &mut M, ins: u16) -> Instructions;
-    fn decode_and_mul(&mut self, mem: &mut M, ins: u16) -> Instructions;
-    fn decode_add(&mut self, mem: &mut M, ins: u16) -> Instructions;
-    fn decode_shift(&mut self, mem: &mut M, ins: u16) -> Instructions;
-    fn decode_extension(&mut self, mem: &mut M, ins: u16) -> Instructions;
-}
+    // Next extension word of the instruction being decoded
+    fn extension_word(&mut self, mem: &mut M) -> u16;
 
-impl<M: Memory + ?Sized> Decoder<M> for CPU {
-    fn decode(&self, ins: u16) -> Instructions {
-        todo!()
+    fn decode(&mut self, mem: &mut M, ins: u16) -> Instructions {
+        match OPCM[(ins >> 12) as usize] {
+            OpCodeType::BitManipulation => self.decode_bit_manipulation(mem, ins),
+            OpCodeType::MovByte => self.decode_mov_byte(mem, ins),
+            OpCodeType::MovLong => self.decode_mov_long(mem, ins),
+            OpCodeType::MovWord => self.decode_mov_word(mem, ins),
+            OpCodeType::Misc => self.decode_misc(mem, ins),
+            OpCodeType::AddqSubq => self.decode_addq_subq(mem, ins),
+            OpCodeType::Branch => self.decode_branch(mem, ins),
+            OpCodeType::Moveq => self.decode_moveq(mem, ins),
+            OpCodeType::OrDiv => self.decode_or_div(mem, ins),
+            OpCodeType::Sub => self.decode_sub(mem, ins),
+            OpCodeType::Reserved => self.decode_reserved(mem, ins),
+            OpCodeType::CmpEor => self.decode_cmp_eor(mem, ins),
+            OpCodeType::AndMul => self.decode_and_mul(mem, ins),
+            OpCodeType::Add => self.decode_add(mem, ins),
+            OpCodeType::Shift => self.decode_shift(mem, ins),
+            OpCodeType::Extension => self.decode_extension(mem, ins),
+        }
     }
 
+    // ORI, ANDI, SUBI, ADDI, EORI, CMPI, BTST, BCHG, BCLR, BSET and MOVEP
     fn decode_bit_manipulation(&mut self, mem: &mut M, ins: u16) -> Instructions {
-        todo!()
+        if ins & 0x0100 != 0 {
+            let reg = Registers::data(ins >> 9);
+            if (ins >> 3) & 7 == 1 {
+                let (size, dir) = match (ins >> 6) & 7 {
+                    4 => (Size::Word, Direction::MemoryToRegister),
+                    5 => (Size::Long, Direction::MemoryToRegister),
+                    6 => (Size::Word, Direction::RegisterToMemory),
+                    _ => (Size::Long, Direction::RegisterToMemory),
+                };
+                let disp = self.extension_word(mem) as i16;
+                return Instructions::MOVEP(reg, Registers::address(ins), disp, size, dir);
+            }
+            let kind = (ins >> 6) & 3;
+            let allowed = if kind == 0 {
+                EA_DATA
+            } else {
+                EA_DATA_ALTERABLE
+            };
+            return bit_instruction(kind, Target::DnDirect(reg), self, mem, ins, allowed);
+        }
+
+        match ins {
+            0x003C => return Instructions::ORItoCCR((self.extension_word(mem) & 0xFF) as u32),
+            0x007C => return Instructions::ORItoSR(self.extension_word(mem) as u32),
+            0x023C => return Instructions::ANDItoCCR(self.extension_word(mem) as u8),
+            0x027C => return Instructions::ANDItoSR(self.extension_word(mem)),
+            0x0A3C => return Instructions::EORtoCCR(self.extension_word(mem) as u8),
+            0x0A7C => return Instructions::EORtoSR(self.extension_word(mem)),
+            _ => {}
+        }
+
+        if (ins >> 9) & 7 == 4 {
+            let kind = (ins >> 6) & 3;
+            let allowed = if kind == 0 {
+                EA_DATA & !EA_IMMEDIATE
+            } else {
+                EA_DATA_ALTERABLE
+            };
+            if ea_kind(ins & 0x3F) & allowed == 0 {
+                return Instructions::NotImplemented;
+            }
+            let bit = Target::Immediate((self.extension_word(mem) & 0xFF) as u32);
+            return bit_instruction(kind, bit, self, mem, ins, allowed);
+        }
+
+        let op: fn(u32, Target, Size) -> Instructions = match (ins >> 9) & 7 {
+            0 => Instructions::ORI,
+            1 => Instructions::ANDI,
+            2 => Instructions::SUBI,
+            3 => Instructions::ADDI,
+            5 => Instructions::EORI,
+            6 => Instructions::CMPI,
+            _ => return Instructions::NotImplemented,
+        };
+        let size = match size_field(ins >> 6) {
+            Some(size) if ea_kind(ins & 0x3F) & EA_DATA_ALTERABLE != 0 => size,
+            _ => return Instructions::NotImplemented,
+        };
+        let imm = immediate(self, mem, size);
+        match effective_address(self, mem, ins & 0x3F, size, EA_DATA_ALTERABLE) {
+            Some(dst) => op(imm, dst, size),
+            None => Instructions::NotImplemented,
+        }
     }
 
     fn decode_mov_byte(&mut self, mem: &mut M, ins: u16) -> Instructions {
-        todo!()
+        move_instruction(self, mem, ins, Size::Byte)
     }
 
     fn decode_mov_long(&mut self, mem: &mut M, ins: u16) -> Instructions {
-        todo!()
+        move_instruction(self, mem, ins, Size::Long)
     }
 
     fn decode_mov_word(&mut self, mem: &mut M, ins: u16) -> Instructions {
-        todo!()
+        move_instruction(self, mem, ins, Size::Word)
     }
 
     fn decode_misc(&mut self, mem: &mut M, ins: u16) -> Instructions {
-        todo!()
+        match ins {
+            0x4AFC => return Instructions::ILLEGAL,
+            0x4E70 => return Instructions::RESET,
+            0x4E71 => return Instructions::NOP,
+            0x4E72 => return Instructions::STOP(self.extension_word(mem)),
+            0x4E73 => return Instructions::RTE,
+            0x4E74 => return Instructions::RTD(self.extension_word(mem) as i16),
+            0x4E75 => return Instructions::RTS,
+            0x4E76 => return Instructions::TRAPV,
+            0x4E77 => return Instructions::RTR,
+            0x4E7A | 0x4E7B => {
+                let ext = self.extension_word(mem);
+                if ext & 0x0FFF != 0x0801 {
+                    return Instructions::NotImplemented;
+                }
+                let reg = if ext & 0x8000 != 0 {
+                    Target::AnDirect(Registers::address(ext >> 12))
+                } else {
+                    Target::DnDirect(Registers::data(ext >> 12))
+                };
+                let dir = if ins & 1 != 0 {
+                    Direction::RegisterToMemory
+                } else {
+                    Direction::MemoryToRegister
+                };
+                return Instructions::MOVEC(reg, ControlRegister::VBR, dir);
+            }
+            _ => {}
+        }
+
+        let field = ins & 0x3F;
+        let mode = field >> 3;
+        match ins & 0xFFF8 {
+            0x4E40 | 0x4E48 => return Instructions::TRAP((ins & 0xF) as u8),
+            0x4E50 => {
+                let disp = self.extension_word(mem) as i16 as i32;
+                return Instructions::LINK(Registers::address(ins), disp);
+            }
+            0x4E58 => return Instructions::UNLK(Registers::address(ins)),
+            0x4E60 => {
+                let reg = Target::AnDirect(Registers::address(ins));
+                return Instructions::MOVEUSP(reg, Direction::RegisterToMemory);
+            }
+            0x4E68 => {
+                let reg = Target::AnDirect(Registers::address(ins));
+                return Instructions::MOVEUSP(reg, Direction::MemoryToRegister);
+            }
+            0x4840 => return Instructions::SWAP(Registers::data(ins)),
+            0x4880 => return Instructions::EXT(Registers::data(ins), Size::Byte, Size::Word),
+            0x48C0 => return Instructions::EXT(Registers::data(ins), Size::Word, Size::Long),
+            _ => {}
+        }
+
+        let ea = |decoder: &mut Self, mem: &mut M, size: Size, allowed: u16| {
+            effective_address(decoder, mem, field, size, allowed)
+        };
+        let decoded = match ins & 0xFFC0 {
+            0x4E80 => ea(self, mem, Size::Long, EA_CONTROL).map(Instructions::JSR),
+            0x4EC0 => ea(self, mem, Size::Long, EA_CONTROL).map(Instructions::JMP),
+            0x40C0 => ea(self, mem, Size::Word, EA_DATA_ALTERABLE).map(Instructions::MOVEfromSR),
+            0x42C0 => ea(self, mem, Size::Word, EA_DATA_ALTERABLE).map(Instructions::MOVEfromCCR),
+            0x44C0 => ea(self, mem, Size::Word, EA_DATA).map(Instructions::MOVEtoCCR),
+            0x46C0 => ea(self, mem, Size::Word, EA_DATA).map(Instructions::MOVEtoSR),
+            0x4800 => ea(self, mem, Size::Byte, EA_DATA_ALTERABLE).map(Instructions::NBCD),
+            0x4840 => ea(self, mem, Size::Long, EA_CONTROL).map(Instructions::PEA),
+            0x4AC0 => ea(self, mem, Size::Byte, EA_DATA_ALTERABLE).map(Instructions::TAS),
+            0x4880 | 0x48C0 | 0x4C80 | 0x4CC0 => {
+                let size = if ins & 0x0040 != 0 {
+                    Size::Long
+                } else {
+                    Size::Word
+                };
+                let (dir, allowed) = if ins & 0x0400 != 0 {
+                    (Direction::MemoryToRegister, EA_CONTROL | EA_POSTINC)
+                } else {
+                    (
+                        Direction::RegisterToMemory,
+                        EA_CONTROL_ALTERABLE | EA_PREDEC,
+                    )
+                };
+                if ea_kind(field) & allowed == 0 {
+                    return Instructions::NotImplemented;
+                }
+                let mask = self.extension_word(mem) as i16;
+                ea(self, mem, size, allowed)
+                    .map(|target| Instructions::MOVEM(target, size, mask, dir))
+            }
+            _ if ins & 0x01C0 == 0x01C0 => ea(self, mem, Size::Long, EA_CONTROL)
+                .map(|src| Instructions::LEA(src, Registers::address(ins >> 9))),
+            _ if ins & 0x01C0 == 0x0180 => ea(self, mem, Size::Word, EA_DATA)
+                .map(|src| Instructions::CHK(src, Registers::data(ins >> 9), Size::Word)),
+            _ => {
+                let op: fn(Target, Size) -> Instructions = match ins & 0xFF00 {
+                    0x4000 => Instructions::NEGX,
+                    0x4200 => Instructions::CLR,
+                    0x4400 => Instructions::NEG,
+                    0x4600 => Instructions::NOT,
+                    0x4A00 => Instructions::TST,
+                    _ => return Instructions::NotImplemented,
+                };
+                let size = match size_field(ins >> 6) {
+                    Some(size) if mode != 1 => size,
+                    _ => return Instructions::NotImplemented,
+                };
+                ea(self, mem, size, EA_DATA_ALTERABLE).map(|dst| op(dst, size))
+            }
+        };
+        decoded.unwrap_or(Instructions::NotImplemented)
     }
 
+    // ADDQ, SUBQ, Scc and DBcc
     fn decode_addq_subq(&mut self, mem: &mut M, ins: u16) -> Instructions {
-        todo!()
+        let field = ins & 0x3F;
+        let size = match size_field(ins >> 6) {
+            Some(size) => size,
+            None if field >> 3 == 1 => {
+                let disp = self.extension_word(mem) as i16;
+                return Instructions::DBcc(condition(ins >> 8), Registers::data(ins), disp);
+            }
+            None => {
+                return effective_address(self, mem, field, Size::Byte, EA_DATA_ALTERABLE)
+                    .map(|dst| Instructions::Scc(condition(ins >> 8), dst))
+                    .unwrap_or(Instructions::NotImplemented)
+            }
+        };
+        let data = match (ins >> 9) & 7 {
+            0 => 8,
+            data => data as u32,
+        };
+        match effective_address(self, mem, field, size, EA_ALTERABLE) {
+            Some(dst) if ins & 0x0100 == 0 => Instructions::ADDQ(data, dst, size),
+            Some(dst) => Instructions::SUBQ(data, dst, size),
+            None => Instructions::NotImplemented,
+        }
     }
 
+    // Bcc, BRA and BSR
     fn decode_branch(&mut self, mem: &mut M, ins: u16) -> Instructions {
-        todo!()
+        let disp = match ins & 0xFF {
+            0 => self.extension_word(mem) as i16 as i32,
+            disp => disp as u8 as i8 as i32,
+        };
+        match (ins >> 8) & 0xF {
+            0 => Instructions::BRA(disp),
+            1 => Instructions::BSR(disp),
+            cc => Instructions::Bcc(condition(cc), disp),
+        }
     }
 
-    fn decode_moveq(&mut self, mem: &mut M, ins: u16) -> Instructions {
-        todo!()
+    fn decode_moveq(&mut self, _mem: &mut M, ins: u16) -> Instructions {
+        if ins & 0x0100 != 0 {
+            return Instructions::NotImplemented;
+        }
+        Instructions::MOVEQ(ins as u8, Registers::data(ins >> 9))
     }
 
+    // OR, DIVU, DIVS and SBCD
     fn decode_or_div(&mut self, mem: &mut M, ins: u16) -> Instructions {
-        todo!()
+        let field = ins & 0x3F;
+        let reg = Registers::data(ins >> 9);
+        match (ins >> 6) & 7 {
+            3 => effective_address(self, mem, field, Size::Word, EA_DATA)
+                .map(|src| Instructions::DIVUW(src, reg, Size::Word))
+                .unwrap_or(Instructions::NotImplemented),
+            7 => effective_address(self, mem, field, Size::Word, EA_DATA)
+                .map(|src| Instructions::DIVSW(src, reg, Size::Word))
+                .unwrap_or(Instructions::NotImplemented),
+            4 if field >> 3 < 2 => extended_operands(ins)
+                .map(|(src, dst)| Instructions::SBCD(src, dst))
+                .unwrap_or(Instructions::NotImplemented),
+            _ => arithmetic(self, mem, ins, EA_DATA, EA_MEMORY_ALTERABLE)
+                .map(|(src, dst, size)| Instructions::OR(src, dst, size))
+                .unwrap_or(Instructions::NotImplemented),
+        }
     }
 
+    // SUB, SUBA and SUBX
     fn decode_sub(&mut self, mem: &mut M, ins: u16) -> Instructions {
-        todo!()
+        match (ins >> 6) & 7 {
+            3 | 7 => address_arithmetic(self, mem, ins)
+                .map(|(src, reg, size)| Instructions::SUBA(src, reg, size)),
+            4..=6 if (ins >> 3) & 7 < 2 => extended_operands(ins)
+                .map(|(src, dst)| Instructions::SUBX(src, dst, size_field(ins >> 6).unwrap())),
+            _ => arithmetic(self, mem, ins, EA_ALL, EA_MEMORY_ALTERABLE)
+                .map(|(src, dst, size)| Instructions::SUB(src, dst, size)),
+        }
+        .unwrap_or(Instructions::NotImplemented)
     }
 
-    fn decode_reserved(&mut self, mem: &mut M, ins: u16) -> Instructions {
-        todo!()
+    // Line A is unassigned on the 68000 and traps
+    fn decode_reserved(&mut self, _mem: &mut M, _ins: u16) -> Instructions {
+        Instructions::NotImplemented
     }
 
+    // CMP, CMPA, CMPM and EOR
     fn decode_cmp_eor(&mut self, mem: &mut M, ins: u16) -> Instructions {
-        todo!()
+        match (ins >> 6) & 7 {
+            3 | 7 => address_arithmetic(self, mem, ins)
+                .map(|(src, reg, size)| Instructions::CMPA(src, reg, size)),
+            4..=6 if (ins >> 3) & 7 == 1 => Some(Instructions::CMPM(
+                Target::AnIndirectPostInc(Registers::address(ins)),
+                Target::AnIndirectPostInc(Registers::address(ins >> 9)),
+                size_field(ins >> 6).unwrap(),
+            )),
+            4..=6 => arithmetic(self, mem, ins, 0, EA_DATA_ALTERABLE)
+                .map(|(src, dst, size)| Instructions::EOR(src, dst, size)),
+            _ => arithmetic(self, mem, ins, EA_ALL, 0)
+                .map(|(src, dst, size)| Instructions::CMP(src, dst, size)),
+        }
+        .unwrap_or(Instructions::NotImplemented)
     }
 
+    // AND, MULU, MULS, ABCD and EXG
     fn decode_and_mul(&mut self, mem: &mut M, ins: u16) -> Instructions {
-        todo!()
+        let field = ins & 0x3F;
+        let x = ins >> 9;
+        match ((ins >> 6) & 7, field >> 3) {
+            (3, _) => effective_address(self, mem, field, Size::Word, EA_DATA)
+                .map(|src| Instructions::MULUW(src, Registers::data(x))),
+            (7, _) => effective_address(self, mem, field, Size::Word, EA_DATA)
+                .map(|src| Instructions::MULSW(src, Registers::data(x))),
+            (4, 0 | 1) => extended_operands(ins).map(|(src, dst)| Instructions::ABCD(src, dst)),
+            (5, 0) => Some(Instructions::EXG(
+                Target::DnDirect(Registers::data(x)),
+                Target::DnDirect(Registers::data(ins)),
+            )),
+            (5, 1) => Some(Instructions::EXG(
+                Target::AnDirect(Registers::address(x)),
+                Target::AnDirect(Registers::address(ins)),
+            )),
+            (6, 1) => Some(Instructions::EXG(
+                Target::DnDirect(Registers::data(x)),
+                Target::AnDirect(Registers::address(ins)),
+            )),
+            _ => arithmetic(self, mem, ins, EA_DATA, EA_MEMORY_ALTERABLE)
+                .map(|(src, dst, size)| Instructions::AND(src, dst, size)),
+        }
+        .unwrap_or(Instructions::NotImplemented)
     }
 
+    // ADD, ADDA and ADDX
     fn decode_add(&mut self, mem: &mut M, ins: u16) -> Instructions {
-        todo!()
+        match (ins >> 6) & 7 {
+            3 | 7 => address_arithmetic(self, mem, ins)
+                .map(|(src, reg, size)| Instructions::ADDA(src, reg, size)),
+            4..=6 if (ins >> 3) & 7 < 2 => extended_operands(ins)
+                .map(|(src, dst)| Instructions::ADDX(src, dst, size_field(ins >> 6).unwrap())),
+            _ => arithmetic(self, mem, ins, EA_ALL, EA_MEMORY_ALTERABLE)
+                .map(|(src, dst, size)| Instructions::ADD(src, dst, size)),
+        }
+        .unwrap_or(Instructions::NotImplemented)
     }
 
+    // ASd, LSd, ROXd and ROd
     fn decode_shift(&mut self, mem: &mut M, ins: u16) -> Instructions {
-        todo!()
+        let left = ins & 0x0100 != 0;
+        let (kind, count, dst, size) = match size_field(ins >> 6) {
+            Some(size) => {
+                let count = match ((ins >> 9) & 7, ins & 0x0020 != 0) {
+                    (reg, true) => Target::DnDirect(Registers::data(reg)),
+                    (0, false) => Target::Immediate(8),
+                    (count, false) => Target::Immediate(count as u32),
+                };
+                let dst = Target::DnDirect(Registers::data(ins));
+                ((ins >> 3) & 3, count, dst, size)
+            }
+            None => {
+                if ins & 0x0800 != 0 {
+                    return Instructions::NotImplemented;
+                }
+                match effective_address(self, mem, ins & 0x3F, Size::Word, EA_MEMORY_ALTERABLE) {
+                    Some(dst) => ((ins >> 9) & 3, Target::Immediate(1), dst, Size::Word),
+                    None => return Instructions::NotImplemented,
+                }
+            }
+        };
+        match (kind, left) {
+            (0, true) => Instructions::ASL(count, dst, size),
+            (0, false) => Instructions::ASR(count, dst, size),
+            (1, true) => Instructions::LSL(count, dst, size),
+            (1, false) => Instructions::LSR(count, dst, size),
+            (2, true) => Instructions::ROXL(count, dst, size),
+            (2, false) => Instructions::ROXR(count, dst, size),
+            (_, true) => Instructions::ROL(count, dst, size),
+            (_, false) => Instructions::ROR(count, dst, size),
+        }
+    }
+
+    // Line F is the coprocessor interface, unassigned on the 68000
+    fn decode_extension(&mut self, _mem: &mut M, _ins: u16) -> Instructions {
+        Instructions::NotImplemented
+    }
+}
+
+impl<M: Memory + ?Sized> Decoder<M> for CPU {
+    fn extension_word(&mut self, mem: &mut M) -> u16 {
+        let word = mem.read_at_address_word(self.registers.PC).unwrap_or(0);
+        self.registers.PC = self.registers.PC.wrapping_add(2);
+        word
+    }
+}
+
+// Decodes straight from memory without a CPU, e.g. for disassembly
+struct MemoryReader {
+    address: u32,
+}
+
+impl<M: Memory + ?Sized> Decoder<M> for MemoryReader {
+    fn extension_word(&mut self, mem: &mut M) -> u16 {
+        let word = mem.read_at_address_word(self.address).unwrap_or(0);
+        self.address = self.address.wrapping_add(2);
+        word
+    }
+}
+
+// Decodes the instruction at address and returns it with the address following it;
+// words that do not decode count as a single word, as for instruction_length
+pub fn disassemble<M: Memory + ?Sized>(mem: &mut M, address: u32) -> (Instructions, u32) {
+    let mut reader = MemoryReader {
+        address: address.wrapping_add(2),
+    };
+    let ins = mem.read_at_address_word(address).unwrap_or(0);
+    match reader.decode(mem, ins) {
+        Instructions::NotImplemented => (Instructions::NotImplemented, address.wrapping_add(2)),
+        decoded => (decoded, reader.address),
+    }
+}
+
+// Disassembles every instruction from address from up to to, labelling those
+// that start at a symbol
+pub fn disassemble_range<'a, M: Memory + ?Sized>(
+    mem: &mut M,
+    from: u32,
+    to: u32,
+    symbols: &'a SymbolTable,
+) -> Vec<(u32, Option<&'a str>, Instructions)> {
+    let mut listing = Vec::new();
+    let mut address = from;
+    while address < to {
+        let (ins, next) = disassemble(mem, address);
+        listing.push((address, symbols.name_at(address), ins));
+        // Stop at the top of the address space rather than wrap around
+        if next <= address {
+            break;
+        }
+        address = next;
+    }
+    listing
+}
+
+// Length in bytes of the instruction starting with opcode, worked out from the
+// opcode and its effective address fields without decoding it. Only MOVEC looks
+// at next_words, to validate its control register; words that do not decode
+// are two bytes long.
+pub fn instruction_length(opcode: u16, next_words: &[u16]) -> u32 {
+    2 + 2 * extension_words(opcode, next_words).unwrap_or(0)
+}
+
+// Extension words needed by an effective address field, or None if the mode is
+// not one of the allowed classes
+fn ea_words(field: u16, size: Size, allowed: u16) -> Option<u32> {
+    let kind = ea_kind(field);
+    if kind & allowed == 0 || (kind == EA_AN && size == Size::Byte) {
+        return None;
+    }
+    Some(match (field >> 3, field & 7) {
+        (5 | 6, _) => 1,
+        (7, 1) => 2,
+        (7, 4) if size == Size::Long => 2,
+        (7, _) => 1,
+        _ => 0,
+    })
+}
+
+fn arithmetic_words(opcode: u16, to_register: u16, to_memory: u16) -> Option<u32> {
+    let size = size_field(opcode >> 6)?;
+    let allowed = if opcode & 0x0100 == 0 {
+        to_register
+    } else {
+        to_memory
+    };
+    ea_words(opcode & 0x3F, size, allowed)
+}
+
+// Mirrors the validity rules of the Decoder methods group by group
+fn extension_words(opcode: u16, next_words: &[u16]) -> Option<u32> {
+    let field = opcode & 0x3F;
+    let mode = field >> 3;
+    let opmode = (opcode >> 6) & 7;
+    let word_or_long = if opcode & 0x0100 != 0 {
+        Size::Long
+    } else {
+        Size::Word
+    };
+    match OPCM[(opcode >> 12) as usize] {
+        OpCodeType::BitManipulation => {
+            let bit_size = if mode == 0 { Size::Long } else { Size::Byte };
+            let bit_allowed = if opmode & 3 == 0 {
+                EA_DATA
+            } else {
+                EA_DATA_ALTERABLE
+            };
+            if opcode & 0x0100 != 0 {
+                return if mode == 1 {
+                    Some(1)
+                } else {
+                    ea_words(field, bit_size, bit_allowed)
+                };
+            }
+            match opcode {
+                0x003C | 0x007C | 0x023C | 0x027C | 0x0A3C | 0x0A7C => return Some(1),
+                _ => {}
+            }
+            match (opcode >> 9) & 7 {
+                4 => Some(1 + ea_words(field, bit_size, bit_allowed & !EA_IMMEDIATE)?),
+                0 | 1 | 2 | 3 | 5 | 6 => {
+                    let size = size_field(opcode >> 6)?;
+                    let immediate = if size == Size::Long { 2 } else { 1 };
+                    Some(immediate + ea_words(field, size, EA_DATA_ALTERABLE)?)
+                }
+                _ => None,
+            }
+        }
+        OpCodeType::MovByte | OpCodeType::MovLong | OpCodeType::MovWord => {
+            let size = match opcode >> 12 {
+                1 => Size::Byte,
+                2 => Size::Long,
+                _ => Size::Word,
+            };
+            let dst_field = (opcode >> 3) & 0x38 | (opcode >> 9) & 7;
+            let allowed = if dst_field >> 3 == 1 {
+                EA_AN
+            } else {
+                EA_DATA_ALTERABLE
+            };
+            Some(ea_words(field, size, EA_ALL)? + ea_words(dst_field, size, allowed)?)
+        }
+        OpCodeType::Misc => {
+            match opcode {
+                0x4AFC | 0x4E70 | 0x4E71 | 0x4E73 | 0x4E75 | 0x4E76 | 0x4E77 => return Some(0),
+                0x4E72 | 0x4E74 => return Some(1),
+                0x4E7A | 0x4E7B => {
+                    return match next_words.first() {
+                        Some(ext) if ext & 0x0FFF != 0x0801 => None,
+                        _ => Some(1),
+                    }
+                }
+                _ => {}
+            }
+            match opcode & 0xFFF8 {
+                0x4E50 => return Some(1),
+                0x4E40 | 0x4E48 | 0x4E58 | 0x4E60 | 0x4E68 | 0x4840 | 0x4880 | 0x48C0 => {
+                    return Some(0)
+                }
+                _ => {}
+            }
+            match opcode & 0xFFC0 {
+                0x4E80 | 0x4EC0 | 0x4840 => ea_words(field, Size::Long, EA_CONTROL),
+                0x40C0 | 0x42C0 => ea_words(field, Size::Word, EA_DATA_ALTERABLE),
+                0x44C0 | 0x46C0 => ea_words(field, Size::Word, EA_DATA),
+                0x4800 | 0x4AC0 => ea_words(field, Size::Byte, EA_DATA_ALTERABLE),
+                0x4880 | 0x48C0 | 0x4C80 | 0x4CC0 => {
+                    let allowed = if opcode & 0x0400 != 0 {
+                        EA_CONTROL | EA_POSTINC
+                    } else {
+                        EA_CONTROL_ALTERABLE | EA_PREDEC
+                    };
+                    Some(1 + ea_words(field, Size::Word, allowed)?)
+                }
+                _ if opcode & 0x01C0 == 0x01C0 => ea_words(field, Size::Long, EA_CONTROL),
+                _ if opcode & 0x01C0 == 0x0180 => ea_words(field, Size::Word, EA_DATA),
+                _ => match opcode & 0xFF00 {
+                    0x4000 | 0x4200 | 0x4400 | 0x4600 | 0x4A00 if mode != 1 => {
+                        ea_words(field, size_field(opcode >> 6)?, EA_DATA_ALTERABLE)
+                    }
+                    _ => None,
+                },
+            }
+        }
+        OpCodeType::AddqSubq => match size_field(opcode >> 6) {
+            Some(size) => ea_words(field, size, EA_ALTERABLE),
+            None if mode == 1 => Some(1),
+            None => ea_words(field, Size::Byte, EA_DATA_ALTERABLE),
+        },
+        OpCodeType::Branch => Some(if opcode & 0xFF == 0 { 1 } else { 0 }),
+        OpCodeType::Moveq => match opcode & 0x0100 {
+            0 => Some(0),
+            _ => None,
+        },
+        OpCodeType::OrDiv => match opmode {
+            3 | 7 => ea_words(field, Size::Word, EA_DATA),
+            4 if mode < 2 => Some(0),
+            _ => arithmetic_words(opcode, EA_DATA, EA_MEMORY_ALTERABLE),
+        },
+        OpCodeType::Sub | OpCodeType::Add => match opmode {
+            3 | 7 => ea_words(field, word_or_long, EA_ALL),
+            4..=6 if mode < 2 => Some(0),
+            _ => arithmetic_words(opcode, EA_ALL, EA_MEMORY_ALTERABLE),
+        },
+        OpCodeType::CmpEor => match opmode {
+            3 | 7 => ea_words(field, word_or_long, EA_ALL),
+            4..=6 if mode == 1 => Some(0),
+            4..=6 => arithmetic_words(opcode, 0, EA_DATA_ALTERABLE),
+            _ => arithmetic_words(opcode, EA_ALL, 0),
+        },
+        OpCodeType::AndMul => match (opmode, mode) {
+            (3 | 7, _) => ea_words(field, Size::Word, EA_DATA),
+            (4, 0 | 1) | (5, 0 | 1) | (6, 1) => Some(0),
+            _ => arithmetic_words(opcode, EA_DATA, EA_MEMORY_ALTERABLE),
+        },
+        OpCodeType::Shift => match size_field(opcode >> 6) {
+            Some(_) => Some(0),
+            None if opcode & 0x0800 != 0 => None,
+            None => ea_words(field, Size::Word, EA_MEMORY_ALTERABLE),
+        },
+        OpCodeType::Reserved | OpCodeType::Extension => None,
+    }
+}
+
+// Inverse of encoder::condition_code
+fn condition(code: u16) -> Condition {
+    match code & 0xF {
+        0x0 => Condition::True,
+        0x1 => Condition::False,
+        0x2 => Condition::High,
+        0x3 => Condition::Low,
+        0x4 => Condition::CarryClear,
+        0x5 => Condition::CarrySet,
+        0x6 => Condition::Neq,
+        0x7 => Condition::Equal,
+        0x8 => Condition::OverflowClear,
+        0x9 => Condition::OverflowSet,
+        0xA => Condition::Plus,
+        0xB => Condition::Minus,
+        0xC => Condition::Geq,
+        0xD => Condition::Lt,
+        0xE => Condition::Gt,
+        _ => Condition::Leq,
+    }
+}
+
+// Two-bit size field: 00 byte, 01 word, 10 long, 11 marks a different instruction
+fn size_field(bits: u16) -> Option<Size> {
+    match bits & 3 {
+        0 => Some(Size::Byte),
+        1 => Some(Size::Word),
+        2 => Some(Size::Long),
+        _ => None,
+    }
+}
+
+fn immediate<M, D>(decoder: &mut D, mem: &mut M, size: Size) -> u32
+where
+    M: Memory + ?Sized,
+    D: Decoder<M> + ?Sized,
+{
+    match size {
+        Size::Byte => (decoder.extension_word(mem) & 0xFF) as u32,
+        Size::Word => decoder.extension_word(mem) as u32,
+        Size::Long => {
+            let high = decoder.extension_word(mem) as u32;
+            high << 16 | decoder.extension_word(mem) as u32
+        }
+    }
+}
+
+// Decodes a 6-bit effective address field, reading its extension words, or
+// returns None when the mode is not one of the allowed classes
+fn effective_address<M, D>(
+    decoder: &mut D,
+    mem: &mut M,
+    field: u16,
+    size: Size,
+    allowed: u16,
+) -> Option<Target>
+where
+    M: Memory + ?Sized,
+    D: Decoder<M> + ?Sized,
+{
+    let kind = ea_kind(field);
+    if kind & allowed == 0 || (kind == EA_AN && size == Size::Byte) {
+        return None;
+    }
+    let reg = field & 7;
+    let index = |ext: u16| IndexRegister {
+        register: if ext & 0x8000 != 0 {
+            Registers::address(ext >> 12)
+        } else {
+            Registers::data(ext >> 12)
+        },
+        size: if ext & 0x0800 != 0 {
+            Size::Long
+        } else {
+            Size::Word
+        },
+        scale: 1,
+    };
+    Some(match (field >> 3, reg) {
+        (0, _) => Target::DnDirect(Registers::data(reg)),
+        (1, _) => Target::AnDirect(Registers::address(reg)),
+        (2, _) => Target::AnIndirect(Registers::address(reg)),
+        (3, _) => Target::AnIndirectPostInc(Registers::address(reg)),
+        (4, _) => Target::AnIndirectPreDec(Registers::address(reg)),
+        (5, _) => {
+            let disp = decoder.extension_word(mem) as i16 as i32;
+            Target::AnIndirectDisplacement(Registers::address(reg), disp)
+        }
+        (6, _) => {
+            let ext = decoder.extension_word(mem);
+            Target::AnIndirectIndex(ext as u8 as i8 as i32, Registers::address(reg), index(ext))
+        }
+        (7, 0) => Target::AbsoluteShortAddress(decoder.extension_word(mem) as i16 as i32),
+        (7, 1) => {
+            let high = decoder.extension_word(mem) as u32;
+            Target::AbsoluteLongAddress(high, decoder.extension_word(mem) as u32)
+        }
+        (7, 2) => {
+            let disp = decoder.extension_word(mem) as i16 as i32;
+            Target::PCIndirectDisplacement(disp, Registers::PC)
+        }
+        (7, 3) => {
+            let ext = decoder.extension_word(mem);
+            Target::PCIndirectIndex(ext as u8 as i8 as i32, Registers::PC, index(ext))
+        }
+        _ => Target::Immediate(immediate(decoder, mem, size)),
+    })
+}
+
+fn move_instruction<M, D>(decoder: &mut D, mem: &mut M, ins: u16, size: Size) -> Instructions
+where
+    M: Memory + ?Sized,
+    D: Decoder<M> + ?Sized,
+{
+    let dst_field = (ins >> 3) & 0x38 | (ins >> 9) & 7;
+    let allowed = if dst_field >> 3 == 1 {
+        EA_AN
+    } else {
+        EA_DATA_ALTERABLE
+    };
+    let src_kind = ea_kind(ins & 0x3F);
+    if ea_kind(dst_field) & allowed == 0
+        || src_kind & EA_ALL == 0
+        || (src_kind | allowed) & EA_AN != 0 && size == Size::Byte
+    {
+        return Instructions::NotImplemented;
+    }
+    let src = match effective_address(decoder, mem, ins & 0x3F, size, EA_ALL) {
+        Some(src) => src,
+        None => return Instructions::NotImplemented,
+    };
+    match effective_address(decoder, mem, dst_field, size, allowed) {
+        Some(Target::AnDirect(reg)) => Instructions::MOVEA(src, reg, size),
+        Some(dst) => Instructions::MOVE(src, dst, size),
+        None => Instructions::NotImplemented,
+    }
+}
+
+// BTST, BCHG, BCLR and BSET, long sized on data registers and byte sized in memory
+fn bit_instruction<M, D>(
+    kind: u16,
+    bit: Target,
+    decoder: &mut D,
+    mem: &mut M,
+    ins: u16,
+    allowed: u16,
+) -> Instructions
+where
+    M: Memory + ?Sized,
+    D: Decoder<M> + ?Sized,
+{
+    let size = if (ins >> 3) & 7 == 0 {
+        Size::Long
+    } else {
+        Size::Byte
+    };
+    match effective_address(decoder, mem, ins & 0x3F, size, allowed) {
+        Some(dst) => match kind {
+            0 => Instructions::BTST(bit, dst, size),
+            1 => Instructions::BCHG(bit, dst, size),
+            2 => Instructions::BCLR(bit, dst, size),
+            _ => Instructions::BSET(bit, dst, size),
+        },
+        None => Instructions::NotImplemented,
+    }
+}
+
+// Operands of the <ea>,Dn (opmode 0-2) and Dn,<ea> (opmode 4-6) arithmetic forms
+fn arithmetic<M, D>(
+    decoder: &mut D,
+    mem: &mut M,
+    ins: u16,
+    to_register: u16,
+    to_memory: u16,
+) -> Option<(Target, Target, Size)>
+where
+    M: Memory + ?Sized,
+    D: Decoder<M> + ?Sized,
+{
+    let size = size_field(ins >> 6)?;
+    let reg = Target::DnDirect(Registers::data(ins >> 9));
+    if ins & 0x0100 == 0 {
+        let src = effective_address(decoder, mem, ins & 0x3F, size, to_register)?;
+        Some((src, reg, size))
+    } else {
+        let dst = effective_address(decoder, mem, ins & 0x3F, size, to_memory)?;
+        Some((reg, dst, size))
+    }
+}
+
+// Operands of ADDA, SUBA and CMPA
+fn address_arithmetic<M, D>(
+    decoder: &mut D,
+    mem: &mut M,
+    ins: u16,
+) -> Option<(Target, Registers, Size)>
+where
+    M: Memory + ?Sized,
+    D: Decoder<M> + ?Sized,
+{
+    let size = if ins & 0x0100 != 0 {
+        Size::Long
+    } else {
+        Size::Word
+    };
+    let src = effective_address(decoder, mem, ins & 0x3F, size, EA_ALL)?;
+    Some((src, Registers::address(ins >> 9), size))
+}
+
+// Operands of ABCD, SBCD, ADDX and SUBX: Dy,Dx or -(Ay),-(Ax)
+fn extended_operands(ins: u16) -> Option<(Target, Target)> {
+    if ins & 0x0008 == 0 {
+        Some((
+            Target::DnDirect(Registers::data(ins)),
+            Target::DnDirect(Registers::data(ins >> 9)),
+        ))
+    } else {
+        Some((
+            Target::AnIndirectPreDec(Registers::address(ins)),
+            Target::AnIndirectPreDec(Registers::address(ins >> 9)),
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{disassemble, instruction_length};
+    use crate::encoder::encode;
+    use crate::instruction::Instructions;
+
+    // Extension words tried after each opcode: zeros, index registers of
+    // both kinds and sizes with positive and negative displacements, and
+    // MOVEC's control registers
+    const EXTENSIONS: [[u16; 4]; 5] = [
+        [0x0000, 0x0000, 0x0000, 0x0000],
+        [0x1234, 0x5678, 0x9ABC, 0xDEF0],
+        [0x8801, 0x8000, 0x7FFF, 0x0001],
+        [0xF8FE, 0xFFFF, 0xFFFF, 0xFFFE],
+        [0x0801, 0x0002, 0x0003, 0x0004],
+    ];
+
+    fn decode(words: &[u16]) -> (Instructions, u32) {
+        let mut memory = [0u16; 5];
+        memory[..words.len()].copy_from_slice(words);
+        disassemble(&mut memory[..], 0)
     }
 
-    fn decode_extension(&mut self, mem: &mut M, ins: u16) -> Instructions {
-        todo!()
+    #[test]
+    fn every_opcode_round_trips() {
+        for opcode in 0..=0xFFFFu16 {
+            for extension in EXTENSIONS {
+                let mut words = vec![opcode];
+                words.extend(extension);
+                let (ins, length) = decode(&words);
+                assert_eq!(length, instruction_length(opcode, &extension));
+                if ins == Instructions::NotImplemented {
+                    continue;
+                }
+                let encoded =
+                    encode(&ins).unwrap_or_else(|e| panic!("{:04X} {:?}: {}", opcode, ins, e));
+                let (decoded, length) = decode(&encoded);
+                assert_eq!(decoded, ins, "{:04X} encoded as {:04X?}", opcode, encoded);
+                assert_eq!(length as usize, encoded.len() * 2, "{:?}", ins);
+                assert_eq!(
+                    instruction_length(encoded[0], &encoded[1..]) as usize,
+                    encoded.len() * 2
+                );
+            }
+        }
     }
 }
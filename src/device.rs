@@ -0,0 +1,214 @@
+pub mod acia;
+pub mod console;
+pub mod duart;
+pub mod mfp;
+pub mod pit;
+
+use std::cell::RefCell;
+
+// Vector of the level 1 autovector; level n uses AUTOVECTOR + n - 1
+pub const AUTOVECTOR: u8 = 25;
+// Vector taken when no device answers an interrupt acknowledge
+pub const SPURIOUS_INTERRUPT: u8 = 24;
+
+// A memory-mapped peripheral with byte-wide registers numbered from 0
+pub trait Device {
+    // Part name, for listings
+    fn name(&self) -> &str;
+    // Number of registers the device decodes
+    fn registers(&self) -> u32;
+    fn read(&mut self, register: u32) -> u8;
+    fn write(&mut self, register: u32, value: u8);
+    // Advances the device by cycles CPU clock cycles
+    fn tick(&mut self, _cycles: u32) {}
+    // Whether the interrupt request output is asserted
+    fn interrupt(&self) -> bool {
+        false
+    }
+    // Vector the device puts on the bus when its interrupt is acknowledged,
+    // None to have the CPU use the autovector
+    fn acknowledge(&mut self) -> Option<u8> {
+        None
+    }
+    // The RESET line, from a board reset or the RESET instruction
+    fn reset(&mut self) {}
+    // Where the host side of the device is connected, for listings
+    fn describe(&self) -> String {
+        String::new()
+    }
+    // Register and timer state for save states, the same length every time.
+    // Host connections such as consoles are not part of it.
+    fn save_state(&self) -> Vec<u8> {
+        Vec::new()
+    }
+    // Puts back state from save_state; the caller checks the length first
+    fn restore_state(&mut self, _state: &[u8]) {}
+}
+
+// Builds save_state: fields appended big-endian in a fixed order
+#[derive(Default)]
+pub struct StateWriter {
+    pub data: Vec<u8>,
+}
+
+impl StateWriter {
+    pub fn byte(&mut self, value: u8) -> &mut Self {
+        self.data.push(value);
+        self
+    }
+
+    pub fn bytes(&mut self, values: &[u8]) -> &mut Self {
+        self.data.extend_from_slice(values);
+        self
+    }
+
+    pub fn flag(&mut self, value: bool) -> &mut Self {
+        self.byte(value as u8)
+    }
+
+    pub fn flags(&mut self, values: &[bool]) -> &mut Self {
+        for value in values {
+            self.flag(*value);
+        }
+        self
+    }
+
+    pub fn word(&mut self, value: u16) -> &mut Self {
+        self.bytes(&value.to_be_bytes())
+    }
+
+    pub fn long(&mut self, value: u32) -> &mut Self {
+        self.bytes(&value.to_be_bytes())
+    }
+
+    pub fn quad(&mut self, value: u64) -> &mut Self {
+        self.bytes(&value.to_be_bytes())
+    }
+}
+
+// Reads fields back in the order StateWriter wrote them. The length has been
+// checked, so reads past the end give zeros rather than errors.
+pub struct StateReader<'a> {
+    data: &'a [u8],
+    offset: usize,
+}
+
+impl<'a> StateReader<'a> {
+    pub fn new(data: &'a [u8]) -> Self {
+        Self { data, offset: 0 }
+    }
+
+    pub fn bytes<const N: usize>(&mut self) -> [u8; N] {
+        let mut values = [0; N];
+        for value in &mut values {
+            *value = self.data.get(self.offset).copied().unwrap_or(0);
+            self.offset += 1;
+        }
+        values
+    }
+
+    pub fn byte(&mut self) -> u8 {
+        self.bytes::<1>()[0]
+    }
+
+    pub fn flag(&mut self) -> bool {
+        self.byte() != 0
+    }
+
+    pub fn flags<const N: usize>(&mut self) -> [bool; N] {
+        self.bytes::<N>().map(|value| value != 0)
+    }
+
+    pub fn word(&mut self) -> u16 {
+        u16::from_be_bytes(self.bytes())
+    }
+
+    pub fn long(&mut self) -> u32 {
+        u32::from_be_bytes(self.bytes())
+    }
+
+    pub fn quad(&mut self) -> u64 {
+        u64::from_be_bytes(self.bytes())
+    }
+}
+
+// A device decoded at base, with its registers stride bytes apart on one
+// byte lane and its interrupt request wired to an IPL level (0 for none)
+pub struct Mapping {
+    pub base: u32,
+    pub stride: u32,
+    pub level: u8,
+    pub device: RefCell<Box<dyn Device>>,
+}
+
+impl Mapping {
+    pub fn new(base: u32, stride: u32, level: u8, device: Box<dyn Device>) -> Self {
+        Self {
+            base,
+            stride: stride.max(1),
+            level,
+            device: RefCell::new(device),
+        }
+    }
+
+    // Bytes from base covered by the registers
+    pub fn span(&self) -> u32 {
+        self.device.borrow().registers() * self.stride
+    }
+
+    pub fn contains(&self, address: u32) -> bool {
+        address.wrapping_sub(self.base) < self.span()
+    }
+
+    // Register at address, None for the other byte lanes inside the span
+    fn register(&self, address: u32) -> Option<u32> {
+        let offset = address.wrapping_sub(self.base);
+        offset
+            .is_multiple_of(self.stride)
+            .then_some(offset / self.stride)
+    }
+
+    // Reads a byte inside the span; the other byte lanes float high
+    pub fn read(&self, address: u32) -> u8 {
+        match self.register(address) {
+            Some(register) => self.device.borrow_mut().read(register),
+            None => 0xFF,
+        }
+    }
+
+    pub fn write(&self, address: u32, value: u8) {
+        if let Some(register) = self.register(address) {
+            self.device.borrow_mut().write(register, value);
+        }
+    }
+
+    fn requesting(&self) -> bool {
+        self.level > 0 && self.device.borrow().interrupt()
+    }
+}
+
+// Highest IPL level asserted by the devices
+pub fn interrupt_level(devices: &[Mapping]) -> u8 {
+    devices
+        .iter()
+        .filter(|mapping| mapping.requesting())
+        .map(|mapping| mapping.level)
+        .max()
+        .unwrap_or(0)
+}
+
+// Runs the interrupt acknowledge cycle for level: the first device
+// requesting on it supplies a vector or asks for the autovector
+pub fn acknowledge(devices: &[Mapping], level: u8) -> u8 {
+    match devices
+        .iter()
+        .find(|mapping| mapping.level == level && mapping.requesting())
+    {
+        Some(mapping) => mapping
+            .device
+            .borrow_mut()
+            .acknowledge()
+            .unwrap_or(AUTOVECTOR + level - 1),
+        None => SPURIOUS_INTERRUPT,
+    }
+}
@@ -0,0 +1,178 @@
+use crate::device::console::Console;
+use crate::device::{Device, StateReader, StateWriter};
+
+// Register numbers, selected by RS: status and control share one, receive
+// and transmit data the other
+const STATUS_CONTROL: u32 = 0;
+const DATA: u32 = 1;
+
+// Status register bits
+const RDRF: u8 = 0x01;
+const TDRE: u8 = 0x02;
+const IRQ: u8 = 0x80;
+
+// Control register fields
+const COUNTER_DIVIDE: u8 = 0x03;
+const MASTER_RESET: u8 = 0x03;
+const TRANSMIT_CONTROL: u8 = 0x60;
+const TRANSMIT_INTERRUPT: u8 = 0x20;
+const RECEIVE_INTERRUPT: u8 = 0x80;
+
+// MC6850 asynchronous communications interface adapter. Characters go to
+// and come from a host console as soon as the guest is ready for them, so
+// the baud rate and word format set in the control register do not matter.
+pub struct Acia {
+    console: Console,
+    control: u8,
+    receive: u8,
+    // The receive data register holds a character not yet read
+    full: bool,
+}
+
+impl Acia {
+    pub fn new(console: Console) -> Self {
+        Self {
+            console,
+            control: MASTER_RESET,
+            receive: 0,
+            full: false,
+        }
+    }
+
+    // Held in master reset until the guest sets another counter divide
+    fn in_reset(&self) -> bool {
+        self.control & COUNTER_DIVIDE == MASTER_RESET
+    }
+
+    fn status(&self) -> u8 {
+        if self.in_reset() {
+            return 0;
+        }
+        let mut status = TDRE;
+        if self.full {
+            status |= RDRF;
+        }
+        if self.interrupt() {
+            status |= IRQ;
+        }
+        status
+    }
+}
+
+impl Device for Acia {
+    fn name(&self) -> &str {
+        "MC6850 ACIA"
+    }
+
+    fn registers(&self) -> u32 {
+        2
+    }
+
+    fn read(&mut self, register: u32) -> u8 {
+        match register {
+            STATUS_CONTROL => self.status(),
+            DATA => {
+                self.full = false;
+                self.receive
+            }
+            _ => 0xFF,
+        }
+    }
+
+    fn write(&mut self, register: u32, value: u8) {
+        match register {
+            STATUS_CONTROL => {
+                self.control = value;
+                if self.in_reset() {
+                    self.full = false;
+                }
+            }
+            DATA if !self.in_reset() => self.console.write(value),
+            _ => {}
+        }
+    }
+
+    // Fetches the next host character once the last one has been read
+    fn tick(&mut self, _cycles: u32) {
+        if self.full || self.in_reset() {
+            return;
+        }
+        if let Some(byte) = self.console.read() {
+            self.receive = byte;
+            self.full = true;
+        }
+    }
+
+    fn interrupt(&self) -> bool {
+        if self.in_reset() {
+            return false;
+        }
+        let receive = self.control & RECEIVE_INTERRUPT != 0 && self.full;
+        let transmit = self.control & TRANSMIT_CONTROL == TRANSMIT_INTERRUPT;
+        receive || transmit
+    }
+
+    fn describe(&self) -> String {
+        self.console.name().to_string()
+    }
+
+    fn save_state(&self) -> Vec<u8> {
+        let mut state = StateWriter::default();
+        state.byte(self.control).byte(self.receive).flag(self.full);
+        state.data
+    }
+
+    fn restore_state(&mut self, state: &[u8]) {
+        let mut state = StateReader::new(state);
+        self.control = state.byte();
+        self.receive = state.byte();
+        self.full = state.flag();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn acia() -> Acia {
+        Acia::new(Console::open("none").unwrap())
+    }
+
+    #[test]
+    fn status_is_clear_in_master_reset() {
+        let mut acia = acia();
+        assert_eq!(acia.read(STATUS_CONTROL), 0);
+        acia.write(STATUS_CONTROL, 0x15);
+        assert_eq!(acia.read(STATUS_CONTROL), TDRE);
+        acia.write(STATUS_CONTROL, MASTER_RESET);
+        assert_eq!(acia.read(STATUS_CONTROL), 0);
+    }
+
+    #[test]
+    fn received_characters_set_rdrf_until_read() {
+        let mut acia = acia();
+        acia.write(STATUS_CONTROL, 0x15);
+        acia.receive = b'A';
+        acia.full = true;
+        assert_eq!(acia.read(STATUS_CONTROL), TDRE | RDRF);
+        assert!(!acia.interrupt());
+        assert_eq!(acia.read(DATA), b'A');
+        assert_eq!(acia.read(STATUS_CONTROL), TDRE);
+    }
+
+    #[test]
+    fn interrupts_follow_the_enable_bits() {
+        let mut acia = acia();
+        acia.write(STATUS_CONTROL, RECEIVE_INTERRUPT | 0x15);
+        assert!(!acia.interrupt());
+        acia.full = true;
+        assert!(acia.interrupt());
+        assert_eq!(acia.read(STATUS_CONTROL), IRQ | TDRE | RDRF);
+        acia.read(DATA);
+        assert!(!acia.interrupt());
+
+        acia.write(STATUS_CONTROL, TRANSMIT_INTERRUPT | 0x15);
+        assert!(acia.interrupt());
+        assert_eq!(acia.read(STATUS_CONTROL), IRQ | TDRE);
+    }
+}
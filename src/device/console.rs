@@ -0,0 +1,199 @@
+use std::fs::File;
+use std::io::{self, ErrorKind, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+// How long reader threads wait before retrying a stream with nothing to read
+const RETRY_DELAY: Duration = Duration::from_millis(10);
+
+// Host end of a serial line: bytes typed on the host arrive through a reader
+// thread, so a device can poll for them without blocking the emulator
+pub struct Console {
+    input: Option<Receiver<u8>>,
+    output: Output,
+    // Reads stdin on first poll, so the debugger keeps it until then
+    lazy_stdin: bool,
+    name: String,
+}
+
+enum Output {
+    Stdout,
+    File(File),
+    // The connected client, if any; output is dropped while nobody listens
+    Tcp(Arc<Mutex<Option<TcpStream>>>),
+    None,
+}
+
+impl Console {
+    // Opens a console from its command line form: stdio, pty, tcp:PORT
+    // (listening on localhost) or none
+    pub fn open(spec: &str) -> Result<Self, String> {
+        match spec {
+            "stdio" => Ok(Self {
+                input: None,
+                output: Output::Stdout,
+                lazy_stdin: true,
+                name: "stdio".to_string(),
+            }),
+            "pty" => pty(),
+            "none" => Ok(Self {
+                input: None,
+                output: Output::None,
+                lazy_stdin: false,
+                name: "none".to_string(),
+            }),
+            _ => match spec.strip_prefix("tcp:").map(str::parse::<u16>) {
+                Some(Ok(port)) => tcp(port),
+                _ => Err(format!(
+                    "Unknown console {}, use stdio, pty, tcp:PORT or none",
+                    spec
+                )),
+            },
+        }
+    }
+
+    // Next byte from the host, if one has arrived
+    pub fn read(&mut self) -> Option<u8> {
+        if self.lazy_stdin {
+            self.lazy_stdin = false;
+            self.input = Some(spawn_reader(io::stdin()));
+        }
+        self.input.as_ref()?.try_recv().ok()
+    }
+
+    pub fn write(&mut self, byte: u8) {
+        let _ = match &mut self.output {
+            Output::Stdout => {
+                let mut out = io::stdout();
+                out.write_all(&[byte]).and_then(|_| out.flush())
+            }
+            Output::File(file) => file.write_all(&[byte]),
+            Output::Tcp(client) => match client.lock().ok().as_mut().and_then(|c| c.as_mut()) {
+                Some(stream) => stream.write_all(&[byte]),
+                None => Ok(()),
+            },
+            Output::None => Ok(()),
+        };
+    }
+
+    // Where the console is connected, such as the PTY to open
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+}
+
+// Forwards everything read from source to the returned channel, waiting
+// and retrying while the source has nothing or no peer
+fn spawn_reader<R: Read + Send + 'static>(mut source: R) -> Receiver<u8> {
+    let (sender, receiver) = mpsc::channel();
+    thread::spawn(move || {
+        let mut buffer = [0; 256];
+        loop {
+            match source.read(&mut buffer) {
+                Ok(0) => return,
+                Ok(count) => {
+                    if !forward(&sender, &buffer[..count]) {
+                        return;
+                    }
+                }
+                Err(e) if e.kind() == ErrorKind::Interrupted => {}
+                Err(_) => thread::sleep(RETRY_DELAY),
+            }
+        }
+    });
+    receiver
+}
+
+// False once the console is gone
+fn forward(sender: &Sender<u8>, bytes: &[u8]) -> bool {
+    bytes.iter().all(|byte| sender.send(*byte).is_ok())
+}
+
+// Accepts one client at a time on localhost:port, taking the next when the
+// current one disconnects
+fn tcp(port: u16) -> Result<Console, String> {
+    let listener =
+        TcpListener::bind(("127.0.0.1", port)).map_err(|e| format!("Port {}: {}", port, e))?;
+    let client: Arc<Mutex<Option<TcpStream>>> = Arc::new(Mutex::new(None));
+    let (sender, receiver) = mpsc::channel();
+    let shared = Arc::clone(&client);
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            let Ok(mut stream) = stream else { continue };
+            let _ = stream.set_nodelay(true);
+            if let Ok(mut slot) = shared.lock() {
+                *slot = stream.try_clone().ok();
+            }
+            let mut buffer = [0; 256];
+            while let Ok(count @ 1..) = stream.read(&mut buffer) {
+                if !forward(&sender, &buffer[..count]) {
+                    return;
+                }
+            }
+            if let Ok(mut slot) = shared.lock() {
+                *slot = None;
+            }
+        }
+    });
+    Ok(Console {
+        input: Some(receiver),
+        output: Output::Tcp(client),
+        lazy_stdin: false,
+        name: format!("tcp:{}", port),
+    })
+}
+
+// A pseudo-terminal whose slave side a terminal program such as screen or
+// minicom can open. The master is non-blocking, so output written while no
+// program has the slave open is dropped instead of stalling the emulator.
+#[cfg(target_os = "linux")]
+fn pty() -> Result<Console, String> {
+    use std::ffi::CStr;
+    use std::os::raw::{c_char, c_int};
+    use std::os::unix::fs::OpenOptionsExt;
+    use std::os::unix::io::AsRawFd;
+
+    const O_NOCTTY: i32 = 0o400;
+    const O_NONBLOCK: i32 = 0o4000;
+
+    extern "C" {
+        fn grantpt(fd: c_int) -> c_int;
+        fn unlockpt(fd: c_int) -> c_int;
+        fn ptsname(fd: c_int) -> *const c_char;
+    }
+
+    let master = File::options()
+        .read(true)
+        .write(true)
+        .custom_flags(O_NOCTTY | O_NONBLOCK)
+        .open("/dev/ptmx")
+        .map_err(|e| format!("/dev/ptmx: {}", e))?;
+    let fd = master.as_raw_fd();
+    // SAFETY: fd is an open PTY master, and ptsname's result is copied
+    // before any other call could overwrite it
+    let name = unsafe {
+        if grantpt(fd) != 0 || unlockpt(fd) != 0 {
+            return Err(format!("Cannot unlock PTY: {}", io::Error::last_os_error()));
+        }
+        let name = ptsname(fd);
+        if name.is_null() {
+            return Err(format!("Cannot name PTY: {}", io::Error::last_os_error()));
+        }
+        CStr::from_ptr(name).to_string_lossy().into_owned()
+    };
+    let reader = master.try_clone().map_err(|e| e.to_string())?;
+    Ok(Console {
+        input: Some(spawn_reader(reader)),
+        output: Output::File(master),
+        lazy_stdin: false,
+        name,
+    })
+}
+
+#[cfg(not(target_os = "linux"))]
+fn pty() -> Result<Console, String> {
+    Err("PTY consoles are only supported on Linux".to_string())
+}
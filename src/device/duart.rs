@@ -0,0 +1,533 @@
+use std::collections::VecDeque;
+
+use crate::device::console::Console;
+use crate::device::{Device, StateReader, StateWriter};
+
+// Frequency of the X1/CLK crystal the counter/timer and baud rates run from
+const CRYSTAL: u64 = 3_686_400;
+// Depth of each receiver FIFO
+const FIFO_DEPTH: usize = 3;
+
+// Registers, as read / as written
+const MR_A: u32 = 0;
+const SR_CSR_A: u32 = 1;
+const CR_A: u32 = 2;
+const RHR_THR_A: u32 = 3;
+const IPCR_ACR: u32 = 4;
+const ISR_IMR: u32 = 5;
+const CUR_CTUR: u32 = 6;
+const CLR_CTLR: u32 = 7;
+const MR_B: u32 = 8;
+const SR_CSR_B: u32 = 9;
+const CR_B: u32 = 10;
+const RHR_THR_B: u32 = 11;
+const IVR: u32 = 12;
+const IP_OPCR: u32 = 13;
+// Start counter command / set output port bits
+const START_SOPR: u32 = 14;
+// Stop counter command / reset output port bits
+const STOP_ROPR: u32 = 15;
+
+// Status register bits
+const RXRDY: u8 = 0x01;
+const FFULL: u8 = 0x02;
+const TXRDY: u8 = 0x04;
+const TXEMT: u8 = 0x08;
+
+// Interrupt status bits; channel B's are channel A's shifted left by 4
+const ISR_TXRDY: u8 = 0x01;
+const ISR_RXRDY: u8 = 0x02;
+const ISR_COUNTER: u8 = 0x08;
+
+// MR1 bit choosing FFULL instead of RxRDY as the receiver interrupt
+const RX_INTERRUPT_FFULL: u8 = 0x40;
+
+// Vector the IVR holds after reset, the uninitialized interrupt vector
+const RESET_VECTOR: u8 = 0x0F;
+
+// One serial channel, bridged to a host console
+struct Channel {
+    console: Console,
+    mr1: u8,
+    mr2: u8,
+    // MR reads and writes go to MR2 after the first one
+    mr2_selected: bool,
+    csr: u8,
+    fifo: VecDeque<u8>,
+    receiving: bool,
+    transmitting: bool,
+}
+
+impl Channel {
+    fn new(console: Console) -> Self {
+        Self {
+            console,
+            mr1: 0,
+            mr2: 0,
+            mr2_selected: false,
+            csr: 0,
+            fifo: VecDeque::new(),
+            receiving: false,
+            transmitting: false,
+        }
+    }
+
+    fn reset(&mut self) {
+        self.mr2_selected = false;
+        self.fifo.clear();
+        self.receiving = false;
+        self.transmitting = false;
+    }
+
+    fn read_mode(&mut self) -> u8 {
+        let mode = if self.mr2_selected {
+            self.mr2
+        } else {
+            self.mr1
+        };
+        self.mr2_selected = true;
+        mode
+    }
+
+    fn write_mode(&mut self, value: u8) {
+        if self.mr2_selected {
+            self.mr2 = value;
+        } else {
+            self.mr1 = value;
+        }
+        self.mr2_selected = true;
+    }
+
+    fn command(&mut self, value: u8) {
+        match value & 0x03 {
+            0x01 => self.receiving = true,
+            0x02 => self.receiving = false,
+            _ => {}
+        }
+        match value & 0x0C {
+            0x04 => self.transmitting = true,
+            0x08 => self.transmitting = false,
+            _ => {}
+        }
+        match value >> 4 & 7 {
+            1 => self.mr2_selected = false,
+            2 => {
+                self.receiving = false;
+                self.fifo.clear();
+            }
+            3 => self.transmitting = false,
+            _ => {}
+        }
+    }
+
+    // Characters are sent the moment they are written, so the transmitter
+    // is always ready and empty while enabled
+    fn status(&self) -> u8 {
+        let mut status = 0;
+        if !self.fifo.is_empty() {
+            status |= RXRDY;
+        }
+        if self.fifo.len() == FIFO_DEPTH {
+            status |= FFULL;
+        }
+        if self.transmitting {
+            status |= TXRDY | TXEMT;
+        }
+        status
+    }
+
+    // This channel's interrupt status bits, as channel A's
+    fn interrupts(&self) -> u8 {
+        let status = self.status();
+        let mut isr = 0;
+        if status & TXRDY != 0 {
+            isr |= ISR_TXRDY;
+        }
+        let receiver = if self.mr1 & RX_INTERRUPT_FFULL != 0 {
+            FFULL
+        } else {
+            RXRDY
+        };
+        if status & receiver != 0 {
+            isr |= ISR_RXRDY;
+        }
+        isr
+    }
+
+    fn receive(&mut self) -> u8 {
+        self.fifo.pop_front().unwrap_or(0)
+    }
+
+    fn transmit(&mut self, value: u8) {
+        if self.transmitting {
+            self.console.write(value);
+        }
+    }
+
+    // Moves host input into the FIFO while the receiver is on and has room
+    fn poll(&mut self) {
+        while self.receiving && self.fifo.len() < FIFO_DEPTH {
+            match self.console.read() {
+                Some(byte) => self.fifo.push_back(byte),
+                None => break,
+            }
+        }
+    }
+}
+
+// MC68681 dual asynchronous receiver/transmitter: two serial channels, a
+// 16-bit counter/timer, input and output ports, and one vectored interrupt
+// output. Characters move as soon as the guest is ready for them, so the
+// baud rates set in the CSRs do not matter; the counter/timer runs from the
+// crystal, converted from CPU clock cycles.
+pub struct Duart {
+    channels: [Channel; 2],
+    cpu_clock: u64,
+    // Crystal cycles owed, in units of 1/cpu_clock
+    clock_fraction: u64,
+    // Crystal cycles not yet counted by a divide-by-16 source
+    prescaler: u64,
+    acr: u8,
+    imr: u8,
+    ivr: u8,
+    opcr: u8,
+    opr: u8,
+    preload: u16,
+    counter: u16,
+    // Counter mode counts only between start and stop commands
+    counting: bool,
+    // Timer mode square wave output level
+    output: bool,
+    counter_ready: bool,
+}
+
+impl Duart {
+    pub fn new(channel_a: Console, channel_b: Console, cpu_clock: u32) -> Self {
+        Self {
+            channels: [Channel::new(channel_a), Channel::new(channel_b)],
+            cpu_clock: cpu_clock.max(1) as u64,
+            clock_fraction: 0,
+            prescaler: 0,
+            acr: 0,
+            imr: 0,
+            ivr: RESET_VECTOR,
+            opcr: 0,
+            opr: 0,
+            preload: 0,
+            counter: 0,
+            counting: false,
+            output: true,
+            counter_ready: false,
+        }
+    }
+
+    fn timer_mode(&self) -> bool {
+        self.acr & 0x40 != 0
+    }
+
+    fn isr(&self) -> u8 {
+        let mut isr = self.channels[0].interrupts() | self.channels[1].interrupts() << 4;
+        if self.counter_ready {
+            isr |= ISR_COUNTER;
+        }
+        isr
+    }
+
+    // Crystal cycles counted by the counter/timer source in ACR bits 4-6;
+    // the IP2, TxCA and TxCB sources have no clock attached
+    fn source_cycles(&mut self, crystal: u64) -> u64 {
+        match self.acr >> 4 & 7 {
+            3 | 7 => {
+                self.prescaler += crystal;
+                let cycles = self.prescaler / 16;
+                self.prescaler %= 16;
+                cycles
+            }
+            6 => crystal,
+            _ => 0,
+        }
+    }
+
+    fn run_counter(&mut self, mut cycles: u64) {
+        if self.timer_mode() {
+            // The square wave toggles each time the count runs out and the
+            // preload is reloaded; each full cycle sets counter ready
+            let period = match self.preload {
+                0 => 0x1_0000,
+                preload => preload as u64,
+            };
+            let mut remaining = match self.counter {
+                0 => period,
+                counter => counter as u64,
+            };
+            while cycles >= remaining {
+                cycles -= remaining;
+                remaining = period;
+                self.output = !self.output;
+                if self.output {
+                    self.counter_ready = true;
+                }
+            }
+            self.counter = (remaining - cycles) as u16;
+        } else if self.counting {
+            // Counts down through zero to $FFFF, flagging terminal count
+            if cycles >= self.counter as u64 {
+                self.counter_ready = true;
+            }
+            self.counter = self.counter.wrapping_sub(cycles as u16);
+        }
+    }
+
+    fn start_counter(&mut self) {
+        self.counter = self.preload;
+        self.counting = true;
+    }
+
+    fn stop_counter(&mut self) {
+        self.counter_ready = false;
+        if !self.timer_mode() {
+            self.counting = false;
+        }
+    }
+}
+
+impl Device for Duart {
+    fn name(&self) -> &str {
+        "MC68681 DUART"
+    }
+
+    fn registers(&self) -> u32 {
+        16
+    }
+
+    fn read(&mut self, register: u32) -> u8 {
+        match register {
+            MR_A => self.channels[0].read_mode(),
+            MR_B => self.channels[1].read_mode(),
+            SR_CSR_A => self.channels[0].status(),
+            SR_CSR_B => self.channels[1].status(),
+            RHR_THR_A => self.channels[0].receive(),
+            RHR_THR_B => self.channels[1].receive(),
+            // Nothing drives the input port, whose pins float high
+            IPCR_ACR => 0x0F,
+            ISR_IMR => self.isr(),
+            CUR_CTUR => (self.counter >> 8) as u8,
+            CLR_CTLR => self.counter as u8,
+            IVR => self.ivr,
+            IP_OPCR => 0xFF,
+            START_SOPR => {
+                self.start_counter();
+                0xFF
+            }
+            STOP_ROPR => {
+                self.stop_counter();
+                0xFF
+            }
+            _ => 0xFF,
+        }
+    }
+
+    fn write(&mut self, register: u32, value: u8) {
+        match register {
+            MR_A => self.channels[0].write_mode(value),
+            MR_B => self.channels[1].write_mode(value),
+            SR_CSR_A => self.channels[0].csr = value,
+            SR_CSR_B => self.channels[1].csr = value,
+            CR_A => self.channels[0].command(value),
+            CR_B => self.channels[1].command(value),
+            RHR_THR_A => self.channels[0].transmit(value),
+            RHR_THR_B => self.channels[1].transmit(value),
+            IPCR_ACR => {
+                let was_timer = self.timer_mode();
+                self.acr = value;
+                if self.timer_mode() && !was_timer {
+                    self.start_counter();
+                }
+            }
+            ISR_IMR => self.imr = value,
+            CUR_CTUR => self.preload = self.preload & 0x00FF | (value as u16) << 8,
+            CLR_CTLR => self.preload = self.preload & 0xFF00 | value as u16,
+            IVR => self.ivr = value,
+            IP_OPCR => self.opcr = value,
+            START_SOPR => self.opr |= value,
+            STOP_ROPR => self.opr &= !value,
+            _ => {}
+        }
+    }
+
+    fn tick(&mut self, cycles: u32) {
+        for channel in &mut self.channels {
+            channel.poll();
+        }
+        self.clock_fraction += cycles as u64 * CRYSTAL;
+        let crystal = self.clock_fraction / self.cpu_clock;
+        self.clock_fraction %= self.cpu_clock;
+        let cycles = self.source_cycles(crystal);
+        if cycles > 0 {
+            self.run_counter(cycles);
+        }
+    }
+
+    fn interrupt(&self) -> bool {
+        self.isr() & self.imr != 0
+    }
+
+    fn acknowledge(&mut self) -> Option<u8> {
+        Some(self.ivr)
+    }
+
+    fn reset(&mut self) {
+        for channel in &mut self.channels {
+            channel.reset();
+        }
+        self.acr = 0;
+        self.imr = 0;
+        self.ivr = RESET_VECTOR;
+        self.opcr = 0;
+        self.opr = 0;
+        self.counting = false;
+        self.output = true;
+        self.counter_ready = false;
+    }
+
+    fn describe(&self) -> String {
+        format!(
+            "A: {}  B: {}",
+            self.channels[0].console.name(),
+            self.channels[1].console.name()
+        )
+    }
+
+    fn save_state(&self) -> Vec<u8> {
+        let mut state = StateWriter::default();
+        for channel in &self.channels {
+            // The FIFO padded to its full depth, after its length
+            let mut fifo = [0; FIFO_DEPTH];
+            for (slot, byte) in fifo.iter_mut().zip(&channel.fifo) {
+                *slot = *byte;
+            }
+            state
+                .byte(channel.mr1)
+                .byte(channel.mr2)
+                .flag(channel.mr2_selected)
+                .byte(channel.csr)
+                .byte(channel.fifo.len() as u8)
+                .bytes(&fifo)
+                .flag(channel.receiving)
+                .flag(channel.transmitting);
+        }
+        state
+            .quad(self.clock_fraction)
+            .quad(self.prescaler)
+            .byte(self.acr)
+            .byte(self.imr)
+            .byte(self.ivr)
+            .byte(self.opcr)
+            .byte(self.opr)
+            .word(self.preload)
+            .word(self.counter)
+            .flag(self.counting)
+            .flag(self.output)
+            .flag(self.counter_ready);
+        state.data
+    }
+
+    fn restore_state(&mut self, state: &[u8]) {
+        let mut state = StateReader::new(state);
+        for channel in &mut self.channels {
+            channel.mr1 = state.byte();
+            channel.mr2 = state.byte();
+            channel.mr2_selected = state.flag();
+            channel.csr = state.byte();
+            let length = (state.byte() as usize).min(FIFO_DEPTH);
+            let fifo = state.bytes::<FIFO_DEPTH>();
+            channel.fifo = fifo[..length].iter().copied().collect();
+            channel.receiving = state.flag();
+            channel.transmitting = state.flag();
+        }
+        self.clock_fraction = state.quad();
+        self.prescaler = state.quad();
+        self.acr = state.byte();
+        self.imr = state.byte();
+        self.ivr = state.byte();
+        self.opcr = state.byte();
+        self.opr = state.byte();
+        self.preload = state.word();
+        self.counter = state.word();
+        self.counting = state.flag();
+        self.output = state.flag();
+        self.counter_ready = state.flag();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Clocked from the crystal itself, so CPU cycles are crystal cycles
+    fn duart() -> Duart {
+        let none = || Console::open("none").unwrap();
+        Duart::new(none(), none(), CRYSTAL as u32)
+    }
+
+    #[test]
+    fn timer_sets_counter_ready_each_full_period() {
+        let mut duart = duart();
+        duart.write(CUR_CTUR, 0);
+        duart.write(CLR_CTLR, 100);
+        duart.write(IPCR_ACR, 0x60);
+        duart.tick(150);
+        assert_eq!(duart.read(ISR_IMR) & ISR_COUNTER, 0);
+        duart.tick(50);
+        assert_eq!(duart.read(ISR_IMR) & ISR_COUNTER, ISR_COUNTER);
+        // Stop clears the flag but leaves the timer running
+        duart.read(STOP_ROPR);
+        assert_eq!(duart.read(ISR_IMR) & ISR_COUNTER, 0);
+        duart.tick(200);
+        assert_eq!(duart.read(ISR_IMR) & ISR_COUNTER, ISR_COUNTER);
+    }
+
+    #[test]
+    fn counter_counts_down_between_start_and_stop() {
+        let mut duart = duart();
+        duart.write(CUR_CTUR, 0);
+        duart.write(CLR_CTLR, 10);
+        // Counter mode, crystal divided by 16
+        duart.write(IPCR_ACR, 0x30);
+        duart.tick(160);
+        assert_eq!(duart.read(CLR_CTLR), 0);
+        duart.read(START_SOPR);
+        duart.tick(80);
+        assert_eq!((duart.read(CUR_CTUR), duart.read(CLR_CTLR)), (0, 5));
+        assert_eq!(duart.read(ISR_IMR) & ISR_COUNTER, 0);
+        duart.tick(96);
+        assert_eq!((duart.read(CUR_CTUR), duart.read(CLR_CTLR)), (0xFF, 0xFF));
+        assert_eq!(duart.read(ISR_IMR) & ISR_COUNTER, ISR_COUNTER);
+        duart.read(STOP_ROPR);
+        duart.tick(160);
+        assert_eq!(duart.read(CLR_CTLR), 0xFF);
+        assert_eq!(duart.read(ISR_IMR) & ISR_COUNTER, 0);
+    }
+
+    #[test]
+    fn masked_sources_interrupt_with_the_ivr() {
+        let mut duart = duart();
+        assert_eq!(duart.acknowledge(), Some(RESET_VECTOR));
+        duart.write(IVR, 0x45);
+        // Transmitter A ready, but masked
+        duart.write(CR_A, 0x04);
+        assert_eq!(duart.read(ISR_IMR), ISR_TXRDY);
+        assert!(!duart.interrupt());
+        duart.write(ISR_IMR, ISR_COUNTER);
+        assert!(!duart.interrupt());
+        duart.write(ISR_IMR, ISR_TXRDY);
+        assert!(duart.interrupt());
+        assert_eq!(duart.acknowledge(), Some(0x45));
+        // Channel B's bits sit in the upper half
+        duart.write(CR_B, 0x04);
+        assert_eq!(duart.read(ISR_IMR), ISR_TXRDY | ISR_TXRDY << 4);
+        duart.reset();
+        assert!(!duart.interrupt());
+        assert_eq!(duart.acknowledge(), Some(RESET_VECTOR));
+    }
+}
@@ -0,0 +1,554 @@
+use crate::device::console::Console;
+use crate::device::{Device, StateReader, StateWriter};
+
+// Frequency of the timer clock input, the usual 2.4576 MHz crystal
+const TIMER_CLOCK: u64 = 2_457_600;
+
+// Registers
+const GPDR: u32 = 0;
+const AER: u32 = 1;
+const DDR: u32 = 2;
+const IERA: u32 = 3;
+const IERB: u32 = 4;
+const IPRA: u32 = 5;
+const IPRB: u32 = 6;
+const ISRA: u32 = 7;
+const ISRB: u32 = 8;
+const IMRA: u32 = 9;
+const IMRB: u32 = 10;
+const VR: u32 = 11;
+const TACR: u32 = 12;
+const TBCR: u32 = 13;
+const TCDCR: u32 = 14;
+const TADR: u32 = 15;
+const TBDR: u32 = 16;
+const TCDR: u32 = 17;
+const TDDR: u32 = 18;
+const SCR: u32 = 19;
+const UCR: u32 = 20;
+const RSR: u32 = 21;
+const TSR: u32 = 22;
+const UDR: u32 = 23;
+
+// Interrupt channels, 15 the highest priority
+const TIMER_D: usize = 4;
+const TIMER_C: usize = 5;
+const TIMER_B: usize = 8;
+const TRANSMIT_EMPTY: usize = 10;
+const RECEIVE_FULL: usize = 12;
+const TIMER_A: usize = 13;
+// Channel of each GPIP line
+const GPIP_CHANNELS: [usize; 8] = [0, 1, 2, 3, 6, 7, 14, 15];
+// Timers A to D by channel
+const TIMER_CHANNELS: [usize; 4] = [TIMER_A, TIMER_B, TIMER_C, TIMER_D];
+
+// Vector register bit selecting software end-of-interrupt, which keeps
+// lower priority channels blocked until the handler clears its ISR bit
+const SOFTWARE_EOI: u8 = 0x08;
+
+// Timer control modes: 0 stops, 1-7 are delay mode with a prescaler, 8 is
+// event count mode and 9-15 pulse width mode with the same prescalers
+const EVENT_MODE: u8 = 8;
+const PRESCALERS: [u64; 7] = [4, 10, 16, 50, 64, 100, 200];
+
+// USART receiver and transmitter status bits
+const BUFFER_FULL: u8 = 0x80;
+const RECEIVER_ENABLE: u8 = 0x01;
+const BUFFER_EMPTY: u8 = 0x80;
+const END: u8 = 0x10;
+const TRANSMITTER_ENABLE: u8 = 0x01;
+
+// One of the four 8-bit down counters
+#[derive(Default)]
+struct Timer {
+    mode: u8,
+    data: u8,
+    // Count left before the next time-out, 1 to 256
+    counter: u16,
+    // Timer clocks not yet divided down by the prescaler
+    prescaled: u64,
+    // Level of the TAI or TBI input, for event count and pulse width modes
+    input: bool,
+}
+
+impl Timer {
+    fn period(&self) -> u16 {
+        match self.data {
+            0 => 256,
+            data => data as u16,
+        }
+    }
+
+    fn set_mode(&mut self, mode: u8) {
+        if self.mode == 0 && mode != 0 {
+            self.prescaled = 0;
+        }
+        self.mode = mode;
+    }
+
+    // Writing the data register of a stopped timer loads the counter too
+    fn set_data(&mut self, data: u8) {
+        self.data = data;
+        if self.mode == 0 {
+            self.counter = self.period();
+        }
+    }
+
+    // Runs for clocks timer clock cycles with the input at its active level
+    // or not, returning the number of time-outs
+    fn run(&mut self, clocks: u64, active: bool) -> u64 {
+        let prescaler = match self.mode {
+            1..=7 => PRESCALERS[self.mode as usize - 1],
+            9..=15 if active => PRESCALERS[self.mode as usize - 9],
+            _ => return 0,
+        };
+        self.prescaled += clocks;
+        let counts = self.prescaled / prescaler;
+        self.prescaled %= prescaler;
+        self.count(counts)
+    }
+
+    // Counts down by counts, reloading from the data register at each
+    // time-out
+    fn count(&mut self, counts: u64) -> u64 {
+        let counter = self.counter.max(1) as u64;
+        if counts < counter {
+            self.counter = (counter - counts) as u16;
+            return 0;
+        }
+        let period = self.period() as u64;
+        let after = counts - counter;
+        self.counter = (period - after % period) as u16;
+        1 + after / period
+    }
+}
+
+// MC68901 multi-function peripheral: eight GPIP lines, four timers, a USART
+// bridged to a host console and a 16 channel prioritised interrupt
+// controller supplying its own vectors. Timers run from the timer clock,
+// converted from CPU cycles. The USART moves characters as soon as the guest
+// is ready for them, whatever the baud rate timer D sets.
+pub struct Mfp {
+    console: Console,
+    cpu_clock: u64,
+    // Timer clock cycles owed, in units of 1/cpu_clock
+    clock_fraction: u64,
+    gpdr: u8,
+    aer: u8,
+    ddr: u8,
+    // Levels driven onto the GPIP lines from outside; unconnected lines
+    // float high
+    inputs: u8,
+    // One bit per channel, as IERA:IERB and so on
+    enabled: u16,
+    pending: u16,
+    in_service: u16,
+    masked: u16,
+    vr: u8,
+    timers: [Timer; 4],
+    scr: u8,
+    ucr: u8,
+    rsr: u8,
+    tsr: u8,
+    receive: u8,
+}
+
+impl Mfp {
+    pub fn new(console: Console, cpu_clock: u32) -> Self {
+        Self {
+            console,
+            cpu_clock: cpu_clock.max(1) as u64,
+            clock_fraction: 0,
+            gpdr: 0,
+            aer: 0,
+            ddr: 0,
+            inputs: 0xFF,
+            enabled: 0,
+            pending: 0,
+            in_service: 0,
+            masked: 0,
+            vr: 0,
+            timers: Default::default(),
+            scr: 0,
+            ucr: 0,
+            rsr: 0,
+            tsr: 0,
+            receive: 0,
+        }
+    }
+
+    // Drives the GPIP input lines. A line configured as an input requests
+    // an interrupt when it changes to the level its AER bit selects: 0 for
+    // a falling edge, 1 for a rising one.
+    pub fn set_inputs(&mut self, inputs: u8) {
+        let changed = (self.inputs ^ inputs) & !self.ddr;
+        let triggered = changed & !(inputs ^ self.aer);
+        self.inputs = inputs;
+        for (line, channel) in GPIP_CHANNELS.iter().enumerate() {
+            if triggered & 1 << line != 0 {
+                self.request(*channel);
+            }
+        }
+    }
+
+    // Drives the TAI (timer 0) or TBI (timer 1) input. Event count mode
+    // counts changes to the active level, which AER bits 4 and 3 select,
+    // and pulse width mode counts while the input is at it.
+    pub fn set_timer_input(&mut self, timer: usize, level: bool) {
+        let Some(active) = self.timer_active_level(timer) else {
+            return;
+        };
+        let state = &mut self.timers[timer];
+        let edge = level != state.input && level == active;
+        state.input = level;
+        if edge && state.mode == EVENT_MODE && state.count(1) > 0 {
+            self.request(TIMER_CHANNELS[timer]);
+        }
+    }
+
+    fn timer_active_level(&self, timer: usize) -> Option<bool> {
+        match timer {
+            0 => Some(self.aer & 0x10 != 0),
+            1 => Some(self.aer & 0x08 != 0),
+            _ => None,
+        }
+    }
+
+    // An event on channel, which becomes pending if the channel is enabled
+    fn request(&mut self, channel: usize) {
+        self.pending |= self.enabled & 1 << channel;
+    }
+
+    // The highest priority channel pending, unmasked and above every
+    // channel in service
+    fn highest_request(&self) -> Option<usize> {
+        let requests = self.pending & self.masked;
+        if requests == 0 {
+            return None;
+        }
+        let channel = 15 - requests.leading_zeros() as usize;
+        let blocked = match self.in_service {
+            0 => false,
+            in_service => channel <= 15 - in_service.leading_zeros() as usize,
+        };
+        (!blocked).then_some(channel)
+    }
+
+    fn write_control(&mut self, timer: usize, mode: u8) {
+        self.timers[timer].set_mode(mode & 0x0F);
+    }
+
+    // Characters go out as soon as they are written, so the buffer is
+    // always empty, and the transmitter has ended while disabled
+    fn transmitter_status(&self) -> u8 {
+        let mut status = self.tsr | BUFFER_EMPTY;
+        if self.tsr & TRANSMITTER_ENABLE == 0 {
+            status |= END;
+        }
+        status
+    }
+}
+
+impl Device for Mfp {
+    fn name(&self) -> &str {
+        "MC68901 MFP"
+    }
+
+    fn registers(&self) -> u32 {
+        24
+    }
+
+    fn read(&mut self, register: u32) -> u8 {
+        match register {
+            GPDR => self.gpdr & self.ddr | self.inputs & !self.ddr,
+            AER => self.aer,
+            DDR => self.ddr,
+            IERA => (self.enabled >> 8) as u8,
+            IERB => self.enabled as u8,
+            IPRA => (self.pending >> 8) as u8,
+            IPRB => self.pending as u8,
+            ISRA => (self.in_service >> 8) as u8,
+            ISRB => self.in_service as u8,
+            IMRA => (self.masked >> 8) as u8,
+            IMRB => self.masked as u8,
+            VR => self.vr,
+            TACR => self.timers[0].mode,
+            TBCR => self.timers[1].mode,
+            TCDCR => self.timers[2].mode << 4 | self.timers[3].mode,
+            TADR => self.timers[0].counter as u8,
+            TBDR => self.timers[1].counter as u8,
+            TCDR => self.timers[2].counter as u8,
+            TDDR => self.timers[3].counter as u8,
+            SCR => self.scr,
+            UCR => self.ucr,
+            RSR => self.rsr,
+            TSR => self.transmitter_status(),
+            UDR => {
+                self.rsr &= !BUFFER_FULL;
+                self.receive
+            }
+            _ => 0xFF,
+        }
+    }
+
+    fn write(&mut self, register: u32, value: u8) {
+        let value16 = value as u16;
+        match register {
+            GPDR => self.gpdr = value,
+            AER => self.aer = value,
+            DDR => self.ddr = value,
+            // Disabling a channel also clears its pending bit
+            IERA => {
+                self.enabled = self.enabled & 0x00FF | value16 << 8;
+                self.pending &= self.enabled;
+            }
+            IERB => {
+                self.enabled = self.enabled & 0xFF00 | value16;
+                self.pending &= self.enabled;
+            }
+            // Writing zeros to the pending and in-service registers clears
+            // those bits and leaves the rest
+            IPRA => self.pending &= value16 << 8 | 0x00FF,
+            IPRB => self.pending &= 0xFF00 | value16,
+            ISRA => self.in_service &= value16 << 8 | 0x00FF,
+            ISRB => self.in_service &= 0xFF00 | value16,
+            IMRA => self.masked = self.masked & 0x00FF | value16 << 8,
+            IMRB => self.masked = self.masked & 0xFF00 | value16,
+            VR => {
+                self.vr = value;
+                if value & SOFTWARE_EOI == 0 {
+                    self.in_service = 0;
+                }
+            }
+            TACR => self.write_control(0, value),
+            TBCR => self.write_control(1, value),
+            TCDCR => {
+                self.write_control(2, value >> 4 & 7);
+                self.write_control(3, value & 7);
+            }
+            TADR => self.timers[0].set_data(value),
+            TBDR => self.timers[1].set_data(value),
+            TCDR => self.timers[2].set_data(value),
+            TDDR => self.timers[3].set_data(value),
+            SCR => self.scr = value,
+            UCR => self.ucr = value,
+            RSR => self.rsr = self.rsr & BUFFER_FULL | value & 0x3F,
+            TSR => self.tsr = value & 0x0F,
+            UDR if self.tsr & TRANSMITTER_ENABLE != 0 => {
+                self.console.write(value);
+                self.request(TRANSMIT_EMPTY);
+            }
+            _ => {}
+        }
+    }
+
+    fn tick(&mut self, cycles: u32) {
+        if self.rsr & (RECEIVER_ENABLE | BUFFER_FULL) == RECEIVER_ENABLE {
+            if let Some(byte) = self.console.read() {
+                self.receive = byte;
+                self.rsr |= BUFFER_FULL;
+                self.request(RECEIVE_FULL);
+            }
+        }
+
+        self.clock_fraction += cycles as u64 * TIMER_CLOCK;
+        let clocks = self.clock_fraction / self.cpu_clock;
+        self.clock_fraction %= self.cpu_clock;
+        for (timer, channel) in TIMER_CHANNELS.iter().enumerate() {
+            let active = self.timer_active_level(timer) == Some(self.timers[timer].input);
+            if self.timers[timer].run(clocks, active) > 0 {
+                self.request(*channel);
+            }
+        }
+    }
+
+    fn interrupt(&self) -> bool {
+        self.highest_request().is_some()
+    }
+
+    // The acknowledged channel stops pending, and stays in service until
+    // the handler clears it when software end-of-interrupt is on
+    fn acknowledge(&mut self) -> Option<u8> {
+        let channel = self.highest_request()?;
+        self.pending &= !(1 << channel);
+        if self.vr & SOFTWARE_EOI != 0 {
+            self.in_service |= 1 << channel;
+        }
+        Some(self.vr & 0xF0 | channel as u8)
+    }
+
+    // Clears every register but the timer data and USART data registers,
+    // stopping the timers and the USART
+    fn reset(&mut self) {
+        self.gpdr = 0;
+        self.aer = 0;
+        self.ddr = 0;
+        self.enabled = 0;
+        self.pending = 0;
+        self.in_service = 0;
+        self.masked = 0;
+        self.vr = 0;
+        for timer in &mut self.timers {
+            timer.mode = 0;
+        }
+        self.scr = 0;
+        self.ucr = 0;
+        self.rsr = 0;
+        self.tsr = 0;
+    }
+
+    fn describe(&self) -> String {
+        self.console.name().to_string()
+    }
+
+    fn save_state(&self) -> Vec<u8> {
+        let mut state = StateWriter::default();
+        state
+            .quad(self.clock_fraction)
+            .byte(self.gpdr)
+            .byte(self.aer)
+            .byte(self.ddr)
+            .byte(self.inputs)
+            .word(self.enabled)
+            .word(self.pending)
+            .word(self.in_service)
+            .word(self.masked)
+            .byte(self.vr);
+        for timer in &self.timers {
+            state
+                .byte(timer.mode)
+                .byte(timer.data)
+                .word(timer.counter)
+                .quad(timer.prescaled)
+                .flag(timer.input);
+        }
+        state
+            .byte(self.scr)
+            .byte(self.ucr)
+            .byte(self.rsr)
+            .byte(self.tsr)
+            .byte(self.receive);
+        state.data
+    }
+
+    fn restore_state(&mut self, state: &[u8]) {
+        let mut state = StateReader::new(state);
+        self.clock_fraction = state.quad();
+        self.gpdr = state.byte();
+        self.aer = state.byte();
+        self.ddr = state.byte();
+        self.inputs = state.byte();
+        self.enabled = state.word();
+        self.pending = state.word();
+        self.in_service = state.word();
+        self.masked = state.word();
+        self.vr = state.byte();
+        for timer in &mut self.timers {
+            timer.mode = state.byte();
+            timer.data = state.byte();
+            timer.counter = state.word();
+            timer.prescaled = state.quad();
+            timer.input = state.flag();
+        }
+        self.scr = state.byte();
+        self.ucr = state.byte();
+        self.rsr = state.byte();
+        self.tsr = state.byte();
+        self.receive = state.byte();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Clocked from the timer clock itself, so CPU cycles are timer clocks
+    fn mfp() -> Mfp {
+        Mfp::new(Console::open("none").unwrap(), TIMER_CLOCK as u32)
+    }
+
+    #[test]
+    fn delay_mode_times_out_after_data_times_prescaler() {
+        let mut mfp = mfp();
+        mfp.write(IERA, 0x20);
+        mfp.write(IMRA, 0x20);
+        mfp.write(VR, 0x40);
+        mfp.write(TADR, 10);
+        // Divide by 4
+        mfp.write(TACR, 1);
+        mfp.tick(36);
+        assert_eq!(mfp.read(TADR), 1);
+        mfp.tick(3);
+        assert!(!mfp.interrupt());
+        mfp.tick(1);
+        assert!(mfp.interrupt());
+        assert_eq!(mfp.read(IPRA), 0x20);
+        // Reloaded from the data register
+        assert_eq!(mfp.read(TADR), 10);
+        assert_eq!(mfp.acknowledge(), Some(0x40 | TIMER_A as u8));
+        assert!(!mfp.interrupt());
+        // Stopping holds the count
+        mfp.write(TACR, 0);
+        mfp.tick(100);
+        assert_eq!(mfp.read(TADR), 10);
+        assert_eq!(mfp.read(IPRA), 0);
+    }
+
+    // Times out timers A and B together
+    fn both_timers(mfp: &mut Mfp) {
+        mfp.write(TADR, 1);
+        mfp.write(TBDR, 1);
+        mfp.write(TACR, 1);
+        mfp.write(TBCR, 1);
+        mfp.tick(4);
+        mfp.write(TACR, 0);
+        mfp.write(TBCR, 0);
+    }
+
+    #[test]
+    fn highest_channel_is_acknowledged_first() {
+        let mut mfp = mfp();
+        mfp.write(IERA, 0x21);
+        mfp.write(IMRA, 0x21);
+        mfp.write(VR, 0x40);
+        both_timers(&mut mfp);
+        assert_eq!(mfp.read(IPRA), 0x21);
+        assert_eq!(mfp.acknowledge(), Some(0x40 | TIMER_A as u8));
+        assert_eq!(mfp.acknowledge(), Some(0x40 | TIMER_B as u8));
+        assert_eq!(mfp.acknowledge(), None);
+        // Masked channels stay pending without interrupting
+        mfp.write(IMRA, 0x00);
+        both_timers(&mut mfp);
+        assert!(!mfp.interrupt());
+        assert_eq!(mfp.read(IPRA), 0x21);
+        // Disabling a channel drops its pending request
+        mfp.write(IERA, 0x20);
+        assert_eq!(mfp.read(IPRA), 0x20);
+    }
+
+    #[test]
+    fn in_service_channels_block_lower_priorities() {
+        let mut mfp = mfp();
+        mfp.write(IERA, 0x21);
+        mfp.write(IMRA, 0x21);
+        mfp.write(VR, 0x40 | SOFTWARE_EOI);
+        both_timers(&mut mfp);
+        assert_eq!(mfp.acknowledge(), Some(0x40 | TIMER_A as u8));
+        assert_eq!(mfp.read(ISRA), 0x20);
+        assert!(!mfp.interrupt());
+        // The handler clears its in-service bit
+        mfp.write(ISRA, !0x20);
+        assert!(mfp.interrupt());
+        assert_eq!(mfp.acknowledge(), Some(0x40 | TIMER_B as u8));
+        assert_eq!(mfp.read(ISRA), 0x01);
+        // A higher priority channel still gets through
+        mfp.write(TADR, 1);
+        mfp.write(TACR, 1);
+        mfp.tick(4);
+        assert_eq!(mfp.acknowledge(), Some(0x40 | TIMER_A as u8));
+        assert_eq!(mfp.read(ISRA), 0x21);
+        // Leaving software end-of-interrupt clears every in-service bit
+        mfp.write(VR, 0x40);
+        assert_eq!(mfp.read(ISRA), 0);
+    }
+}
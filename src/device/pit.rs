@@ -0,0 +1,476 @@
+use crate::device::{Device, StateReader, StateWriter};
+
+// Registers; the gaps are null registers that read as zero
+const PGCR: u32 = 0x00;
+const PSRR: u32 = 0x01;
+const PADDR: u32 = 0x02;
+const PBDDR: u32 = 0x03;
+const PCDDR: u32 = 0x04;
+const PIVR: u32 = 0x05;
+const PACR: u32 = 0x06;
+const PBCR: u32 = 0x07;
+const PADR: u32 = 0x08;
+const PBDR: u32 = 0x09;
+const PAAR: u32 = 0x0A;
+const PBAR: u32 = 0x0B;
+const PCDR: u32 = 0x0C;
+const PSR: u32 = 0x0D;
+const TCR: u32 = 0x10;
+const TIVR: u32 = 0x11;
+const CPRH: u32 = 0x13;
+const CPRM: u32 = 0x14;
+const CPRL: u32 = 0x15;
+const CNTRH: u32 = 0x17;
+const CNTRM: u32 = 0x18;
+const CNTRL: u32 = 0x19;
+const TSR: u32 = 0x1A;
+
+// Port general control bits enabling the H1/H2 and H3/H4 handshake pairs
+const H12_ENABLE: u8 = 0x10;
+const H34_ENABLE: u8 = 0x20;
+// Port A/B control bits enabling the H1/H3 and H2/H4 interrupts
+const ODD_INTERRUPT: u8 = 0x02;
+const EVEN_INTERRUPT: u8 = 0x04;
+// Port A/B submodes, from control bits 6-7; the others are bit I/O
+const DOUBLE_BUFFERED_INPUT: u8 = 0;
+const DOUBLE_BUFFERED_OUTPUT: u8 = 1;
+// Port service request bits: PC5 is PIRQ, PC6 is PIACK
+const PIRQ: u8 = 0x08;
+const PIACK: u8 = 0x10;
+// Order in which the handshake status bits H1S to H4S are serviced, for
+// each port interrupt priority setting in PSRR bits 0-2
+const PRIORITIES: [[usize; 4]; 8] = [
+    [0, 1, 2, 3],
+    [1, 0, 2, 3],
+    [0, 1, 3, 2],
+    [1, 0, 3, 2],
+    [2, 3, 0, 1],
+    [3, 2, 0, 1],
+    [2, 3, 1, 0],
+    [3, 2, 1, 0],
+];
+
+// Timer control fields
+const TIMER_ENABLE: u8 = 0x01;
+const CLOCK_CONTROL: u8 = 0x06;
+const ROLLOVER: u8 = 0x10;
+const TOUT_CONTROL: u8 = 0xE0;
+// TOUT/TIACK settings with the timer interrupt enabled, vectored through
+// TIACK or autovectored
+const VECTORED_INTERRUPT: u8 = 0xA0;
+const AUTOVECTORED_INTERRUPT: u8 = 0xE0;
+// Settings where TOUT is a square wave
+const SQUARE_WAVE: [u8; 2] = [0x40, 0x60];
+// Timer status bit set at zero detect
+const ZERO_DETECT: u8 = 0x01;
+// The prescaler divides CLK by 32
+const PRESCALER: u64 = 32;
+
+// MC68230 parallel interface/timer: ports A, B and C, four handshake lines
+// and a 24-bit timer, with separate port and timer interrupt requests on one
+// IPL level. Ports A and B each follow their own submode whatever the PGCR
+// mode: H1 and H3 latch input or acknowledge output in the double-buffered
+// submodes, and every handshake line is an edge input setting its status
+// bit. CLK is taken to be the CPU clock. Nothing drives the port pins, which
+// float high, or the timer input TIN, so only the CLK/32 timer source runs.
+pub struct Pit {
+    pgcr: u8,
+    psrr: u8,
+    ddr: [u8; 3],
+    pivr: u8,
+    control: [u8; 2],
+    // Output latches of ports A, B and C
+    latch: [u8; 3],
+    // Levels on the port pins from outside
+    pins: [u8; 3],
+    // Ports A and B as latched by H1 and H3 in double-buffered input
+    input: [u8; 2],
+    // Levels on H1 to H4, and their status bits H1S to H4S
+    handshake: [bool; 4],
+    status: [bool; 4],
+    tcr: u8,
+    tivr: u8,
+    preload: u32,
+    counter: u32,
+    // CLK cycles not yet divided by the prescaler
+    prescaled: u64,
+    zero_detect: bool,
+    // TOUT level when it is a square wave
+    tout: bool,
+}
+
+impl Pit {
+    pub fn new() -> Self {
+        Self {
+            pgcr: 0,
+            psrr: 0,
+            ddr: [0; 3],
+            pivr: 0x0F,
+            control: [0; 2],
+            latch: [0; 3],
+            pins: [0xFF; 3],
+            input: [0xFF; 2],
+            handshake: [true; 4],
+            status: [false; 4],
+            tcr: 0,
+            tivr: 0x0F,
+            preload: 0,
+            counter: 0,
+            prescaled: 0,
+            zero_detect: false,
+            tout: true,
+        }
+    }
+
+    // Drives the pins of port 0 (A), 1 (B) or 2 (C)
+    pub fn set_port(&mut self, port: usize, pins: u8) {
+        if let Some(levels) = self.pins.get_mut(port) {
+            *levels = pins;
+        }
+    }
+
+    // Drives H1 to H4 (0 to 3). An enabled handshake pin sets its status
+    // bit when it changes to the level its PGCR sense bit asserts.
+    pub fn set_handshake(&mut self, line: usize, level: bool) {
+        if line > 3 {
+            return;
+        }
+        let asserted = self.pgcr & 1 << line != 0;
+        let enabled = self.pgcr & if line < 2 { H12_ENABLE } else { H34_ENABLE } != 0;
+        if enabled && level != self.handshake[line] && level == asserted {
+            self.status[line] = true;
+            if line.is_multiple_of(2) && self.submode(line / 2) == DOUBLE_BUFFERED_INPUT {
+                self.input[line / 2] = self.pins[line / 2];
+            }
+        }
+        self.handshake[line] = level;
+    }
+
+    fn submode(&self, port: usize) -> u8 {
+        self.control[port] >> 6
+    }
+
+    fn port(&self, port: usize) -> u8 {
+        self.latch[port] & self.ddr[port] | self.pins[port] & !self.ddr[port]
+    }
+
+    // Port A or B data: input bits come from the latch in double-buffered
+    // input, where reading frees the latch for the next H1 or H3 strobe
+    fn read_data(&mut self, port: usize) -> u8 {
+        if self.submode(port) != DOUBLE_BUFFERED_INPUT {
+            return self.port(port);
+        }
+        self.status[port * 2] = false;
+        self.latch[port] & self.ddr[port] | self.input[port] & !self.ddr[port]
+    }
+
+    // In double-buffered output the status bit shows an empty buffer, until
+    // the peripheral acknowledges the new data on H1 or H3
+    fn write_data(&mut self, port: usize, value: u8) {
+        self.latch[port] = value;
+        if self.submode(port) == DOUBLE_BUFFERED_OUTPUT {
+            self.status[port * 2] = false;
+        }
+    }
+
+    // Handshake status bit whose interrupt is enabled and first in the
+    // PSRR priority order, if port interrupts are on
+    fn port_request(&self) -> Option<usize> {
+        if self.psrr & PIRQ == 0 {
+            return None;
+        }
+        PRIORITIES[self.psrr as usize & 7]
+            .iter()
+            .copied()
+            .find(|line| {
+                let enable = if line.is_multiple_of(2) {
+                    ODD_INTERRUPT
+                } else {
+                    EVEN_INTERRUPT
+                };
+                self.status[*line] && self.control[line / 2] & enable != 0
+            })
+    }
+
+    fn timer_request(&self) -> bool {
+        let tout = self.tcr & TOUT_CONTROL;
+        self.zero_detect && (tout == VECTORED_INTERRUPT || tout == AUTOVECTORED_INTERRUPT)
+    }
+
+    fn psr(&self) -> u8 {
+        (0..4).fold(0, |psr, line| {
+            psr | (self.handshake[line] as u8) << (line + 4) | (self.status[line] as u8) << line
+        })
+    }
+
+    fn set_preload(&mut self, shift: u32, value: u8) {
+        self.preload = self.preload & !(0xFF << shift) | (value as u32) << shift;
+    }
+
+    fn set_tcr(&mut self, value: u8) {
+        let starting = self.tcr & TIMER_ENABLE == 0 && value & TIMER_ENABLE != 0;
+        self.tcr = value;
+        if starting {
+            self.counter = self.preload;
+            self.prescaled = 0;
+        }
+        if value & TIMER_ENABLE == 0 {
+            self.tout = true;
+        }
+    }
+
+    // Counts down by counts. Reaching zero is a zero detect, and the count
+    // after it reloads the preload or rolls over to $FFFFFF.
+    fn count(&mut self, mut counts: u64) {
+        while counts > 0 {
+            if self.counter == 0 {
+                self.counter = if self.tcr & ROLLOVER != 0 {
+                    0xFF_FFFF
+                } else {
+                    self.preload
+                };
+                counts -= 1;
+                // A zero preload holds the counter at zero
+                if self.counter == 0 {
+                    return;
+                }
+                continue;
+            }
+            let step = counts.min(self.counter as u64);
+            self.counter -= step as u32;
+            counts -= step;
+            if self.counter == 0 {
+                self.zero_detect = true;
+                self.tout = !self.tout;
+            }
+        }
+    }
+}
+
+impl Default for Pit {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Device for Pit {
+    fn name(&self) -> &str {
+        "MC68230 PI/T"
+    }
+
+    fn registers(&self) -> u32 {
+        32
+    }
+
+    fn read(&mut self, register: u32) -> u8 {
+        match register {
+            PGCR => self.pgcr,
+            PSRR => self.psrr,
+            PADDR => self.ddr[0],
+            PBDDR => self.ddr[1],
+            PCDDR => self.ddr[2],
+            PIVR => self.pivr,
+            PACR => self.control[0],
+            PBCR => self.control[1],
+            PADR => self.read_data(0),
+            PBDR => self.read_data(1),
+            PAAR => self.pins[0],
+            PBAR => self.pins[1],
+            PCDR => {
+                let pins = self.port(2);
+                // PC3 shows TOUT when it is a square wave
+                match SQUARE_WAVE.contains(&(self.tcr & TOUT_CONTROL)) {
+                    true => pins & !0x08 | (self.tout as u8) << 3,
+                    false => pins,
+                }
+            }
+            PSR => self.psr(),
+            TCR => self.tcr,
+            TIVR => self.tivr,
+            CPRH => (self.preload >> 16) as u8,
+            CPRM => (self.preload >> 8) as u8,
+            CPRL => self.preload as u8,
+            CNTRH => (self.counter >> 16) as u8,
+            CNTRM => (self.counter >> 8) as u8,
+            CNTRL => self.counter as u8,
+            TSR => self.zero_detect as u8,
+            _ => 0,
+        }
+    }
+
+    fn write(&mut self, register: u32, value: u8) {
+        match register {
+            PGCR => self.pgcr = value,
+            PSRR => self.psrr = value & 0x7F,
+            PADDR => self.ddr[0] = value,
+            PBDDR => self.ddr[1] = value,
+            PCDDR => self.ddr[2] = value,
+            // The low two bits come from the interrupting source
+            PIVR => self.pivr = value & 0xFC,
+            PACR => self.control[0] = value,
+            PBCR => self.control[1] = value,
+            PADR => self.write_data(0, value),
+            PBDR => self.write_data(1, value),
+            PCDR => self.latch[2] = value,
+            // Writing ones clears the handshake status bits
+            PSR => {
+                for line in 0..4 {
+                    if value & 1 << line != 0 {
+                        self.status[line] = false;
+                    }
+                }
+            }
+            TCR => self.set_tcr(value),
+            TIVR => self.tivr = value,
+            CPRH => self.set_preload(16, value),
+            CPRM => self.set_preload(8, value),
+            CPRL => self.set_preload(0, value),
+            TSR if value & ZERO_DETECT != 0 => self.zero_detect = false,
+            _ => {}
+        }
+    }
+
+    fn tick(&mut self, cycles: u32) {
+        if self.tcr & TIMER_ENABLE == 0 || self.tcr & CLOCK_CONTROL != 0 {
+            return;
+        }
+        self.prescaled += cycles as u64;
+        let counts = self.prescaled / PRESCALER;
+        self.prescaled %= PRESCALER;
+        self.count(counts);
+    }
+
+    fn interrupt(&self) -> bool {
+        self.timer_request() || self.port_request().is_some()
+    }
+
+    // The timer answers first when both request. A vector comes from TIVR
+    // or PIVR when TIACK or PIACK is wired up, otherwise the CPU autovectors.
+    fn acknowledge(&mut self) -> Option<u8> {
+        if self.timer_request() {
+            return (self.tcr & TOUT_CONTROL == VECTORED_INTERRUPT).then_some(self.tivr);
+        }
+        let line = self.port_request()?;
+        (self.psrr & PIACK != 0).then_some(self.pivr | line as u8)
+    }
+
+    fn reset(&mut self) {
+        *self = Self {
+            preload: self.preload,
+            counter: self.counter,
+            latch: self.latch,
+            pins: self.pins,
+            input: self.input,
+            handshake: self.handshake,
+            ..Self::new()
+        };
+    }
+
+    fn save_state(&self) -> Vec<u8> {
+        let mut state = StateWriter::default();
+        state
+            .byte(self.pgcr)
+            .byte(self.psrr)
+            .bytes(&self.ddr)
+            .byte(self.pivr)
+            .bytes(&self.control)
+            .bytes(&self.latch)
+            .bytes(&self.pins)
+            .bytes(&self.input)
+            .flags(&self.handshake)
+            .flags(&self.status)
+            .byte(self.tcr)
+            .byte(self.tivr)
+            .long(self.preload)
+            .long(self.counter)
+            .quad(self.prescaled)
+            .flag(self.zero_detect)
+            .flag(self.tout);
+        state.data
+    }
+
+    fn restore_state(&mut self, state: &[u8]) {
+        let mut state = StateReader::new(state);
+        self.pgcr = state.byte();
+        self.psrr = state.byte();
+        self.ddr = state.bytes();
+        self.pivr = state.byte();
+        self.control = state.bytes();
+        self.latch = state.bytes();
+        self.pins = state.bytes();
+        self.input = state.bytes();
+        self.handshake = state.flags();
+        self.status = state.flags();
+        self.tcr = state.byte();
+        self.tivr = state.byte();
+        self.preload = state.long();
+        self.counter = state.long();
+        self.prescaled = state.quad();
+        self.zero_detect = state.flag();
+        self.tout = state.flag();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn counter(pit: &mut Pit) -> u32 {
+        (pit.read(CNTRH) as u32) << 16 | (pit.read(CNTRM) as u32) << 8 | pit.read(CNTRL) as u32
+    }
+
+    // A running timer loaded with preload, counting CLK/32
+    fn timer(preload: u32, tcr: u8) -> Pit {
+        let mut pit = Pit::new();
+        pit.write(CPRH, (preload >> 16) as u8);
+        pit.write(CPRM, (preload >> 8) as u8);
+        pit.write(CPRL, preload as u8);
+        pit.write(TIVR, 0x48);
+        pit.write(TCR, tcr);
+        pit
+    }
+
+    #[test]
+    fn counts_down_all_24_bits_to_a_vectored_zero_detect() {
+        let mut pit = timer(0x01_2345, VECTORED_INTERRUPT | TIMER_ENABLE);
+        assert_eq!(counter(&mut pit), 0x01_2345);
+        pit.tick(31);
+        assert_eq!(counter(&mut pit), 0x01_2345);
+        pit.tick(1 + 32 * 0x01_2343);
+        assert_eq!(counter(&mut pit), 1);
+        assert_eq!(pit.read(TSR), 0);
+        assert!(!pit.interrupt());
+        pit.tick(32);
+        assert_eq!(counter(&mut pit), 0);
+        assert_eq!(pit.read(TSR), ZERO_DETECT);
+        assert!(pit.interrupt());
+        assert_eq!(pit.acknowledge(), Some(0x48));
+        // The next count reloads the preload
+        pit.tick(32);
+        assert_eq!(counter(&mut pit), 0x01_2345);
+        pit.write(TSR, ZERO_DETECT);
+        assert!(!pit.interrupt());
+    }
+
+    #[test]
+    fn rollover_and_autovectored_interrupts() {
+        let mut pit = timer(2, AUTOVECTORED_INTERRUPT | ROLLOVER | TIMER_ENABLE);
+        pit.tick(64);
+        assert!(pit.interrupt());
+        assert_eq!(pit.acknowledge(), None);
+        pit.tick(32);
+        assert_eq!(counter(&mut pit), 0xFF_FFFF);
+    }
+
+    #[test]
+    fn zero_detect_without_an_interrupt_setting_only_sets_status() {
+        let mut pit = timer(1, TIMER_ENABLE);
+        pit.tick(32);
+        assert_eq!(pit.read(TSR), ZERO_DETECT);
+        assert!(!pit.interrupt());
+        // Stopped, the timer holds its count
+        pit.write(TCR, 0);
+        pit.tick(320);
+        assert_eq!(counter(&mut pit), 0);
+    }
+}
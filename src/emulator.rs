@@ -0,0 +1,1544 @@
+use crate::cpu::{AccessKind, CPUState, StatusRegister, CPU};
+use crate::decoder::disassemble;
+use crate::instruction::{
+    ControlRegister, Direction, IndexRegister, Instructions, Registers, Size, Target,
+};
+use crate::memory::Memory;
+
+// Exception vector numbers
+const BUS_ERROR: u8 = 2;
+const ADDRESS_ERROR: u8 = 3;
+const ILLEGAL_INSTRUCTION: u8 = 4;
+const ZERO_DIVIDE: u8 = 5;
+const CHK_INSTRUCTION: u8 = 6;
+const TRAPV_INSTRUCTION: u8 = 7;
+const PRIVILEGE_VIOLATION: u8 = 8;
+const TRACE: u8 = 9;
+const LINE_A: u8 = 10;
+const LINE_F: u8 = 11;
+const TRAP_BASE: u8 = 32;
+
+// Clock cycles of exception processing, from the 68000 timing tables
+const ACCESS_ERROR_CYCLES: u64 = 50;
+const INTERRUPT_CYCLES: u64 = 44;
+
+// Why an instruction did not complete
+enum Exception {
+    // Bus or address error on the access to address (group 0)
+    Access(u8, u32, AccessKind),
+    // Taken with the address of the instruction stacked: illegal, line A,
+    // line F and privilege violation
+    Fault(u8),
+    // Taken with the address of the next instruction stacked: TRAP, TRAPV,
+    // CHK and divide by zero
+    Trap(u8),
+}
+
+type Exec<T> = Result<T, Exception>;
+
+// Where an operand lives once its effective address has been calculated
+#[derive(Debug, Clone, Copy)]
+enum Location {
+    // Registers::get number: D0-D7 are 0-7, A0-A7 are 8-15
+    Register(usize),
+    Memory(u32),
+    Value(u32),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Shift {
+    Asl,
+    Asr,
+    Lsl,
+    Lsr,
+    Rol,
+    Ror,
+    Roxl,
+    Roxr,
+}
+
+impl CPU {
+    // Executes the instruction at PC, or the exception processing it causes,
+    // and adds its clock cycles to the cycle counter. Returns the instruction.
+    // Errors are what the 68000 cannot recover from: an exception whose
+    // vector is not set, or a double fault, which halts the CPU.
+    pub fn step(&mut self) -> Result<Instructions, String> {
+        let pc = self.registers.PC;
+        match self.state {
+            CPUState::Halting => return Err(format!("CPU halted at ${:08X}", pc)),
+            // Waits for an interrupt, which take_interrupt delivers
+            CPUState::Stopped => {
+                self.cycles += 4;
+                return Ok(Instructions::STOP(self.registers.SR.to_word()));
+            }
+            _ => {}
+        }
+        let tracing = self.registers.SR.trace_mode;
+        if pc & 1 != 0 {
+            self.take(
+                Exception::Access(ADDRESS_ERROR, pc, AccessKind::Read),
+                pc,
+                0,
+            )?;
+            return Ok(Instructions::NotImplemented);
+        }
+        let Some(opcode) = self.memory_bus.memory.read_at_address_word(pc) else {
+            self.take(Exception::Access(BUS_ERROR, pc, AccessKind::Read), pc, 0)?;
+            return Ok(Instructions::NotImplemented);
+        };
+        let (ins, next) = disassemble(&mut self.memory_bus.memory, pc);
+        self.registers.PC = next;
+        match self.execute(&ins, pc, next, opcode) {
+            Ok(cycles) => {
+                self.cycles += cycles as u64;
+                if tracing {
+                    self.exception(TRACE, self.registers.PC, None)?;
+                    self.cycles += 34;
+                }
+            }
+            Err(exception) => self.take(exception, pc, opcode)?,
+        }
+        Ok(ins)
+    }
+
+    // Exception processing for an interrupt at level: stacks the PC and SR,
+    // enters supervisor mode with the mask raised to level and jumps through
+    // the vector from the acknowledge cycle. Ends a STOP.
+    pub fn interrupt(&mut self, level: u8) -> Result<(), String> {
+        let vector = self.memory_bus.acknowledge(level);
+        self.exception(vector, self.registers.PC, None)?;
+        self.registers.SR.interrupt_mask = level;
+        self.nmi_taken = level == 7;
+        self.cycles += INTERRUPT_CYCLES;
+        Ok(())
+    }
+
+    // Loads SR, switching between the user and supervisor stack pointers
+    // when the S bit changes
+    pub fn set_sr(&mut self, word: u16) {
+        let supervisor = word & 0x2000 != 0;
+        if supervisor != self.registers.SR.supervisor_state {
+            let r = &mut self.registers;
+            std::mem::swap(&mut r.SP, &mut r.ALT_SP);
+        }
+        self.registers.SR = StatusRegister::from_word(word);
+    }
+
+    // Runs exception processing for an instruction that stopped with
+    // exception, pc and opcode being its address and first word
+    fn take(&mut self, exception: Exception, pc: u32, opcode: u16) -> Result<(), String> {
+        let next = self.registers.PC;
+        let (vector, stacked, access, cycles) = match exception {
+            Exception::Access(vector, address, kind) => (
+                vector,
+                next,
+                Some((address, kind, opcode)),
+                ACCESS_ERROR_CYCLES,
+            ),
+            Exception::Fault(vector) => (vector, pc, None, 34),
+            Exception::Trap(vector) => {
+                let cycles = match vector {
+                    CHK_INSTRUCTION => 40,
+                    ZERO_DIVIDE => 38,
+                    _ => 34,
+                };
+                (vector, next, None, cycles)
+            }
+        };
+        if let Err(e) = self.exception(vector, stacked, access) {
+            // Leave the PC on the instruction that caused it
+            self.registers.PC = pc;
+            return Err(e);
+        }
+        self.cycles += cycles;
+        Ok(())
+    }
+
+    // Stacks pc and SR on the supervisor stack, plus the access details for
+    // bus and address errors, and jumps through vector. A vector that is not
+    // set is reported rather than taken, leaving the CPU as it was; a fault
+    // while stacking halts the CPU.
+    fn exception(
+        &mut self,
+        vector: u8,
+        pc: u32,
+        access: Option<(u32, AccessKind, u16)>,
+    ) -> Result<(), String> {
+        let handler = match self
+            .memory_bus
+            .read_at_address_long(self.vbr.wrapping_add(vector as u32 * 4))
+        {
+            Some(0) => {
+                return Err(format!(
+                    "Unhandled {} at ${:08X}: vector {} is not set",
+                    exception_name(vector),
+                    pc,
+                    vector
+                ))
+            }
+            Some(handler) if handler & 1 == 0 => handler,
+            _ => {
+                self.state = CPUState::Halting;
+                return Err(format!(
+                    "Double fault: bad vector {} for {} at ${:08X}, CPU halted",
+                    vector,
+                    exception_name(vector),
+                    pc
+                ));
+            }
+        };
+        let sr = self.registers.SR.to_word();
+        let supervisor = self.registers.SR.supervisor_state;
+        self.set_sr(sr & !0x8000 | 0x2000);
+        let mut stacked = self
+            .push(pc, Size::Long)
+            .and_then(|_| self.push(sr as u32, Size::Word));
+        if let Some((address, kind, opcode)) = access {
+            // Read/write, instruction/not and the function code of the access
+            let function = if supervisor { 5 } else { 1 };
+            let status = ((kind == AccessKind::Read) as u32) << 4 | function;
+            stacked = stacked
+                .and_then(|_| self.push(opcode as u32, Size::Word))
+                .and_then(|_| self.push(address, Size::Long))
+                .and_then(|_| self.push(status, Size::Word));
+        }
+        if stacked.is_err() {
+            self.state = CPUState::Halting;
+            return Err(format!(
+                "Double fault: stack at ${:08X} unusable for {} at ${:08X}, CPU halted",
+                self.registers.SP,
+                exception_name(vector),
+                pc
+            ));
+        }
+        self.registers.PC = handler;
+        self.state = CPUState::Fetching;
+        Ok(())
+    }
+
+    // Executes ins, decoded from pc with the PC already at next, and returns
+    // its clock cycles
+    fn execute(&mut self, ins: &Instructions, pc: u32, next: u32, opcode: u16) -> Exec<u32> {
+        // PC-relative operands count from their extension word, which
+        // follows the opcode unless a bit number or register mask comes first
+        let base = pc.wrapping_add(2);
+        match ins {
+            Instructions::ABCD(..)
+            | Instructions::SBCD(..)
+            | Instructions::ADD(..)
+            | Instructions::SUB(..)
+            | Instructions::AND(..)
+            | Instructions::OR(..)
+            | Instructions::EOR(..)
+            | Instructions::ADDA(..)
+            | Instructions::SUBA(..)
+            | Instructions::ADDI(..)
+            | Instructions::SUBI(..)
+            | Instructions::ANDI(..)
+            | Instructions::ORI(..)
+            | Instructions::EORI(..)
+            | Instructions::ADDQ(..)
+            | Instructions::SUBQ(..)
+            | Instructions::ADDX(..)
+            | Instructions::SUBX(..)
+            | Instructions::CLR(..)
+            | Instructions::NEG(..)
+            | Instructions::NEGX(..)
+            | Instructions::NOT(..)
+            | Instructions::CMP(..)
+            | Instructions::CMPA(..)
+            | Instructions::CMPI(..)
+            | Instructions::CMPM(..)
+            | Instructions::DIVUW(..)
+            | Instructions::DIVSW(..)
+            | Instructions::EXT(..)
+            | Instructions::MULUW(..)
+            | Instructions::MULSW(..)
+            | Instructions::NBCD(..)
+            | Instructions::TST(..) => self.arithmetic(ins, base),
+            Instructions::ASL(..)
+            | Instructions::ASR(..)
+            | Instructions::LSL(..)
+            | Instructions::LSR(..)
+            | Instructions::ROL(..)
+            | Instructions::ROR(..)
+            | Instructions::ROXL(..)
+            | Instructions::ROXR(..) => self.shift_rotate(ins, base),
+            Instructions::BTST(..)
+            | Instructions::BCHG(..)
+            | Instructions::BCLR(..)
+            | Instructions::BSET(..)
+            | Instructions::TAS(..) => self.bit_manipulation(ins, base),
+            Instructions::EXG(..)
+            | Instructions::MOVE(..)
+            | Instructions::MOVEA(..)
+            | Instructions::MOVEM(..)
+            | Instructions::MOVEP(..)
+            | Instructions::MOVEQ(..)
+            | Instructions::SWAP(..) => self.data_movement(ins, base),
+            Instructions::Bcc(..)
+            | Instructions::BRA(..)
+            | Instructions::BSR(..)
+            | Instructions::DBcc(..)
+            | Instructions::JMP(..)
+            | Instructions::JSR(..)
+            | Instructions::LEA(..)
+            | Instructions::PEA(..)
+            | Instructions::LINK(..)
+            | Instructions::UNLK(..)
+            | Instructions::NOP
+            | Instructions::RTD(..)
+            | Instructions::RTR
+            | Instructions::RTS
+            | Instructions::Scc(..) => self.program_control(ins, next, base),
+            Instructions::ANDItoCCR(..)
+            | Instructions::ORItoCCR(..)
+            | Instructions::EORtoCCR(..)
+            | Instructions::ANDItoSR(..)
+            | Instructions::ORItoSR(..)
+            | Instructions::EORtoSR(..)
+            | Instructions::CHK(..)
+            | Instructions::ILLEGAL
+            | Instructions::MOVEfromSR(..)
+            | Instructions::MOVEfromCCR(..)
+            | Instructions::MOVEtoCCR(..)
+            | Instructions::MOVEtoSR(..)
+            | Instructions::MOVEUSP(..)
+            | Instructions::MOVEC(..)
+            | Instructions::RESET
+            | Instructions::RTE
+            | Instructions::STOP(..)
+            | Instructions::TRAP(..)
+            | Instructions::TRAPV
+            | Instructions::NotImplemented => self.system(ins, opcode, base),
+        }
+    }
+
+    // Integer and decimal arithmetic, logic, compares and tests
+    fn arithmetic(&mut self, ins: &Instructions, base: u32) -> Exec<u32> {
+        let cycles = match ins {
+            Instructions::ABCD(src, dst) | Instructions::SBCD(src, dst) => {
+                let s = self.operand(src, Size::Byte, base)?;
+                let dst = self.resolve(dst, Size::Byte, base)?;
+                let d = self.read(dst, Size::Byte)?;
+                let result = match ins {
+                    Instructions::ABCD(..) => self.abcd(s, d),
+                    _ => self.sbcd(s, d),
+                };
+                self.write(dst, Size::Byte, result)?;
+                if matches!(dst, Location::Register(_)) {
+                    6
+                } else {
+                    18
+                }
+            }
+            Instructions::ADD(src, dst, size)
+            | Instructions::SUB(src, dst, size)
+            | Instructions::AND(src, dst, size)
+            | Instructions::OR(src, dst, size)
+            | Instructions::EOR(src, dst, size) => {
+                let s = self.operand(src, *size, base)?;
+                let loc = self.resolve(dst, *size, base)?;
+                let d = self.read(loc, *size)?;
+                let result = match ins {
+                    Instructions::ADD(..) => self.add(s, d, false, *size),
+                    Instructions::SUB(..) => self.sub(s, d, false, *size),
+                    Instructions::AND(..) => self.logic(s & d, *size),
+                    Instructions::OR(..) => self.logic(s | d, *size),
+                    _ => self.logic(s ^ d, *size),
+                };
+                self.write(loc, *size, result)?;
+                arithmetic_cycles(src, dst, *size)
+            }
+            Instructions::ADDA(src, reg, size) | Instructions::SUBA(src, reg, size) => {
+                let s = sign_extend(self.operand(src, *size, base)?, *size);
+                let n = index(reg);
+                let a = self.registers.get(n);
+                let result = match ins {
+                    Instructions::ADDA(..) => a.wrapping_add(s),
+                    _ => a.wrapping_sub(s),
+                };
+                self.registers.set(n, result);
+                match size {
+                    Size::Long if register_or_immediate(src) => 8 + ea_cycles(src, *size),
+                    Size::Long => 6 + ea_cycles(src, *size),
+                    _ => 8 + ea_cycles(src, *size),
+                }
+            }
+            Instructions::ADDI(imm, dst, size)
+            | Instructions::SUBI(imm, dst, size)
+            | Instructions::ANDI(imm, dst, size)
+            | Instructions::ORI(imm, dst, size)
+            | Instructions::EORI(imm, dst, size) => {
+                let loc = self.resolve(dst, *size, base)?;
+                let d = self.read(loc, *size)?;
+                let result = match ins {
+                    Instructions::ADDI(..) => self.add(*imm, d, false, *size),
+                    Instructions::SUBI(..) => self.sub(*imm, d, false, *size),
+                    Instructions::ANDI(..) => self.logic(imm & d, *size),
+                    Instructions::ORI(..) => self.logic(imm | d, *size),
+                    _ => self.logic(imm ^ d, *size),
+                };
+                self.write(loc, *size, result)?;
+                match (dst, size) {
+                    (Target::DnDirect(_), Size::Long) => 16,
+                    (Target::DnDirect(_), _) => 8,
+                    (_, Size::Long) => 20 + ea_cycles(dst, *size),
+                    _ => 12 + ea_cycles(dst, *size),
+                }
+            }
+            Instructions::ADDQ(data, dst, size) | Instructions::SUBQ(data, dst, size) => {
+                let add = matches!(ins, Instructions::ADDQ(..));
+                if let Target::AnDirect(reg) = dst {
+                    // Whole register and no flags, whatever the size
+                    let a = self.registers.get(index(reg));
+                    let result = if add {
+                        a.wrapping_add(*data)
+                    } else {
+                        a.wrapping_sub(*data)
+                    };
+                    self.registers.set(index(reg), result);
+                    8
+                } else {
+                    let loc = self.resolve(dst, *size, base)?;
+                    let d = self.read(loc, *size)?;
+                    let result = if add {
+                        self.add(*data, d, false, *size)
+                    } else {
+                        self.sub(*data, d, false, *size)
+                    };
+                    self.write(loc, *size, result)?;
+                    match (dst, size) {
+                        (Target::DnDirect(_), Size::Long) => 8,
+                        (Target::DnDirect(_), _) => 4,
+                        (_, Size::Long) => 12 + ea_cycles(dst, *size),
+                        _ => 8 + ea_cycles(dst, *size),
+                    }
+                }
+            }
+            Instructions::ADDX(src, dst, size) | Instructions::SUBX(src, dst, size) => {
+                let s = self.operand(src, *size, base)?;
+                let loc = self.resolve(dst, *size, base)?;
+                let d = self.read(loc, *size)?;
+                let result = match ins {
+                    Instructions::ADDX(..) => self.add(s, d, true, *size),
+                    _ => self.sub(s, d, true, *size),
+                };
+                self.write(loc, *size, result)?;
+                match (dst, size) {
+                    (Target::DnDirect(_), Size::Long) => 8,
+                    (Target::DnDirect(_), _) => 4,
+                    (_, Size::Long) => 30,
+                    _ => 18,
+                }
+            }
+            Instructions::CLR(dst, size)
+            | Instructions::NEG(dst, size)
+            | Instructions::NEGX(dst, size)
+            | Instructions::NOT(dst, size) => {
+                let loc = self.resolve(dst, *size, base)?;
+                let d = self.read(loc, *size)?;
+                let result = match ins {
+                    Instructions::CLR(..) => self.logic(0, *size),
+                    Instructions::NEG(..) => self.sub(d, 0, false, *size),
+                    Instructions::NEGX(..) => self.sub(d, 0, true, *size),
+                    _ => self.logic(!d, *size),
+                };
+                self.write(loc, *size, result)?;
+                match (dst, size) {
+                    (Target::DnDirect(_), Size::Long) => 6,
+                    (Target::DnDirect(_), _) => 4,
+                    (_, Size::Long) => 12 + ea_cycles(dst, *size),
+                    _ => 8 + ea_cycles(dst, *size),
+                }
+            }
+            Instructions::CMP(src, dst, size) => {
+                let s = self.operand(src, *size, base)?;
+                let d = self.operand(dst, *size, base)?;
+                self.compare(s, d, *size);
+                match size {
+                    Size::Long => 6 + ea_cycles(src, *size),
+                    _ => 4 + ea_cycles(src, *size),
+                }
+            }
+            Instructions::CMPA(src, reg, size) => {
+                let s = sign_extend(self.operand(src, *size, base)?, *size);
+                self.compare(s, self.registers.get(index(reg)), Size::Long);
+                6 + ea_cycles(src, *size)
+            }
+            Instructions::CMPI(imm, dst, size) => {
+                let d = self.operand(dst, *size, base)?;
+                self.compare(*imm, d, *size);
+                match (dst, size) {
+                    (Target::DnDirect(_), Size::Long) => 14,
+                    (Target::DnDirect(_), _) => 8,
+                    (_, Size::Long) => 12 + ea_cycles(dst, *size),
+                    _ => 8 + ea_cycles(dst, *size),
+                }
+            }
+            Instructions::CMPM(src, dst, size) => {
+                let s = self.operand(src, *size, base)?;
+                let d = self.operand(dst, *size, base)?;
+                self.compare(s, d, *size);
+                if *size == Size::Long {
+                    20
+                } else {
+                    12
+                }
+            }
+            Instructions::DIVUW(src, reg, _) | Instructions::DIVSW(src, reg, _) => {
+                let divisor = self.operand(src, Size::Word, base)?;
+                if divisor == 0 {
+                    return Err(Exception::Trap(ZERO_DIVIDE));
+                }
+                let n = index(reg);
+                let dividend = self.registers.get(n);
+                let signed = matches!(ins, Instructions::DIVSW(..));
+                // Quotient and remainder, or None when the quotient overflows
+                let result = if signed {
+                    let divisor = divisor as u16 as i16 as i32;
+                    let quotient = (dividend as i32).checked_div(divisor);
+                    quotient
+                        .filter(|q| i16::try_from(*q).is_ok())
+                        .map(|q| (q as u32, (dividend as i32 % divisor) as u32))
+                } else {
+                    let quotient = dividend / divisor;
+                    (quotient <= 0xFFFF).then_some((quotient, dividend % divisor))
+                };
+                match result {
+                    Some((quotient, remainder)) => {
+                        self.registers.set(n, remainder << 16 | quotient & 0xFFFF);
+                        self.logic(quotient, Size::Word);
+                    }
+                    None => {
+                        self.registers.SR.overflow = true;
+                        self.registers.SR.carry = false;
+                    }
+                }
+                // Worst case; the real time depends on the operands
+                (if signed { 158 } else { 140 }) + ea_cycles(src, Size::Word)
+            }
+            Instructions::EXT(reg, from, to) => {
+                let n = index(reg);
+                let value = sign_extend(self.registers.get(n), *from);
+                self.write(Location::Register(n), *to, value)?;
+                self.logic(value, *to);
+                4
+            }
+            Instructions::MULUW(src, reg) | Instructions::MULSW(src, reg) => {
+                let s = self.operand(src, Size::Word, base)?;
+                let n = index(reg);
+                let d = self.registers.get(n) & 0xFFFF;
+                let (result, cycles) = match ins {
+                    Instructions::MULUW(..) => (s * d, 38 + 2 * s.count_ones()),
+                    // One extra step for each 01 or 10 pair in the source
+                    _ => (
+                        (s as u16 as i16 as i32 * d as u16 as i16 as i32) as u32,
+                        38 + 2 * ((s << 1 ^ s) & 0xFFFF).count_ones(),
+                    ),
+                };
+                self.registers.set(n, result);
+                self.logic(result, Size::Long);
+                cycles + ea_cycles(src, Size::Word)
+            }
+            Instructions::NBCD(dst) => {
+                let loc = self.resolve(dst, Size::Byte, base)?;
+                let d = self.read(loc, Size::Byte)?;
+                let result = self.sbcd(d, 0);
+                self.write(loc, Size::Byte, result)?;
+                match dst {
+                    Target::DnDirect(_) => 6,
+                    dst => 8 + ea_cycles(dst, Size::Byte),
+                }
+            }
+            Instructions::TST(dst, size) => {
+                let d = self.operand(dst, *size, base)?;
+                self.logic(d, *size);
+                4 + ea_cycles(dst, *size)
+            }
+            _ => unreachable!("{ins:?} is not handled by arithmetic"),
+        };
+        Ok(cycles)
+    }
+
+    // Shifts and rotates, by register count, immediate or one bit in memory
+    fn shift_rotate(&mut self, ins: &Instructions, base: u32) -> Exec<u32> {
+        let cycles = match ins {
+            Instructions::ASL(count, dst, size)
+            | Instructions::ASR(count, dst, size)
+            | Instructions::LSL(count, dst, size)
+            | Instructions::LSR(count, dst, size)
+            | Instructions::ROL(count, dst, size)
+            | Instructions::ROR(count, dst, size)
+            | Instructions::ROXL(count, dst, size)
+            | Instructions::ROXR(count, dst, size) => {
+                let kind = match ins {
+                    Instructions::ASL(..) => Shift::Asl,
+                    Instructions::ASR(..) => Shift::Asr,
+                    Instructions::LSL(..) => Shift::Lsl,
+                    Instructions::LSR(..) => Shift::Lsr,
+                    Instructions::ROL(..) => Shift::Rol,
+                    Instructions::ROR(..) => Shift::Ror,
+                    Instructions::ROXL(..) => Shift::Roxl,
+                    _ => Shift::Roxr,
+                };
+                // Counts in a register are taken modulo 64
+                let n = match count {
+                    Target::DnDirect(reg) => self.registers.get(index(reg)) % 64,
+                    count => self.operand(count, Size::Long, base)?,
+                };
+                let loc = self.resolve(dst, *size, base)?;
+                let d = self.read(loc, *size)?;
+                let result = self.shift(kind, n, d, *size);
+                self.write(loc, *size, result)?;
+                match (dst, size) {
+                    (Target::DnDirect(_), Size::Long) => 8 + 2 * n,
+                    (Target::DnDirect(_), _) => 6 + 2 * n,
+                    _ => 8 + ea_cycles(dst, *size),
+                }
+            }
+            _ => unreachable!("{ins:?} is not handled by shift_rotate"),
+        };
+        Ok(cycles)
+    }
+
+    // Single bit tests and changes, and TAS
+    fn bit_manipulation(&mut self, ins: &Instructions, base: u32) -> Exec<u32> {
+        let cycles = match ins {
+            Instructions::BTST(bit, dst, size)
+            | Instructions::BCHG(bit, dst, size)
+            | Instructions::BCLR(bit, dst, size)
+            | Instructions::BSET(bit, dst, size) => {
+                let dynamic = matches!(bit, Target::DnDirect(_));
+                let n = self.operand(bit, Size::Long, base)? % (bytes(*size) * 8);
+                let base = if dynamic { base } else { base.wrapping_add(2) };
+                let loc = self.resolve(dst, *size, base)?;
+                let d = self.read(loc, *size)?;
+                self.registers.SR.zero = d & 1 << n == 0;
+                let result = match ins {
+                    Instructions::BCHG(..) => Some(d ^ 1 << n),
+                    Instructions::BCLR(..) => Some(d & !(1 << n)),
+                    Instructions::BSET(..) => Some(d | 1 << n),
+                    _ => None,
+                };
+                if let Some(result) = result {
+                    self.write(loc, *size, result)?;
+                }
+                let register = matches!(dst, Target::DnDirect(_));
+                let ea = ea_cycles(dst, *size);
+                match (ins, dynamic, register) {
+                    (Instructions::BTST(..), true, true) => 6,
+                    (Instructions::BTST(..), true, false) => 4 + ea,
+                    (Instructions::BTST(..), false, true) => 10,
+                    (Instructions::BTST(..), false, false) => 8 + ea,
+                    (Instructions::BCLR(..), true, true) => 10,
+                    (Instructions::BCLR(..), false, true) => 14,
+                    (_, true, true) => 8,
+                    (_, false, true) => 12,
+                    (_, true, false) => 8 + ea,
+                    (_, false, false) => 12 + ea,
+                }
+            }
+            Instructions::TAS(dst) => {
+                let loc = self.resolve(dst, Size::Byte, base)?;
+                let d = self.read(loc, Size::Byte)?;
+                self.logic(d, Size::Byte);
+                self.write(loc, Size::Byte, d | 0x80)?;
+                match dst {
+                    Target::DnDirect(_) => 4,
+                    dst => 14 + ea_cycles(dst, Size::Byte),
+                }
+            }
+            _ => unreachable!("{ins:?} is not handled by bit_manipulation"),
+        };
+        Ok(cycles)
+    }
+
+    // Moves between registers and memory
+    fn data_movement(&mut self, ins: &Instructions, base: u32) -> Exec<u32> {
+        let cycles = match ins {
+            Instructions::EXG(a, b) => {
+                let (a, b) = (target_index(a), target_index(b));
+                let value = self.registers.get(a);
+                self.registers.set(a, self.registers.get(b));
+                self.registers.set(b, value);
+                6
+            }
+            Instructions::MOVE(src, dst, size) => {
+                let value = self.operand(src, *size, base)?;
+                let loc = self.resolve(dst, *size, base)?;
+                self.write(loc, *size, value)?;
+                self.logic(value, *size);
+                // Predecrement costs no more than (An) as a destination
+                let dst_cycles = match dst {
+                    Target::AnIndirectPreDec(reg) => ea_cycles(&Target::AnIndirect(*reg), *size),
+                    dst => ea_cycles(dst, *size),
+                };
+                4 + ea_cycles(src, *size) + dst_cycles
+            }
+            Instructions::MOVEA(src, reg, size) => {
+                let value = sign_extend(self.operand(src, *size, base)?, *size);
+                self.registers.set(index(reg), value);
+                4 + ea_cycles(src, *size)
+            }
+            Instructions::MOVEM(target, size, mask, dir) => {
+                let step = bytes(*size);
+                let mask = *mask as u16;
+                let count = mask.count_ones();
+                let per_register = if *size == Size::Long { 8 } else { 4 };
+                match (target, dir) {
+                    // The mask runs from A7 in bit 0 to D0 in bit 15
+                    (Target::AnIndirectPreDec(reg), _) => {
+                        let mut address = self.registers.get(index(reg));
+                        for n in (0..16).filter(|n| mask & 1 << n != 0) {
+                            address = address.wrapping_sub(step);
+                            let value = self.registers.get(15 - n);
+                            self.write(Location::Memory(address), *size, value)?;
+                        }
+                        self.registers.set(index(reg), address);
+                        8 + per_register * count
+                    }
+                    (_, Direction::RegisterToMemory) => {
+                        let mut address = self.address(target, base.wrapping_add(2))?;
+                        for n in (0..16).filter(|n| mask & 1 << n != 0) {
+                            let value = self.registers.get(n);
+                            self.write(Location::Memory(address), *size, value)?;
+                            address = address.wrapping_add(step);
+                        }
+                        [8, 12, 14, 12, 16, 12, 14][control_mode(target)] + per_register * count
+                    }
+                    (_, Direction::MemoryToRegister) => {
+                        let mut address = match target {
+                            Target::AnIndirectPostInc(reg) => self.registers.get(index(reg)),
+                            target => self.address(target, base.wrapping_add(2))?,
+                        };
+                        for n in (0..16).filter(|n| mask & 1 << n != 0) {
+                            let value = self.read(Location::Memory(address), *size)?;
+                            self.registers.set(n, sign_extend(value, *size));
+                            address = address.wrapping_add(step);
+                        }
+                        if let Target::AnIndirectPostInc(reg) = target {
+                            self.registers.set(index(reg), address);
+                        }
+                        [12, 16, 18, 16, 20, 16, 18][control_mode(target)] + per_register * count
+                    }
+                }
+            }
+            Instructions::MOVEP(data, reg, disp, size, dir) => {
+                let n = index(data);
+                let address = self
+                    .registers
+                    .get(index(reg))
+                    .wrapping_add(*disp as i32 as u32);
+                let count = bytes(*size);
+                let mut value = 0;
+                for byte in 0..count {
+                    let at = Location::Memory(address.wrapping_add(2 * byte));
+                    let shift = 8 * (count - 1 - byte);
+                    match dir {
+                        Direction::RegisterToMemory => {
+                            self.write(at, Size::Byte, self.registers.get(n) >> shift)?
+                        }
+                        Direction::MemoryToRegister => {
+                            value |= self.read(at, Size::Byte)? << shift;
+                        }
+                    }
+                }
+                if *dir == Direction::MemoryToRegister {
+                    self.write(Location::Register(n), *size, value)?;
+                }
+                if *size == Size::Long {
+                    24
+                } else {
+                    16
+                }
+            }
+            Instructions::MOVEQ(imm, reg) => {
+                let value = *imm as i8 as i32 as u32;
+                self.registers.set(index(reg), value);
+                self.logic(value, Size::Long);
+                4
+            }
+            Instructions::SWAP(reg) => {
+                let n = index(reg);
+                let value = self.registers.get(n).rotate_left(16);
+                self.registers.set(n, value);
+                self.logic(value, Size::Long);
+                4
+            }
+            _ => unreachable!("{ins:?} is not handled by data_movement"),
+        };
+        Ok(cycles)
+    }
+
+    // Branches, jumps, subroutines, stack frames and address calculation
+    fn program_control(&mut self, ins: &Instructions, next: u32, base: u32) -> Exec<u32> {
+        let cycles = match ins {
+            Instructions::Bcc(cond, disp) => {
+                if self.registers.SR.condition(*cond) {
+                    self.registers.PC = base.wrapping_add(*disp as u32);
+                    10
+                } else if next == base {
+                    8
+                } else {
+                    12
+                }
+            }
+            Instructions::BRA(disp) => {
+                self.registers.PC = base.wrapping_add(*disp as u32);
+                10
+            }
+            Instructions::BSR(disp) => {
+                self.push(next, Size::Long)?;
+                self.registers.PC = base.wrapping_add(*disp as u32);
+                18
+            }
+            Instructions::DBcc(cond, reg, disp) => {
+                if self.registers.SR.condition(*cond) {
+                    12
+                } else {
+                    let n = index(reg);
+                    let count = (self.registers.get(n) as u16).wrapping_sub(1);
+                    self.write(Location::Register(n), Size::Word, count as u32)?;
+                    if count == 0xFFFF {
+                        14
+                    } else {
+                        self.registers.PC = base.wrapping_add(*disp as i32 as u32);
+                        10
+                    }
+                }
+            }
+            Instructions::JMP(dst) => {
+                self.registers.PC = self.address(dst, base)?;
+                [8, 10, 14, 10, 12, 10, 14][control_mode(dst)]
+            }
+            Instructions::JSR(dst) => {
+                let address = self.address(dst, base)?;
+                self.push(next, Size::Long)?;
+                self.registers.PC = address;
+                [16, 18, 22, 18, 20, 18, 22][control_mode(dst)]
+            }
+            Instructions::LEA(src, reg) => {
+                let address = self.address(src, base)?;
+                self.registers.set(index(reg), address);
+                [4, 8, 12, 8, 12, 8, 12][control_mode(src)]
+            }
+            Instructions::PEA(src) => {
+                let address = self.address(src, base)?;
+                self.push(address, Size::Long)?;
+                [12, 16, 20, 16, 20, 16, 20][control_mode(src)]
+            }
+            Instructions::LINK(reg, disp) => {
+                let n = index(reg);
+                self.push(self.registers.get(n), Size::Long)?;
+                self.registers.set(n, self.registers.SP);
+                self.registers.SP = self.registers.SP.wrapping_add(*disp as u32);
+                16
+            }
+            Instructions::UNLK(reg) => {
+                let n = index(reg);
+                self.registers.SP = self.registers.get(n);
+                let value = self.pop(Size::Long)?;
+                self.registers.set(n, value);
+                12
+            }
+            Instructions::NOP => 4,
+            Instructions::RTD(disp) => {
+                self.registers.PC = self.pop(Size::Long)?;
+                self.registers.SP = self.registers.SP.wrapping_add(*disp as i32 as u32);
+                16
+            }
+            Instructions::RTR => {
+                let ccr = self.pop(Size::Word)?;
+                self.registers.PC = self.pop(Size::Long)?;
+                self.set_ccr(ccr as u16);
+                20
+            }
+            Instructions::RTS => {
+                self.registers.PC = self.pop(Size::Long)?;
+                16
+            }
+            Instructions::Scc(cond, dst) => {
+                let set = self.registers.SR.condition(*cond);
+                let loc = self.resolve(dst, Size::Byte, base)?;
+                self.write(loc, Size::Byte, if set { 0xFF } else { 0 })?;
+                match dst {
+                    Target::DnDirect(_) if set => 6,
+                    Target::DnDirect(_) => 4,
+                    dst => 8 + ea_cycles(dst, Size::Byte),
+                }
+            }
+            _ => unreachable!("{ins:?} is not handled by program_control"),
+        };
+        Ok(cycles)
+    }
+
+    // Status register access, privileged instructions, traps and illegal opcodes
+    fn system(&mut self, ins: &Instructions, opcode: u16, base: u32) -> Exec<u32> {
+        let cycles = match ins {
+            Instructions::ANDItoCCR(imm) => {
+                self.set_ccr(self.registers.SR.to_word() & *imm as u16);
+                20
+            }
+            Instructions::ORItoCCR(imm) => {
+                self.set_ccr(self.registers.SR.to_word() | *imm as u16);
+                20
+            }
+            Instructions::EORtoCCR(imm) => {
+                self.set_ccr(self.registers.SR.to_word() ^ *imm as u16);
+                20
+            }
+            Instructions::ANDItoSR(imm) => {
+                self.supervisor()?;
+                self.set_sr(self.registers.SR.to_word() & imm);
+                20
+            }
+            Instructions::ORItoSR(imm) => {
+                self.supervisor()?;
+                self.set_sr(self.registers.SR.to_word() | *imm as u16);
+                20
+            }
+            Instructions::EORtoSR(imm) => {
+                self.supervisor()?;
+                self.set_sr(self.registers.SR.to_word() ^ imm);
+                20
+            }
+            Instructions::CHK(src, reg, size) => {
+                let bound = sign_extend(self.operand(src, *size, base)?, *size) as i32;
+                let value = sign_extend(self.registers.get(index(reg)), *size) as i32;
+                if value < 0 || value > bound {
+                    self.registers.SR.negative = value < 0;
+                    return Err(Exception::Trap(CHK_INSTRUCTION));
+                }
+                10 + ea_cycles(src, *size)
+            }
+            Instructions::ILLEGAL => return Err(Exception::Fault(ILLEGAL_INSTRUCTION)),
+            Instructions::MOVEfromSR(dst) | Instructions::MOVEfromCCR(dst) => {
+                let mut value = self.registers.SR.to_word() as u32;
+                if matches!(ins, Instructions::MOVEfromCCR(_)) {
+                    value &= 0x1F;
+                }
+                let loc = self.resolve(dst, Size::Word, base)?;
+                self.write(loc, Size::Word, value)?;
+                match dst {
+                    Target::DnDirect(_) => 6,
+                    dst => 8 + ea_cycles(dst, Size::Word),
+                }
+            }
+            Instructions::MOVEtoCCR(src) => {
+                let value = self.operand(src, Size::Word, base)?;
+                self.set_ccr(value as u16);
+                12 + ea_cycles(src, Size::Word)
+            }
+            Instructions::MOVEtoSR(src) => {
+                self.supervisor()?;
+                let value = self.operand(src, Size::Word, base)?;
+                self.set_sr(value as u16);
+                12 + ea_cycles(src, Size::Word)
+            }
+            Instructions::MOVEUSP(reg, dir) => {
+                self.supervisor()?;
+                let n = target_index(reg);
+                // In supervisor mode the user stack pointer is the alternate one
+                match dir {
+                    Direction::RegisterToMemory => self.registers.ALT_SP = self.registers.get(n),
+                    Direction::MemoryToRegister => self.registers.set(n, self.registers.ALT_SP),
+                }
+                4
+            }
+            Instructions::MOVEC(reg, ControlRegister::VBR, dir) => {
+                self.supervisor()?;
+                let n = target_index(reg);
+                match dir {
+                    Direction::RegisterToMemory => self.vbr = self.registers.get(n),
+                    Direction::MemoryToRegister => self.registers.set(n, self.vbr),
+                }
+                12
+            }
+            Instructions::RESET => {
+                self.supervisor()?;
+                self.memory_bus.reset();
+                132
+            }
+            Instructions::RTE => {
+                self.supervisor()?;
+                let sr = self.pop(Size::Word)?;
+                self.registers.PC = self.pop(Size::Long)?;
+                self.set_sr(sr as u16);
+                20
+            }
+            Instructions::STOP(imm) => {
+                self.supervisor()?;
+                self.set_sr(*imm);
+                self.state = CPUState::Stopped;
+                4
+            }
+            Instructions::TRAP(vector) => return Err(Exception::Trap(TRAP_BASE + vector)),
+            Instructions::TRAPV => {
+                if self.registers.SR.overflow {
+                    return Err(Exception::Trap(TRAPV_INSTRUCTION));
+                }
+                4
+            }
+            Instructions::NotImplemented => {
+                return Err(Exception::Fault(match opcode >> 12 {
+                    0xA => LINE_A,
+                    0xF => LINE_F,
+                    _ => ILLEGAL_INSTRUCTION,
+                }))
+            }
+            _ => unreachable!("{ins:?} is not handled by system"),
+        };
+        Ok(cycles)
+    }
+
+    fn supervisor(&self) -> Exec<()> {
+        if self.registers.SR.supervisor_state {
+            Ok(())
+        } else {
+            Err(Exception::Fault(PRIVILEGE_VIOLATION))
+        }
+    }
+
+    // Sets X N Z V C from the low five bits of ccr
+    fn set_ccr(&mut self, ccr: u16) {
+        let sr = &mut self.registers.SR;
+        sr.extend = ccr & 0x10 != 0;
+        sr.negative = ccr & 0x08 != 0;
+        sr.zero = ccr & 0x04 != 0;
+        sr.overflow = ccr & 0x02 != 0;
+        sr.carry = ccr & 0x01 != 0;
+    }
+
+    // Calculates an effective address, making the postincrement or
+    // predecrement it asks for. base is the address PC-relative modes count
+    // from.
+    fn resolve(&mut self, target: &Target, size: Size, base: u32) -> Exec<Location> {
+        let r = &mut self.registers;
+        Ok(match target {
+            Target::DnDirect(reg) | Target::AnDirect(reg) => Location::Register(index(reg)),
+            Target::AnIndirect(reg) => Location::Memory(r.get(index(reg))),
+            Target::AnIndirectPostInc(reg) => {
+                let address = r.get(index(reg));
+                r.set(index(reg), address.wrapping_add(step(reg, size)));
+                Location::Memory(address)
+            }
+            Target::AnIndirectPreDec(reg) => {
+                let address = r.get(index(reg)).wrapping_sub(step(reg, size));
+                r.set(index(reg), address);
+                Location::Memory(address)
+            }
+            Target::AnIndirectDisplacement(reg, disp) => {
+                Location::Memory(r.get(index(reg)).wrapping_add(*disp as u32))
+            }
+            Target::AnIndirectIndex(disp, reg, index_register) => Location::Memory(
+                r.get(index(reg))
+                    .wrapping_add(*disp as u32)
+                    .wrapping_add(self.index_value(index_register)),
+            ),
+            Target::PCIndirectDisplacement(disp, _) => {
+                Location::Memory(base.wrapping_add(*disp as u32))
+            }
+            Target::PCIndirectIndex(disp, _, index_register) => Location::Memory(
+                base.wrapping_add(*disp as u32)
+                    .wrapping_add(self.index_value(index_register)),
+            ),
+            Target::AbsoluteShortAddress(address) => Location::Memory(*address as u32),
+            Target::AbsoluteLongAddress(high, low) => Location::Memory(high << 16 | low),
+            Target::Immediate(value) => Location::Value(*value),
+            // 68020 modes, which the decoder does not produce
+            _ => return Err(Exception::Fault(ILLEGAL_INSTRUCTION)),
+        })
+    }
+
+    fn index_value(&self, index_register: &IndexRegister) -> u32 {
+        let value = self.registers.get(index(&index_register.register));
+        sign_extend(value, index_register.size).wrapping_mul(index_register.scale as u32)
+    }
+
+    // Address of a control addressing mode operand
+    fn address(&mut self, target: &Target, base: u32) -> Exec<u32> {
+        match self.resolve(target, Size::Long, base)? {
+            Location::Memory(address) => Ok(address),
+            _ => Err(Exception::Fault(ILLEGAL_INSTRUCTION)),
+        }
+    }
+
+    // Value of a source operand
+    fn operand(&mut self, target: &Target, size: Size, base: u32) -> Exec<u32> {
+        let location = self.resolve(target, size, base)?;
+        self.read(location, size)
+    }
+
+    fn read(&self, location: Location, size: Size) -> Exec<u32> {
+        match location {
+            Location::Register(n) => Ok(self.registers.get(n) & mask(size)),
+            Location::Value(value) => Ok(value & mask(size)),
+            Location::Memory(address) => {
+                if size != Size::Byte && address & 1 != 0 {
+                    return Err(Exception::Access(ADDRESS_ERROR, address, AccessKind::Read));
+                }
+                let bus = &self.memory_bus;
+                match size {
+                    Size::Byte => bus.read_at_address_byte(address).map(u32::from),
+                    Size::Word => bus.read_at_address_word(address).map(u32::from),
+                    Size::Long => bus.read_at_address_long(address),
+                }
+                .ok_or(Exception::Access(BUS_ERROR, address, AccessKind::Read))
+            }
+        }
+    }
+
+    // Writes the low size bits of value; data registers keep their other
+    // bits, address registers take the whole long
+    fn write(&mut self, location: Location, size: Size, value: u32) -> Exec<()> {
+        match location {
+            Location::Register(n) if n < 8 => {
+                let old = self.registers.get(n);
+                self.registers
+                    .set(n, old & !mask(size) | value & mask(size));
+                Ok(())
+            }
+            Location::Register(n) => {
+                self.registers.set(n, value);
+                Ok(())
+            }
+            Location::Memory(address) => {
+                if size != Size::Byte && address & 1 != 0 {
+                    return Err(Exception::Access(ADDRESS_ERROR, address, AccessKind::Write));
+                }
+                let bus = &mut self.memory_bus;
+                match size {
+                    Size::Byte => bus.write_at_address_byte(address, value as u8),
+                    Size::Word => bus.write_at_address_word(address, value as u16),
+                    Size::Long => bus.write_at_address_long(address, value),
+                }
+                .map_err(|_| Exception::Access(BUS_ERROR, address, AccessKind::Write))
+            }
+            Location::Value(_) => Err(Exception::Fault(ILLEGAL_INSTRUCTION)),
+        }
+    }
+
+    fn push(&mut self, value: u32, size: Size) -> Exec<()> {
+        self.registers.SP = self.registers.SP.wrapping_sub(bytes(size));
+        self.write(Location::Memory(self.registers.SP), size, value)
+    }
+
+    fn pop(&mut self, size: Size) -> Exec<u32> {
+        let value = self.read(Location::Memory(self.registers.SP), size)?;
+        self.registers.SP = self.registers.SP.wrapping_add(bytes(size));
+        Ok(value)
+    }
+
+    // Sets N and Z from value and clears V and C, as logical operations and
+    // moves do
+    fn logic(&mut self, value: u32, size: Size) -> u32 {
+        let value = value & mask(size);
+        let sr = &mut self.registers.SR;
+        sr.negative = value & sign_bit(size) != 0;
+        sr.zero = value == 0;
+        sr.overflow = false;
+        sr.carry = false;
+        value
+    }
+
+    // dst + src, plus X for ADDX, which only clears Z
+    fn add(&mut self, src: u32, dst: u32, extend: bool, size: Size) -> u32 {
+        let (src, dst) = (src & mask(size), dst & mask(size));
+        let x = (extend && self.registers.SR.extend) as u64;
+        let sum = src as u64 + dst as u64 + x;
+        let result = sum as u32 & mask(size);
+        let sr = &mut self.registers.SR;
+        sr.carry = sum > mask(size) as u64;
+        sr.extend = sr.carry;
+        sr.overflow = !(src ^ dst) & (result ^ dst) & sign_bit(size) != 0;
+        sr.negative = result & sign_bit(size) != 0;
+        sr.zero = result == 0 && (!extend || sr.zero);
+        result
+    }
+
+    // dst - src, minus X for SUBX and NEGX, which only clear Z
+    fn sub(&mut self, src: u32, dst: u32, extend: bool, size: Size) -> u32 {
+        let (src, dst) = (src & mask(size), dst & mask(size));
+        let x = (extend && self.registers.SR.extend) as u32;
+        let result = dst.wrapping_sub(src).wrapping_sub(x) & mask(size);
+        let sr = &mut self.registers.SR;
+        sr.carry = src as u64 + x as u64 > dst as u64;
+        sr.extend = sr.carry;
+        sr.overflow = (src ^ dst) & (result ^ dst) & sign_bit(size) != 0;
+        sr.negative = result & sign_bit(size) != 0;
+        sr.zero = result == 0 && (!extend || sr.zero);
+        result
+    }
+
+    // Flags of dst - src, leaving X alone
+    fn compare(&mut self, src: u32, dst: u32, size: Size) {
+        let extend = self.registers.SR.extend;
+        self.sub(src, dst, false, size);
+        self.registers.SR.extend = extend;
+    }
+
+    // Packed BCD dst + src + X
+    fn abcd(&mut self, src: u32, dst: u32) -> u32 {
+        let x = self.registers.SR.extend as u32;
+        let mut result = (src & 0x0F) + (dst & 0x0F) + x;
+        if result > 9 {
+            result += 6;
+        }
+        result += (src & 0xF0) + (dst & 0xF0);
+        let carry = result > 0x99;
+        if carry {
+            result -= 0xA0;
+        }
+        self.bcd_flags(result & 0xFF, carry)
+    }
+
+    // Packed BCD dst - src - X, also NBCD as 0 - src - X
+    fn sbcd(&mut self, src: u32, dst: u32) -> u32 {
+        let x = self.registers.SR.extend as u32;
+        let mut result = (dst & 0x0F).wrapping_sub(src & 0x0F).wrapping_sub(x);
+        if result > 9 {
+            result = result.wrapping_sub(6);
+        }
+        result = result.wrapping_add(dst & 0xF0).wrapping_sub(src & 0xF0);
+        let carry = result > 0x99;
+        if carry {
+            result = result.wrapping_add(0xA0);
+        }
+        self.bcd_flags(result & 0xFF, carry)
+    }
+
+    fn bcd_flags(&mut self, result: u32, carry: bool) -> u32 {
+        let sr = &mut self.registers.SR;
+        sr.carry = carry;
+        sr.extend = carry;
+        sr.negative = result & 0x80 != 0;
+        sr.zero = result == 0 && sr.zero;
+        result
+    }
+
+    fn shift(&mut self, kind: Shift, count: u32, value: u32, size: Size) -> u32 {
+        let (mask, sign) = (mask(size), sign_bit(size));
+        let mut value = value & mask;
+        let mut carry = false;
+        let mut overflow = false;
+        let mut extend = self.registers.SR.extend;
+        for _ in 0..count {
+            match kind {
+                Shift::Asl | Shift::Lsl => {
+                    carry = value & sign != 0;
+                    let shifted = value << 1 & mask;
+                    // ASL sets V if the sign bit changes at any point
+                    overflow |= kind == Shift::Asl && (shifted ^ value) & sign != 0;
+                    value = shifted;
+                    extend = carry;
+                }
+                Shift::Asr | Shift::Lsr => {
+                    carry = value & 1 != 0;
+                    let fill = if kind == Shift::Asr { value & sign } else { 0 };
+                    value = value >> 1 | fill;
+                    extend = carry;
+                }
+                Shift::Rol => {
+                    carry = value & sign != 0;
+                    value = value << 1 & mask | carry as u32;
+                }
+                Shift::Ror => {
+                    carry = value & 1 != 0;
+                    value = value >> 1 | if carry { sign } else { 0 };
+                }
+                Shift::Roxl => {
+                    carry = value & sign != 0;
+                    value = value << 1 & mask | extend as u32;
+                    extend = carry;
+                }
+                Shift::Roxr => {
+                    carry = value & 1 != 0;
+                    value = value >> 1 | if extend { sign } else { 0 };
+                    extend = carry;
+                }
+            }
+        }
+        // A zero count through X copies X to C
+        if count == 0 && matches!(kind, Shift::Roxl | Shift::Roxr) {
+            carry = extend;
+        }
+        let sr = &mut self.registers.SR;
+        sr.carry = carry;
+        sr.extend = extend;
+        sr.overflow = overflow;
+        sr.negative = value & sign != 0;
+        sr.zero = value == 0;
+        value
+    }
+}
+
+// Registers::get number of a data or address register
+fn index(reg: &Registers) -> usize {
+    let n = reg.number().unwrap_or(0) as usize;
+    if reg.is_address() {
+        n + 8
+    } else {
+        n
+    }
+}
+
+fn target_index(target: &Target) -> usize {
+    match target {
+        Target::DnDirect(reg) | Target::AnDirect(reg) => index(reg),
+        _ => 0,
+    }
+}
+
+fn bytes(size: Size) -> u32 {
+    match size {
+        Size::Byte => 1,
+        Size::Word => 2,
+        Size::Long => 4,
+    }
+}
+
+fn mask(size: Size) -> u32 {
+    match size {
+        Size::Byte => 0xFF,
+        Size::Word => 0xFFFF,
+        Size::Long => 0xFFFF_FFFF,
+    }
+}
+
+fn sign_bit(size: Size) -> u32 {
+    match size {
+        Size::Byte => 0x80,
+        Size::Word => 0x8000,
+        Size::Long => 0x8000_0000,
+    }
+}
+
+fn sign_extend(value: u32, size: Size) -> u32 {
+    match size {
+        Size::Byte => value as u8 as i8 as u32,
+        Size::Word => value as u16 as i16 as u32,
+        Size::Long => value,
+    }
+}
+
+// Postincrement and predecrement step; byte accesses keep A7 word aligned
+fn step(reg: &Registers, size: Size) -> u32 {
+    match (reg, size) {
+        (Registers::SP, Size::Byte) => 2,
+        _ => bytes(size),
+    }
+}
+
+fn register_or_immediate(target: &Target) -> bool {
+    matches!(
+        target,
+        Target::DnDirect(_) | Target::AnDirect(_) | Target::Immediate(_)
+    )
+}
+
+// Effective address calculation time from the 68000 timing tables, for byte
+// and word or for long operands
+fn ea_cycles(target: &Target, size: Size) -> u32 {
+    let (short, long) = match target {
+        Target::AnIndirect(_) | Target::AnIndirectPostInc(_) | Target::Immediate(_) => (4, 8),
+        Target::AnIndirectPreDec(_) => (6, 10),
+        Target::AnIndirectDisplacement(..)
+        | Target::AbsoluteShortAddress(_)
+        | Target::PCIndirectDisplacement(..) => (8, 12),
+        Target::AnIndirectIndex(..) | Target::PCIndirectIndex(..) => (10, 14),
+        Target::AbsoluteLongAddress(..) => (12, 16),
+        _ => (0, 0),
+    };
+    if size == Size::Long {
+        long
+    } else {
+        short
+    }
+}
+
+// Column of the control addressing mode tables: (An), d16(An), d8(An,Xn),
+// abs.W, abs.L, d16(PC), d8(PC,Xn). MOVEM's (An)+ and -(An) cost as (An).
+fn control_mode(target: &Target) -> usize {
+    match target {
+        Target::AnIndirectDisplacement(..) => 1,
+        Target::AnIndirectIndex(..) => 2,
+        Target::AbsoluteShortAddress(_) => 3,
+        Target::AbsoluteLongAddress(..) => 4,
+        Target::PCIndirectDisplacement(..) => 5,
+        Target::PCIndirectIndex(..) => 6,
+        _ => 0,
+    }
+}
+
+// ADD, SUB, AND, OR and EOR: <ea>,Dn or Dn,<ea>
+fn arithmetic_cycles(src: &Target, dst: &Target, size: Size) -> u32 {
+    match (dst, size) {
+        (Target::DnDirect(_), Size::Long) if register_or_immediate(src) => 8 + ea_cycles(src, size),
+        (Target::DnDirect(_), Size::Long) => 6 + ea_cycles(src, size),
+        (Target::DnDirect(_), _) => 4 + ea_cycles(src, size),
+        (_, Size::Long) => 12 + ea_cycles(dst, size),
+        _ => 8 + ea_cycles(dst, size),
+    }
+}
+
+pub fn exception_name(vector: u8) -> String {
+    match vector {
+        BUS_ERROR => "bus error".to_string(),
+        ADDRESS_ERROR => "address error".to_string(),
+        ILLEGAL_INSTRUCTION => "illegal instruction".to_string(),
+        ZERO_DIVIDE => "divide by zero".to_string(),
+        CHK_INSTRUCTION => "CHK exception".to_string(),
+        TRAPV_INSTRUCTION => "TRAPV exception".to_string(),
+        PRIVILEGE_VIOLATION => "privilege violation".to_string(),
+        TRACE => "trace exception".to_string(),
+        LINE_A => "line A instruction".to_string(),
+        LINE_F => "line F instruction".to_string(),
+        24 => "spurious interrupt".to_string(),
+        25..=31 => format!("level {} interrupt", vector - 24),
+        32..=47 => format!("TRAP #{}", vector - TRAP_BASE),
+        _ => format!("exception {}", vector),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assembler::assemble;
+
+    const ORIGIN: u32 = 0x1000;
+    const STACK: u32 = 0x8000;
+
+    // A supervisor mode CPU with source assembled at ORIGIN
+    fn cpu(source: &str) -> CPU {
+        let mut cpu = CPU::with_memory(0x10000);
+        let program = assemble(&format!(" org ${:x}\n{}", ORIGIN, source)).unwrap();
+        program.load(&mut cpu.memory_bus.memory).unwrap();
+        cpu.set_sr(0x2700);
+        cpu.registers.PC = ORIGIN;
+        cpu.registers.SP = STACK;
+        cpu
+    }
+
+    fn run(cpu: &mut CPU, steps: usize) {
+        for _ in 0..steps {
+            cpu.step().unwrap();
+        }
+    }
+
+    fn set_vector(cpu: &mut CPU, vector: u8, handler: u32) {
+        let mem = &mut cpu.memory_bus.memory;
+        mem.write_at_address_long(vector as u32 * 4, handler)
+            .unwrap();
+    }
+
+    #[test]
+    fn arithmetic_sets_flags() {
+        let mut cpu = cpu(" move.l #$7fffffff,d0\n addq.l #1,d0\n moveq #-1,d1\n add.b #1,d1\n");
+        run(&mut cpu, 2);
+        assert_eq!(cpu.registers.D0, 0x8000_0000);
+        assert_eq!(cpu.registers.SR.to_word() & 0x1F, 0x0A);
+        run(&mut cpu, 2);
+        assert_eq!(cpu.registers.D1, 0xFFFF_FF00);
+        assert_eq!(cpu.registers.SR.to_word() & 0x1F, 0x15);
+    }
+
+    #[test]
+    fn loop_counts_cycles() {
+        let mut cpu = cpu(" moveq #3,d0\nloop: dbf d0,loop\n nop\n");
+        run(&mut cpu, 5);
+        assert_eq!(cpu.registers.D0 & 0xFFFF, 0xFFFF);
+        assert_eq!(cpu.registers.PC, ORIGIN + 6);
+        // MOVEQ, three taken DBFs and the one that falls through
+        assert_eq!(cpu.cycles, 4 + 3 * 10 + 14);
+    }
+
+    #[test]
+    fn subroutines_use_the_stack() {
+        let mut cpu = cpu(
+            " moveq #5,d2\n bsr sub\n bra *\nsub: movem.l d2/a0,-(sp)\n moveq #0,d2\n movem.l (sp)+,d2/a0\n rts\n",
+        );
+        run(&mut cpu, 3);
+        assert_eq!(cpu.registers.SP, STACK - 12);
+        run(&mut cpu, 3);
+        assert_eq!(cpu.registers.D2, 5);
+        assert_eq!(cpu.registers.SP, STACK);
+        assert_eq!(cpu.registers.PC, ORIGIN + 6);
+    }
+
+    #[test]
+    fn decimal_arithmetic() {
+        let mut cpu = cpu(" move.b #$19,d0\n move.b #$28,d1\n abcd d0,d1\n sbcd d0,d1\n");
+        run(&mut cpu, 3);
+        assert_eq!(cpu.registers.D1 & 0xFF, 0x47);
+        run(&mut cpu, 1);
+        assert_eq!(cpu.registers.D1 & 0xFF, 0x28);
+    }
+
+    #[test]
+    fn divide_by_zero_traps() {
+        let mut cpu = cpu(" moveq #0,d1\n divu d1,d0\n nop\nhandler: rte\n");
+        set_vector(&mut cpu, ZERO_DIVIDE, ORIGIN + 6);
+        run(&mut cpu, 2);
+        assert_eq!(cpu.registers.PC, ORIGIN + 6);
+        let mem = &cpu.memory_bus.memory;
+        assert_eq!(mem.read_at_address_long(STACK - 4), Some(ORIGIN + 4));
+        run(&mut cpu, 1);
+        assert_eq!(cpu.registers.PC, ORIGIN + 4);
+        assert_eq!(cpu.registers.SP, STACK);
+    }
+
+    #[test]
+    fn unset_vector_is_an_error() {
+        let mut cpu = cpu(" illegal\n");
+        assert!(cpu.step().unwrap_err().contains("illegal instruction"));
+        assert_eq!(cpu.registers.PC, ORIGIN);
+        assert_eq!(cpu.registers.SP, STACK);
+    }
+
+    #[test]
+    fn user_mode_has_its_own_stack() {
+        let mut cpu = cpu(" lea $4000,a0\n move a0,usp\n andi #$dfff,sr\n move #$2700,sr\n");
+        set_vector(&mut cpu, PRIVILEGE_VIOLATION, ORIGIN + 0x100);
+        run(&mut cpu, 3);
+        assert_eq!(cpu.registers.SP, 0x4000);
+        assert_eq!(cpu.registers.ALT_SP, STACK);
+        // MOVE to SR is privileged
+        run(&mut cpu, 1);
+        assert_eq!(cpu.registers.PC, ORIGIN + 0x100);
+        assert_eq!(cpu.registers.SP, STACK - 6);
+        assert_eq!(cpu.registers.ALT_SP, 0x4000);
+    }
+
+    #[test]
+    fn odd_word_access_is_an_address_error() {
+        let mut cpu = cpu(" lea $2001,a0\n move.w (a0),d0\n");
+        set_vector(&mut cpu, ADDRESS_ERROR, ORIGIN + 0x100);
+        run(&mut cpu, 2);
+        assert_eq!(cpu.registers.PC, ORIGIN + 0x100);
+        assert_eq!(cpu.registers.SP, STACK - 14);
+        let mem = &cpu.memory_bus.memory;
+        assert_eq!(mem.read_at_address_long(STACK - 12), Some(0x2001));
+    }
+
+    #[test]
+    fn shifts_and_rotates() {
+        let mut cpu = cpu(" move.w #$8001,d0\n asl.w #1,d0\n roxr.w #1,d0\n lsr.l #4,d0\n");
+        run(&mut cpu, 2);
+        assert_eq!(cpu.registers.D0 & 0xFFFF, 0x0002);
+        assert_eq!(cpu.registers.SR.to_word() & 0x13, 0x13);
+        run(&mut cpu, 1);
+        assert_eq!(cpu.registers.D0 & 0xFFFF, 0x8001);
+        run(&mut cpu, 1);
+        assert_eq!(cpu.registers.D0, 0x0800);
+    }
+
+    #[test]
+    fn stop_waits_for_an_interrupt() {
+        let mut cpu = cpu(" stop #$2000\n nop\n");
+        run(&mut cpu, 1);
+        assert_eq!(cpu.state, CPUState::Stopped);
+        run(&mut cpu, 10);
+        assert_eq!(cpu.registers.PC, ORIGIN + 4);
+    }
+}
@@ -0,0 +1,627 @@
+use crate::instruction::{
+    Condition, ControlRegister, Direction, IndexRegister, Instructions, Registers, Size, Target,
+};
+
+/* Effective address classes, one bit per mode (see ea_kind) */
+pub const EA_DN: u16 = 1 << 0;
+pub const EA_AN: u16 = 1 << 1;
+pub const EA_AN_INDIRECT: u16 = 1 << 2;
+pub const EA_POSTINC: u16 = 1 << 3;
+pub const EA_PREDEC: u16 = 1 << 4;
+pub const EA_DISPLACEMENT: u16 = 1 << 5;
+pub const EA_INDEX: u16 = 1 << 6;
+pub const EA_ABS_SHORT: u16 = 1 << 7;
+pub const EA_ABS_LONG: u16 = 1 << 8;
+pub const EA_PC_DISPLACEMENT: u16 = 1 << 9;
+pub const EA_PC_INDEX: u16 = 1 << 10;
+pub const EA_IMMEDIATE: u16 = 1 << 11;
+
+pub const EA_ALL: u16 = 0x0FFF;
+pub const EA_DATA: u16 = EA_ALL & !EA_AN;
+pub const EA_MEMORY: u16 = EA_ALL & !(EA_DN | EA_AN);
+pub const EA_CONTROL: u16 = EA_AN_INDIRECT
+    | EA_DISPLACEMENT
+    | EA_INDEX
+    | EA_ABS_SHORT
+    | EA_ABS_LONG
+    | EA_PC_DISPLACEMENT
+    | EA_PC_INDEX;
+pub const EA_ALTERABLE: u16 = EA_ALL & !(EA_PC_DISPLACEMENT | EA_PC_INDEX | EA_IMMEDIATE);
+pub const EA_DATA_ALTERABLE: u16 = EA_ALTERABLE & !EA_AN;
+pub const EA_MEMORY_ALTERABLE: u16 = EA_ALTERABLE & !(EA_DN | EA_AN);
+pub const EA_CONTROL_ALTERABLE: u16 = EA_CONTROL & EA_ALTERABLE;
+
+// Class bit of a 6-bit effective address field (mode << 3 | register)
+pub fn ea_kind(field: u16) -> u16 {
+    let mode = (field >> 3) & 7;
+    let reg = field & 7;
+    match mode {
+        7 if reg > 4 => 0,
+        7 => 1 << (7 + reg),
+        _ => 1 << mode,
+    }
+}
+
+// Condition field used by Bcc, DBcc and Scc
+pub fn condition_code(cond: &Condition) -> u16 {
+    match cond {
+        Condition::True => 0x0,
+        Condition::False => 0x1,
+        Condition::High => 0x2,
+        Condition::Low => 0x3,
+        Condition::CarryClear => 0x4,
+        Condition::CarrySet => 0x5,
+        Condition::Neq => 0x6,
+        Condition::Equal => 0x7,
+        Condition::OverflowClear => 0x8,
+        Condition::OverflowSet => 0x9,
+        Condition::Plus => 0xA,
+        Condition::Minus => 0xB,
+        Condition::Geq => 0xC,
+        Condition::Lt => 0xD,
+        Condition::Gt => 0xE,
+        Condition::Leq => 0xF,
+    }
+}
+
+// Size field used by most instructions: 00 byte, 01 word, 10 long
+fn size_bits(size: &Size) -> u16 {
+    match size {
+        Size::Byte => 0,
+        Size::Word => 1,
+        Size::Long => 2,
+    }
+}
+
+fn data_register(reg: &Registers) -> Result<u16, &'static str> {
+    match reg.number() {
+        Some(n) if reg.is_data() => Ok(n),
+        _ => Err("Expected a data register"),
+    }
+}
+
+fn address_register(reg: &Registers) -> Result<u16, &'static str> {
+    match reg.number() {
+        Some(n) if reg.is_address() => Ok(n),
+        _ => Err("Expected an address register"),
+    }
+}
+
+fn data_target(target: &Target) -> Result<u16, &'static str> {
+    match target {
+        Target::DnDirect(reg) => data_register(reg),
+        _ => Err("Expected a data register"),
+    }
+}
+
+fn address_target(target: &Target) -> Result<u16, &'static str> {
+    match target {
+        Target::AnDirect(reg) => address_register(reg),
+        _ => Err("Expected an address register"),
+    }
+}
+
+fn fits_i8(value: i32) -> bool {
+    (i8::MIN as i32..=i8::MAX as i32).contains(&value)
+}
+
+fn fits_i16(value: i32) -> bool {
+    (i16::MIN as i32..=i16::MAX as i32).contains(&value)
+}
+
+// Brief extension word used by the indexed addressing modes
+fn brief_extension(index: &IndexRegister, displacement: i32) -> Result<u16, &'static str> {
+    if !fits_i8(displacement) {
+        return Err("Index displacement out of range");
+    }
+    if index.scale != 1 {
+        return Err("Scaled index not available on the 68000");
+    }
+    let number = index.register.number().ok_or("Invalid index register")?;
+    let long = match index.size {
+        Size::Word => 0,
+        Size::Long => 1,
+        Size::Byte => return Err("Invalid index register size"),
+    };
+    let address = if index.register.is_address() { 1 } else { 0 };
+    Ok(address << 15 | number << 12 | long << 11 | (displacement as u16 & 0xFF))
+}
+
+// Extension words of an immediate operand of the given size
+fn immediate(value: u32, size: &Size) -> Result<Vec<u16>, &'static str> {
+    match size {
+        Size::Byte if value <= 0xFF => Ok(vec![value as u16]),
+        Size::Word if value <= 0xFFFF => Ok(vec![value as u16]),
+        Size::Long => Ok(vec![(value >> 16) as u16, value as u16]),
+        _ => Err("Immediate data out of range"),
+    }
+}
+
+// Encodes a target as a 6-bit effective address field and its extension words,
+// checking it against the addressing classes the instruction allows
+fn effective_address(
+    target: &Target,
+    size: &Size,
+    allowed: u16,
+) -> Result<(u16, Vec<u16>), &'static str> {
+    let (field, extension) = match target {
+        Target::DnDirect(reg) => (data_register(reg)?, vec![]),
+        Target::AnDirect(reg) => (1 << 3 | address_register(reg)?, vec![]),
+        Target::AnIndirect(reg) => (2 << 3 | address_register(reg)?, vec![]),
+        Target::AnIndirectPostInc(reg) => (3 << 3 | address_register(reg)?, vec![]),
+        Target::AnIndirectPreDec(reg) => (4 << 3 | address_register(reg)?, vec![]),
+        Target::AnIndirectDisplacement(reg, disp) => {
+            if !fits_i16(*disp) {
+                return Err("Displacement out of range");
+            }
+            (5 << 3 | address_register(reg)?, vec![*disp as u16])
+        }
+        Target::AnIndirectIndex(disp, reg, index) => (
+            6 << 3 | address_register(reg)?,
+            vec![brief_extension(index, *disp)?],
+        ),
+        Target::AbsoluteShortAddress(address) => {
+            if !fits_i16(*address) {
+                return Err("Absolute short address out of range");
+            }
+            (7 << 3, vec![*address as u16])
+        }
+        Target::AbsoluteLongAddress(high, low) => {
+            if *high > 0xFFFF || *low > 0xFFFF {
+                return Err("Absolute long address out of range");
+            }
+            (7 << 3 | 1, vec![*high as u16, *low as u16])
+        }
+        Target::PCIndirectDisplacement(disp, _) => {
+            if !fits_i16(*disp) {
+                return Err("Displacement out of range");
+            }
+            (7 << 3 | 2, vec![*disp as u16])
+        }
+        Target::PCIndirectIndex(disp, _, index) => {
+            (7 << 3 | 3, vec![brief_extension(index, *disp)?])
+        }
+        Target::Immediate(value) => (7 << 3 | 4, immediate(*value, size)?),
+        _ => return Err("Addressing mode not available on the 68000"),
+    };
+    if ea_kind(field) & allowed == 0 {
+        return Err("Addressing mode not allowed for this instruction");
+    }
+    if ea_kind(field) == EA_AN && *size == Size::Byte {
+        return Err("Byte size not allowed on address registers");
+    }
+    Ok((field, extension))
+}
+
+// Opcode with an effective address in the low six bits, followed by its extension words
+fn with_ea(
+    opcode: u16,
+    target: &Target,
+    size: &Size,
+    allowed: u16,
+) -> Result<Vec<u16>, &'static str> {
+    let (field, extension) = effective_address(target, size, allowed)?;
+    let mut words = vec![opcode | field];
+    words.extend(extension);
+    Ok(words)
+}
+
+// ORI, ANDI, SUBI, ADDI, EORI and CMPI: opcode, immediate data, effective address
+fn immediate_op(
+    opcode: u16,
+    value: u32,
+    dst: &Target,
+    size: &Size,
+) -> Result<Vec<u16>, &'static str> {
+    let (field, extension) = effective_address(dst, size, EA_DATA_ALTERABLE)?;
+    let mut words = vec![opcode | size_bits(size) << 6 | field];
+    words.extend(immediate(value, size)?);
+    words.extend(extension);
+    Ok(words)
+}
+
+// ADD, SUB, AND, OR, CMP and EOR in their <ea>,Dn and Dn,<ea> forms
+fn arithmetic_op(
+    opcode: u16,
+    src: &Target,
+    dst: &Target,
+    size: &Size,
+    to_register: u16,
+    to_memory: u16,
+) -> Result<Vec<u16>, &'static str> {
+    match dst {
+        Target::DnDirect(reg) if to_register != 0 => with_ea(
+            opcode | data_register(reg)? << 9 | size_bits(size) << 6,
+            src,
+            size,
+            to_register,
+        ),
+        _ => with_ea(
+            opcode | data_target(src)? << 9 | 0x0100 | size_bits(size) << 6,
+            dst,
+            size,
+            to_memory,
+        ),
+    }
+}
+
+// ADDA, SUBA and CMPA
+fn address_op(
+    opcode: u16,
+    src: &Target,
+    reg: &Registers,
+    size: &Size,
+) -> Result<Vec<u16>, &'static str> {
+    let opmode = match size {
+        Size::Word => 0x00C0,
+        Size::Long => 0x01C0,
+        Size::Byte => return Err("Byte size not allowed on address registers"),
+    };
+    with_ea(
+        opcode | address_register(reg)? << 9 | opmode,
+        src,
+        size,
+        EA_ALL,
+    )
+}
+
+// ABCD, SBCD, ADDX and SUBX: Dy,Dx or -(Ay),-(Ax)
+fn extended_op(opcode: u16, src: &Target, dst: &Target) -> Result<Vec<u16>, &'static str> {
+    match (src, dst) {
+        (Target::DnDirect(y), Target::DnDirect(x)) => {
+            Ok(vec![opcode | data_register(x)? << 9 | data_register(y)?])
+        }
+        (Target::AnIndirectPreDec(y), Target::AnIndirectPreDec(x)) => Ok(vec![
+            opcode | address_register(x)? << 9 | 0x0008 | address_register(y)?,
+        ]),
+        _ => Err("Expected Dy,Dx or -(Ay),-(Ax)"),
+    }
+}
+
+// ASd, LSd, ROXd and ROd in their register and memory forms
+fn shift_op(
+    kind: u16,
+    left: bool,
+    count: &Target,
+    dst: &Target,
+    size: &Size,
+) -> Result<Vec<u16>, &'static str> {
+    let direction = if left { 0x0100 } else { 0 };
+    match dst {
+        Target::DnDirect(reg) => {
+            let (count, register) = match count {
+                Target::Immediate(n) if (1..=8).contains(n) => (*n as u16 & 7, 0),
+                Target::DnDirect(count) => (data_register(count)?, 0x0020),
+                _ => return Err("Shift count must be #1-8 or a data register"),
+            };
+            Ok(vec![
+                0xE000
+                    | count << 9
+                    | direction
+                    | size_bits(size) << 6
+                    | register
+                    | kind << 3
+                    | data_register(reg)?,
+            ])
+        }
+        _ => {
+            if *count != Target::Immediate(1) || *size != Size::Word {
+                return Err("Memory shifts are word sized by one bit");
+            }
+            with_ea(
+                0xE0C0 | kind << 9 | direction,
+                dst,
+                size,
+                EA_MEMORY_ALTERABLE,
+            )
+        }
+    }
+}
+
+// BTST, BCHG, BCLR and BSET with a dynamic or static bit number
+fn bit_op(kind: u16, src: &Target, dst: &Target, size: &Size) -> Result<Vec<u16>, &'static str> {
+    let expected = match dst {
+        Target::DnDirect(_) => Size::Long,
+        _ => Size::Byte,
+    };
+    if *size != expected {
+        return Err("Bit operations are long on registers and byte in memory");
+    }
+    match src {
+        Target::DnDirect(reg) => {
+            let allowed = if kind == 0 {
+                EA_DATA
+            } else {
+                EA_DATA_ALTERABLE
+            };
+            with_ea(
+                0x0100 | data_register(reg)? << 9 | kind << 6,
+                dst,
+                size,
+                allowed,
+            )
+        }
+        Target::Immediate(bit) if *bit <= 0xFF => {
+            let allowed = if kind == 0 {
+                EA_DATA & !EA_IMMEDIATE
+            } else {
+                EA_DATA_ALTERABLE
+            };
+            let (field, extension) = effective_address(dst, size, allowed)?;
+            let mut words = vec![0x0800 | kind << 6 | field, *bit as u16];
+            words.extend(extension);
+            Ok(words)
+        }
+        _ => Err("Bit number must be immediate or a data register"),
+    }
+}
+
+// Bcc, BRA and BSR with the shortest displacement that reaches the target
+fn branch(cc: u16, displacement: i32) -> Result<Vec<u16>, &'static str> {
+    if displacement != 0 && fits_i8(displacement) {
+        Ok(vec![0x6000 | cc << 8 | (displacement as u16 & 0xFF)])
+    } else if fits_i16(displacement) {
+        Ok(vec![0x6000 | cc << 8, displacement as u16])
+    } else {
+        Err("Branch displacement out of range")
+    }
+}
+
+// ADDQ and SUBQ with data 1-8
+fn quick_op(opcode: u16, data: u32, dst: &Target, size: &Size) -> Result<Vec<u16>, &'static str> {
+    if !(1..=8).contains(&data) {
+        return Err("Quick data must be 1-8");
+    }
+    with_ea(
+        opcode | (data as u16 & 7) << 9 | size_bits(size) << 6,
+        dst,
+        size,
+        EA_ALTERABLE,
+    )
+}
+
+fn single_op(opcode: u16, dst: &Target, size: &Size) -> Result<Vec<u16>, &'static str> {
+    with_ea(opcode | size_bits(size) << 6, dst, size, EA_DATA_ALTERABLE)
+}
+
+fn word_only(size: &Size) -> Result<(), &'static str> {
+    match size {
+        Size::Word => Ok(()),
+        _ => Err("Only word size is available on the 68000"),
+    }
+}
+
+// Encodes an instruction into its opcode word followed by its extension words
+pub fn encode(ins: &Instructions) -> Result<Vec<u16>, &'static str> {
+    match ins {
+        Instructions::ABCD(src, dst) => extended_op(0xC100, src, dst),
+        Instructions::ADD(src, dst, size) => {
+            arithmetic_op(0xD000, src, dst, size, EA_ALL, EA_MEMORY_ALTERABLE)
+        }
+        Instructions::ADDA(src, reg, size) => address_op(0xD000, src, reg, size),
+        Instructions::ADDI(imm, dst, size) => immediate_op(0x0600, *imm, dst, size),
+        Instructions::ADDQ(imm, dst, size) => quick_op(0x5000, *imm, dst, size),
+        Instructions::ADDX(src, dst, size) => extended_op(0xD100 | size_bits(size) << 6, src, dst),
+        Instructions::AND(src, dst, size) => {
+            arithmetic_op(0xC000, src, dst, size, EA_DATA, EA_MEMORY_ALTERABLE)
+        }
+        Instructions::ANDI(imm, dst, size) => immediate_op(0x0200, *imm, dst, size),
+        Instructions::ANDItoCCR(imm) => Ok(vec![0x023C, *imm as u16]),
+        Instructions::ANDItoSR(imm) => Ok(vec![0x027C, *imm]),
+        Instructions::ASL(src, dst, size) => shift_op(0, true, src, dst, size),
+        Instructions::ASR(src, dst, size) => shift_op(0, false, src, dst, size),
+        Instructions::Bcc(cond, label) => match cond {
+            Condition::True | Condition::False => Err("Use BRA or BSR"),
+            _ => branch(condition_code(cond), *label),
+        },
+        Instructions::BCHG(src, dst, size) => bit_op(1, src, dst, size),
+        Instructions::BCLR(src, dst, size) => bit_op(2, src, dst, size),
+        Instructions::BRA(label) => branch(0, *label),
+        Instructions::BSET(src, dst, size) => bit_op(3, src, dst, size),
+        Instructions::BSR(label) => branch(1, *label),
+        Instructions::BTST(src, dst, size) => bit_op(0, src, dst, size),
+        Instructions::CHK(src, reg, size) => {
+            word_only(size)?;
+            with_ea(0x4180 | data_register(reg)? << 9, src, size, EA_DATA)
+        }
+        Instructions::CLR(dst, size) => single_op(0x4200, dst, size),
+        Instructions::CMP(src, dst, size) => arithmetic_op(0xB000, src, dst, size, EA_ALL, 0),
+        Instructions::CMPA(src, reg, size) => address_op(0xB000, src, reg, size),
+        Instructions::CMPI(imm, dst, size) => immediate_op(0x0C00, *imm, dst, size),
+        Instructions::CMPM(src, dst, size) => match (src, dst) {
+            (Target::AnIndirectPostInc(y), Target::AnIndirectPostInc(x)) => Ok(vec![
+                0xB108 | address_register(x)? << 9 | size_bits(size) << 6 | address_register(y)?,
+            ]),
+            _ => Err("Expected (Ay)+,(Ax)+"),
+        },
+        Instructions::DBcc(cond, reg, label) => Ok(vec![
+            0x50C8 | condition_code(cond) << 8 | data_register(reg)?,
+            *label as u16,
+        ]),
+        Instructions::DIVSW(src, reg, size) => {
+            word_only(size)?;
+            with_ea(0x81C0 | data_register(reg)? << 9, src, size, EA_DATA)
+        }
+        Instructions::DIVUW(src, reg, size) => {
+            word_only(size)?;
+            with_ea(0x80C0 | data_register(reg)? << 9, src, size, EA_DATA)
+        }
+        Instructions::EOR(src, dst, size) => {
+            arithmetic_op(0xB000, src, dst, size, 0, EA_DATA_ALTERABLE)
+        }
+        Instructions::EORtoCCR(imm) => Ok(vec![0x0A3C, *imm as u16]),
+        Instructions::EORtoSR(imm) => Ok(vec![0x0A7C, *imm]),
+        Instructions::EORI(imm, dst, size) => immediate_op(0x0A00, *imm, dst, size),
+        Instructions::EXG(src, dst) => match (src, dst) {
+            (Target::DnDirect(x), Target::DnDirect(y)) => {
+                Ok(vec![0xC140 | data_register(x)? << 9 | data_register(y)?])
+            }
+            (Target::AnDirect(x), Target::AnDirect(y)) => Ok(vec![
+                0xC148 | address_register(x)? << 9 | address_register(y)?,
+            ]),
+            (Target::DnDirect(x), Target::AnDirect(y))
+            | (Target::AnDirect(y), Target::DnDirect(x)) => {
+                Ok(vec![0xC188 | data_register(x)? << 9 | address_register(y)?])
+            }
+            _ => Err("EXG needs two registers"),
+        },
+        Instructions::EXT(reg, from, to) => match (from, to) {
+            (Size::Byte, Size::Word) => Ok(vec![0x4880 | data_register(reg)?]),
+            (Size::Word, Size::Long) => Ok(vec![0x48C0 | data_register(reg)?]),
+            _ => Err("Sign extension not available on the 68000"),
+        },
+        Instructions::ILLEGAL => Ok(vec![0x4AFC]),
+        Instructions::JMP(dst) => with_ea(0x4EC0, dst, &Size::Long, EA_CONTROL),
+        Instructions::JSR(dst) => with_ea(0x4E80, dst, &Size::Long, EA_CONTROL),
+        Instructions::LEA(src, reg) => with_ea(
+            0x41C0 | address_register(reg)? << 9,
+            src,
+            &Size::Long,
+            EA_CONTROL,
+        ),
+        Instructions::LINK(reg, disp) => {
+            if !fits_i16(*disp) {
+                return Err("Displacement out of range");
+            }
+            Ok(vec![0x4E50 | address_register(reg)?, *disp as u16])
+        }
+        Instructions::LSL(src, dst, size) => shift_op(1, true, src, dst, size),
+        Instructions::LSR(src, dst, size) => shift_op(1, false, src, dst, size),
+        Instructions::MOVE(src, dst, size) => {
+            let size_field = match size {
+                Size::Byte => 1,
+                Size::Word => 3,
+                Size::Long => 2,
+            };
+            let (src_field, src_extension) = effective_address(src, size, EA_ALL)?;
+            let (dst_field, dst_extension) = effective_address(dst, size, EA_DATA_ALTERABLE)?;
+            let mut words =
+                vec![size_field << 12 | (dst_field & 7) << 9 | (dst_field >> 3) << 6 | src_field];
+            words.extend(src_extension);
+            words.extend(dst_extension);
+            Ok(words)
+        }
+        Instructions::MOVEA(src, reg, size) => {
+            let size_field = match size {
+                Size::Word => 3,
+                Size::Long => 2,
+                Size::Byte => return Err("Byte size not allowed on address registers"),
+            };
+            with_ea(
+                size_field << 12 | address_register(reg)? << 9 | 0x0040,
+                src,
+                size,
+                EA_ALL,
+            )
+        }
+        Instructions::MOVEfromCCR(dst) => with_ea(0x42C0, dst, &Size::Word, EA_DATA_ALTERABLE),
+        Instructions::MOVEtoCCR(src) => with_ea(0x44C0, src, &Size::Word, EA_DATA),
+        Instructions::MOVEfromSR(dst) => with_ea(0x40C0, dst, &Size::Word, EA_DATA_ALTERABLE),
+        Instructions::MOVEtoSR(src) => with_ea(0x46C0, src, &Size::Word, EA_DATA),
+        Instructions::MOVEUSP(reg, dir) => {
+            let dir = match dir {
+                Direction::RegisterToMemory => 0,
+                Direction::MemoryToRegister => 0x0008,
+            };
+            Ok(vec![0x4E60 | dir | address_target(reg)?])
+        }
+        Instructions::MOVEC(reg, control, dir) => {
+            let opcode = match dir {
+                Direction::RegisterToMemory => 0x4E7B,
+                Direction::MemoryToRegister => 0x4E7A,
+            };
+            let register = match reg {
+                Target::DnDirect(reg) => data_register(reg)? << 12,
+                Target::AnDirect(reg) => 0x8000 | address_register(reg)? << 12,
+                _ => return Err("MOVEC needs a general register"),
+            };
+            let control = match control {
+                ControlRegister::VBR => 0x0801,
+            };
+            Ok(vec![opcode, register | control])
+        }
+        Instructions::MOVEM(target, size, mask, dir) => {
+            let (opcode, allowed) = match dir {
+                Direction::RegisterToMemory => (0x4880, EA_CONTROL_ALTERABLE | EA_PREDEC),
+                Direction::MemoryToRegister => (0x4C80, EA_CONTROL | EA_POSTINC),
+            };
+            let size_bit = match size {
+                Size::Word => 0,
+                Size::Long => 0x0040,
+                Size::Byte => return Err("MOVEM is word or long sized"),
+            };
+            let (field, extension) = effective_address(target, size, allowed)?;
+            let mut words = vec![opcode | size_bit | field, *mask as u16];
+            words.extend(extension);
+            Ok(words)
+        }
+        Instructions::MOVEP(data, address, disp, size, dir) => {
+            let opmode = match (dir, size) {
+                (Direction::MemoryToRegister, Size::Word) => 4,
+                (Direction::MemoryToRegister, Size::Long) => 5,
+                (Direction::RegisterToMemory, Size::Word) => 6,
+                (Direction::RegisterToMemory, Size::Long) => 7,
+                _ => return Err("MOVEP is word or long sized"),
+            };
+            Ok(vec![
+                data_register(data)? << 9 | opmode << 6 | 0x0008 | address_register(address)?,
+                *disp as u16,
+            ])
+        }
+        Instructions::MOVEQ(imm, reg) => Ok(vec![0x7000 | data_register(reg)? << 9 | *imm as u16]),
+        Instructions::MULSW(src, reg) => {
+            with_ea(0xC1C0 | data_register(reg)? << 9, src, &Size::Word, EA_DATA)
+        }
+        Instructions::MULUW(src, reg) => {
+            with_ea(0xC0C0 | data_register(reg)? << 9, src, &Size::Word, EA_DATA)
+        }
+        Instructions::NBCD(dst) => with_ea(0x4800, dst, &Size::Byte, EA_DATA_ALTERABLE),
+        Instructions::NEG(dst, size) => single_op(0x4400, dst, size),
+        Instructions::NEGX(dst, size) => single_op(0x4000, dst, size),
+        Instructions::NOP => Ok(vec![0x4E71]),
+        Instructions::NOT(dst, size) => single_op(0x4600, dst, size),
+        Instructions::OR(src, dst, size) => {
+            arithmetic_op(0x8000, src, dst, size, EA_DATA, EA_MEMORY_ALTERABLE)
+        }
+        Instructions::ORI(imm, dst, size) => immediate_op(0x0000, *imm, dst, size),
+        Instructions::ORItoCCR(imm) => Ok(vec![0x003C, immediate(*imm, &Size::Byte)?[0]]),
+        Instructions::ORItoSR(imm) => Ok(vec![0x007C, immediate(*imm, &Size::Word)?[0]]),
+        Instructions::PEA(src) => with_ea(0x4840, src, &Size::Long, EA_CONTROL),
+        Instructions::RESET => Ok(vec![0x4E70]),
+        Instructions::ROL(src, dst, size) => shift_op(3, true, src, dst, size),
+        Instructions::ROR(src, dst, size) => shift_op(3, false, src, dst, size),
+        Instructions::ROXL(src, dst, size) => shift_op(2, true, src, dst, size),
+        Instructions::ROXR(src, dst, size) => shift_op(2, false, src, dst, size),
+        Instructions::RTD(disp) => Ok(vec![0x4E74, *disp as u16]),
+        Instructions::RTE => Ok(vec![0x4E73]),
+        Instructions::RTR => Ok(vec![0x4E77]),
+        Instructions::RTS => Ok(vec![0x4E75]),
+        Instructions::SBCD(src, dst) => extended_op(0x8100, src, dst),
+        Instructions::Scc(cond, dst) => with_ea(
+            0x50C0 | condition_code(cond) << 8,
+            dst,
+            &Size::Byte,
+            EA_DATA_ALTERABLE,
+        ),
+        Instructions::STOP(imm) => Ok(vec![0x4E72, *imm]),
+        Instructions::SUB(src, dst, size) => {
+            arithmetic_op(0x9000, src, dst, size, EA_ALL, EA_MEMORY_ALTERABLE)
+        }
+        Instructions::SUBA(src, reg, size) => address_op(0x9000, src, reg, size),
+        Instructions::SUBI(imm, dst, size) => immediate_op(0x0400, *imm, dst, size),
+        Instructions::SUBQ(imm, dst, size) => quick_op(0x5100, *imm, dst, size),
+        Instructions::SUBX(src, dst, size) => extended_op(0x9100 | size_bits(size) << 6, src, dst),
+        Instructions::SWAP(reg) => Ok(vec![0x4840 | data_register(reg)?]),
+        Instructions::TAS(dst) => with_ea(0x4AC0, dst, &Size::Byte, EA_DATA_ALTERABLE),
+        Instructions::TRAP(vector) => {
+            if *vector > 15 {
+                return Err("Trap vector out of range");
+            }
+            Ok(vec![0x4E40 | *vector as u16])
+        }
+        Instructions::TRAPV => Ok(vec![0x4E76]),
+        Instructions::TST(dst, size) => single_op(0x4A00, dst, size),
+        Instructions::UNLK(reg) => Ok(vec![0x4E58 | address_register(reg)?]),
+        Instructions::NotImplemented => Err("Instruction not implemented"),
+    }
+}
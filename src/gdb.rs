@@ -0,0 +1,443 @@
+use std::io::{self, Read, Write};
+use std::net::{TcpListener, TcpStream};
+
+use crate::cpu::Registers;
+use crate::debugger::{Debugger, Stop, WatchKind, Watchpoint};
+use crate::memory::Memory;
+use crate::recorder::Recorder;
+
+const SIGINT: u8 = 2;
+const SIGILL: u8 = 4;
+const SIGTRAP: u8 = 5;
+
+// Largest packet GDB may send or take, as advertised in qSupported
+const PACKET_SIZE: u32 = 0x4000;
+// Bytes of memory that fit a reply packet as hex
+const MAX_READ: u32 = PACKET_SIZE / 2;
+
+const TARGET_XML: &str = r#"<?xml version="1.0"?>
+<!DOCTYPE target SYSTEM "gdb-target.dtd">
+<target version="1.0">
+  <architecture>m68k:68000</architecture>
+  <feature name="org.gnu.gdb.m68k.core">
+    <reg name="d0" bitsize="32"/>
+    <reg name="d1" bitsize="32"/>
+    <reg name="d2" bitsize="32"/>
+    <reg name="d3" bitsize="32"/>
+    <reg name="d4" bitsize="32"/>
+    <reg name="d5" bitsize="32"/>
+    <reg name="d6" bitsize="32"/>
+    <reg name="d7" bitsize="32"/>
+    <reg name="a0" bitsize="32" type="data_ptr"/>
+    <reg name="a1" bitsize="32" type="data_ptr"/>
+    <reg name="a2" bitsize="32" type="data_ptr"/>
+    <reg name="a3" bitsize="32" type="data_ptr"/>
+    <reg name="a4" bitsize="32" type="data_ptr"/>
+    <reg name="a5" bitsize="32" type="data_ptr"/>
+    <reg name="fp" bitsize="32" type="data_ptr"/>
+    <reg name="sp" bitsize="32" type="data_ptr"/>
+    <reg name="ps" bitsize="32"/>
+    <reg name="pc" bitsize="32" type="code_ptr"/>
+  </feature>
+</target>
+"#;
+
+// GDB remote serial protocol server for one debugger session
+pub struct GdbStub<'a> {
+    debugger: &'a mut Debugger,
+    stream: TcpStream,
+    // Bytes received but not yet parsed into packets
+    input: Vec<u8>,
+}
+
+// Waits for GDB on localhost:port and serves it until it detaches or kills
+// the session
+pub fn serve(debugger: &mut Debugger, port: u16) -> io::Result<()> {
+    let listener = TcpListener::bind(("127.0.0.1", port))?;
+    println!("Waiting for GDB on localhost:{}", port);
+    let (stream, peer) = listener.accept()?;
+    println!("GDB connected from {}", peer);
+    stream.set_nodelay(true)?;
+    // GDB steps backwards with bs and bc without asking to record first
+    if debugger.recorder.is_none() {
+        debugger.recorder = Some(Recorder::new());
+    }
+    let mut stub = GdbStub {
+        debugger,
+        stream,
+        input: Vec::new(),
+    };
+    stub.session()
+}
+
+impl GdbStub<'_> {
+    fn session(&mut self) -> io::Result<()> {
+        while let Some(packet) = self.packet()? {
+            let reply = match packet.as_str() {
+                "D" => {
+                    self.send("OK")?;
+                    return Ok(());
+                }
+                "k" => return Ok(()),
+                _ => self.handle(&packet),
+            };
+            self.send(&reply)?;
+        }
+        Ok(())
+    }
+
+    // Reply to one packet; an empty reply tells GDB the packet is unsupported
+    fn handle(&mut self, packet: &str) -> String {
+        let mut chars = packet.chars();
+        let Some(command) = chars.next() else {
+            return String::new();
+        };
+        let body = chars.as_str();
+        match command {
+            '?' => format!("S{:02x}", SIGTRAP),
+            'g' => (0..Registers::COUNT)
+                .map(|n| format!("{:08x}", self.debugger.cpu.registers.get(n)))
+                .collect(),
+            'G' => {
+                let values: Option<Vec<u32>> = (0..Registers::COUNT)
+                    .map(|n| body.get(n * 8..n * 8 + 8).and_then(parse_hex))
+                    .collect();
+                let Some(values) = values else {
+                    return "E01".to_string();
+                };
+                // SR first, so that SP lands in the stack pointer of the new mode
+                self.set_register(Registers::SR, values[Registers::SR]);
+                for (n, value) in values.into_iter().enumerate() {
+                    if n != Registers::SR {
+                        self.set_register(n, value);
+                    }
+                }
+                "OK".to_string()
+            }
+            'p' => match parse_hex(body) {
+                Some(n) if (n as usize) < Registers::COUNT => {
+                    format!("{:08x}", self.debugger.cpu.registers.get(n as usize))
+                }
+                _ => "E01".to_string(),
+            },
+            'P' => match body
+                .split_once('=')
+                .map(|(n, v)| (parse_hex(n), parse_hex(v)))
+            {
+                Some((Some(n), Some(value))) if (n as usize) < Registers::COUNT => {
+                    self.set_register(n as usize, value);
+                    "OK".to_string()
+                }
+                _ => "E01".to_string(),
+            },
+            'm' => self.read_memory(body).unwrap_or_else(|| "E01".to_string()),
+            'M' => self.write_memory(body).unwrap_or_else(|| "E01".to_string()),
+            'Z' | 'z' => self.breakpoint(command == 'Z', body),
+            's' | 'c' => {
+                if let Some(address) = parse_hex(body) {
+                    self.debugger.cpu.registers.PC = address;
+                }
+                self.resume(command == 's')
+            }
+            'b' if body == "s" || body == "c" => self.reverse(body == "s"),
+            'v' => self.v_packet(packet),
+            'q' => self.query(packet),
+            'H' => "OK".to_string(),
+            'T' => "OK".to_string(),
+            _ => String::new(),
+        }
+    }
+
+    // Writes register n in GDB's numbering. SR goes through set_sr, so that
+    // toggling S swaps the stack pointers as the CPU would.
+    fn set_register(&mut self, n: usize, value: u32) {
+        let cpu = &mut self.debugger.cpu;
+        match n {
+            Registers::SR => cpu.set_sr(value as u16),
+            n => cpu.registers.set(n, value),
+        }
+    }
+
+    fn query(&mut self, packet: &str) -> String {
+        if packet.starts_with("qSupported") {
+            return format!("PacketSize={:x};qXfer:features:read+;swbreak+;hwbreak+;vContSupported+;ReverseStep+;ReverseContinue+", PACKET_SIZE);
+        }
+        if let Some(annex) = packet.strip_prefix("qXfer:features:read:target.xml:") {
+            let Some((offset, length)) = annex.split_once(',') else {
+                return "E01".to_string();
+            };
+            let (Some(offset), Some(length)) = (parse_hex(offset), parse_hex(length)) else {
+                return "E01".to_string();
+            };
+            let start = (offset as usize).min(TARGET_XML.len());
+            let end = (start + length as usize).min(TARGET_XML.len());
+            let more = if end < TARGET_XML.len() { 'm' } else { 'l' };
+            return format!("{}{}", more, &TARGET_XML[start..end]);
+        }
+        match packet {
+            "qAttached" => "1".to_string(),
+            "qC" => "QC1".to_string(),
+            "qfThreadInfo" => "m1".to_string(),
+            "qsThreadInfo" => "l".to_string(),
+            "qSymbol::" => "OK".to_string(),
+            _ => String::new(),
+        }
+    }
+
+    fn v_packet(&mut self, packet: &str) -> String {
+        if packet == "vCont?" {
+            return "vCont;c;C;s;S".to_string();
+        }
+        // One thread, so the first action applies to it whatever its thread id
+        match packet.strip_prefix("vCont;") {
+            Some(actions) => match actions.split(';').next().and_then(|a| a.bytes().next()) {
+                Some(b'c') | Some(b'C') => self.resume(false),
+                Some(b's') | Some(b'S') => self.resume(true),
+                _ => "E01".to_string(),
+            },
+            None => String::new(),
+        }
+    }
+
+    fn breakpoint(&mut self, insert: bool, body: &str) -> String {
+        let mut fields = body.split(',');
+        let (Some(kind), Some(address), Some(length)) = (
+            fields.next(),
+            fields.next().and_then(parse_hex),
+            fields.next().and_then(parse_hex),
+        ) else {
+            return "E01".to_string();
+        };
+        let watch = match kind {
+            // Software and hardware breakpoints are the same to the emulator
+            "0" | "1" => {
+                if insert {
+                    self.debugger.breakpoints.entry(address).or_default();
+                } else {
+                    self.debugger.breakpoints.remove(&address);
+                }
+                return "OK".to_string();
+            }
+            "2" => WatchKind::Write,
+            "3" => WatchKind::Read,
+            "4" => WatchKind::Access,
+            _ => return String::new(),
+        };
+        if insert {
+            let watchpoint = Watchpoint::new(watch, address, length);
+            self.debugger.watchpoints.push(watchpoint);
+        } else {
+            self.debugger
+                .watchpoints
+                .retain(|w| (w.kind, w.address, w.length) != (watch, address, length));
+        }
+        "OK".to_string()
+    }
+
+    fn resume(&mut self, step: bool) -> String {
+        let stop = if step {
+            match self.debugger.step_one() {
+                Some(stop) => stop,
+                None => return self.stepped(),
+            }
+        } else {
+            let stream = &mut self.stream;
+            let input = &mut self.input;
+            self.debugger.run(None, &mut || interrupted(stream, input))
+        };
+        self.debugger.flush_trace();
+        stop_reply(&stop)
+    }
+
+    // Runs backwards for bs and bc
+    fn reverse(&mut self, step: bool) -> String {
+        let stop = if step {
+            match self.debugger.reverse_step() {
+                Some(stop) => stop,
+                None => return self.stepped(),
+            }
+        } else {
+            let stream = &mut self.stream;
+            let input = &mut self.input;
+            self.debugger
+                .reverse_run(&mut || interrupted(stream, input))
+        };
+        stop_reply(&stop)
+    }
+
+    fn stepped(&mut self) -> String {
+        self.debugger.flush_trace();
+        format!("S{:02x}", SIGTRAP)
+    }
+
+    fn read_memory(&mut self, body: &str) -> Option<String> {
+        let (address, length) = body.split_once(',')?;
+        let (address, length) = (parse_hex(address)?, parse_hex(length)?);
+        let mem = &self.debugger.cpu.memory_bus.memory;
+        // GDB accepts fewer bytes than it asked for
+        (0..length.min(MAX_READ))
+            .map(|offset| {
+                mem.read_at_address_byte(address.wrapping_add(offset))
+                    .map(|byte| format!("{:02x}", byte))
+            })
+            .collect()
+    }
+
+    fn write_memory(&mut self, body: &str) -> Option<String> {
+        let (range, data) = body.split_once(':')?;
+        let (address, length) = range.split_once(',')?;
+        let (address, length) = (parse_hex(address)?, parse_hex(length)?);
+        if data.len() != length as usize * 2 {
+            return None;
+        }
+        let mem = &mut self.debugger.cpu.memory_bus.memory;
+        for offset in 0..length {
+            let at = offset as usize * 2;
+            let byte = u8::from_str_radix(data.get(at..at + 2)?, 16).ok()?;
+            mem.write_at_address_byte(address.wrapping_add(offset), byte)
+                .ok()?;
+        }
+        Some("OK".to_string())
+    }
+
+    // Next packet with a good checksum, acknowledging each one; None when GDB
+    // closes the connection
+    fn packet(&mut self) -> io::Result<Option<String>> {
+        loop {
+            if let Some(start) = self.input.iter().position(|b| *b == b'$') {
+                if let Some(hash) = self.input[start..].iter().position(|b| *b == b'#') {
+                    let end = start + hash;
+                    if self.input.len() >= end + 3 {
+                        let data = self.input[start + 1..end].to_vec();
+                        let checksum = std::str::from_utf8(&self.input[end + 1..end + 3])
+                            .ok()
+                            .and_then(|c| u8::from_str_radix(c, 16).ok());
+                        self.input.drain(..end + 3);
+                        if checksum != Some(sum(&data)) {
+                            self.stream.write_all(b"-")?;
+                            continue;
+                        }
+                        self.stream.write_all(b"+")?;
+                        return Ok(Some(String::from_utf8_lossy(&data).into_owned()));
+                    }
+                }
+            } else {
+                // Acks and stray interrupts between packets carry no request
+                self.input.clear();
+            }
+            let mut buffer = [0; 4096];
+            match self.stream.read(&mut buffer)? {
+                0 => return Ok(None),
+                count => self.input.extend_from_slice(&buffer[..count]),
+            }
+        }
+    }
+
+    fn send(&mut self, reply: &str) -> io::Result<()> {
+        let packet = format!("${}#{:02x}", reply, sum(reply.as_bytes()));
+        self.stream.write_all(packet.as_bytes())?;
+        self.stream.flush()
+    }
+}
+
+// Whether GDB sent a break (0x03) while the target runs, keeping any other
+// bytes for the packet reader
+fn interrupted(stream: &mut TcpStream, input: &mut Vec<u8>) -> bool {
+    let mut buffer = [0; 256];
+    if stream.set_nonblocking(true).is_err() {
+        return false;
+    }
+    let read = stream.read(&mut buffer);
+    let _ = stream.set_nonblocking(false);
+    match read {
+        Ok(count) if count > 0 => {
+            let data = &buffer[..count];
+            input.extend(data.iter().filter(|b| **b != 0x03));
+            data.contains(&0x03)
+        }
+        _ => false,
+    }
+}
+
+fn stop_reply(stop: &Stop) -> String {
+    match stop {
+        Stop::Breakpoint(_) => format!("T{:02x}swbreak:;", SIGTRAP),
+        Stop::Watchpoint(kind, access) => {
+            let name = match kind {
+                WatchKind::Write => "watch",
+                WatchKind::Read => "rwatch",
+                WatchKind::Access => "awatch",
+            };
+            format!("T{:02x}{}:{:x};", SIGTRAP, name, access.address)
+        }
+        Stop::Interrupted => format!("S{:02x}", SIGINT),
+        Stop::HistoryStart => format!("T{:02x}replaylog:begin;", SIGTRAP),
+        Stop::Exited(status) => format!("W{:02x}", *status as u8),
+        Stop::Fault(_) => format!("S{:02x}", SIGILL),
+    }
+}
+
+fn sum(data: &[u8]) -> u8 {
+    data.iter().fold(0u8, |acc, b| acc.wrapping_add(*b))
+}
+
+fn parse_hex(text: &str) -> Option<u32> {
+    u32::from_str_radix(text, 16).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::{TcpListener, TcpStream};
+
+    use super::{GdbStub, MAX_READ};
+    use crate::cpu::CPU;
+    use crate::debugger::Debugger;
+
+    #[test]
+    fn malformed_packets_get_an_error_or_empty_reply() {
+        let listener = TcpListener::bind(("127.0.0.1", 0)).unwrap();
+        let _gdb = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (stream, _) = listener.accept().unwrap();
+        let mut debugger = Debugger::new(CPU::with_memory(0x10000));
+        let mut stub = GdbStub {
+            debugger: &mut debugger,
+            stream,
+            input: Vec::new(),
+        };
+        for packet in ["", "é", "vCont;", "vCont;;c", "G12", "Z0", "m0,ffffffff"] {
+            let reply = stub.handle(packet);
+            assert!(
+                reply.is_empty() || reply.starts_with('E') || packet.starts_with('m'),
+                "{:?} got {:?}",
+                packet,
+                reply
+            );
+        }
+        assert_eq!(stub.handle("m0,ffffffff").len(), MAX_READ as usize * 2);
+    }
+
+    #[test]
+    fn writing_sr_switches_stack_pointers() {
+        let listener = TcpListener::bind(("127.0.0.1", 0)).unwrap();
+        let _gdb = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (stream, _) = listener.accept().unwrap();
+        let mut debugger = Debugger::new(CPU::with_memory(0x10000));
+        debugger.cpu.set_sr(0x2700);
+        debugger.cpu.registers.SP = 0x8000;
+        debugger.cpu.registers.ALT_SP = 0x4000;
+        let mut stub = GdbStub {
+            debugger: &mut debugger,
+            stream,
+            input: Vec::new(),
+        };
+        assert_eq!(stub.handle("P10=00000000"), "OK");
+        assert_eq!(stub.handle("pf"), "00004000");
+
+        // G sets SR before SP, so SP is the stack of the mode it selects
+        let mut registers = "00000000".repeat(15);
+        registers.push_str("00001000000027000000abcd");
+        assert_eq!(stub.handle(&format!("G{}", registers)), "OK");
+        let r = &stub.debugger.cpu.registers;
+        assert_eq!((r.SP, r.ALT_SP, r.PC), (0x1000, 0x4000, 0xABCD));
+    }
+}
@@ -1,27 +1,30 @@
 use core::fmt;
-use std::fmt::write;
 
 type LongLabel = i32;
 type WordLabel = i16;
 
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum Size {
     Byte,
     Word,
     Long,
 }
 
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum Direction {
     RegisterToMemory,
     MemoryToRegister,
 }
 
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub struct IndexRegister {
-    register: Registers,
-    size: Size,
-    scale: u8,
+    pub register: Registers,
+    pub size: Size,
+    pub scale: u8,
 }
 
 #[allow(non_snake_case)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum Registers {
     /* Data Registers */
     D0,
@@ -48,6 +51,7 @@ pub enum Registers {
     SR,
 }
 
+#[derive(Debug, Clone, PartialEq)]
 pub enum Target {
     DnDirect(Registers),
     AnDirect(Registers),
@@ -89,7 +93,10 @@ pub enum Target {
     Immediate(u32),
 }
 
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum Condition {
+    True,
+    False,
     CarryClear,
     CarrySet,
     Equal,
@@ -106,10 +113,75 @@ pub enum Condition {
     OverflowSet,
 }
 
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum ControlRegister {
     VBR,
 }
 
+impl Registers {
+    // Data register Dn for the 3-bit register number n
+    pub fn data(n: u16) -> Self {
+        match n & 7 {
+            0 => Registers::D0,
+            1 => Registers::D1,
+            2 => Registers::D2,
+            3 => Registers::D3,
+            4 => Registers::D4,
+            5 => Registers::D5,
+            6 => Registers::D6,
+            _ => Registers::D7,
+        }
+    }
+
+    // Address register An for the 3-bit register number n, A7 being SP
+    pub fn address(n: u16) -> Self {
+        match n & 7 {
+            0 => Registers::A0,
+            1 => Registers::A1,
+            2 => Registers::A2,
+            3 => Registers::A3,
+            4 => Registers::A4,
+            5 => Registers::A5,
+            6 => Registers::A6,
+            _ => Registers::SP,
+        }
+    }
+
+    pub fn is_data(&self) -> bool {
+        self.number().is_some() && !self.is_address()
+    }
+
+    pub fn is_address(&self) -> bool {
+        matches!(
+            self,
+            Registers::A0
+                | Registers::A1
+                | Registers::A2
+                | Registers::A3
+                | Registers::A4
+                | Registers::A5
+                | Registers::A6
+                | Registers::SP
+        )
+    }
+
+    // Register number as it appears in register fields of the opcode
+    pub fn number(&self) -> Option<u16> {
+        match self {
+            Registers::D0 | Registers::A0 => Some(0),
+            Registers::D1 | Registers::A1 => Some(1),
+            Registers::D2 | Registers::A2 => Some(2),
+            Registers::D3 | Registers::A3 => Some(3),
+            Registers::D4 | Registers::A4 => Some(4),
+            Registers::D5 | Registers::A5 => Some(5),
+            Registers::D6 | Registers::A6 => Some(6),
+            Registers::D7 | Registers::SP => Some(7),
+            Registers::PC | Registers::SR => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
 pub enum Instructions {
     // Add Decimal With Extend: Dst + Src + X -> Dst
     ABCD(Target, Target),
@@ -127,6 +199,10 @@ pub enum Instructions {
     AND(Target, Target, Size),
     // And Immediate: Dst & Immediate Data -> Dst
     ANDI(u32, Target, Size),
+    // And Immediate to CCR: Src & CCR -> CCR
+    ANDItoCCR(u8),
+    // And Immediate to SR: if supervisor state then Src & SR -> SR else TRAP
+    ANDItoSR(u16),
     // Arithmetic Shift Left: Dst << n -> Dst
     ASL(Target, Target, Size),
     // Arithmetic Shift Right: Dst >> n -> Dst
@@ -285,162 +361,397 @@ pub enum Instructions {
     NotImplemented,
 }
 
+impl fmt::Display for Size {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Size::Byte => write!(f, "b"),
+            Size::Word => write!(f, "w"),
+            Size::Long => write!(f, "l"),
+        }
+    }
+}
+
+impl fmt::Display for Registers {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Registers::PC => write!(f, "PC"),
+            Registers::SR => write!(f, "SR"),
+            reg if reg.is_address() => write!(f, "A{}", reg.number().unwrap()),
+            reg => write!(f, "D{}", reg.number().unwrap()),
+        }
+    }
+}
+
+impl fmt::Display for Condition {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Condition::True => "t",
+            Condition::False => "f",
+            Condition::High => "hi",
+            Condition::Low => "ls",
+            Condition::CarryClear => "cc",
+            Condition::CarrySet => "cs",
+            Condition::Neq => "ne",
+            Condition::Equal => "eq",
+            Condition::OverflowClear => "vc",
+            Condition::OverflowSet => "vs",
+            Condition::Plus => "pl",
+            Condition::Minus => "mi",
+            Condition::Geq => "ge",
+            Condition::Lt => "lt",
+            Condition::Gt => "gt",
+            Condition::Leq => "le",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+impl fmt::Display for ControlRegister {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ControlRegister::VBR => write!(f, "VBR"),
+        }
+    }
+}
+
+impl fmt::Display for IndexRegister {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}.{}", self.register, self.size)?;
+        if self.scale > 1 {
+            write!(f, "*{}", self.scale)?;
+        }
+        Ok(())
+    }
+}
+
+// Hexadecimal with the sign in front of the $, as in -$8
+fn signed_hex(value: i32) -> String {
+    if value < 0 {
+        format!("-${:x}", value.unsigned_abs())
+    } else {
+        format!("${:x}", value)
+    }
+}
+
+// Base displacement, base register and index of the 68020 memory indirect
+// forms, leaving out whatever is suppressed
+fn indirect_parts(
+    disp: &Option<i32>,
+    base: Option<Registers>,
+    index: &Option<IndexRegister>,
+) -> Vec<String> {
+    let mut parts = Vec::new();
+    if let Some(disp) = disp {
+        parts.push(signed_hex(*disp));
+    }
+    if let Some(base) = base {
+        parts.push(base.to_string());
+    }
+    if let Some(index) = index {
+        parts.push(index.to_string());
+    }
+    parts
+}
+
 impl fmt::Display for Target {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
-            Target::DnDirect(reg) => write!(f, "%d{}", reg),
-            Target::AnDirect(reg) => write!(f, "%a{}", reg),
-            Target::AnIndirect(reg) => write!(f, "(%a{})", reg),
-            Target::AnIndirectPostInc(reg) => write!(f, "(%a{}) +", reg),
-            Target::AnIndirectPreDec(reg) => write!(f, "- (%a{})", reg),
-            Target::AnIndirectDisplacement(disp, reg) => write!(f, "({}_16, %a{})", disp, reg),
-            Target::AnIndirectIndex(disp, rega, regidx) => write!(
-                f,
-                "({}_8, %a{}, %x{}.{}*{})",
-                disp, rega, regidx.register, regidx.size, regidx.scale
+            Target::DnDirect(reg) | Target::AnDirect(reg) => write!(f, "{}", reg),
+            Target::AnIndirect(reg) => write!(f, "({})", reg),
+            Target::AnIndirectPostInc(reg) => write!(f, "({})+", reg),
+            Target::AnIndirectPreDec(reg) => write!(f, "-({})", reg),
+            Target::AnIndirectDisplacement(reg, disp) => {
+                write!(f, "({},{})", signed_hex(*disp), reg)
+            }
+            Target::AnIndirectIndex(0, reg, index) => write!(f, "({},{})", reg, index),
+            Target::AnIndirectIndex(disp, reg, index) => {
+                write!(f, "({},{},{})", signed_hex(*disp), reg, index)
+            }
+            Target::AnIndirectIndexBase(disp, reg, index) => {
+                let parts = indirect_parts(disp, Some(*reg), &Some(*index));
+                write!(f, "({})", parts.join(","))
+            }
+            Target::PCIndirectDisplacement(disp, _) => write!(f, "({},PC)", signed_hex(*disp)),
+            Target::PCIndirectIndex(0, _, index) => write!(f, "(PC,{})", index),
+            Target::PCIndirectIndex(disp, _, index) => {
+                write!(f, "({},PC,{})", signed_hex(*disp), index)
+            }
+            Target::PCIndirectIndexBase(disp, _, index) => {
+                let parts = indirect_parts(disp, Some(Registers::PC), index);
+                write!(f, "({})", parts.join(","))
+            }
+            Target::MemoryIndirectPostIndex(disp, base, index, outer)
+            | Target::PCMemoryIndirectPostIndex(disp, base, index, outer) => {
+                write!(f, "([{}]", indirect_parts(disp, *base, &None).join(","))?;
+                if let Some(index) = index {
+                    write!(f, ",{}", index)?;
+                }
+                if let Some(outer) = outer {
+                    write!(f, ",{}", signed_hex(*outer))?;
+                }
+                write!(f, ")")
+            }
+            Target::MemoryIndirectPreIndex(disp, base, index, outer)
+            | Target::PCMemoryIndirectPreIndex(disp, base, index, outer) => {
+                write!(f, "([{}]", indirect_parts(disp, *base, index).join(","))?;
+                if let Some(outer) = outer {
+                    write!(f, ",{}", signed_hex(*outer))?;
+                }
+                write!(f, ")")
+            }
+            Target::AbsoluteShortAddress(address) => write!(f, "${:x}.w", *address as u32),
+            Target::AbsoluteLongAddress(high, low) => write!(f, "${:x}.l", high << 16 | low),
+            Target::Immediate(value) => write!(f, "#${:x}", value),
+        }
+    }
+}
+
+// MOVEM register list such as D0-D3/A0/A6, from a mask with D0 in bit 0, or
+// A7 in bit 0 for the predecrement form
+fn register_list(mask: u16, predecrement: bool) -> String {
+    let mask = if predecrement {
+        mask.reverse_bits()
+    } else {
+        mask
+    };
+    let mut groups = Vec::new();
+    for (bank, prefix) in [(0, 'D'), (8, 'A')] {
+        let mut n = 0;
+        while n < 8 {
+            if mask & 1 << (bank + n) == 0 {
+                n += 1;
+                continue;
+            }
+            let first = n;
+            while n < 8 && mask & 1 << (bank + n) != 0 {
+                n += 1;
+            }
+            groups.push(match n - first {
+                1 => format!("{}{}", prefix, first),
+                _ => format!("{}{}-{}{}", prefix, first, prefix, n - 1),
+            });
+        }
+    }
+    groups.join("/")
+}
+
+// Shift and rotate operands: a count and a data register, or a single
+// memory operand shifted by one
+fn shift_operands(count: &Target, dst: &Target) -> Vec<String> {
+    match (count, dst) {
+        (Target::Immediate(count), Target::DnDirect(_)) => {
+            vec![format!("#{}", count), dst.to_string()]
+        }
+        (_, Target::DnDirect(_)) => vec![count.to_string(), dst.to_string()],
+        _ => vec![dst.to_string()],
+    }
+}
+
+// An instruction with the address it was decoded from, which lets branch
+// targets print as absolute addresses
+pub struct Listing<'a> {
+    ins: &'a Instructions,
+    address: u32,
+}
+
+impl fmt::Display for Listing<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.ins.write_syntax(f, Some(self.address))
+    }
+}
+
+impl Instructions {
+    pub fn at(&self, address: u32) -> Listing<'_> {
+        Listing { ins: self, address }
+    }
+
+    // Lowercase mnemonic with its size suffix and the operands in Motorola
+    // syntax. Branch targets are absolute when address is known and relative
+    // to the instruction (*+$n) otherwise.
+    fn write_syntax(&self, f: &mut fmt::Formatter<'_>, address: Option<u32>) -> fmt::Result {
+        let target = |disp: i32| match address {
+            Some(address) => format!("${:x}", address.wrapping_add(2).wrapping_add(disp as u32)),
+            None if disp + 2 < 0 => format!("*{}", signed_hex(disp + 2)),
+            None => format!("*+{}", signed_hex(disp + 2)),
+        };
+        let sized = |name: &str, size: &Size| format!("{}.{}", name, size);
+        let two = |a: &dyn fmt::Display, b: &dyn fmt::Display| vec![a.to_string(), b.to_string()];
+        let (mnemonic, operands) = match self {
+            Instructions::ABCD(src, dst) => ("abcd".to_string(), two(src, dst)),
+            Instructions::ADD(src, dst, size) => (sized("add", size), two(src, dst)),
+            Instructions::ADDA(src, reg, size) => (sized("adda", size), two(src, reg)),
+            Instructions::ADDI(imm, dst, size) => {
+                (sized("addi", size), two(&Target::Immediate(*imm), dst))
+            }
+            Instructions::ADDQ(imm, dst, size) => {
+                (sized("addq", size), two(&format!("#{}", imm), dst))
+            }
+            Instructions::ADDX(src, dst, size) => (sized("addx", size), two(src, dst)),
+            Instructions::AND(src, dst, size) => (sized("and", size), two(src, dst)),
+            Instructions::ANDI(imm, dst, size) => {
+                (sized("andi", size), two(&Target::Immediate(*imm), dst))
+            }
+            Instructions::ANDItoCCR(imm) => (
+                "andi".to_string(),
+                two(&Target::Immediate(*imm as u32), &"CCR"),
             ),
-            Target::AnIndirectIndexBase(bdisp, rega, regidx) => write!(
-                f,
-                "({}, %a{}, %x{}.{}*{})",
-                bdisp, rega, regidx.register, regidx.size, regidx.scale
+            Instructions::ANDItoSR(imm) => (
+                "andi".to_string(),
+                two(&Target::Immediate(*imm as u32), &"SR"),
             ),
-            Target::MemoryIndirectPostIndex(bdisp, rega, regidx, odisp) => write!(
-                f,
-                "([{}, %a{}], %x{}.{}*{}, {})",
-                bdisp, rega, regidx.register, regidx.size, regidx.scale, odisp
+            Instructions::ASL(count, dst, size) => (sized("asl", size), shift_operands(count, dst)),
+            Instructions::ASR(count, dst, size) => (sized("asr", size), shift_operands(count, dst)),
+            Instructions::Bcc(cond, disp) => (format!("b{}", cond), vec![target(*disp)]),
+            Instructions::BCHG(bit, dst, _) => ("bchg".to_string(), two(bit, dst)),
+            Instructions::BCLR(bit, dst, _) => ("bclr".to_string(), two(bit, dst)),
+            Instructions::BRA(disp) => ("bra".to_string(), vec![target(*disp)]),
+            Instructions::BSET(bit, dst, _) => ("bset".to_string(), two(bit, dst)),
+            Instructions::BSR(disp) => ("bsr".to_string(), vec![target(*disp)]),
+            Instructions::BTST(bit, dst, _) => ("btst".to_string(), two(bit, dst)),
+            Instructions::CHK(src, reg, size) => (sized("chk", size), two(src, reg)),
+            Instructions::CLR(dst, size) => (sized("clr", size), vec![dst.to_string()]),
+            Instructions::CMP(src, dst, size) => (sized("cmp", size), two(src, dst)),
+            Instructions::CMPA(src, reg, size) => (sized("cmpa", size), two(src, reg)),
+            Instructions::CMPI(imm, dst, size) => {
+                (sized("cmpi", size), two(&Target::Immediate(*imm), dst))
+            }
+            Instructions::CMPM(src, dst, size) => (sized("cmpm", size), two(src, dst)),
+            Instructions::DBcc(cond, reg, disp) => {
+                (format!("db{}", cond), two(reg, &target(*disp as i32)))
+            }
+            Instructions::DIVSW(src, reg, _) => ("divs.w".to_string(), two(src, reg)),
+            Instructions::DIVUW(src, reg, _) => ("divu.w".to_string(), two(src, reg)),
+            Instructions::EOR(src, dst, size) => (sized("eor", size), two(src, dst)),
+            Instructions::EORtoCCR(imm) => (
+                "eori".to_string(),
+                two(&Target::Immediate(*imm as u32), &"CCR"),
             ),
-            Target::MemoryIndirectPreIndex(bdisp, rega, regidx, odisp) => write!(
-                f,
-                "([{}, %a{}, %x{}.{}*{}], {})",
-                bdisp, rega, regidx.register, regidx.size, regidx.scale, odisp
+            Instructions::EORtoSR(imm) => (
+                "eori".to_string(),
+                two(&Target::Immediate(*imm as u32), &"SR"),
             ),
-            Target::PCIndirectDisplacement(disp, pc) => write!(f, "({}_16, %pc{})", disp, pc),
-            Target::PCIndirectIndex(disp, pc, regidx) => write!(
-                f,
-                "({}_8, %pc{}, %x{}.{}*{})",
-                disp, pc, regidx.register, regidx.size, regidx.scale
+            Instructions::EORI(imm, dst, size) => {
+                (sized("eori", size), two(&Target::Immediate(*imm), dst))
+            }
+            Instructions::EXG(src, dst) => ("exg".to_string(), two(src, dst)),
+            Instructions::EXT(reg, _, to) => (sized("ext", to), vec![reg.to_string()]),
+            Instructions::ILLEGAL => ("illegal".to_string(), vec![]),
+            Instructions::JMP(dst) => ("jmp".to_string(), vec![dst.to_string()]),
+            Instructions::JSR(dst) => ("jsr".to_string(), vec![dst.to_string()]),
+            Instructions::LEA(src, reg) => ("lea".to_string(), two(src, reg)),
+            Instructions::LINK(reg, disp) => (
+                "link".to_string(),
+                two(reg, &format!("#{}", signed_hex(*disp))),
             ),
-            Target::PCIndirectIndexBase(bdisp, pc, regidx) => write!(
-                f,
-                "({}, %pc{}, %x{},{}*{})",
-                bdisp, pc, regidx.register, regidx.size, regidx.scale
+            Instructions::LSL(count, dst, size) => (sized("lsl", size), shift_operands(count, dst)),
+            Instructions::LSR(count, dst, size) => (sized("lsr", size), shift_operands(count, dst)),
+            Instructions::MOVE(src, dst, size) => (sized("move", size), two(src, dst)),
+            Instructions::MOVEA(src, reg, size) => (sized("movea", size), two(src, reg)),
+            Instructions::MOVEfromCCR(dst) => ("move".to_string(), two(&"CCR", dst)),
+            Instructions::MOVEtoCCR(src) => ("move".to_string(), two(src, &"CCR")),
+            Instructions::MOVEfromSR(dst) => ("move".to_string(), two(&"SR", dst)),
+            Instructions::MOVEtoSR(src) => ("move".to_string(), two(src, &"SR")),
+            Instructions::MOVEUSP(reg, Direction::RegisterToMemory) => {
+                ("move".to_string(), two(reg, &"USP"))
+            }
+            Instructions::MOVEUSP(reg, Direction::MemoryToRegister) => {
+                ("move".to_string(), two(&"USP", reg))
+            }
+            Instructions::MOVEC(reg, control, Direction::RegisterToMemory) => {
+                ("movec".to_string(), two(reg, control))
+            }
+            Instructions::MOVEC(reg, control, Direction::MemoryToRegister) => {
+                ("movec".to_string(), two(control, reg))
+            }
+            Instructions::MOVEM(target, size, mask, dir) => {
+                let predecrement = matches!(target, Target::AnIndirectPreDec(_));
+                let list = register_list(*mask as u16, predecrement);
+                let operands = match dir {
+                    Direction::RegisterToMemory => two(&list, target),
+                    Direction::MemoryToRegister => two(target, &list),
+                };
+                (sized("movem", size), operands)
+            }
+            Instructions::MOVEP(data, reg, disp, size, dir) => {
+                let memory = Target::AnIndirectDisplacement(*reg, *disp as i32);
+                let operands = match dir {
+                    Direction::RegisterToMemory => two(data, &memory),
+                    Direction::MemoryToRegister => two(&memory, data),
+                };
+                (sized("movep", size), operands)
+            }
+            Instructions::MOVEQ(imm, reg) => (
+                "moveq".to_string(),
+                two(&format!("#{}", signed_hex(*imm as i8 as i32)), reg),
             ),
-            Target::PCMemoryIndirectPostIndex(bdisp, pc, regidx, odisp) => write!(
-                f,
-                "([{}, %pc{}], %x{}.{}*{}, {})",
-                bdisp, pc, regidx.register, regidx.size, regidx.scale, odisp
+            Instructions::MULSW(src, reg) => ("muls.w".to_string(), two(src, reg)),
+            Instructions::MULUW(src, reg) => ("mulu.w".to_string(), two(src, reg)),
+            Instructions::NBCD(dst) => ("nbcd".to_string(), vec![dst.to_string()]),
+            Instructions::NEG(dst, size) => (sized("neg", size), vec![dst.to_string()]),
+            Instructions::NEGX(dst, size) => (sized("negx", size), vec![dst.to_string()]),
+            Instructions::NOP => ("nop".to_string(), vec![]),
+            Instructions::NOT(dst, size) => (sized("not", size), vec![dst.to_string()]),
+            Instructions::OR(src, dst, size) => (sized("or", size), two(src, dst)),
+            Instructions::ORI(imm, dst, size) => {
+                (sized("ori", size), two(&Target::Immediate(*imm), dst))
+            }
+            Instructions::ORItoCCR(imm) => {
+                ("ori".to_string(), two(&Target::Immediate(*imm), &"CCR"))
+            }
+            Instructions::ORItoSR(imm) => ("ori".to_string(), two(&Target::Immediate(*imm), &"SR")),
+            Instructions::PEA(src) => ("pea".to_string(), vec![src.to_string()]),
+            Instructions::RESET => ("reset".to_string(), vec![]),
+            Instructions::ROL(count, dst, size) => (sized("rol", size), shift_operands(count, dst)),
+            Instructions::ROR(count, dst, size) => (sized("ror", size), shift_operands(count, dst)),
+            Instructions::ROXL(count, dst, size) => {
+                (sized("roxl", size), shift_operands(count, dst))
+            }
+            Instructions::ROXR(count, dst, size) => {
+                (sized("roxr", size), shift_operands(count, dst))
+            }
+            Instructions::RTD(disp) => (
+                "rtd".to_string(),
+                vec![format!("#{}", signed_hex(*disp as i32))],
             ),
-            Target::PCMemoryIndirectPreIndex(bdisp, pc, regidx, odisp) => write!(
-                f,
-                "([{}, %pc{}, %x{}.{}*{}], {})",
-                bdisp, pc, regidx.register, regidx.size, regidx.scale, odisp
+            Instructions::RTE => ("rte".to_string(), vec![]),
+            Instructions::RTR => ("rtr".to_string(), vec![]),
+            Instructions::RTS => ("rts".to_string(), vec![]),
+            Instructions::SBCD(src, dst) => ("sbcd".to_string(), two(src, dst)),
+            Instructions::Scc(cond, dst) => (format!("s{}", cond), vec![dst.to_string()]),
+            Instructions::STOP(imm) => (
+                "stop".to_string(),
+                vec![Target::Immediate(*imm as u32).to_string()],
             ),
-            Target::AbsoluteShortAddress(extw) => write!(f, "({}).W", extw),
-            Target::AbsoluteLongAddress(addrh, addrl) => {
-                write!(f, "({}).L", (addrh as u32) << 16 | addrl)
+            Instructions::SUB(src, dst, size) => (sized("sub", size), two(src, dst)),
+            Instructions::SUBA(src, reg, size) => (sized("suba", size), two(src, reg)),
+            Instructions::SUBI(imm, dst, size) => {
+                (sized("subi", size), two(&Target::Immediate(*imm), dst))
+            }
+            Instructions::SUBQ(imm, dst, size) => {
+                (sized("subq", size), two(&format!("#{}", imm), dst))
             }
-            Target::Immediate(value) => write!(f, "#<{}>", value),
+            Instructions::SUBX(src, dst, size) => (sized("subx", size), two(src, dst)),
+            Instructions::SWAP(reg) => ("swap".to_string(), vec![reg.to_string()]),
+            Instructions::TAS(dst) => ("tas".to_string(), vec![dst.to_string()]),
+            Instructions::TRAP(vector) => (
+                "trap".to_string(),
+                vec![Target::Immediate(*vector as u32).to_string()],
+            ),
+            Instructions::TRAPV => ("trapv".to_string(), vec![]),
+            Instructions::TST(dst, size) => (sized("tst", size), vec![dst.to_string()]),
+            Instructions::UNLK(reg) => ("unlk".to_string(), vec![reg.to_string()]),
+            Instructions::NotImplemented => ("dc.w".to_string(), vec![]),
+        };
+        if operands.is_empty() {
+            write!(f, "{}", mnemonic)
+        } else {
+            write!(f, "{:<8}{}", mnemonic, operands.join(", "))
         }
     }
 }
 
 impl fmt::Display for Instructions {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        match self {
-            Instructions::ABCD(src, dst) => write!(f, "ABCD\t {}, {}", src, dst),
-            Instructions::ADD(src, dst, size) => write!(f, "ADD{}\t {}, {}", size, src, dst),
-            Instructions::ADDA(src, dst, size) => write!(f, "ADDA{}\t {}, {}", size, src, dst),
-            Instructions::ADDI(imm, dst, size) => write!(f, "ADDI{}\t #<{}>, {}", size, imm, dst),
-            Instructions::ADDQ(imm, dst, size) => write!(f, "ADDQ{}\t #<{}>, {}", size, imm, dst),
-            Instructions::ADDX(src, dst, size) => write!(f, "ADDX{}\t {}, {}", size, src, dst),
-            Instructions::AND(src, dst, size) => write!(f, "AND{}\t {}, {}", size, src, dst),
-            Instructions::ANDI(imm, dst, size) => write!(f, "ANDI{}\t #<{}>, {}", size, imm, dst),
-            Instructions::ASL(src, dst, size) => write!(f, "ASL{}\t {}, {}", size, src, dst),
-            Instructions::ASR(src, dst, size) => write!(f, "ASR{}\t {}, {}", size, src, dst),
-            Instructions::Bcc(cond, label) => write!(f, "Bcc {}\t {}", cond, label),
-            Instructions::BCHG(src, dst, size) => write!(f, "BCHG{}\t {}, {}", size, src, dst),
-            Instructions::BCLR(src, dst, size) => write!(f, "BCLR{}\t {}, {}", size, src, dst),
-            Instructions::BRA(label) => write!(f, "BRA\t {}", label),
-            Instructions::BSET(src, dst, size) => write!(f, "BSET{}\t {}, {}", size, src, dst),
-            Instructions::BSR(label) => write!(f, "BSR\t  {}", label),
-            Instructions::BTST(src, dst, size) => write!(f, "BTST{}\t {}, {}", size, src, dst),
-            Instructions::CHK(src, reg, size) => write!(f, "CHK{}\t {}, {}", size, src, dst),
-            Instructions::CLR(dst, size) => write!(f, "CLR{}\t {}", size, dst),
-            Instructions::CMP(src, dst, size) => write!(f, "CMP{}\t {}, {}", size, src, dst),
-            Instructions::CMPA(src, reg, size) => write!(f, "CMPA{}\t {}, {}", size, reg, dst),
-            Instructions::CMPI(imm, dst, size) => write!(f, "CMPI{}\t {}, {}", size, imm, dst),
-            Instructions::CMPM(src, dst, siz) => write!(f, "CMPM{}\t {}, {}", size, src, dst),
-            Instructions::DBcc(cond, reg, label) => {
-                write!(f, "DBcc\t {}\t {}\t {}", cond, reg, label)
-            }
-            Instructions::DIVSW(src, reg, size) => write!(f, "DIVSW{}\t {}, {}", size, src, reg),
-            Instructions::DIVUW(src, reg, size) => write!(f, "DIVUW{}\t {}, {}", size, src, reg),
-            Instructions::EOR(src, dst, size) => write!(f, "EOR{}\t {}, {}", size, src, dst),
-            Instructions::EORtoCCR(imm) => write!(f, "EORtoCCR\t #<{}>, CCR", imm),
-            Instructions::EORtoSR(imm) => write!(f, "EORtoCCR\t #<{}>, CCR", imm),
-            Instructions::EORI(imm, dst, size) => write!(f, "EORI{}\t #<{}>, {}", size, imm, dst),
-            Instructions::EXG(src, dst) => write!(f, "EXG\t {}, {}", src, dst),
-            Instructions::EXT(reg, size, size) => write!(f, "EXT\t {}\t {}\t {}", reg, size, size),
-            Instructions::ILLEGAL => write!(f, "ILLEGAL"),
-            Instructions::JMP(dst) => write!(f, "JMP\t {}", dst),
-            Instructions::JSR(dst) => write!(f, "JSR\t {}", dst),
-            Instructions::LEA(src, reg) => write!(f, "LEA\t {}, {}", src, reg),
-            Instructions::LINK(reg, disp) => write!(f, "LINK\t {}, {}", reg, disp),
-            Instructions::LSL(src, dst, size) => write!(f, "LSL{}\t {}, {}", size, src, dst),
-            Instructions::LSR(src, dst, size) => write!(f, "LSL{}\t {}, {}", size, src, dst),
-            Instructions::MOVE(src, dst, size) => write!(f, "MOVE{}\t {}, {}", size, src, dst),
-            Instructions::MOVEA(src, dst, size) => write!(f, "MOVEA{}\t {}, {}", size, src, dst),
-            Instructions::MOVEfromCCR(dst) => write!(f, "MOVEfromCCR\t {}", dst),
-            Instructions::MOVEtoCCR(src) => write!(f, "MOVEtoCCR\t {}", src),
-            Instructions::MOVEfromSR(dst) => write!(f, "MOVEfromSR\t {}", dst),
-            Instructions::MOVEtoSR(src) => write!(f, "MOVEtoSR\t {}", src),
-            Instructions::MOVEUSP(targ, dir) => write!(f, "MOVEUSP\t {}\t {}", targ, dir),
-            Instructions::MOVEC(targ, control, dir) => {
-                write!(f, "MOVEC\t {}, {}\t {}", targ, control, dir)
-            }
-            Instructions::MOVEM(targ, size, label, dir) => {
-                write!(f, "MOVEM{}\t {}\t {}\t {}", size, targ, label, dir)
-            }
-            Instructions::MOVEP(src, dst, label, size, dir) => {
-                write!(f, "MOVEP{}\t {}, {}\t {}\t {}", size, src, dst, label, dir)
-            }
-            Instructions::MOVEQ(imm, reg) => write!(f, "MOVEQ\t #<{}>, {}", imm, reg),
-            Instructions::MULSW(src, reg) => write!(f, "MULSW\t {}, {}", src, reg),
-            Instructions::MULUW(src, reg) => write!(f, "MULUW\t {}, {}", src, reg),
-            Instructions::NBCD(dst) => write!(f, "NBCD\t {}", dst),
-            Instructions::NEG(dst, size) => write!(f, "NEG{}\t {}", size, dst),
-            Instructions::NEGX(dst, size) => write!(f, "NEGX{}\t {}", size, dst),
-            Instructions::NOP => write!(f, "NOP"),
-            Instructions::NOT(dst, size) => write!(f, "NOT{}\t {}", size, dst),
-            Instructions::OR(src, dst, size) => write!(f, "OR{}\t {}, {}", size, src, dst),
-            Instructions::ORI(imm, dst, size) => write!(f, "ORI{}\t #<{}>, {}", size, imm, dst),
-            Instructions::ORItoCCR(imm) => write!(f, "ORItoCCR\t #<{}>, CCR", imm),
-            Instructions::ORItoSR(imm) => write!(f, "ORItoSR\t #<{}>, CCR", imm),
-            Instructions::PEA(targ) => write!(f, "PEA\t {}", targ),
-            Instructions::RESET => write!(f, "RESET"),
-            Instructions::ROL(src, dst, size) => write!(f, "ROL{}\t {}, {}", size, src, dst),
-            Instructions::ROR(src, dst, size) => write!(f, "ROR{}\t {}, {}", size, src, dst),
-            Instructions::ROXL(src, dst, size) => write!(f, "ROXL{}\t {}, {}", size, src, dst),
-            Instructions::ROXR(src, dst, size) => write!(f, "ROXR{}\t {}, {}", size, src, dst),
-            Instructions::RTD(label) => write!(f, "RTD\t {}", label),
-            Instructions::RTE => write!(f, "RTE"),
-            Instructions::RTR => write!(f, "RTR"),
-            Instructions::RTS => write!(f, "RTS"),
-            Instructions::SBCD(src, dst) => write!(f, "SBCD\t {}, {}", src, dst),
-            Instructions::Scc(cond, dst) => write!(f, "Scc\t {}\t {}", cond, dst),
-            Instructions::STOP(imm) => write!(f, "STOP\t #<{}>", imm),
-            Instructions::SUB(src, dst, size) => write!(f, "SUB{}\t {}, {}", size, src, dst),
-            Instructions::SUBA(src, reg, size) => write!(f, "SUBA{}\t {}, {}", size, src, reg),
-            Instructions::SUBI(imm, dst, size) => write!(f, "SUBI{}\t #<{}>, {}", size, imm, dst),
-            Instructions::SUBQ(imm, dst, size) => write!(f, "SUBQ{}\t #<{}>, {}", size, imm, dst),
-            Instructions::SUBX(src, dst, size) => write!(f, "SUBX{}\t {}, {}", site, src, dst),
-            Instructions::SWAP(reg) => write!(f, "SWAP\t {}", reg),
-            Instructions::TAS(dst) => write!(f, "TAS\t {}", dst),
-            Instructions::TRAP(imm) => write!(f, "TRAP\t #<{}>", imm),
-            Instructions::TRAPV => write!(f, "TRAPV"),
-            Instructions::TST(dst, size) => write!(f, "TST{}\t {}", size, dst),
-            Instructions::UNLK(reg) => write!(f, "UNLNK\t {}", reg),
-            Instructions::NotImplemented => write!(f, "Instruction not implemented!"),
-        }
+        self.write_syntax(f, None)
     }
 }
@@ -0,0 +1,45 @@
+pub mod binary;
+pub mod dwarf;
+pub mod elf;
+pub mod hunk;
+pub mod ihex;
+pub mod srecord;
+pub mod tos;
+
+use crate::memory::Memory;
+
+// Copies bytes into memory starting at address
+pub(crate) fn write_bytes<M: Memory + ?Sized>(
+    mem: &mut M,
+    address: u32,
+    data: &[u8],
+) -> Result<(), String> {
+    for (offset, byte) in data.iter().enumerate() {
+        let target = address.wrapping_add(offset as u32);
+        mem.write_at_address_byte(target, *byte)
+            .map_err(|_| format!("Address ${:X} out of bounds", target))?;
+    }
+    Ok(())
+}
+
+// Big-endian word at offset in a file image
+pub(crate) fn read_word(data: &[u8], offset: usize) -> Result<u16, String> {
+    data.get(offset..offset + 2)
+        .map(|b| u16::from_be_bytes([b[0], b[1]]))
+        .ok_or_else(|| format!("Truncated file at offset ${:X}", offset))
+}
+
+// Big-endian long at offset in a file image
+pub(crate) fn read_long(data: &[u8], offset: usize) -> Result<u32, String> {
+    data.get(offset..offset + 4)
+        .map(|b| u32::from_be_bytes([b[0], b[1], b[2], b[3]]))
+        .ok_or_else(|| format!("Truncated file at offset ${:X}", offset))
+}
+
+// Slice of a file image, checked against its length
+pub(crate) fn slice(data: &[u8], offset: usize, length: usize) -> Result<&[u8], String> {
+    offset
+        .checked_add(length)
+        .and_then(|end| data.get(offset..end))
+        .ok_or_else(|| format!("Truncated file at offset ${:X}", offset))
+}
@@ -0,0 +1,85 @@
+use crate::cpu::CPU;
+use crate::loader::write_bytes;
+use crate::memory::Memory;
+
+// Copies a raw image into memory at base
+pub fn load<M: Memory + ?Sized>(data: &[u8], mem: &mut M, base: u32) -> Result<(), String> {
+    write_bytes(mem, base, data)
+}
+
+// Loads an image from a pair of byte-wide ROMs, the even chip holding the high
+// byte of every word (D15-D8) and the odd chip the low byte (D7-D0)
+pub fn load_split<M: Memory + ?Sized>(
+    even: &[u8],
+    odd: &[u8],
+    mem: &mut M,
+    base: u32,
+) -> Result<(), String> {
+    write_bytes(mem, base, &interleave(even, odd)?)
+}
+
+// Loads an image holding the reset vectors at its start, taking the initial SSP
+// and PC from its first two longs as the 68000 does on reset
+pub fn load_cpu(data: &[u8], cpu: &mut CPU, base: u32) -> Result<(), String> {
+    load(data, &mut cpu.memory_bus.memory, base)?;
+    reset_vectors(cpu, base)
+}
+
+// Split ROM variant of load_cpu
+pub fn load_split_cpu(even: &[u8], odd: &[u8], cpu: &mut CPU, base: u32) -> Result<(), String> {
+    load_split(even, odd, &mut cpu.memory_bus.memory, base)?;
+    reset_vectors(cpu, base)
+}
+
+// Merges even and odd ROM images into one word-wide image
+pub fn interleave(even: &[u8], odd: &[u8]) -> Result<Vec<u8>, String> {
+    if even.len() != odd.len() {
+        return Err(format!(
+            "Even ROM is {} bytes but odd ROM is {} bytes",
+            even.len(),
+            odd.len()
+        ));
+    }
+    Ok(even
+        .iter()
+        .zip(odd)
+        .flat_map(|(high, low)| [*high, *low])
+        .collect())
+}
+
+fn reset_vectors(cpu: &mut CPU, base: u32) -> Result<(), String> {
+    let mem = &cpu.memory_bus.memory;
+    let (ssp, pc) = mem
+        .read_at_address_long(base)
+        .zip(mem.read_at_address_long(base.wrapping_add(4)))
+        .ok_or("Image too short for reset vectors")?;
+    cpu.registers.SP = ssp;
+    cpu.registers.PC = pc;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{interleave, load_split_cpu};
+    use crate::cpu::CPU;
+
+    #[test]
+    fn even_rom_holds_the_high_bytes() {
+        assert_eq!(
+            interleave(&[0x12, 0x56], &[0x34, 0x78]).unwrap(),
+            [0x12, 0x34, 0x56, 0x78]
+        );
+        assert!(interleave(&[0x12, 0x56], &[0x34]).is_err());
+    }
+
+    #[test]
+    fn split_pair_supplies_the_reset_vectors() {
+        let mut cpu = CPU::with_memory(0x1000);
+        // Vectors $00001000 and $00000804, a byte from each chip in turn
+        let even = [0x00, 0x10, 0x00, 0x08];
+        let odd = [0x00, 0x00, 0x00, 0x04];
+        load_split_cpu(&even, &odd, &mut cpu, 0x100).unwrap();
+        assert_eq!(cpu.registers.SP, 0x0000_1000);
+        assert_eq!(cpu.registers.PC, 0x0000_0804);
+    }
+}
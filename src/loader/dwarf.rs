@@ -0,0 +1,506 @@
+use crate::loader::slice;
+
+// Standard line number program opcodes
+const DW_LNS_COPY: u8 = 1;
+const DW_LNS_ADVANCE_PC: u8 = 2;
+const DW_LNS_ADVANCE_LINE: u8 = 3;
+const DW_LNS_SET_FILE: u8 = 4;
+const DW_LNS_CONST_ADD_PC: u8 = 8;
+const DW_LNS_FIXED_ADVANCE_PC: u8 = 9;
+
+// Extended opcodes, after a zero byte and a length
+const DW_LNE_END_SEQUENCE: u8 = 1;
+const DW_LNE_SET_ADDRESS: u8 = 2;
+const DW_LNE_DEFINE_FILE: u8 = 3;
+
+// Attribute forms used by DWARF 5 directory and file entries
+const DW_FORM_BLOCK: u64 = 0x09;
+const DW_FORM_DATA1: u64 = 0x0B;
+const DW_FORM_DATA2: u64 = 0x05;
+const DW_FORM_DATA4: u64 = 0x06;
+const DW_FORM_DATA8: u64 = 0x07;
+const DW_FORM_DATA16: u64 = 0x1E;
+const DW_FORM_STRING: u64 = 0x08;
+const DW_FORM_STRP: u64 = 0x0E;
+const DW_FORM_LINE_STRP: u64 = 0x1F;
+const DW_FORM_UDATA: u64 = 0x0F;
+
+// Content types of DWARF 5 entry formats
+const DW_LNCT_PATH: u64 = 1;
+const DW_LNCT_DIRECTORY_INDEX: u64 = 2;
+
+// Start of the code for one source line
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LineRow {
+    pub address: u32,
+    // Index into LineTable::files
+    pub file: usize,
+    // 0 for the address just past a sequence, which has no line
+    pub line: u32,
+}
+
+// Addresses mapped to source lines, from a DWARF .debug_line section
+#[derive(Debug, Clone, Default)]
+pub struct LineTable {
+    pub files: Vec<String>,
+    // Sorted by address
+    pub rows: Vec<LineRow>,
+}
+
+// The string sections DW_FORM_strp and DW_FORM_line_strp point into
+#[derive(Default)]
+pub struct Strings<'a> {
+    pub debug_str: &'a [u8],
+    pub debug_line_str: &'a [u8],
+}
+
+impl LineTable {
+    pub fn is_empty(&self) -> bool {
+        self.rows.is_empty()
+    }
+
+    // Index of the file named name in directory, adding it if new
+    fn add_file(&mut self, name: &str, directory: &str) -> usize {
+        let path = if name.starts_with('/') || directory.is_empty() {
+            name.to_string()
+        } else {
+            format!("{}/{}", directory.trim_end_matches('/'), name)
+        };
+        match self.files.iter().position(|file| *file == path) {
+            Some(index) => index,
+            None => {
+                self.files.push(path);
+                self.files.len() - 1
+            }
+        }
+    }
+
+    // Source file and line of the code at address
+    pub fn lookup(&self, address: u32) -> Option<(&str, u32)> {
+        let index = self.rows.partition_point(|row| row.address <= address);
+        let row = self.rows.get(index.checked_sub(1)?)?;
+        if row.line == 0 {
+            return None;
+        }
+        Some((&self.files[row.file], row.line))
+    }
+
+    // Reads every line number program in a .debug_line section, DWARF 2 to
+    // 5 with 32-bit addresses
+    pub fn parse(section: &[u8], strings: &Strings) -> Result<Self, String> {
+        let mut table = LineTable::default();
+        let mut reader = Reader {
+            data: section,
+            offset: 0,
+        };
+        while reader.offset < section.len() {
+            let (length, offset_size) = match reader.u32()? {
+                0xFFFF_FFFF => (reader.u64()? as usize, 8),
+                length => (length as usize, 4),
+            };
+            let end = reader
+                .offset
+                .checked_add(length)
+                .ok_or("DWARF line table unit too long")?;
+            let unit = slice(section, 0, end)?;
+            let mut unit_reader = Reader {
+                data: unit,
+                offset: reader.offset,
+            };
+            unit_reader.program(&mut table, offset_size, strings)?;
+            reader.offset = end;
+        }
+        table.rows.sort_by_key(|row| (row.address, row.line != 0));
+        Ok(table)
+    }
+}
+
+// Line number after moving it by delta, which a malformed table can push out
+// of range
+fn advance_line(line: i64, delta: i64) -> Result<i64, String> {
+    line.checked_add(delta)
+        .ok_or_else(|| "DWARF line number out of range".to_string())
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
+    offset: usize,
+}
+
+impl<'a> Reader<'a> {
+    // One line number program, from the version field of its header
+    fn program(
+        &mut self,
+        table: &mut LineTable,
+        offset_size: usize,
+        strings: &Strings,
+    ) -> Result<(), String> {
+        let version = self.u16()?;
+        if !(2..=5).contains(&version) {
+            return Err(format!("Unsupported DWARF line table version {}", version));
+        }
+        if version >= 5 {
+            // Address and segment selector sizes
+            self.bytes(2)?;
+        }
+        let header_length = self.offset_value(offset_size)?;
+        let program = self
+            .offset
+            .checked_add(header_length)
+            .ok_or("DWARF line table header too long")?;
+        let minimum_length = self.u8()? as u32;
+        if version >= 4 {
+            // Maximum operations per instruction, 1 for non-VLIW machines
+            self.u8()?;
+        }
+        self.u8()?; // default_is_stmt
+        let line_base = self.u8()? as i8 as i64;
+        let line_range = self.u8()?;
+        let opcode_base = self.u8()?;
+        if line_range == 0 {
+            return Err("DWARF line table has a zero line range".to_string());
+        }
+        let opcode_lengths = self.bytes(opcode_base.saturating_sub(1) as usize)?.to_vec();
+
+        // Files of this unit as indices into table.files. Before DWARF 5
+        // file numbers start at 1.
+        let mut files = if version >= 5 {
+            self.entries_v5(table, offset_size, strings)?
+        } else {
+            self.entries(table)?
+        };
+        self.offset = program;
+
+        let first_file = if version >= 5 { 0 } else { 1 };
+        let (mut address, mut file, mut line) = (0u32, first_file, 1i64);
+        while self.offset < self.data.len() {
+            let opcode = self.u8()?;
+            let mut emit = false;
+            match opcode {
+                0 => {
+                    let length = self.uleb()? as usize;
+                    let end = self
+                        .offset
+                        .checked_add(length)
+                        .ok_or("DWARF extended opcode too long")?;
+                    match self.u8()? {
+                        DW_LNE_END_SEQUENCE => {
+                            self.row(table, &files, first_file, address, file, 0);
+                            (address, file, line) = (0, first_file, 1);
+                        }
+                        DW_LNE_SET_ADDRESS => address = self.u32()?,
+                        DW_LNE_DEFINE_FILE => {
+                            // Directory, modification time and length follow
+                            let name = self.string()?;
+                            files.push(table.add_file(&name, ""));
+                        }
+                        _ => {}
+                    }
+                    self.offset = end;
+                }
+                DW_LNS_COPY => emit = true,
+                DW_LNS_ADVANCE_PC => {
+                    let advance = (self.uleb()? as u32).wrapping_mul(minimum_length);
+                    address = address.wrapping_add(advance);
+                }
+                DW_LNS_ADVANCE_LINE => line = advance_line(line, self.sleb()?)?,
+                DW_LNS_SET_FILE => file = self.uleb()? as usize,
+                DW_LNS_CONST_ADD_PC => {
+                    let advance = (255 - opcode_base) / line_range;
+                    address = address.wrapping_add((advance as u32).wrapping_mul(minimum_length));
+                }
+                DW_LNS_FIXED_ADVANCE_PC => address = address.wrapping_add(self.u16()? as u32),
+                _ if opcode < opcode_base => {
+                    // Other standard opcodes only set state not kept here
+                    for _ in 0..opcode_lengths[opcode as usize - 1] {
+                        self.uleb()?;
+                    }
+                }
+                _ => {
+                    let adjusted = opcode - opcode_base;
+                    let advance = ((adjusted / line_range) as u32).wrapping_mul(minimum_length);
+                    address = address.wrapping_add(advance);
+                    line = advance_line(line, line_base + (adjusted % line_range) as i64)?;
+                    emit = true;
+                }
+            }
+            if emit {
+                self.row(table, &files, first_file, address, file, line.max(1) as u32);
+            }
+        }
+        Ok(())
+    }
+
+    fn row(
+        &self,
+        table: &mut LineTable,
+        files: &[usize],
+        first_file: usize,
+        address: u32,
+        file: usize,
+        line: u32,
+    ) {
+        if let Some(file) = file.checked_sub(first_file).and_then(|n| files.get(n)) {
+            table.rows.push(LineRow {
+                address,
+                file: *file,
+                line,
+            });
+        }
+    }
+
+    // DWARF 2 to 4 include_directories and file_names
+    fn entries(&mut self, table: &mut LineTable) -> Result<Vec<usize>, String> {
+        let mut directories = vec![String::new()];
+        loop {
+            let directory = self.string()?;
+            if directory.is_empty() {
+                break;
+            }
+            directories.push(directory);
+        }
+        let mut files = Vec::new();
+        loop {
+            let name = self.string()?;
+            if name.is_empty() {
+                return Ok(files);
+            }
+            let directory = self.uleb()? as usize;
+            let _ = (self.uleb()?, self.uleb()?);
+            let directory = directories.get(directory).map_or("", String::as_str);
+            files.push(table.add_file(&name, directory));
+        }
+    }
+
+    // DWARF 5 directory and file name tables, each described by a list of
+    // content type and form pairs
+    fn entries_v5(
+        &mut self,
+        table: &mut LineTable,
+        offset_size: usize,
+        strings: &Strings,
+    ) -> Result<Vec<usize>, String> {
+        let mut directories = Vec::new();
+        for (path, _) in self.entry_table(offset_size, strings)? {
+            directories.push(path);
+        }
+        let mut files = Vec::new();
+        for (name, directory) in self.entry_table(offset_size, strings)? {
+            let directory = directories.get(directory).map_or("", String::as_str);
+            files.push(table.add_file(&name, directory));
+        }
+        Ok(files)
+    }
+
+    fn entry_table(
+        &mut self,
+        offset_size: usize,
+        strings: &Strings,
+    ) -> Result<Vec<(String, usize)>, String> {
+        let format_count = self.u8()?;
+        let mut format = Vec::new();
+        for _ in 0..format_count {
+            format.push((self.uleb()?, self.uleb()?));
+        }
+        let count = self.uleb()?;
+        // Each entry takes at least a byte
+        if count > self.data.len().saturating_sub(self.offset) as u64 {
+            return Err("DWARF line table entry count too large".to_string());
+        }
+        let mut entries = Vec::new();
+        for _ in 0..count {
+            let (mut path, mut directory) = (String::new(), 0);
+            for (content, form) in &format {
+                let (number, text) = self.form(*form, offset_size, strings)?;
+                match *content {
+                    DW_LNCT_PATH => path = text.unwrap_or_default(),
+                    DW_LNCT_DIRECTORY_INDEX => directory = number as usize,
+                    _ => {}
+                }
+            }
+            entries.push((path, directory));
+        }
+        Ok(entries)
+    }
+
+    // A value of the given form, as a number or a string
+    fn form(
+        &mut self,
+        form: u64,
+        offset_size: usize,
+        strings: &Strings,
+    ) -> Result<(u64, Option<String>), String> {
+        Ok(match form {
+            DW_FORM_STRING => (0, Some(self.string()?)),
+            DW_FORM_STRP | DW_FORM_LINE_STRP => {
+                let section = if form == DW_FORM_STRP {
+                    strings.debug_str
+                } else {
+                    strings.debug_line_str
+                };
+                let offset = self.offset_value(offset_size)?;
+                let mut reader = Reader {
+                    data: section,
+                    offset,
+                };
+                (0, Some(reader.string()?))
+            }
+            DW_FORM_UDATA => (self.uleb()?, None),
+            DW_FORM_DATA1 => (self.u8()? as u64, None),
+            DW_FORM_DATA2 => (self.u16()? as u64, None),
+            DW_FORM_DATA4 => (self.u32()? as u64, None),
+            DW_FORM_DATA8 => (self.u64()?, None),
+            DW_FORM_DATA16 => {
+                self.bytes(16)?;
+                (0, None)
+            }
+            DW_FORM_BLOCK => {
+                let length = self.uleb()? as usize;
+                self.bytes(length)?;
+                (0, None)
+            }
+            _ => return Err(format!("Unsupported DWARF form ${:X} in line table", form)),
+        })
+    }
+
+    fn bytes(&mut self, length: usize) -> Result<&'a [u8], String> {
+        let bytes = slice(self.data, self.offset, length)?;
+        self.offset += length;
+        Ok(bytes)
+    }
+
+    fn u8(&mut self) -> Result<u8, String> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16, String> {
+        let b = self.bytes(2)?;
+        Ok(u16::from_be_bytes([b[0], b[1]]))
+    }
+
+    fn u32(&mut self) -> Result<u32, String> {
+        let b = self.bytes(4)?;
+        Ok(u32::from_be_bytes([b[0], b[1], b[2], b[3]]))
+    }
+
+    fn u64(&mut self) -> Result<u64, String> {
+        Ok((self.u32()? as u64) << 32 | self.u32()? as u64)
+    }
+
+    // A section offset, 4 or 8 bytes in 32 or 64-bit DWARF
+    fn offset_value(&mut self, offset_size: usize) -> Result<usize, String> {
+        Ok(if offset_size == 8 {
+            self.u64()? as usize
+        } else {
+            self.u32()? as usize
+        })
+    }
+
+    fn uleb(&mut self) -> Result<u64, String> {
+        let mut value = 0u64;
+        let mut shift = 0;
+        loop {
+            let byte = self.u8()?;
+            if shift < 64 {
+                value |= ((byte & 0x7F) as u64) << shift;
+            }
+            shift += 7;
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+    }
+
+    fn sleb(&mut self) -> Result<i64, String> {
+        let mut value = 0i64;
+        let mut shift = 0;
+        loop {
+            let byte = self.u8()?;
+            if shift < 64 {
+                value |= ((byte & 0x7F) as i64) << shift;
+            }
+            shift += 7;
+            if byte & 0x80 == 0 {
+                if shift < 64 && byte & 0x40 != 0 {
+                    value |= -1 << shift;
+                }
+                return Ok(value);
+            }
+        }
+    }
+
+    // NUL terminated string
+    fn string(&mut self) -> Result<String, String> {
+        let rest = self.data.get(self.offset..).unwrap_or_default();
+        let length = rest
+            .iter()
+            .position(|b| *b == 0)
+            .ok_or("Unterminated string in DWARF data")?;
+        self.offset += length + 1;
+        Ok(String::from_utf8_lossy(&rest[..length]).into_owned())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{LineTable, Strings};
+
+    // A DWARF 2 line table for a.c with program as its line number program
+    fn unit(program: &[u8]) -> Vec<u8> {
+        let mut header = vec![2, 1, 0xFB, 14, 13, 0, 1, 1, 1, 1, 0, 0, 0, 1, 0, 0, 1];
+        header.extend(b"\0a.c\0\0\0\0\0");
+        let mut unit = vec![0, 2];
+        unit.extend((header.len() as u32).to_be_bytes());
+        unit.extend(header);
+        unit.extend(program);
+        let mut section = (unit.len() as u32).to_be_bytes().to_vec();
+        section.extend(unit);
+        section
+    }
+
+    fn parse(section: &[u8]) -> Result<LineTable, String> {
+        LineTable::parse(section, &Strings::default())
+    }
+
+    #[test]
+    fn rows_map_addresses_to_lines() {
+        // Set the address to $1000, copy, advance by two instructions and a
+        // line with a special opcode, advance one more and end the sequence
+        let table = parse(&unit(&[0, 5, 2, 0, 0, 0x10, 0, 1, 47, 2, 1, 0, 1, 1])).unwrap();
+        assert_eq!(table.lookup(0x0FFF), None);
+        assert_eq!(table.lookup(0x1000), Some(("a.c", 1)));
+        assert_eq!(table.lookup(0x1003), Some(("a.c", 1)));
+        assert_eq!(table.lookup(0x1005), Some(("a.c", 2)));
+        assert_eq!(table.lookup(0x1006), None);
+    }
+
+    #[test]
+    fn malformed_tables_are_errors() {
+        // Huge address advances wrap like the address itself
+        assert!(parse(&unit(&[2, 0xFF, 0xFF, 0xFF, 0xFF, 0x0F, 1])).is_ok());
+
+        // Advancing the line by 2^62 twice overflows it
+        let mut program = Vec::new();
+        for _ in 0..2 {
+            program.push(3);
+            program.extend([0x80; 8]);
+            program.extend([0xC0, 0x00]);
+        }
+        assert!(parse(&unit(&program)).is_err());
+
+        // 64-bit unit length running past the end of the address space
+        let mut section = vec![0xFF; 12];
+        assert!(parse(&section).is_err());
+
+        // 64-bit header length doing the same
+        section = vec![0xFF, 0xFF, 0xFF, 0xFF, 0, 0, 0, 0, 0, 0, 0, 10, 0, 2];
+        section.extend([0xFF; 8]);
+        assert!(parse(&section).is_err());
+
+        // Extended opcode longer than memory
+        assert!(parse(&unit(&[
+            0, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 1, 1
+        ]))
+        .is_err());
+    }
+}
@@ -0,0 +1,333 @@
+use crate::cpu::CPU;
+use crate::loader::dwarf::{LineTable, Strings};
+use crate::loader::{read_long, read_word, slice, write_bytes};
+use crate::memory::Memory;
+use crate::symbols::SymbolTable;
+
+const ELF_MAGIC: [u8; 4] = [0x7F, b'E', b'L', b'F'];
+const ELFCLASS32: u8 = 1;
+const ELFDATA2MSB: u8 = 2;
+const ET_EXEC: u16 = 2;
+const EM_68K: u16 = 4;
+
+const PT_LOAD: u32 = 1;
+
+const SHT_SYMTAB: u32 = 2;
+const SHN_UNDEF: u16 = 0;
+const SHN_LORESERVE: u16 = 0xFF00;
+const SHN_ABS: u16 = 0xFFF1;
+
+const STT_NOTYPE: u8 = 0;
+const STT_OBJECT: u8 = 1;
+const STT_FUNC: u8 = 2;
+
+// Sizes of the ELF32 structures
+const EHDR_SIZE: usize = 52;
+const SYM_SIZE: usize = 16;
+
+// Memory occupied by one PT_LOAD segment
+pub struct Segment {
+    pub address: u32,
+    pub file_size: u32,
+    pub memory_size: u32,
+}
+
+pub struct Elf {
+    pub entry: u32,
+    pub segments: Vec<Segment>,
+    // Address of the program headers when a segment maps them, for AT_PHDR
+    pub program_headers: Option<u32>,
+    pub program_header_size: u16,
+    pub program_header_count: u16,
+    pub symbols: SymbolTable,
+    // Source lines from .debug_line, empty without debugging information
+    pub lines: LineTable,
+}
+
+// Maps the PT_LOAD segments of an EM_68K executable into memory, zeroing the
+// part of each segment past its file contents (.bss)
+pub fn load<M: Memory + ?Sized>(data: &[u8], mem: &mut M) -> Result<Elf, String> {
+    let header = slice(data, 0, EHDR_SIZE).map_err(|_| "File too short for an ELF header")?;
+    if header[..4] != ELF_MAGIC {
+        return Err("Not an ELF file".to_string());
+    }
+    if header[4] != ELFCLASS32 || header[5] != ELFDATA2MSB {
+        return Err("Not a 32-bit big-endian ELF file".to_string());
+    }
+    if read_word(data, 18)? != EM_68K {
+        return Err("Not an m68k ELF file".to_string());
+    }
+    if read_word(data, 16)? != ET_EXEC {
+        return Err("Not an executable ELF file".to_string());
+    }
+
+    let entry = read_long(data, 24)?;
+    let phoff = read_long(data, 28)? as usize;
+    let shoff = read_long(data, 32)? as usize;
+    let phentsize = read_word(data, 42)? as usize;
+    let phnum = read_word(data, 44)? as usize;
+    let shentsize = read_word(data, 46)? as usize;
+    let shnum = read_word(data, 48)? as usize;
+    let shstrndx = read_word(data, 50)? as usize;
+
+    let mut segments = Vec::new();
+    let mut program_headers = None;
+    for i in 0..phnum {
+        let ph = phoff + i * phentsize;
+        if read_long(data, ph)? != PT_LOAD {
+            continue;
+        }
+        let offset = read_long(data, ph + 4)? as usize;
+        let address = read_long(data, ph + 12)?;
+        let file_size = read_long(data, ph + 16)?;
+        let memory_size = read_long(data, ph + 20)?;
+        if file_size > memory_size {
+            return Err(format!(
+                "Segment at ${:08X} larger in file than in memory",
+                address
+            ));
+        }
+        // p_memsz comes from the file, so the segment must end inside memory
+        // before any of it is zeroed
+        let last = address.checked_add(memory_size.saturating_sub(1));
+        if memory_size > 0
+            && last
+                .and_then(|last| mem.read_at_address_byte(last))
+                .is_none()
+        {
+            return Err(format!(
+                "Segment at ${:08X} does not fit into memory",
+                address
+            ));
+        }
+        if (offset..offset + file_size as usize).contains(&phoff) {
+            program_headers = Some(address.wrapping_add((phoff - offset) as u32));
+        }
+        write_bytes(mem, address, slice(data, offset, file_size as usize)?)?;
+        if memory_size > file_size {
+            for bss in address.wrapping_add(file_size)..=address.wrapping_add(memory_size - 1) {
+                mem.write_at_address_byte(bss, 0)
+                    .map_err(|_| format!("Address ${:X} out of bounds", bss))?;
+            }
+        }
+        segments.push(Segment {
+            address,
+            file_size,
+            memory_size,
+        });
+    }
+
+    let (symbols, lines) = if shoff != 0 {
+        let section = |name| section(data, shoff, shentsize, shnum, shstrndx, name);
+        let strings = Strings {
+            debug_str: section(".debug_str")?.unwrap_or_default(),
+            debug_line_str: section(".debug_line_str")?.unwrap_or_default(),
+        };
+        let lines = match section(".debug_line")? {
+            Some(debug_line) => LineTable::parse(debug_line, &strings)?,
+            None => LineTable::default(),
+        };
+        (symbol_table(data, shoff, shentsize, shnum)?, lines)
+    } else {
+        (SymbolTable::new(), LineTable::default())
+    };
+
+    Ok(Elf {
+        entry,
+        segments,
+        program_headers,
+        program_header_size: phentsize as u16,
+        program_header_count: phnum as u16,
+        symbols,
+        lines,
+    })
+}
+
+// Loads an executable into the CPU memory and points the PC at its entry
+pub fn load_cpu(data: &[u8], cpu: &mut CPU) -> Result<Elf, String> {
+    let elf = load(data, &mut cpu.memory_bus.memory)?;
+    cpu.registers.PC = elf.entry;
+    Ok(elf)
+}
+
+// Contents of the section called name, if there is one
+fn section<'a>(
+    data: &'a [u8],
+    shoff: usize,
+    shentsize: usize,
+    shnum: usize,
+    shstrndx: usize,
+    name: &str,
+) -> Result<Option<&'a [u8]>, String> {
+    if shstrndx >= shnum {
+        return Ok(None);
+    }
+    let strsh = shoff + shstrndx * shentsize;
+    let names = slice(
+        data,
+        read_long(data, strsh + 16)? as usize,
+        read_long(data, strsh + 20)? as usize,
+    )?;
+    for i in 0..shnum {
+        let sh = shoff + i * shentsize;
+        let start = read_long(data, sh)? as usize;
+        let section_name = names
+            .get(start..)
+            .and_then(|s| s.split(|b| *b == 0).next())
+            .unwrap_or_default();
+        if section_name == name.as_bytes() {
+            let offset = read_long(data, sh + 16)? as usize;
+            let size = read_long(data, sh + 20)? as usize;
+            return slice(data, offset, size).map(Some);
+        }
+    }
+    Ok(None)
+}
+
+// Defined function, object and untyped symbols from every SHT_SYMTAB section
+fn symbol_table(
+    data: &[u8],
+    shoff: usize,
+    shentsize: usize,
+    shnum: usize,
+) -> Result<SymbolTable, String> {
+    let mut symbols = SymbolTable::new();
+    for i in 0..shnum {
+        let sh = shoff + i * shentsize;
+        if read_long(data, sh + 4)? != SHT_SYMTAB {
+            continue;
+        }
+        let offset = read_long(data, sh + 16)? as usize;
+        let size = read_long(data, sh + 20)? as usize;
+        let link = read_long(data, sh + 24)? as usize;
+        if link >= shnum {
+            return Err("Symbol table links to a missing string table".to_string());
+        }
+        let strsh = shoff + link * shentsize;
+        let strtab = slice(
+            data,
+            read_long(data, strsh + 16)? as usize,
+            read_long(data, strsh + 20)? as usize,
+        )?;
+
+        // Entry 0 is the reserved null symbol
+        for sym in slice(data, offset, size)?.chunks_exact(SYM_SIZE).skip(1) {
+            let name = u32::from_be_bytes([sym[0], sym[1], sym[2], sym[3]]) as usize;
+            let value = u32::from_be_bytes([sym[4], sym[5], sym[6], sym[7]]);
+            let kind = sym[12] & 0x0F;
+            let shndx = u16::from_be_bytes([sym[14], sym[15]]);
+            if !matches!(kind, STT_NOTYPE | STT_OBJECT | STT_FUNC)
+                || shndx == SHN_UNDEF
+                || (shndx >= SHN_LORESERVE && shndx != SHN_ABS)
+            {
+                continue;
+            }
+            let name = strtab
+                .get(name..)
+                .and_then(|s| s.split(|b| *b == 0).next())
+                .map(String::from_utf8_lossy)
+                .unwrap_or_default();
+            if !name.is_empty() {
+                symbols.insert(&name, value);
+            }
+        }
+    }
+    Ok(symbols)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::load;
+
+    fn put(data: &mut [u8], offset: usize, bytes: &[u8]) {
+        data[offset..offset + bytes.len()].copy_from_slice(bytes);
+    }
+
+    // An executable with one PT_LOAD segment of 8 bytes of code and 8 bytes
+    // of .bss at $1000, and a symbol table
+    fn executable(memory_size: u32) -> Vec<u8> {
+        let mut data = vec![0u8; 0x200];
+        put(&mut data, 0, b"\x7FELF\x01\x02\x01");
+        put(&mut data, 16, &[0, 2, 0, 4, 0, 0, 0, 1]);
+        put(&mut data, 24, &0x1004u32.to_be_bytes());
+        put(&mut data, 28, &52u32.to_be_bytes());
+        put(&mut data, 32, &0x100u32.to_be_bytes());
+        put(&mut data, 42, &[0, 32, 0, 1, 0, 40, 0, 4, 0, 3]);
+
+        // PT_LOAD of the code at offset $80
+        for (n, value) in [1, 0x80, 0x1000, 0x1000, 8, memory_size].iter().enumerate() {
+            put(&mut data, 52 + n * 4, &u32::to_be_bytes(*value));
+        }
+        put(
+            &mut data,
+            0x80,
+            &[0x4E, 0x71, 0x4E, 0x71, 0x4E, 0x75, 0x4E, 0x75],
+        );
+
+        // Symbols at $88: the null symbol, a function, an object, an
+        // undefined symbol and a file symbol
+        let symbols: [(u32, u32, u8, u16); 5] = [
+            (0, 0, 0, 0),
+            (1, 0x1004, 0x12, 1),
+            (7, 0x1008, 0x11, 1),
+            (14, 0, 0x10, 0),
+            (20, 0, 0x04, 0xFFF1),
+        ];
+        for (n, (name, value, info, shndx)) in symbols.iter().enumerate() {
+            let sym = 0x88 + n * 16;
+            put(&mut data, sym, &name.to_be_bytes());
+            put(&mut data, sym + 4, &value.to_be_bytes());
+            data[sym + 12] = *info;
+            put(&mut data, sym + 14, &shndx.to_be_bytes());
+        }
+        put(&mut data, 0xE0, b"\0start\0buffer\0extern\0a.c\0");
+        put(&mut data, 0xF8, b"\0.symtab\0.strtab\0.shstrtab\0");
+
+        // Section headers: null, .symtab linked to .strtab, .strtab and
+        // .shstrtab
+        let sections: [(u32, u32, u32, u32, u32); 4] = [
+            (0, 0, 0, 0, 0),
+            (1, 2, 0x88, 80, 2),
+            (9, 3, 0xE0, 24, 0),
+            (17, 3, 0xF8, 27, 0),
+        ];
+        for (n, (name, kind, offset, size, link)) in sections.iter().enumerate() {
+            let sh = 0x100 + n * 40;
+            put(&mut data, sh, &name.to_be_bytes());
+            put(&mut data, sh + 4, &kind.to_be_bytes());
+            put(&mut data, sh + 16, &offset.to_be_bytes());
+            put(&mut data, sh + 20, &size.to_be_bytes());
+            put(&mut data, sh + 24, &link.to_be_bytes());
+        }
+        data
+    }
+
+    #[test]
+    fn loads_segments_bss_entry_and_symbols() {
+        let mut memory = vec![0xFFu8; 0x2000];
+        let elf = load(&executable(16), &mut memory[..]).unwrap();
+        assert_eq!(elf.entry, 0x1004);
+        assert_eq!(
+            memory[0x1000..0x1008],
+            [0x4E, 0x71, 0x4E, 0x71, 0x4E, 0x75, 0x4E, 0x75]
+        );
+        assert_eq!(memory[0x1008..0x1010], [0; 8]);
+        assert_eq!(memory[0x1010], 0xFF);
+        assert_eq!(elf.segments.len(), 1);
+        assert_eq!(elf.symbols.len(), 2);
+        assert_eq!(elf.symbols.address_of("start"), Some(0x1004));
+        assert_eq!(elf.symbols.address_of("buffer"), Some(0x1008));
+        assert_eq!(elf.symbols.address_of("extern"), None);
+    }
+
+    #[test]
+    fn segments_must_fit_into_memory() {
+        let mut memory = vec![0u8; 0x2000];
+        let error = load(&executable(0xFFFF_0000), &mut memory[..]).err();
+        assert_eq!(
+            error.as_deref(),
+            Some("Segment at $00001000 does not fit into memory")
+        );
+        assert!(load(&executable(4), &mut memory[..]).is_err());
+    }
+}
@@ -0,0 +1,295 @@
+use crate::cpu::CPU;
+use crate::loader::{read_long, slice, write_bytes};
+use crate::memory::Memory;
+use crate::symbols::SymbolTable;
+
+const HUNK_NAME: u32 = 0x3E8;
+const HUNK_CODE: u32 = 0x3E9;
+const HUNK_DATA: u32 = 0x3EA;
+const HUNK_BSS: u32 = 0x3EB;
+const HUNK_RELOC32: u32 = 0x3EC;
+const HUNK_SYMBOL: u32 = 0x3F0;
+const HUNK_DEBUG: u32 = 0x3F1;
+const HUNK_END: u32 = 0x3F2;
+const HUNK_HEADER: u32 = 0x3F3;
+const HUNK_DREL32: u32 = 0x3F7;
+const HUNK_RELOC32SHORT: u32 = 0x3FC;
+
+// Memory type bits in the top of hunk sizes and block types
+const MEMORY_FLAGS: u32 = 0xC000_0000;
+// Both flag bits set: the memory attributes follow in an extra long
+const MEMORY_EXTENDED: u32 = 0xC000_0000;
+
+// Each allocation starts with its size and a BPTR to the next segment
+const SEGMENT_HEADER: u32 = 8;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum HunkKind {
+    Code,
+    Data,
+    Bss,
+}
+
+pub struct Hunk {
+    pub kind: HunkKind,
+    // First byte of the hunk contents, past the segment header
+    pub address: u32,
+    pub size: u32,
+}
+
+pub struct Executable {
+    pub hunks: Vec<Hunk>,
+    // BPTR to the first segment, as returned by LoadSeg
+    pub segment_list: u32,
+    pub symbols: SymbolTable,
+}
+
+// Little cursor over the big-endian longs of a hunk file
+struct Reader<'a> {
+    data: &'a [u8],
+    offset: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn long(&mut self) -> Result<u32, String> {
+        let long = read_long(self.data, self.offset)?;
+        self.offset += 4;
+        Ok(long)
+    }
+
+    fn bytes(&mut self, longs: u32) -> Result<&'a [u8], String> {
+        let length = longs as usize * 4;
+        let bytes = slice(self.data, self.offset, length)?;
+        self.offset += length;
+        Ok(bytes)
+    }
+
+    // Relocation entry, a word in the short table formats
+    fn entry(&mut self, short: bool) -> Result<u32, String> {
+        if short {
+            let word = slice(self.data, self.offset, 2)?;
+            self.offset += 2;
+            Ok(u16::from_be_bytes([word[0], word[1]]) as u32)
+        } else {
+            self.long()
+        }
+    }
+
+    fn at_end(&self) -> bool {
+        self.offset >= self.data.len()
+    }
+}
+
+// Allocates the hunks of an executable one after another from base, the way
+// LoadSeg chains them into a segment list, and applies their relocations
+pub fn load<M: Memory + ?Sized>(data: &[u8], mem: &mut M, base: u32) -> Result<Executable, String> {
+    let mut reader = Reader { data, offset: 0 };
+    if reader
+        .long()
+        .map_err(|_| "File too short for a hunk header")?
+        != HUNK_HEADER
+    {
+        return Err("Not an Amiga hunk executable".to_string());
+    }
+    // Resident library names, unused by executables but still present
+    loop {
+        match reader.long()? {
+            0 => break,
+            longs => {
+                reader.bytes(longs)?;
+            }
+        }
+    }
+    reader.long()?;
+    let first = reader.long()?;
+    let last = reader.long()?;
+    if last < first {
+        return Err("Hunk header with no hunks".to_string());
+    }
+
+    let mut sizes = Vec::new();
+    for _ in first..=last {
+        let size = reader.long()?;
+        if size & MEMORY_FLAGS == MEMORY_EXTENDED {
+            reader.long()?;
+        }
+        sizes.push((size & !MEMORY_FLAGS) * 4);
+    }
+
+    // Allocation addresses, aligned to 8 bytes as AllocMem hands them out.
+    // Sizes come from the file, so every allocation must end inside memory
+    // before any hunk is expanded to its size.
+    let too_big = || "Hunks do not fit into memory".to_string();
+    let align = |address: u32| address.checked_add(7).map(|end| end & !7);
+    let mut addresses = Vec::new();
+    let mut next = align(base).ok_or_else(too_big)?;
+    for size in &sizes {
+        let address = next.checked_add(SEGMENT_HEADER).ok_or_else(too_big)?;
+        addresses.push(address);
+        next = address
+            .checked_add(*size)
+            .and_then(align)
+            .ok_or_else(too_big)?;
+    }
+    if mem.read_at_address_byte(next - 1).is_none() {
+        return Err(too_big());
+    }
+    for (i, address) in addresses.iter().enumerate() {
+        let segment = address - SEGMENT_HEADER;
+        let link = addresses.get(i + 1).map_or(0, |a| (a - 4) >> 2);
+        write_bytes(mem, segment, &(sizes[i] + SEGMENT_HEADER).to_be_bytes())?;
+        write_bytes(mem, segment + 4, &link.to_be_bytes())?;
+    }
+
+    let mut hunks = Vec::new();
+    let mut symbols = SymbolTable::new();
+    let mut contents: Vec<u8> = Vec::new();
+    let mut kind = None;
+    while !reader.at_end() {
+        let index = hunks.len();
+        let block = reader.long()? & !MEMORY_FLAGS;
+        match block {
+            HUNK_NAME | HUNK_DEBUG => {
+                let longs = reader.long()?;
+                reader.bytes(longs)?;
+            }
+            HUNK_CODE | HUNK_DATA | HUNK_BSS => {
+                if index >= sizes.len() {
+                    return Err("More hunks than the header declares".to_string());
+                }
+                let longs = reader.long()? & !MEMORY_FLAGS;
+                contents = if block == HUNK_BSS {
+                    Vec::new()
+                } else {
+                    reader.bytes(longs)?.to_vec()
+                };
+                if contents.len() as u32 > sizes[index] {
+                    return Err(format!("Hunk {} larger than its allocation", index));
+                }
+                contents.resize(sizes[index] as usize, 0);
+                kind = Some(match block {
+                    HUNK_CODE => HunkKind::Code,
+                    HUNK_DATA => HunkKind::Data,
+                    _ => HunkKind::Bss,
+                });
+            }
+            HUNK_RELOC32 | HUNK_RELOC32SHORT | HUNK_DREL32 => {
+                let short = block != HUNK_RELOC32;
+                relocate(&mut reader, short, &mut contents, &addresses)?;
+            }
+            HUNK_SYMBOL => loop {
+                let longs = reader.long()? & 0x00FF_FFFF;
+                if longs == 0 {
+                    break;
+                }
+                let name = reader.bytes(longs)?;
+                let name = name.split(|b| *b == 0).next().unwrap_or_default();
+                let value = reader.long()?;
+                if let Some(address) = addresses.get(index) {
+                    symbols.insert(&String::from_utf8_lossy(name), address.wrapping_add(value));
+                }
+            },
+            HUNK_END => {
+                let kind = kind.take().ok_or("HUNK_END without a hunk")?;
+                write_bytes(mem, addresses[index], &contents)?;
+                hunks.push(Hunk {
+                    kind,
+                    address: addresses[index],
+                    size: sizes[index],
+                });
+            }
+            _ => return Err(format!("Unsupported hunk type ${:X}", block)),
+        }
+    }
+    if hunks.len() != sizes.len() {
+        return Err("Fewer hunks than the header declares".to_string());
+    }
+
+    Ok(Executable {
+        hunks,
+        segment_list: (addresses[0] - 4) >> 2,
+        symbols,
+    })
+}
+
+// Loads an executable into the CPU memory and enters it the way the CLI starts
+// a command: arguments in A0, their length in D0, PC at the first hunk. The
+// argument line, ending in a newline, is placed after the last hunk.
+pub fn load_cpu(
+    data: &[u8],
+    cpu: &mut CPU,
+    base: u32,
+    arguments: &str,
+) -> Result<Executable, String> {
+    let executable = load(data, &mut cpu.memory_bus.memory, base)?;
+    let end = executable
+        .hunks
+        .last()
+        .map_or(base, |hunk| hunk.address + hunk.size);
+    let line = format!("{}\n", arguments);
+    write_bytes(&mut cpu.memory_bus.memory, end, line.as_bytes())?;
+    cpu.registers.A0 = end;
+    cpu.registers.D0 = line.len() as u32;
+    cpu.registers.PC = executable.hunks[0].address;
+    Ok(executable)
+}
+
+// Adds hunk addresses to the longs listed by a relocation block: groups of a
+// count, a target hunk and that many offsets, ending with a zero count
+fn relocate(
+    reader: &mut Reader,
+    short: bool,
+    contents: &mut [u8],
+    addresses: &[u32],
+) -> Result<(), String> {
+    let start = reader.offset;
+
+    loop {
+        let count = reader.entry(short)?;
+        if count == 0 {
+            break;
+        }
+        let target = reader.entry(short)? as usize;
+        let address = *addresses
+            .get(target)
+            .ok_or_else(|| format!("Relocation against missing hunk {}", target))?;
+        for _ in 0..count {
+            let offset = reader.entry(short)? as usize;
+            let long = contents
+                .get_mut(offset..offset + 4)
+                .ok_or_else(|| format!("Relocation at ${:X} outside its hunk", offset))?;
+            let value = u32::from_be_bytes([long[0], long[1], long[2], long[3]]);
+            long.copy_from_slice(&value.wrapping_add(address).to_be_bytes());
+        }
+    }
+    // Word-sized tables are padded to a long boundary
+    if short && !(reader.offset - start).is_multiple_of(4) {
+        reader.offset += 2;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{load, HUNK_HEADER};
+
+    // Header for one hunk of size longs, with no hunk blocks after it
+    fn header(size: u32) -> Vec<u8> {
+        [HUNK_HEADER, 0, 1, 0, 0, size]
+            .iter()
+            .flat_map(|long| long.to_be_bytes())
+            .collect()
+    }
+
+    #[test]
+    fn hunks_must_fit_into_memory() {
+        let mut memory = vec![0u8; 0x1000];
+        for size in [0x400, 0x3FFF_FFFF] {
+            let loaded = load(&header(size), &mut memory[..], 0x100);
+            assert_eq!(
+                loaded.err().as_deref(),
+                Some("Hunks do not fit into memory")
+            );
+        }
+    }
+}
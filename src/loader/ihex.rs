@@ -0,0 +1,147 @@
+use crate::cpu::CPU;
+use crate::loader::write_bytes;
+use crate::memory::Memory;
+
+const DATA: u8 = 0x00;
+const END_OF_FILE: u8 = 0x01;
+const EXTENDED_SEGMENT_ADDRESS: u8 = 0x02;
+const START_SEGMENT_ADDRESS: u8 = 0x03;
+const EXTENDED_LINEAR_ADDRESS: u8 = 0x04;
+const START_LINEAR_ADDRESS: u8 = 0x05;
+
+pub struct IntelHex {
+    pub start: Option<u32>,
+    pub records: usize,
+}
+
+// Parses Intel HEX text into memory, with every data address offset by base
+pub fn load<M: Memory + ?Sized>(text: &str, mem: &mut M, base: u32) -> Result<IntelHex, String> {
+    let mut hex = IntelHex {
+        start: None,
+        records: 0,
+    };
+    // Upper address bits set by the last extended address record
+    let mut upper = 0u32;
+    let mut ended = false;
+
+    for (n, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        let error = |message: &str| format!("line {}: {}", n + 1, message);
+        if ended {
+            return Err(error("record after end of file record"));
+        }
+
+        let bytes = parse_record(line).map_err(&error)?;
+        let offset = u16::from_be_bytes([bytes[1], bytes[2]]) as u32;
+        let data = &bytes[4..];
+        match bytes[3] {
+            DATA => {
+                let address = base.wrapping_add(upper).wrapping_add(offset);
+                write_bytes(mem, address, data).map_err(|e| error(&e))?;
+                hex.records += 1;
+            }
+            END_OF_FILE => ended = true,
+            EXTENDED_SEGMENT_ADDRESS if data.len() == 2 => {
+                upper = (u16::from_be_bytes([data[0], data[1]]) as u32) << 4
+            }
+            EXTENDED_LINEAR_ADDRESS if data.len() == 2 => {
+                upper = (u16::from_be_bytes([data[0], data[1]]) as u32) << 16
+            }
+            START_SEGMENT_ADDRESS if data.len() == 4 => {
+                let segment = u16::from_be_bytes([data[0], data[1]]) as u32;
+                let pointer = u16::from_be_bytes([data[2], data[3]]) as u32;
+                hex.start = Some(base.wrapping_add((segment << 4) + pointer));
+            }
+            START_LINEAR_ADDRESS if data.len() == 4 => {
+                let address = u32::from_be_bytes([data[0], data[1], data[2], data[3]]);
+                hex.start = Some(base.wrapping_add(address));
+            }
+            EXTENDED_SEGMENT_ADDRESS..=START_LINEAR_ADDRESS => {
+                return Err(error("wrong length for address record"))
+            }
+            _ => return Err(error("unknown record type")),
+        }
+    }
+    if !ended {
+        return Err("Missing end of file record".to_string());
+    }
+    Ok(hex)
+}
+
+// Loads Intel HEX into the CPU memory and points the PC at the start address
+pub fn load_cpu(text: &str, cpu: &mut CPU, base: u32) -> Result<IntelHex, String> {
+    let hex = load(text, &mut cpu.memory_bus.memory, base)?;
+    if let Some(start) = hex.start {
+        cpu.registers.PC = start;
+    }
+    Ok(hex)
+}
+
+// Decodes a record into count, offset, type and data bytes, checksum removed
+fn parse_record(line: &str) -> Result<Vec<u8>, &'static str> {
+    let digits = line.strip_prefix(':').ok_or("expected Intel HEX record")?;
+    if digits.len() < 10 || !digits.len().is_multiple_of(2) {
+        return Err("wrong number of hex digits");
+    }
+    let mut bytes = Vec::with_capacity(digits.len() / 2);
+    for pair in digits.as_bytes().chunks(2) {
+        let pair = std::str::from_utf8(pair).map_err(|_| "invalid hex digit")?;
+        bytes.push(u8::from_str_radix(pair, 16).map_err(|_| "invalid hex digit")?);
+    }
+    if bytes[0] as usize != bytes.len() - 5 {
+        return Err("byte count does not match record length");
+    }
+    if bytes.iter().fold(0u8, |acc, b| acc.wrapping_add(*b)) != 0 {
+        return Err("checksum mismatch");
+    }
+    bytes.pop();
+    Ok(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::load;
+
+    // One record with its byte count and checksum filled in
+    fn record(kind: u8, offset: u16, data: &[u8]) -> String {
+        let mut bytes = vec![data.len() as u8];
+        bytes.extend(offset.to_be_bytes());
+        bytes.push(kind);
+        bytes.extend(data);
+        bytes.push(bytes.iter().fold(0u8, |acc, b| acc.wrapping_sub(*b)));
+        let digits: String = bytes.iter().map(|b| format!("{:02X}", b)).collect();
+        format!(":{}\n", digits)
+    }
+
+    #[test]
+    fn extended_linear_addresses_select_the_upper_half() {
+        let text = [
+            record(0x00, 0x0010, &[0x11, 0x22]),
+            record(0x04, 0x0000, &[0x00, 0x01]),
+            record(0x00, 0x0010, &[0x33, 0x44]),
+            record(0x05, 0x0000, &[0x00, 0x01, 0x00, 0x10]),
+            record(0x01, 0x0000, &[]),
+        ]
+        .concat();
+        let mut memory = vec![0u8; 0x21000];
+        let hex = load(&text, &mut memory[..], 0x1000).unwrap();
+        assert_eq!(memory[0x1010..0x1012], [0x11, 0x22]);
+        assert_eq!(memory[0x11010..0x11012], [0x33, 0x44]);
+        assert_eq!(hex.start, Some(0x11010));
+        assert_eq!(hex.records, 2);
+    }
+
+    #[test]
+    fn rejects_bad_checksums_and_missing_end() {
+        let mut memory = [0u8; 0x100];
+        let good = record(0x00, 0x0000, &[0x55]);
+        let bad = good.replace("55AA", "55AB");
+        let error = load(&(bad + &record(0x01, 0, &[])), &mut memory[..], 0);
+        assert_eq!(error.err().as_deref(), Some("line 1: checksum mismatch"));
+        let error = load(&good, &mut memory[..], 0);
+        assert_eq!(error.err().as_deref(), Some("Missing end of file record"));
+    }
+}
@@ -0,0 +1,238 @@
+use crate::cpu::CPU;
+use crate::loader::write_bytes;
+use crate::memory::Memory;
+
+// Number of data bytes emitted per record by the writer
+const RECORD_LENGTH: usize = 32;
+
+pub struct SRecords {
+    pub header: Vec<u8>,
+    pub start: Option<u32>,
+    pub records: usize,
+}
+
+// Parses S-record text into memory, validating checksums and record counts
+pub fn load<M: Memory + ?Sized>(text: &str, mem: &mut M) -> Result<SRecords, String> {
+    let mut srecords = SRecords {
+        header: Vec::new(),
+        start: None,
+        records: 0,
+    };
+    let mut terminated = false;
+
+    for (n, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        let error = |message: &str| format!("line {}: {}", n + 1, message);
+        if terminated {
+            return Err(error("record after termination record"));
+        }
+
+        let (kind, bytes) = parse_record(line).map_err(&error)?;
+        let address_length = match kind {
+            b'0' | b'1' | b'5' | b'9' => 2,
+            b'2' | b'6' | b'8' => 3,
+            b'3' | b'7' => 4,
+            _ => return Err(error("unknown record type")),
+        };
+        if bytes.len() < address_length {
+            return Err(error("record too short"));
+        }
+        let address = bytes[..address_length]
+            .iter()
+            .fold(0u32, |acc, b| acc << 8 | *b as u32);
+        let data = &bytes[address_length..];
+
+        match kind {
+            b'0' => srecords.header = data.to_vec(),
+            b'1'..=b'3' => {
+                write_bytes(mem, address, data).map_err(|e| error(&e))?;
+                srecords.records += 1;
+            }
+            b'5' | b'6' => {
+                if !data.is_empty() {
+                    return Err(error("count record carries data"));
+                }
+                if address as usize != srecords.records {
+                    return Err(error(&format!(
+                        "record count {} does not match {} data records",
+                        address, srecords.records
+                    )));
+                }
+            }
+            _ => {
+                if !data.is_empty() {
+                    return Err(error("termination record carries data"));
+                }
+                srecords.start = Some(address);
+                terminated = true;
+            }
+        }
+    }
+    Ok(srecords)
+}
+
+// Loads S-records into the CPU memory and points the PC at the start address
+pub fn load_cpu(text: &str, cpu: &mut CPU) -> Result<SRecords, String> {
+    let srecords = load(text, &mut cpu.memory_bus.memory)?;
+    if let Some(start) = srecords.start {
+        cpu.registers.PC = start;
+    }
+    Ok(srecords)
+}
+
+// Splits a record into its type and checksummed payload (address and data)
+fn parse_record(line: &str) -> Result<(u8, Vec<u8>), &'static str> {
+    let line = line.as_bytes();
+    if line.len() < 4 || line[0] != b'S' {
+        return Err("expected S-record");
+    }
+    if !line.len().is_multiple_of(2) {
+        return Err("odd number of hex digits");
+    }
+    let mut bytes = Vec::with_capacity(line.len() / 2 - 1);
+    for pair in line[2..].chunks(2) {
+        let digits = std::str::from_utf8(pair).map_err(|_| "invalid hex digit")?;
+        bytes.push(u8::from_str_radix(digits, 16).map_err(|_| "invalid hex digit")?);
+    }
+    if bytes[0] as usize != bytes.len() - 1 {
+        return Err("byte count does not match record length");
+    }
+    let sum = bytes[..bytes.len() - 1]
+        .iter()
+        .fold(0u8, |acc, b| acc.wrapping_add(*b));
+    if !sum != bytes[bytes.len() - 1] {
+        return Err("checksum mismatch");
+    }
+    bytes.pop();
+    bytes.remove(0);
+    Ok((line[1], bytes))
+}
+
+// Dumps the given (start, end) memory ranges, end exclusive, as S-records
+pub fn write<M: Memory + ?Sized>(
+    mem: &M,
+    ranges: &[(u32, u32)],
+    header: &str,
+    start: Option<u32>,
+) -> Result<String, String> {
+    let highest = ranges
+        .iter()
+        .map(|(_, end)| end.saturating_sub(1))
+        .chain(start)
+        .max()
+        .unwrap_or(0);
+    let (data_kind, end_kind, address_length) = if highest <= 0xFFFF {
+        (b'1', b'9', 2)
+    } else if highest <= 0xFF_FFFF {
+        (b'2', b'8', 3)
+    } else {
+        (b'3', b'7', 4)
+    };
+
+    let mut text = String::new();
+    let header = &header.as_bytes()[..header.len().min(RECORD_LENGTH)];
+    record(&mut text, b'0', 2, 0, header);
+
+    let mut records = 0;
+    for &(from, to) in ranges {
+        let mut address = from;
+        while address < to {
+            let length = ((to - address) as usize).min(RECORD_LENGTH);
+            let mut data = Vec::with_capacity(length);
+            for offset in 0..length as u32 {
+                let byte = mem
+                    .read_at_address_byte(address + offset)
+                    .ok_or_else(|| format!("Address ${:X} out of bounds", address + offset))?;
+                data.push(byte);
+            }
+            record(&mut text, data_kind, address_length, address, &data);
+            records += 1;
+            address += length as u32;
+        }
+    }
+
+    if records <= 0xFFFF {
+        record(&mut text, b'5', 2, records, &[]);
+    } else if records <= 0xFF_FFFF {
+        record(&mut text, b'6', 3, records, &[]);
+    }
+    record(&mut text, end_kind, address_length, start.unwrap_or(0), &[]);
+    Ok(text)
+}
+
+fn record(text: &mut String, kind: u8, address_length: usize, address: u32, data: &[u8]) {
+    let mut bytes = vec![(address_length + data.len() + 1) as u8];
+    bytes.extend_from_slice(&address.to_be_bytes()[4 - address_length..]);
+    bytes.extend_from_slice(data);
+    let sum = bytes.iter().fold(0u8, |acc, b| acc.wrapping_add(*b));
+    bytes.push(!sum);
+
+    text.push('S');
+    text.push(kind as char);
+    for byte in bytes {
+        text.push_str(&format!("{:02X}", byte));
+    }
+    text.push('\n');
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{load, write};
+
+    const HELLO: &str = "\
+S00F000068656C6C6F202020202000003C
+S11F00007C0802A6900100049421FFF07C6C1B787C8C23783C6000003863000026
+S11F001C4BFFFFE5398000007D83637880010014382100107C0803A64E800020E9
+S111003848656C6C6F20776F726C642E0A0042
+S5030003F9
+S9030000FC
+";
+
+    #[test]
+    fn loads_data_header_and_start() {
+        let mut memory = [0u8; 0x100];
+        let srecords = load(HELLO, &mut memory[..]).unwrap();
+        assert_eq!(srecords.header, b"hello     \0\0");
+        assert_eq!(srecords.records, 3);
+        assert_eq!(srecords.start, Some(0));
+        assert_eq!(memory[..4], [0x7C, 0x08, 0x02, 0xA6]);
+        assert_eq!(&memory[0x38..0x45], b"Hello world.\n");
+    }
+
+    #[test]
+    fn rejects_bad_checksums_and_counts() {
+        let mut memory = [0u8; 0x100];
+        let bad_checksum = HELLO.replace("0A0042", "0A0043");
+        let error = load(&bad_checksum, &mut memory[..]).err().unwrap();
+        assert_eq!(error, "line 4: checksum mismatch");
+        let bad_count = HELLO.replace("S5030003F9", "S5030004F8");
+        let error = load(&bad_count, &mut memory[..]).err().unwrap();
+        assert_eq!(
+            error,
+            "line 5: record count 4 does not match 3 data records"
+        );
+    }
+
+    #[test]
+    fn written_records_load_back() {
+        let mut memory = vec![0u8; 0x10100];
+        for (n, byte) in memory.iter_mut().enumerate() {
+            *byte = (n * 7) as u8;
+        }
+        let ranges = [(0x100, 0x145), (0x10000, 0x10010)];
+        let text = write(&memory[..], &ranges, "test", Some(0x100)).unwrap();
+        assert!(text.lines().nth(1).unwrap().starts_with("S2"));
+
+        let mut copy = vec![0u8; 0x10100];
+        let srecords = load(&text, &mut copy[..]).unwrap();
+        assert_eq!(srecords.header, b"test");
+        assert_eq!(srecords.start, Some(0x100));
+        for (start, end) in ranges.map(|(start, end)| (start as usize, end as usize)) {
+            assert_eq!(copy[start..end], memory[start..end]);
+        }
+        assert_eq!(copy[0x145], 0);
+    }
+}
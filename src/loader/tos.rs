@@ -0,0 +1,230 @@
+use crate::cpu::CPU;
+use crate::loader::{read_long, read_word, slice, write_bytes};
+use crate::memory::Memory;
+use crate::symbols::SymbolTable;
+
+const PRG_MAGIC: u16 = 0x601A;
+const HEADER_SIZE: usize = 28;
+const BASEPAGE_SIZE: u32 = 256;
+// Offset of the command line inside the basepage, also the default DTA
+const COMMAND_LINE: u32 = 0x80;
+const COMMAND_LINE_LENGTH: usize = 125;
+
+// DRI symbol table entry types
+const SYMBOL_SIZE: usize = 14;
+const SYMBOL_BSS: u16 = 0x0100;
+const SYMBOL_TEXT: u16 = 0x0200;
+const SYMBOL_DATA: u16 = 0x0400;
+const SYMBOL_EQUATED: u16 = 0x4000;
+// GST extension: the following entry continues the name
+const SYMBOL_LONG_NAME: u16 = 0x0048;
+
+pub struct Prg {
+    pub basepage: u32,
+    pub text: u32,
+    pub text_length: u32,
+    pub data: u32,
+    pub data_length: u32,
+    pub bss: u32,
+    pub bss_length: u32,
+    // Header flags: fast load, TT RAM, memory protection
+    pub flags: u32,
+    pub symbols: SymbolTable,
+}
+
+// Places a GEMDOS executable in the transient program area (TPA) running from
+// basepage to top, with its basepage at the bottom and the program right after
+pub fn load<M: Memory + ?Sized>(
+    data: &[u8],
+    mem: &mut M,
+    basepage: u32,
+    top: u32,
+    command_line: &str,
+) -> Result<Prg, String> {
+    if read_word(data, 0).map_err(|_| "File too short for a PRG header")? != PRG_MAGIC {
+        return Err("Not a GEMDOS executable".to_string());
+    }
+    let text_length = read_long(data, 2)?;
+    let data_length = read_long(data, 6)?;
+    let bss_length = read_long(data, 10)?;
+    let symbols_length = read_long(data, 14)? as usize;
+    let flags = read_long(data, 22)?;
+    // Non-zero when the program carries no relocation table
+    let absolute = read_word(data, 26)? != 0;
+
+    // Lengths come from the file, so a sum past the top of the address space
+    // is as much a misfit as one past the top of the TPA
+    let too_big = || "Program does not fit into the TPA".to_string();
+    let text = basepage.checked_add(BASEPAGE_SIZE).ok_or_else(too_big)?;
+    let data_start = text.checked_add(text_length).ok_or_else(too_big)?;
+    let bss = data_start.checked_add(data_length).ok_or_else(too_big)?;
+    if bss.checked_add(bss_length).is_none_or(|end| end > top) {
+        return Err(too_big());
+    }
+
+    let image_length = (bss - text) as usize;
+    let mut image = slice(data, HEADER_SIZE, image_length)?.to_vec();
+    let symbols_offset = HEADER_SIZE + image_length;
+    let relocations = symbols_offset + symbols_length;
+    if !absolute {
+        relocate(data, relocations, &mut image, text)?;
+    }
+    write_bytes(mem, text, &image)?;
+    write_bytes(mem, bss, &vec![0; bss_length as usize])?;
+
+    let prg = Prg {
+        basepage,
+        text,
+        text_length,
+        data: data_start,
+        data_length,
+        bss,
+        bss_length,
+        flags,
+        symbols: symbol_table(slice(data, symbols_offset, symbols_length)?, text),
+    };
+    write_basepage(mem, &prg, top, command_line)?;
+    Ok(prg)
+}
+
+// Loads a program into the CPU memory and sets up the registers and stack the
+// way Pexec hands over to a program: the basepage address at 4(SP), A0 clear
+pub fn load_cpu(
+    data: &[u8],
+    cpu: &mut CPU,
+    basepage: u32,
+    top: u32,
+    command_line: &str,
+) -> Result<Prg, String> {
+    let prg = load(
+        data,
+        &mut cpu.memory_bus.memory,
+        basepage,
+        top,
+        command_line,
+    )?;
+    // A zero return address, then the basepage
+    let sp = top
+        .checked_sub(8)
+        .ok_or("No room for the stack below the TPA top")?;
+    let mut frame = 0u32.to_be_bytes().to_vec();
+    frame.extend_from_slice(&basepage.to_be_bytes());
+    write_bytes(&mut cpu.memory_bus.memory, sp, &frame)?;
+    cpu.registers.SP = sp;
+    cpu.registers.A0 = 0;
+    cpu.registers.PC = prg.text;
+    Ok(prg)
+}
+
+// Adds the text address to every long named by the fixup table: a long offset
+// to the first fixup, then byte deltas to the next, 1 meaning skip 254 bytes
+fn relocate(data: &[u8], offset: usize, image: &mut [u8], text: u32) -> Result<(), String> {
+    let first = match data.get(offset..) {
+        Some(rest) if rest.len() >= 4 => read_long(data, offset)?,
+        // Some linkers leave out an empty table altogether
+        _ => 0,
+    };
+    if first == 0 {
+        return Ok(());
+    }
+
+    let mut target = first as usize;
+    let mut deltas = data[offset + 4..].iter();
+    loop {
+        let long = image
+            .get_mut(target..target + 4)
+            .ok_or_else(|| format!("Relocation at ${:X} outside the program", target))?;
+        let value = u32::from_be_bytes([long[0], long[1], long[2], long[3]]).wrapping_add(text);
+        long.copy_from_slice(&value.to_be_bytes());
+
+        loop {
+            match deltas.next() {
+                None => return Err("Unterminated relocation table".to_string()),
+                Some(0) => return Ok(()),
+                Some(1) => target += 254,
+                Some(delta) => {
+                    target += *delta as usize;
+                    break;
+                }
+            }
+        }
+    }
+}
+
+fn write_basepage<M: Memory + ?Sized>(
+    mem: &mut M,
+    prg: &Prg,
+    top: u32,
+    command_line: &str,
+) -> Result<(), String> {
+    let mut page = vec![0u8; BASEPAGE_SIZE as usize];
+    let fields = [
+        prg.basepage,
+        top,
+        prg.text,
+        prg.text_length,
+        prg.data,
+        prg.data_length,
+        prg.bss,
+        prg.bss_length,
+        // load checked that the basepage fits below the text
+        prg.basepage.wrapping_add(COMMAND_LINE),
+    ];
+    for (i, field) in fields.iter().enumerate() {
+        page[i * 4..i * 4 + 4].copy_from_slice(&field.to_be_bytes());
+    }
+
+    let line = &command_line.as_bytes()[..command_line.len().min(COMMAND_LINE_LENGTH)];
+    let at = COMMAND_LINE as usize;
+    page[at] = line.len() as u8;
+    page[at + 1..at + 1 + line.len()].copy_from_slice(line);
+    write_bytes(mem, prg.basepage, &page)
+}
+
+// Symbols from a DRI (or GST extended) symbol table, relocated to text
+fn symbol_table(table: &[u8], text: u32) -> SymbolTable {
+    let mut symbols = SymbolTable::new();
+    let mut entries = table.chunks_exact(SYMBOL_SIZE);
+    while let Some(entry) = entries.next() {
+        let kind = u16::from_be_bytes([entry[8], entry[9]]);
+        let value = u32::from_be_bytes([entry[10], entry[11], entry[12], entry[13]]);
+        let mut name = entry[..8].to_vec();
+        if kind & SYMBOL_LONG_NAME == SYMBOL_LONG_NAME {
+            if let Some(more) = entries.next() {
+                name.extend_from_slice(more);
+            }
+        }
+        let name = name.split(|b| *b == 0).next().unwrap_or_default();
+
+        let address = if kind & SYMBOL_EQUATED != 0 {
+            value
+        } else if kind & (SYMBOL_TEXT | SYMBOL_DATA | SYMBOL_BSS) != 0 {
+            text.wrapping_add(value)
+        } else {
+            continue;
+        };
+        if !name.is_empty() {
+            symbols.insert(&String::from_utf8_lossy(name), address);
+        }
+    }
+    symbols
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{load, HEADER_SIZE, PRG_MAGIC};
+
+    #[test]
+    fn lengths_past_the_address_space_do_not_fit() {
+        let mut data = vec![0; HEADER_SIZE];
+        data[..2].copy_from_slice(&PRG_MAGIC.to_be_bytes());
+        data[2..6].copy_from_slice(&0xFFFF_FF00u32.to_be_bytes());
+        data[6..10].copy_from_slice(&0x200u32.to_be_bytes());
+        let mut memory = vec![0u8; 0x1000];
+        let loaded = load(&data, &mut memory[..], 0x100, 0x1000, "");
+        assert_eq!(
+            loaded.err().as_deref(),
+            Some("Program does not fit into the TPA")
+        );
+    }
+}
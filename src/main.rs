@@ -1,9 +1,153 @@
+pub mod assembler;
+pub mod backtrace;
+pub mod board;
+pub mod coverage;
 pub mod cpu;
+pub mod debugger;
 pub mod decoder;
+pub mod device;
 pub mod emulator;
+pub mod encoder;
+pub mod gdb;
 pub mod instruction;
+pub mod loader;
 pub mod memory;
+pub mod profiler;
+pub mod recorder;
+pub mod savestate;
+pub mod symbols;
+pub mod trace;
+pub mod trap;
+
+use std::process;
+
+use cpu::CPU;
+use debugger::Debugger;
+use trace::{TraceFormat, Tracer};
+use trap::easy68k::Easy68K;
+use trap::semihosting::Semihosting;
+
+const USAGE: &str = "\
+usage: rs68000 [OPTIONS] [PROGRAM [ARGS...]]
+
+  -x FILE          run debugger commands from FILE before reading stdin
+  -r, --run        run PROGRAM to completion and exit with its status
+  --board NAME     boot PROGRAM as the firmware ROM of board NAME
+                   (rosco_m68k), with its serial port on stdio
+  --odd FILE       PROGRAM is the even ROM of a split pair and FILE the
+                   odd one, loaded as a raw image or board firmware
+  --linux          run ELF programs as Linux user-mode processes
+  --easy68k        provide the Easy68K TRAP #15 services
+  --semihost[=N]   provide semihosting calls on TRAP #N (default 15)
+  --trace FILE     log executed instructions to FILE
+  --gdb[=PORT]     serve GDB remote protocol on localhost:PORT (default 1234)";
 
 fn main() {
-    println!("Hello, world!");
+    let mut debugger = Debugger::new(CPU::new());
+    let mut scripts = Vec::new();
+    let mut run = false;
+    let mut gdb_port = None;
+    let mut board = None;
+    let mut odd = None;
+
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let mut args = args.iter();
+    let mut program = None;
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-x" => match args.next() {
+                Some(script) => scripts.push(script),
+                None => usage(),
+            },
+            "-r" | "--run" => run = true,
+            "--trace" => match args.next() {
+                Some(path) => match Tracer::create(path, TraceFormat::Full) {
+                    Ok(tracer) => debugger.tracer = Some(tracer),
+                    Err(e) => {
+                        eprintln!("{}", e);
+                        process::exit(1);
+                    }
+                },
+                None => usage(),
+            },
+            "--board" => match args.next() {
+                Some(name) => board = Some(name),
+                None => usage(),
+            },
+            "--odd" => match args.next() {
+                Some(path) => odd = Some(path.as_str()),
+                None => usage(),
+            },
+            "--linux" => debugger.linux = true,
+            "--easy68k" => debugger.trap_handler = Some(Box::new(Easy68K::new())),
+            "-h" | "--help" => usage(),
+            _ if arg.starts_with("--semihost") => {
+                let vector = match arg.strip_prefix("--semihost=") {
+                    Some(vector) => vector.parse().unwrap_or_else(|_| usage()),
+                    None if arg == "--semihost" => 15,
+                    None => usage(),
+                };
+                debugger.trap_handler = Some(Box::new(Semihosting::new(vector)));
+            }
+            _ if arg.starts_with("--gdb") => {
+                gdb_port = match arg.strip_prefix("--gdb=") {
+                    Some(port) => Some(port.parse().unwrap_or_else(|_| usage())),
+                    None if arg == "--gdb" => Some(1234),
+                    None => usage(),
+                };
+            }
+            _ if arg.starts_with('-') => usage(),
+            _ => {
+                program = Some(arg);
+                break;
+            }
+        }
+    }
+
+    if (board.is_some() || odd.is_some()) && program.is_none() {
+        usage();
+    }
+    if let Some(program) = program {
+        let program_args: Vec<&str> = args.map(String::as_str).collect();
+        let loaded = match (board, odd) {
+            (Some(_), _) | (_, Some(_)) if !program_args.is_empty() => usage(),
+            (Some(board), odd) => debugger.boot(board, program, odd),
+            (None, Some(odd)) => debugger.load_split(program, odd, None),
+            (None, None) => debugger.load(program, None, &program_args),
+        };
+        if let Err(e) = loaded {
+            eprintln!("{}", e);
+            process::exit(1);
+        }
+    }
+    for script in scripts {
+        if let Err(e) = debugger.source(script) {
+            eprintln!("{}", e);
+            process::exit(1);
+        }
+    }
+    if let Some(port) = gdb_port {
+        if let Err(e) = gdb::serve(&mut debugger, port) {
+            eprintln!("{}", e);
+            process::exit(1);
+        }
+    } else if run {
+        debugger.command("continue");
+    } else {
+        debugger.repl();
+    }
+    debugger.flush_trace();
+    // A program that faults, including on an exception with no handler,
+    // fails unless it exited first
+    let status = match debugger.exit_status {
+        Some(status) => status,
+        None if debugger.faulted => 1,
+        None => 0,
+    };
+    process::exit(status);
+}
+
+fn usage() -> ! {
+    eprintln!("{}", USAGE);
+    process::exit(2);
 }
@@ -9,7 +9,7 @@ pub trait Memory {
     fn write_at_address_word(&mut self, address: u32, data: u16) -> Result<(), &str>;
     fn write_at_address_long(&mut self, address: u32, data: u32) -> Result<(), &str>;
 
-    fn iter(&mut self, address: u32) -> MemoryIter<Self> {
+    fn iter(&mut self, address: u32) -> MemoryIter<'_, Self> {
         MemoryIter {
             mem: self,
             next_address: address,
@@ -32,7 +32,7 @@ impl<M: Memory + ?Sized> Iterator for MemoryIter<'_, M> {
     type Item = u16;
 
     fn next(&mut self) -> Option<Self::Item> {
-        if self.next_address % 2 == 0 {
+        if self.next_address.is_multiple_of(2) {
             let current_addr = self.next_address;
             self.next_address += 2;
             Some(
@@ -50,7 +50,7 @@ impl<M: Memory + ?Sized> Iterator for MemoryIter<'_, M> {
 impl Memory for [u8] {
     fn read_at_address_byte(&self, address: u32) -> Option<u8> {
         let address = address as usize;
-        if self.len() >= address {
+        if self.len() > address {
             Some(self[address])
         } else {
             None
@@ -59,7 +59,7 @@ impl Memory for [u8] {
 
     fn read_at_address_word(&self, address: u32) -> Option<u16> {
         let address = address as usize;
-        if self.len() >= address + 1 {
+        if self.len() > address + 1 {
             Some((self[address] as u16) << 8 | self[address + 1] as u16)
         } else {
             None
@@ -68,7 +68,7 @@ impl Memory for [u8] {
 
     fn read_at_address_long(&self, address: u32) -> Option<u32> {
         let address = address as usize;
-        if self.len() >= address + 3 {
+        if self.len() > address + 3 {
             Some(
                 (self[address] as u32) << 24
                     | (self[address + 1] as u32) << 16
@@ -82,7 +82,7 @@ impl Memory for [u8] {
 
     fn write_at_address_byte(&mut self, address: u32, data: u8) -> Result<(), &str> {
         let address = address as usize;
-        if self.len() >= address {
+        if self.len() > address {
             self[address] = data;
             Ok(())
         } else {
@@ -92,7 +92,7 @@ impl Memory for [u8] {
 
     fn write_at_address_word(&mut self, address: u32, data: u16) -> Result<(), &str> {
         let address = address as usize;
-        if self.len() >= address + 1 {
+        if self.len() > address + 1 {
             self[address] = (data >> 8) as u8;
             self[address + 1] = data as u8;
             Ok(())
@@ -103,7 +103,7 @@ impl Memory for [u8] {
 
     fn write_at_address_long(&mut self, address: u32, data: u32) -> Result<(), &str> {
         let address = address as usize;
-        if self.len() >= address + 3 {
+        if self.len() > address + 3 {
             self[address] = (data >> 24) as u8;
             self[address + 1] = (data >> 16) as u8;
             self[address + 2] = (data >> 8) as u8;
@@ -115,59 +115,48 @@ impl Memory for [u8] {
     }
 }
 
+// Word-wide memory addressed in bytes: address 2n is the high byte of word n
 impl Memory for [u16] {
     fn read_at_address_byte(&self, address: u32) -> Option<u8> {
-        let address = address as usize;
-        if self.len() >= address {
-            Some((self[address] >> 8) as u8)
-        } else {
-            None
-        }
+        let word = *self.get(address as usize >> 1)?;
+        Some(if address & 1 == 0 { word >> 8 } else { word } as u8)
     }
 
     fn read_at_address_word(&self, address: u32) -> Option<u16> {
-        let address = address as usize;
-        if self.len() >= address {
-            Some(self[address])
-        } else {
-            None
-        }
+        self.get(address as usize >> 1).copied()
     }
 
     fn read_at_address_long(&self, address: u32) -> Option<u32> {
-        let address = address as usize;
-        if self.len() >= address + 1 {
-            Some((self[address] as u32) << 16 | self[address + 1] as u32)
-        } else {
-            None
-        }
+        let index = address as usize >> 1;
+        let high = *self.get(index)? as u32;
+        Some(high << 16 | *self.get(index + 1)? as u32)
     }
 
     fn write_at_address_byte(&mut self, address: u32, data: u8) -> Result<(), &str> {
-        let address = address as usize >> 1;
-        if self.len() >= address {
-            self[address] = (self[address] & 0x00FF) | ((data as u16) << 8);
-            Ok(())
+        let word = self
+            .get_mut(address as usize >> 1)
+            .ok_or("Address out of Bounds")?;
+        *word = if address & 1 == 0 {
+            *word & 0x00FF | (data as u16) << 8
         } else {
-            Err("Address out of Bounds")
-        }
+            *word & 0xFF00 | data as u16
+        };
+        Ok(())
     }
 
     fn write_at_address_word(&mut self, address: u32, data: u16) -> Result<(), &str> {
-        let address = address as usize >> 1;
-        if self.len() >= address {
-            self[address] = data;
-            Ok(())
-        } else {
-            Err("Address out of Bounds")
-        }
+        let word = self
+            .get_mut(address as usize >> 1)
+            .ok_or("Address out of Bounds")?;
+        *word = data;
+        Ok(())
     }
 
     fn write_at_address_long(&mut self, address: u32, data: u32) -> Result<(), &str> {
-        let address = address as usize >> 1;
-        if self.len() >= address + 1 {
-            self[address] = (data >> 16) as u16;
-            self[address + 1] = data as u16;
+        let index = address as usize >> 1;
+        if self.len() > index + 1 {
+            self[index] = (data >> 16) as u16;
+            self[index + 1] = data as u16;
             Ok(())
         } else {
             Err("Address out of Bounds")
@@ -178,7 +167,7 @@ impl Memory for [u16] {
 impl Memory for Box<[u8]> {
     fn read_at_address_byte(&self, address: u32) -> Option<u8> {
         let address = address as usize;
-        if self.len() >= address {
+        if self.len() > address {
             Some(self[address])
         } else {
             None
@@ -187,7 +176,7 @@ impl Memory for Box<[u8]> {
 
     fn read_at_address_word(&self, address: u32) -> Option<u16> {
         let address = address as usize;
-        if self.len() >= address + 1 {
+        if self.len() > address + 1 {
             Some((self[address] as u16) << 8 | self[address + 1] as u16)
         } else {
             None
@@ -196,7 +185,7 @@ impl Memory for Box<[u8]> {
 
     fn read_at_address_long(&self, address: u32) -> Option<u32> {
         let address = address as usize;
-        if self.len() >= address + 3 {
+        if self.len() > address + 3 {
             Some(
                 (self[address] as u32) << 24
                     | (self[address + 1] as u32) << 16
@@ -210,7 +199,7 @@ impl Memory for Box<[u8]> {
 
     fn write_at_address_byte(&mut self, address: u32, data: u8) -> Result<(), &str> {
         let address = address as usize;
-        if self.len() >= address {
+        if self.len() > address {
             self[address] = data;
             Ok(())
         } else {
@@ -220,7 +209,7 @@ impl Memory for Box<[u8]> {
 
     fn write_at_address_word(&mut self, address: u32, data: u16) -> Result<(), &str> {
         let address = address as usize;
-        if self.len() >= address + 1 {
+        if self.len() > address + 1 {
             self[address] = (data >> 8) as u8;
             self[address + 1] = data as u8;
             Ok(())
@@ -231,7 +220,7 @@ impl Memory for Box<[u8]> {
 
     fn write_at_address_long(&mut self, address: u32, data: u32) -> Result<(), &str> {
         let address = address as usize;
-        if self.len() >= address + 3 {
+        if self.len() > address + 3 {
             self[address] = (data >> 24) as u8;
             self[address + 1] = (data >> 16) as u8;
             self[address + 2] = (data >> 8) as u8;
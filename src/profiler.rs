@@ -0,0 +1,354 @@
+use std::collections::HashMap;
+use std::fmt::Write as _;
+use std::ops::{AddAssign, Sub};
+
+use crate::instruction::Instructions;
+use crate::symbols::SymbolTable;
+
+// Instructions executed and the clock cycles they took
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+struct Cost {
+    instructions: u64,
+    cycles: u64,
+}
+
+impl AddAssign for Cost {
+    fn add_assign(&mut self, other: Cost) {
+        self.instructions += other.instructions;
+        self.cycles += other.cycles;
+    }
+}
+
+impl Sub for Cost {
+    type Output = Cost;
+
+    fn sub(self, other: Cost) -> Cost {
+        Cost {
+            instructions: self.instructions - other.instructions,
+            cycles: self.cycles - other.cycles,
+        }
+    }
+}
+
+// A subroutine call in progress
+struct Frame {
+    entry: u32,
+    return_address: u32,
+    call_site: u32,
+    // Total cost when the call was made
+    started: Cost,
+}
+
+#[derive(Default)]
+struct Call {
+    count: u64,
+    // Cost of the callee and its callees
+    inclusive: Cost,
+}
+
+// Counts instruction executions and cycles per PC, following JSR/BSR and
+// RTS/RTD/RTR/RTE to attribute them to functions and call stacks. Cycles come
+// from the CPU's timing tables, so hot spots are ranked by time, not count.
+pub struct Profiler {
+    counts: HashMap<u32, Cost>,
+    // Entry of the function each PC first ran in
+    functions: HashMap<u32, u32>,
+    stack: Vec<Frame>,
+    // Entry where profiling started, the bottom of every call stack
+    root: Option<u32>,
+    // Function entries of the root and the calls in progress
+    path: Vec<u32>,
+    // Cost per call stack path
+    stacks: HashMap<Vec<u32>, Cost>,
+    // Calls by call site and callee entry
+    calls: HashMap<(u32, u32), Call>,
+    // Taken backward branches by source and target
+    loops: HashMap<(u32, u32), u64>,
+    total: Cost,
+}
+
+impl Profiler {
+    pub fn new() -> Self {
+        Self {
+            counts: HashMap::new(),
+            functions: HashMap::new(),
+            stack: Vec::new(),
+            root: None,
+            path: Vec::new(),
+            stacks: HashMap::new(),
+            calls: HashMap::new(),
+            loops: HashMap::new(),
+            total: Cost::default(),
+        }
+    }
+
+    // Counts ins at pc, which ends at next, took cycles and left the PC at
+    // after
+    pub fn record(&mut self, pc: u32, ins: &Instructions, next: u32, after: u32, cycles: u64) {
+        if self.path.is_empty() {
+            self.path.push(*self.root.get_or_insert(pc));
+        }
+        let entry = self.path[self.path.len() - 1];
+        let cost = Cost {
+            instructions: 1,
+            cycles,
+        };
+        self.total += cost;
+        *self.counts.entry(pc).or_default() += cost;
+        self.functions.entry(pc).or_insert(entry);
+        match self.stacks.get_mut(self.path.as_slice()) {
+            Some(total) => *total += cost,
+            None => {
+                self.stacks.insert(self.path.clone(), cost);
+            }
+        }
+
+        match ins {
+            Instructions::JSR(_) | Instructions::BSR(_) if after != next => {
+                self.calls.entry((pc, after)).or_default().count += 1;
+                self.path.push(after);
+                self.stack.push(Frame {
+                    entry: after,
+                    return_address: next,
+                    call_site: pc,
+                    started: self.total,
+                });
+            }
+            Instructions::RTS | Instructions::RTD(_) | Instructions::RTR | Instructions::RTE => {
+                // Unwind to the frame returned to, so code that pops return
+                // addresses itself does not leave stale frames behind
+                if let Some(depth) = self
+                    .stack
+                    .iter()
+                    .rposition(|frame| frame.return_address == after)
+                {
+                    self.path.truncate(depth + 1);
+                    for frame in self.stack.drain(depth..).rev() {
+                        let call = self.calls.entry((frame.call_site, frame.entry));
+                        call.or_default().inclusive += self.total - frame.started;
+                    }
+                }
+            }
+            _ if after <= pc && after != next => {
+                *self.loops.entry((pc, after)).or_default() += 1;
+            }
+            _ => {}
+        }
+    }
+
+    // Functions and instructions taking the most cycles, count of each, and
+    // the most taken loops
+    pub fn report(&self, symbols: &SymbolTable, count: usize) -> String {
+        let mut text = format!(
+            "{} instructions executed in {} cycles\n",
+            self.total.instructions, self.total.cycles
+        );
+
+        let mut functions: HashMap<String, Cost> = HashMap::new();
+        for (pc, cost) in &self.counts {
+            *functions.entry(self.function_of(symbols, *pc)).or_default() += *cost;
+        }
+        let mut functions: Vec<(String, Cost)> = functions.into_iter().collect();
+        functions.sort_by(|a, b| b.1.cycles.cmp(&a.1.cycles).then_with(|| a.0.cmp(&b.0)));
+        text.push_str("\nFunctions (executions, cycles):\n");
+        for (name, cost) in functions.iter().take(count) {
+            let _ = writeln!(
+                text,
+                "{:>12} {:>12} {:6.2}%  {}",
+                cost.instructions,
+                cost.cycles,
+                self.percent(cost.cycles),
+                name
+            );
+        }
+
+        let mut instructions: Vec<(&u32, &Cost)> = self.counts.iter().collect();
+        instructions.sort_by(|a, b| b.1.cycles.cmp(&a.1.cycles).then_with(|| a.0.cmp(b.0)));
+        text.push_str("\nInstructions (executions, cycles):\n");
+        for (pc, cost) in instructions.iter().take(count) {
+            let _ = writeln!(
+                text,
+                "{:>12} {:>12} {:6.2}%  ${:08X}{}",
+                cost.instructions,
+                cost.cycles,
+                self.percent(cost.cycles),
+                pc,
+                location(symbols, **pc)
+            );
+        }
+
+        let mut loops: Vec<(&(u32, u32), &u64)> = self.loops.iter().collect();
+        loops.sort_by(|a, b| b.1.cmp(a.1).then_with(|| a.0.cmp(b.0)));
+        text.push_str("\nLoops:\n");
+        for ((from, to), iterations) in loops.iter().take(count) {
+            let _ = writeln!(
+                text,
+                "{:>12} iterations  ${:08X} -> ${:08X}{}",
+                iterations,
+                from,
+                to,
+                location(symbols, *to)
+            );
+        }
+        text
+    }
+
+    // Call stacks with the cycles spent in them, one per line, for
+    // flamegraph.pl and similar tools
+    pub fn folded(&self, symbols: &SymbolTable) -> String {
+        let mut lines: Vec<String> = self
+            .stacks
+            .iter()
+            .map(|(stack, cost)| {
+                let names: Vec<String> = stack
+                    .iter()
+                    .map(|entry| self.function_of(symbols, *entry))
+                    .collect();
+                format!("{} {}\n", names.join(";"), cost.cycles)
+            })
+            .collect();
+        lines.sort();
+        lines.concat()
+    }
+
+    // Costs per instruction and calls between functions in the format read
+    // by KCachegrind
+    pub fn callgrind(&self, symbols: &SymbolTable) -> String {
+        let mut text = String::from("# callgrind format\nversion: 1\ncreator: rs68000\n");
+        text.push_str("positions: instr\nevents: Instructions Cycles\n");
+        let _ = writeln!(
+            text,
+            "summary: {} {}",
+            self.total.instructions, self.total.cycles
+        );
+
+        let mut by_function: HashMap<String, Vec<u32>> = HashMap::new();
+        for pc in self.counts.keys() {
+            by_function
+                .entry(self.function_of(symbols, *pc))
+                .or_default()
+                .push(*pc);
+        }
+        let mut functions: Vec<(String, Vec<u32>)> = by_function.into_iter().collect();
+        functions.sort();
+        for (function, mut pcs) in functions {
+            pcs.sort();
+            let _ = writeln!(text, "\nfn={}", function);
+            for pc in pcs {
+                let cost = self.counts[&pc];
+                let _ = writeln!(text, "0x{:X} {} {}", pc, cost.instructions, cost.cycles);
+                let mut calls: Vec<(&(u32, u32), &Call)> = self
+                    .calls
+                    .iter()
+                    .filter(|((site, _), _)| *site == pc)
+                    .collect();
+                calls.sort_by_key(|(key, _)| **key);
+                for ((_, callee), call) in calls {
+                    let _ = writeln!(text, "cfn={}", self.function_of(symbols, *callee));
+                    let _ = writeln!(text, "calls={} 0x{:X}", call.count, callee);
+                    let inclusive = call.inclusive;
+                    let _ = writeln!(
+                        text,
+                        "0x{:X} {} {}",
+                        pc, inclusive.instructions, inclusive.cycles
+                    );
+                }
+            }
+        }
+        text
+    }
+
+    // The symbol containing pc when there is one, otherwise the entry of the
+    // function it ran in
+    fn function_of(&self, symbols: &SymbolTable, pc: u32) -> String {
+        match symbols.lookup(pc) {
+            Some((name, _)) => name.to_string(),
+            None => name(symbols, self.functions.get(&pc).copied().unwrap_or(pc)),
+        }
+    }
+
+    // Share of all cycles
+    fn percent(&self, cycles: u64) -> f64 {
+        cycles as f64 * 100.0 / self.total.cycles.max(1) as f64
+    }
+}
+
+impl Default for Profiler {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn name(symbols: &SymbolTable, address: u32) -> String {
+    match symbols.lookup(address) {
+        Some((name, 0)) => name.to_string(),
+        _ => format!("${:08X}", address),
+    }
+}
+
+fn location(symbols: &SymbolTable, address: u32) -> String {
+    match symbols.lookup(address) {
+        Some(_) => format!(" <{}>", symbols.describe(address)),
+        None => String::new(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Profiler;
+    use crate::assembler::assemble;
+    use crate::symbols::SymbolTable;
+
+    // main calls sub, which runs a NOP and returns to a NOP in main
+    fn profile() -> (Profiler, SymbolTable) {
+        let source = " org $100\nmain jsr sub\n nop\n org $200\nsub nop\n rts\n";
+        let program = assemble(source).unwrap();
+        let ins = |n: usize| &program.listing[n].1;
+        let mut profiler = Profiler::new();
+        profiler.record(0x100, ins(0), 0x106, 0x200, 20);
+        profiler.record(0x200, ins(2), 0x202, 0x202, 4);
+        profiler.record(0x202, ins(3), 0x204, 0x106, 16);
+        profiler.record(0x106, ins(1), 0x108, 0x108, 4);
+        let mut symbols = SymbolTable::new();
+        symbols.insert("main", 0x100);
+        symbols.insert("sub", 0x200);
+        (profiler, symbols)
+    }
+
+    #[test]
+    fn report_ranks_functions_by_cycles() {
+        let (profiler, symbols) = profile();
+        let report = profiler.report(&symbols, 10);
+        assert!(report.starts_with("4 instructions executed in 44 cycles\n"));
+        let main = report.find("  main\n").unwrap();
+        let sub = report.find("  sub\n").unwrap();
+        assert!(main < sub, "{}", report);
+        assert!(report.contains("           1           20  45.45%  $00000100 <main>\n"));
+    }
+
+    #[test]
+    fn folded_stacks_weigh_paths_by_cycles() {
+        let (profiler, symbols) = profile();
+        assert_eq!(profiler.folded(&symbols), "main 24\nmain;sub 20\n");
+    }
+
+    #[test]
+    fn callgrind_lists_costs_and_inclusive_calls() {
+        let (profiler, symbols) = profile();
+        let text = profiler.callgrind(&symbols);
+        assert!(text.contains("events: Instructions Cycles\nsummary: 4 44\n"));
+        let expected = "
+fn=main
+0x100 1 20
+cfn=sub
+calls=1 0x200
+0x100 2 20
+0x106 1 4
+
+fn=sub
+0x200 1 4
+0x202 1 16
+";
+        assert!(text.ends_with(expected), "{}", text);
+    }
+}
@@ -0,0 +1,244 @@
+use std::collections::VecDeque;
+
+use crate::cpu::{Access, AccessKind, CPUState, Registers, CPU};
+use crate::memory::Memory;
+
+// Instructions between full checkpoints of registers and memory
+const CHECKPOINT_INTERVAL: u64 = 100_000;
+// Most instructions kept; older ones are forgotten as new ones are recorded
+const HISTORY_LIMIT: usize = 1_000_000;
+// Most bytes of memory copies kept in checkpoints; the oldest go first, and
+// memories larger than this are never checkpointed
+const CHECKPOINT_MEMORY: usize = 256 << 20;
+
+// What one instruction changed, enough to undo it
+struct Entry {
+    pc: u32,
+    // Registers it changed, by Registers::get number, with their old values
+    registers: Vec<(u8, u32)>,
+    // Old inactive stack pointer, cycle count and execution state
+    alt_sp: u32,
+    cycles: u64,
+    state: CPUState,
+    writes: Vec<Access>,
+}
+
+// Full machine state before the instruction numbered position
+struct Checkpoint {
+    position: u64,
+    registers: Registers,
+    cycles: u64,
+    state: CPUState,
+    memory: Box<[u8]>,
+}
+
+// Where an instruction wrote to memory, as found by last_write
+pub struct Write {
+    // Instruction number, counting from the start of recording
+    pub position: u64,
+    pub pc: u32,
+    pub access: Access,
+}
+
+// Execution history for stepping backwards: an undo entry per instruction and
+// periodic full checkpoints, so long jumps back need not undo every step.
+// Host-side trap handler state, such as open files, and device state are not
+// rolled back.
+pub struct Recorder {
+    entries: VecDeque<Entry>,
+    checkpoints: VecDeque<Checkpoint>,
+    // Number of the oldest instruction still in entries
+    first: u64,
+}
+
+impl Recorder {
+    pub fn new() -> Self {
+        Self {
+            entries: VecDeque::new(),
+            checkpoints: VecDeque::new(),
+            first: 0,
+        }
+    }
+
+    // Number of the next instruction to execute
+    pub fn position(&self) -> u64 {
+        self.first + self.entries.len() as u64
+    }
+
+    // Number of the oldest instruction that can still be undone
+    pub fn first(&self) -> u64 {
+        self.first
+    }
+
+    // Remembers an instruction that ran from before, at cycles in state, to
+    // the CPU's current state, making accesses. Faulting instructions are
+    // recorded too, since they may have changed registers or memory.
+    pub fn record(
+        &mut self,
+        before: &Registers,
+        cycles: u64,
+        state: CPUState,
+        cpu: &CPU,
+        accesses: &[Access],
+    ) {
+        let position = self.position();
+        let kept = CHECKPOINT_MEMORY / cpu.memory_bus.memory.len().max(1);
+        if position.is_multiple_of(CHECKPOINT_INTERVAL) && kept > 0 {
+            let mut memory = cpu.memory_bus.memory.clone();
+            // The instruction has run already; put back what it wrote
+            undo_writes(&mut memory, accesses.iter());
+            self.checkpoints.push_back(Checkpoint {
+                position,
+                registers: before.clone(),
+                cycles,
+                state,
+                memory,
+            });
+            if self.checkpoints.len() > kept {
+                self.checkpoints.pop_front();
+            }
+        }
+        let registers = (0..Registers::COUNT)
+            .filter(|n| before.get(*n) != cpu.registers.get(*n))
+            .map(|n| (n as u8, before.get(n)))
+            .collect();
+        self.entries.push_back(Entry {
+            pc: before.PC,
+            registers,
+            alt_sp: before.ALT_SP,
+            cycles,
+            state,
+            writes: accesses
+                .iter()
+                .filter(|access| access.kind == AccessKind::Write)
+                .copied()
+                .collect(),
+        });
+
+        if self.entries.len() > HISTORY_LIMIT {
+            self.entries.pop_front();
+            self.first += 1;
+            while self
+                .checkpoints
+                .front()
+                .is_some_and(|checkpoint| checkpoint.position < self.first)
+            {
+                self.checkpoints.pop_front();
+            }
+        }
+    }
+
+    // Undoes the latest instruction, returning the memory writes it made;
+    // None at the start of the history
+    pub fn undo(&mut self, cpu: &mut CPU) -> Option<Vec<Access>> {
+        let entry = self.entries.pop_back()?;
+        for (n, value) in &entry.registers {
+            cpu.registers.set(*n as usize, *value);
+        }
+        cpu.registers.ALT_SP = entry.alt_sp;
+        cpu.cycles = entry.cycles;
+        cpu.state = entry.state;
+        undo_writes(&mut cpu.memory_bus.memory, entry.writes.iter());
+        let position = self.position();
+        while self
+            .checkpoints
+            .back()
+            .is_some_and(|checkpoint| checkpoint.position > position)
+        {
+            self.checkpoints.pop_back();
+        }
+        Some(entry.writes)
+    }
+
+    // Goes back count instructions, or to the start of the history, restoring
+    // a checkpoint when that saves undoing most of them
+    pub fn rewind(&mut self, cpu: &mut CPU, count: u64) {
+        let position = self.position();
+        let target = position.saturating_sub(count).max(self.first);
+        let nearest = self
+            .checkpoints
+            .iter()
+            .position(|checkpoint| checkpoint.position >= target)
+            .filter(|n| position - self.checkpoints[*n].position > CHECKPOINT_INTERVAL / 10);
+        if let Some(n) = nearest {
+            self.checkpoints.truncate(n + 1);
+            let checkpoint = &self.checkpoints[n];
+            cpu.registers = checkpoint.registers.clone();
+            cpu.cycles = checkpoint.cycles;
+            cpu.state = checkpoint.state;
+            cpu.memory_bus.memory.copy_from_slice(&checkpoint.memory);
+            self.entries
+                .truncate((checkpoint.position - self.first) as usize);
+        }
+        while self.position() > target {
+            self.undo(cpu);
+        }
+    }
+
+    // The most recent recorded write touching address
+    pub fn last_write(&self, address: u32) -> Option<Write> {
+        self.entries
+            .iter()
+            .enumerate()
+            .rev()
+            .find_map(|(n, entry)| {
+                entry
+                    .writes
+                    .iter()
+                    .rev()
+                    .find(|access| {
+                        address >= access.address
+                            && address < access.address.wrapping_add(access.size as u32)
+                    })
+                    .map(|access| Write {
+                        position: self.first + n as u64,
+                        pc: entry.pc,
+                        access: *access,
+                    })
+            })
+    }
+}
+
+impl Default for Recorder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// Puts back the old values of writes, latest first
+fn undo_writes<'a, M: Memory + ?Sized>(
+    mem: &mut M,
+    writes: impl DoubleEndedIterator<Item = &'a Access>,
+) {
+    for access in writes
+        .rev()
+        .filter(|access| access.kind == AccessKind::Write)
+    {
+        let _ = match access.size {
+            1 => mem.write_at_address_byte(access.address, access.old as u8),
+            2 => mem.write_at_address_word(access.address, access.old as u16),
+            _ => mem.write_at_address_long(access.address, access.old),
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Recorder;
+    use crate::cpu::{CPUState, CPU};
+
+    #[test]
+    fn undo_restores_control_state() {
+        let mut cpu = CPU::with_memory(0x100);
+        let before = cpu.registers.clone();
+        cpu.registers.ALT_SP = 0x80;
+        cpu.cycles = 34;
+        cpu.state = CPUState::Stopped;
+        let mut recorder = Recorder::new();
+        recorder.record(&before, 0, CPUState::Fetching, &cpu, &[]);
+        recorder.undo(&mut cpu);
+        assert_eq!(cpu.registers.ALT_SP, before.ALT_SP);
+        assert_eq!(cpu.cycles, 0);
+        assert_eq!(cpu.state, CPUState::Fetching);
+    }
+}
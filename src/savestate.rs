@@ -0,0 +1,258 @@
+use std::fs;
+use std::ops::Range;
+
+use crate::cpu::{CPUState, Registers, CPU};
+use crate::loader::{read_long, read_word, slice};
+
+// Save state files start with MAGIC and a format version, followed by
+// chunks: a four character tag, a big-endian length and that many bytes.
+// Readers skip chunks they do not know, so later versions can add some.
+const MAGIC: &[u8; 8] = b"RS68SAVE";
+const VERSION: u16 = 1;
+
+// Registers::get values in GDB order, one long each
+const REGISTERS: &[u8; 4] = b"REGS";
+// CPUState::code of the execution state
+const STATE: &[u8; 4] = b"STAT";
+// The whole of memory
+const MEMORY: &[u8; 4] = b"MEMY";
+// The inactive stack pointer and VBR as longs, the cycle count as a quad,
+// then whether a level 7 interrupt has been taken. The 68000 prefetch queue
+// is not modelled, so there is none to save; pending interrupts follow from
+// device state and the level 7 flag.
+const CONTROL: &[u8; 4] = b"CTRL";
+// Read-only address ranges, as start and end longs
+const READ_ONLY: &[u8; 4] = b"ROMS";
+// Each device mapping: base long, name length byte and name, then the
+// length long and bytes of Device::save_state
+const DEVICES: &[u8; 4] = b"DEVS";
+
+// Snapshot of the CPU and its memory
+pub fn save(cpu: &CPU) -> Vec<u8> {
+    let mut data = MAGIC.to_vec();
+    data.extend_from_slice(&VERSION.to_be_bytes());
+
+    let registers: Vec<u8> = (0..Registers::COUNT)
+        .flat_map(|n| cpu.registers.get(n).to_be_bytes())
+        .collect();
+    chunk(&mut data, REGISTERS, &registers);
+    chunk(&mut data, STATE, &[cpu.state.code()]);
+    chunk(&mut data, MEMORY, &cpu.memory_bus.memory);
+
+    let mut control = cpu.registers.ALT_SP.to_be_bytes().to_vec();
+    control.extend_from_slice(&cpu.vbr.to_be_bytes());
+    control.extend_from_slice(&cpu.cycles.to_be_bytes());
+    control.push(cpu.nmi_taken as u8);
+    chunk(&mut data, CONTROL, &control);
+
+    let read_only: Vec<u8> = cpu
+        .memory_bus
+        .read_only
+        .iter()
+        .flat_map(|range| [range.start.to_be_bytes(), range.end.to_be_bytes()])
+        .flatten()
+        .collect();
+    chunk(&mut data, READ_ONLY, &read_only);
+
+    let mut devices = Vec::new();
+    for mapping in &cpu.memory_bus.devices {
+        let device = mapping.device.borrow();
+        let name = device.name().as_bytes();
+        let state = device.save_state();
+        devices.extend_from_slice(&mapping.base.to_be_bytes());
+        devices.push(name.len() as u8);
+        devices.extend_from_slice(name);
+        devices.extend_from_slice(&(state.len() as u32).to_be_bytes());
+        devices.extend_from_slice(&state);
+    }
+    chunk(&mut data, DEVICES, &devices);
+    data
+}
+
+// Puts the CPU back in the state save recorded. Everything is checked before
+// anything changes, so a bad file leaves the CPU as it was. Devices are
+// connected to the host, so they cannot come from the file: the CPU must
+// have the same devices at the same addresses, and only their state is
+// restored. Chunks missing from older files leave that part as it is.
+pub fn restore(cpu: &mut CPU, data: &[u8]) -> Result<(), String> {
+    if !data.starts_with(MAGIC) {
+        return Err("Not a save state".to_string());
+    }
+    let version = read_word(data, MAGIC.len())?;
+    if version > VERSION {
+        return Err(format!(
+            "Save state version {} is newer than {}",
+            version, VERSION
+        ));
+    }
+
+    let (mut registers, mut state, mut memory) = (None, None, None);
+    let (mut control, mut read_only, mut devices) = (None, None, None);
+    let mut offset = MAGIC.len() + 2;
+    while offset < data.len() {
+        let tag: &[u8; 4] = slice(data, offset, 4)?.try_into().unwrap_or(&[0; 4]);
+        let length = read_long(data, offset + 4)? as usize;
+        let body = slice(data, offset + 8, length)?;
+        match tag {
+            REGISTERS => registers = Some(body),
+            STATE => state = Some(body),
+            MEMORY => memory = Some(body),
+            CONTROL => control = Some(body),
+            READ_ONLY => read_only = Some(body),
+            DEVICES => devices = Some(body),
+            _ => {}
+        }
+        offset += 8 + length;
+    }
+
+    let registers = registers
+        .filter(|body| body.len() == Registers::COUNT * 4)
+        .ok_or("Save state has no registers")?;
+    let state = state
+        .and_then(|body| body.first())
+        .and_then(|code| CPUState::from_code(*code))
+        .ok_or("Save state has no valid CPU state")?;
+    let memory = memory.ok_or("Save state has no memory")?;
+    if memory.len() != cpu.memory_bus.memory.len() {
+        return Err(format!(
+            "Save state has {} bytes of memory, not {}",
+            memory.len(),
+            cpu.memory_bus.memory.len()
+        ));
+    }
+    if control.is_some_and(|body| body.len() != 17) {
+        return Err("Save state has bad control registers".to_string());
+    }
+    let read_only = read_only.map(read_only_ranges).transpose()?;
+    let devices = devices.map(|body| device_states(cpu, body)).transpose()?;
+
+    for n in 0..Registers::COUNT {
+        cpu.registers.set(n, read_long(registers, n * 4)?);
+    }
+    cpu.state = state;
+    cpu.memory_bus.memory.copy_from_slice(memory);
+    if let Some(control) = control {
+        cpu.registers.ALT_SP = read_long(control, 0)?;
+        cpu.vbr = read_long(control, 4)?;
+        cpu.cycles = (read_long(control, 8)? as u64) << 32 | read_long(control, 12)? as u64;
+        cpu.nmi_taken = control[16] != 0;
+    }
+    if let Some(read_only) = read_only {
+        cpu.memory_bus.read_only = read_only;
+    }
+    for (mapping, state) in cpu
+        .memory_bus
+        .devices
+        .iter()
+        .zip(devices.unwrap_or_default())
+    {
+        mapping.device.borrow_mut().restore_state(state);
+    }
+    Ok(())
+}
+
+fn read_only_ranges(body: &[u8]) -> Result<Vec<Range<u32>>, String> {
+    if !body.len().is_multiple_of(8) {
+        return Err("Save state has bad read-only ranges".to_string());
+    }
+    (0..body.len())
+        .step_by(8)
+        .map(|offset| Ok(read_long(body, offset)?..read_long(body, offset + 4)?))
+        .collect()
+}
+
+// The state of each device, checked against the devices the CPU has
+fn device_states<'a>(cpu: &CPU, body: &'a [u8]) -> Result<Vec<&'a [u8]>, String> {
+    let mut states = Vec::new();
+    let mut offset = 0;
+    for mapping in &cpu.memory_bus.devices {
+        let device = mapping.device.borrow();
+        let base = read_long(body, offset)?;
+        let length = *slice(body, offset + 4, 1)?.first().unwrap_or(&0) as usize;
+        let name = slice(body, offset + 5, length)?;
+        offset += 5 + length;
+        if base != mapping.base || name != device.name().as_bytes() {
+            return Err(format!(
+                "Save state has {} at ${:X}, not {} at ${:X}",
+                String::from_utf8_lossy(name),
+                base,
+                device.name(),
+                mapping.base
+            ));
+        }
+        let length = read_long(body, offset)? as usize;
+        let state = slice(body, offset + 4, length)?;
+        offset += 4 + length;
+        if length != device.save_state().len() {
+            return Err(format!("Save state has bad {} state", device.name()));
+        }
+        states.push(state);
+    }
+    if offset != body.len() {
+        return Err("Save state has more devices than the CPU".to_string());
+    }
+    Ok(states)
+}
+
+pub fn save_file(cpu: &CPU, path: &str) -> Result<(), String> {
+    fs::write(path, save(cpu)).map_err(|e| format!("{}: {}", path, e))
+}
+
+pub fn restore_file(cpu: &mut CPU, path: &str) -> Result<(), String> {
+    let data = fs::read(path).map_err(|e| format!("{}: {}", path, e))?;
+    restore(cpu, &data).map_err(|e| format!("{}: {}", path, e))
+}
+
+fn chunk(data: &mut Vec<u8>, tag: &[u8; 4], body: &[u8]) {
+    data.extend_from_slice(tag);
+    data.extend_from_slice(&(body.len() as u32).to_be_bytes());
+    data.extend_from_slice(body);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{restore, save};
+    use crate::cpu::CPU;
+    use crate::device::pit::Pit;
+    use crate::device::Mapping;
+
+    // The timer interrupt vector register of a PI/T at $100
+    const TIVR: u32 = 0x100 + 0x11;
+
+    fn with_pit() -> CPU {
+        let mut cpu = CPU::with_memory(0x1000);
+        let pit = Mapping::new(0x100, 1, 2, Box::new(Pit::default()));
+        cpu.memory_bus.devices.push(pit);
+        cpu
+    }
+
+    #[test]
+    fn control_state_and_devices_round_trip() {
+        let mut cpu = with_pit();
+        cpu.registers.ALT_SP = 0x800;
+        cpu.vbr = 0x400;
+        cpu.cycles = 1 << 40;
+        cpu.nmi_taken = true;
+        cpu.memory_bus.read_only.push(0x200..0x300);
+        cpu.memory_bus.devices[0].write(TIVR, 0x40);
+        let data = save(&cpu);
+
+        let mut restored = with_pit();
+        restore(&mut restored, &data).unwrap();
+        assert_eq!(restored.registers.ALT_SP, 0x800);
+        assert_eq!(restored.vbr, 0x400);
+        assert_eq!(restored.cycles, 1 << 40);
+        assert!(restored.nmi_taken);
+        assert_eq!(restored.memory_bus.read_only, vec![0x200..0x300]);
+        assert_eq!(restored.memory_bus.devices[0].read(TIVR), 0x40);
+    }
+
+    #[test]
+    fn different_devices_are_refused() {
+        let data = save(&with_pit());
+        let mut other = CPU::with_memory(0x1000);
+        other.registers.ALT_SP = 0x800;
+        assert!(restore(&mut other, &data).is_err());
+        assert_eq!(other.registers.ALT_SP, 0x800);
+    }
+}
@@ -0,0 +1,89 @@
+use std::collections::{BTreeMap, HashMap};
+
+// Symbol names by address, as read from an executable or assembled program
+#[derive(Debug, Clone, Default)]
+pub struct SymbolTable {
+    by_address: BTreeMap<u32, Vec<String>>,
+    by_name: HashMap<String, u32>,
+}
+
+impl SymbolTable {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn insert(&mut self, name: &str, address: u32) {
+        if let Some(old) = self.by_name.insert(name.to_string(), address) {
+            if let Some(names) = self.by_address.get_mut(&old) {
+                names.retain(|n| n != name);
+                if names.is_empty() {
+                    self.by_address.remove(&old);
+                }
+            }
+        }
+        self.by_address
+            .entry(address)
+            .or_default()
+            .push(name.to_string());
+    }
+
+    pub fn address_of(&self, name: &str) -> Option<u32> {
+        self.by_name.get(name).copied()
+    }
+
+    // First symbol defined exactly at address
+    pub fn name_at(&self, address: u32) -> Option<&str> {
+        self.by_address
+            .get(&address)
+            .and_then(|names| names.first())
+            .map(|name| name.as_str())
+    }
+
+    // Closest symbol at or below address, with the offset from it
+    pub fn lookup(&self, address: u32) -> Option<(&str, u32)> {
+        self.by_address
+            .range(..=address)
+            .next_back()
+            .and_then(|(base, names)| names.first().map(|name| (name.as_str(), address - base)))
+    }
+
+    // Address formatted as symbol+offset, or as plain hex when no symbol precedes it
+    pub fn describe(&self, address: u32) -> String {
+        match self.lookup(address) {
+            Some((name, 0)) => name.to_string(),
+            Some((name, offset)) => format!("{}+${:X}", name, offset),
+            None => format!("${:08X}", address),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.by_name.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.by_name.is_empty()
+    }
+
+    // Symbols in ascending address order
+    pub fn iter(&self) -> impl Iterator<Item = (&str, u32)> {
+        self.by_address
+            .iter()
+            .flat_map(|(address, names)| names.iter().map(move |name| (name.as_str(), *address)))
+    }
+
+    pub fn extend(&mut self, other: &SymbolTable) {
+        for (name, address) in other.iter() {
+            self.insert(name, address);
+        }
+    }
+}
+
+impl From<&HashMap<String, u32>> for SymbolTable {
+    fn from(symbols: &HashMap<String, u32>) -> Self {
+        let mut table = SymbolTable::new();
+        for (name, address) in symbols {
+            table.insert(name, *address);
+        }
+        table
+    }
+}
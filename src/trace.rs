@@ -0,0 +1,187 @@
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+
+use crate::cpu::{Access, AccessKind, Registers};
+use crate::instruction::Instructions;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TraceFormat {
+    // Address, opcode words, disassembly, changed registers, flags and
+    // memory accesses
+    Full,
+    // Address and disassembly only, with lowercase mnemonics and uppercase
+    // registers as MAME's 68000 disassembler writes them, so the two traces
+    // can be compared with diff
+    Mame,
+}
+
+impl TraceFormat {
+    pub fn parse(name: &str) -> Result<Self, String> {
+        match name {
+            "full" => Ok(TraceFormat::Full),
+            "mame" => Ok(TraceFormat::Mame),
+            _ => Err(format!(
+                "Unknown trace format {}, expected full or mame",
+                name
+            )),
+        }
+    }
+}
+
+// Writes one line per executed instruction to a file
+pub struct Tracer {
+    out: BufWriter<File>,
+    format: TraceFormat,
+}
+
+impl Tracer {
+    pub fn create(path: &str, format: TraceFormat) -> Result<Self, String> {
+        let file = File::create(path).map_err(|e| format!("{}: {}", path, e))?;
+        Ok(Self {
+            out: BufWriter::new(file),
+            format,
+        })
+    }
+
+    // Logs the instruction at pc made of words, given the registers before
+    // and after it and the memory accesses it made
+    pub fn record(
+        &mut self,
+        pc: u32,
+        words: &[u16],
+        ins: &Instructions,
+        before: &Registers,
+        after: &Registers,
+        accesses: &[Access],
+    ) -> io::Result<()> {
+        let text = match ins {
+            Instructions::NotImplemented => format!("dc.w    ${:04x}", words[0]),
+            ins => ins.at(pc).to_string(),
+        };
+        if self.format == TraceFormat::Mame {
+            return writeln!(self.out, "{:06X}: {}", pc, text);
+        }
+
+        let words: Vec<String> = words.iter().map(|w| format!("{:04X}", w)).collect();
+        let mut line = format!("{:08X}  {:<24} {:<32}", pc, words.join(" "), text);
+        for ((name, old), new) in named(before).iter().zip(named(after).map(|(_, v)| v)) {
+            if *old != new {
+                line.push_str(&format!(" {}={:08X}", name, new));
+            }
+        }
+        line.push_str(&format!(" SR={:04X} {}", after.SR.to_word(), after.SR));
+        for access in accesses {
+            let width = access.size as usize * 2;
+            match access.kind {
+                AccessKind::Read => line.push_str(&format!(
+                    " R{}[{:08X}]={:0width$X}",
+                    access.size,
+                    access.address,
+                    access.new,
+                    width = width
+                )),
+                AccessKind::Write => line.push_str(&format!(
+                    " W{}[{:08X}]={:0width$X}->{:0width$X}",
+                    access.size,
+                    access.address,
+                    access.old,
+                    access.new,
+                    width = width
+                )),
+            }
+        }
+        writeln!(self.out, "{}", line.trim_end())
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.out.flush()
+    }
+}
+
+// Data and address registers with their trace names
+fn named(r: &Registers) -> [(&'static str, u32); 16] {
+    [
+        ("D0", r.D0),
+        ("D1", r.D1),
+        ("D2", r.D2),
+        ("D3", r.D3),
+        ("D4", r.D4),
+        ("D5", r.D5),
+        ("D6", r.D6),
+        ("D7", r.D7),
+        ("A0", r.A0),
+        ("A1", r.A1),
+        ("A2", r.A2),
+        ("A3", r.A3),
+        ("A4", r.A4),
+        ("A5", r.A5),
+        ("A6", r.A6),
+        ("A7", r.SP),
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::{TraceFormat, Tracer};
+    use crate::assembler::assemble;
+    use crate::cpu::{Access, AccessKind, CPU};
+
+    // Traces the first two instructions of "move.l d0,(a0)+ / bne.s *-2"
+    // at $1000 in format and returns the log
+    fn trace(format: TraceFormat, name: &str) -> String {
+        let program = assemble(" org $1000\n move.l d0,(a0)+\n bne.s *-2\n").unwrap();
+        let path = std::env::temp_dir().join(format!("rs68000-{}-{}", name, std::process::id()));
+        let path = path.to_str().unwrap();
+        let mut tracer = Tracer::create(path, format).unwrap();
+
+        let mut before = CPU::with_memory(0).registers;
+        before.D0 = 0x1234_5678;
+        before.A0 = 0x2000;
+        let mut after = before.clone();
+        after.A0 = 0x2004;
+        let write = Access {
+            kind: AccessKind::Write,
+            address: 0x2000,
+            size: 4,
+            old: 0,
+            new: 0x1234_5678,
+        };
+        let (move_l, bne) = (&program.listing[0].1, &program.listing[1].1);
+        tracer
+            .record(0x1000, &[0x20C0], move_l, &before, &after, &[write])
+            .unwrap();
+        tracer
+            .record(0x1002, &[0x66FC], bne, &after, &after, &[])
+            .unwrap();
+        tracer.flush().unwrap();
+        drop(tracer);
+        let log = fs::read_to_string(path).unwrap();
+        fs::remove_file(path).unwrap();
+        log
+    }
+
+    #[test]
+    fn mame_lines_hold_the_address_and_disassembly() {
+        assert_eq!(
+            trace(TraceFormat::Mame, "mame"),
+            "001000: move.l  D0, (A0)+\n001002: bne     $1000\n"
+        );
+    }
+
+    #[test]
+    fn full_lines_show_changes_and_accesses() {
+        let log = trace(TraceFormat::Full, "full");
+        let lines: Vec<&str> = log.lines().collect();
+        assert_eq!(lines.len(), 2);
+        assert!(lines[0].starts_with("00001000  20C0                     move.l  D0, (A0)+"));
+        assert!(lines[0].contains(" A0=00002004 SR=0000 "), "{}", lines[0]);
+        assert!(
+            lines[0].ends_with(" W4[00002000]=00000000->12345678"),
+            "{}",
+            lines[0]
+        );
+        assert!(!lines[1].contains("A0="), "{}", lines[1]);
+    }
+}
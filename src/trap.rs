@@ -0,0 +1,144 @@
+pub mod easy68k;
+pub mod linux;
+pub mod semihosting;
+
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, IsTerminal, Read, Write};
+
+use crate::cpu::CPU;
+use crate::memory::Memory;
+
+// Outcome of a high-level trap handler
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TrapAction {
+    // The handler serviced the trap; continue after the TRAP instruction
+    Handled,
+    // Not ours: take the TRAP exception through the vector table as usual
+    Exception,
+    // The program asked to stop with this exit status
+    Exit(i32),
+}
+
+// Services TRAP #n instructions on the host instead of in emulated code. The
+// executor calls it with the PC already past the TRAP instruction.
+pub trait TrapHandler {
+    fn trap(&mut self, cpu: &mut CPU, vector: u8) -> TrapAction;
+
+    // Services an opcode the 68000 does not decode, with the PC already past
+    // it; None takes the illegal instruction exception as usual
+    fn illegal(&mut self, _cpu: &mut CPU, _opcode: u16) -> Option<TrapAction> {
+        None
+    }
+}
+
+// Guest file descriptor backed by a host standard stream or file
+pub(crate) enum Descriptor {
+    Stdin,
+    Stdout,
+    Stderr,
+    File(File),
+}
+
+impl Descriptor {
+    // Descriptors 0, 1 and 2 bound to the host standard streams
+    pub(crate) fn standard() -> HashMap<u32, Descriptor> {
+        HashMap::from([
+            (0, Descriptor::Stdin),
+            (1, Descriptor::Stdout),
+            (2, Descriptor::Stderr),
+        ])
+    }
+
+    // None if the descriptor is not open for reading
+    pub(crate) fn read(&mut self, buffer: &mut [u8]) -> Option<io::Result<usize>> {
+        match self {
+            Descriptor::Stdin => Some(io::stdin().read(buffer)),
+            Descriptor::File(file) => Some(file.read(buffer)),
+            _ => None,
+        }
+    }
+
+    // None if the descriptor is not open for writing
+    pub(crate) fn write(&mut self, buffer: &[u8]) -> Option<io::Result<()>> {
+        match self {
+            Descriptor::Stdout => Some(
+                io::stdout()
+                    .write_all(buffer)
+                    .and_then(|_| io::stdout().flush()),
+            ),
+            Descriptor::Stderr => Some(io::stderr().write_all(buffer)),
+            Descriptor::File(file) => Some(file.write_all(buffer)),
+            Descriptor::Stdin => None,
+        }
+    }
+
+    pub(crate) fn is_terminal(&self) -> bool {
+        match self {
+            Descriptor::Stdin => io::stdin().is_terminal(),
+            Descriptor::Stdout => io::stdout().is_terminal(),
+            Descriptor::Stderr => io::stderr().is_terminal(),
+            Descriptor::File(_) => false,
+        }
+    }
+}
+
+// NUL terminated string at address, read byte by byte from emulated memory
+pub(crate) fn read_string<M: Memory + ?Sized>(mem: &M, address: u32) -> Result<String, String> {
+    let mut bytes = Vec::new();
+    let mut at = address;
+    loop {
+        match mem.read_at_address_byte(at) {
+            Some(0) => break,
+            Some(byte) => bytes.push(byte),
+            None => return Err(format!("Address ${:X} out of bounds", at)),
+        }
+        at = at.wrapping_add(1);
+    }
+    Ok(String::from_utf8_lossy(&bytes).into_owned())
+}
+
+// How many of the length bytes from address are backed by memory, so a guest
+// supplied length cannot make the host allocate more than guest memory holds
+pub(crate) fn room<M: Memory + ?Sized>(mem: &M, address: u32, length: u32) -> u32 {
+    let (mut low, mut high) = (0, length);
+    while low < high {
+        let mid = low + (high - low).div_ceil(2);
+        let last = address.checked_add(mid - 1);
+        if last.is_some_and(|last| mem.read_at_address_byte(last).is_some()) {
+            low = mid;
+        } else {
+            high = mid - 1;
+        }
+    }
+    low
+}
+
+// Copy of length bytes of emulated memory starting at address
+pub(crate) fn read_bytes<M: Memory + ?Sized>(
+    mem: &M,
+    address: u32,
+    length: u32,
+) -> Result<Vec<u8>, String> {
+    (0..length)
+        .map(|offset| {
+            let at = address.wrapping_add(offset);
+            mem.read_at_address_byte(at)
+                .ok_or_else(|| format!("Address ${:X} out of bounds", at))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::room;
+
+    #[test]
+    fn room_stops_at_the_end_of_memory() {
+        let memory = [0u8; 16];
+        assert_eq!(room(&memory[..], 0, 8), 8);
+        assert_eq!(room(&memory[..], 12, u32::MAX), 4);
+        assert_eq!(room(&memory[..], 16, 1), 0);
+        assert_eq!(room(&memory[..], u32::MAX, 2), 0);
+    }
+}
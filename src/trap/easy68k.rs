@@ -0,0 +1,280 @@
+use std::collections::HashMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufRead, Read, Seek, SeekFrom, Write};
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::cpu::CPU;
+use crate::loader::write_bytes;
+use crate::trap::{read_bytes, read_string, room, TrapAction, TrapHandler};
+
+// Task codes passed in D0
+const DISPLAY_LINE_LENGTH: u32 = 0;
+const DISPLAY_STRING_LENGTH: u32 = 1;
+const READ_STRING: u32 = 2;
+const DISPLAY_NUMBER: u32 = 3;
+const READ_NUMBER: u32 = 4;
+const READ_CHAR: u32 = 5;
+const DISPLAY_CHAR: u32 = 6;
+const INPUT_PENDING: u32 = 7;
+const TIME: u32 = 8;
+const TERMINATE: u32 = 9;
+const CURSOR: u32 = 11;
+const DISPLAY_LINE: u32 = 13;
+const DISPLAY_STRING: u32 = 14;
+const DISPLAY_UNSIGNED: u32 = 15;
+const DISPLAY_STRING_NUMBER: u32 = 17;
+const PROMPT_NUMBER: u32 = 18;
+const DISPLAY_FIELD: u32 = 20;
+const DELAY: u32 = 23;
+const CLOSE_ALL: u32 = 50;
+const OPEN_EXISTING: u32 = 51;
+const OPEN_NEW: u32 = 52;
+const READ_FILE: u32 = 53;
+const WRITE_FILE: u32 = 54;
+const POSITION_FILE: u32 = 55;
+const CLOSE_FILE: u32 = 56;
+const DELETE_FILE: u32 = 57;
+
+// File task results returned in D0.W
+const SUCCESS: u32 = 0;
+const END_OF_FILE: u32 = 1;
+const ERROR: u32 = 2;
+
+// Longest line READ_STRING stores, as in Easy68K
+const MAX_LINE: usize = 80;
+// D1.W value asking CURSOR to clear the screen
+const CLEAR_SCREEN: u32 = 0xFF00;
+
+// TRAP #15 text and file I/O services of the Easy68K simulator, on the host
+// terminal and file system
+pub struct Easy68K {
+    files: HashMap<u32, File>,
+    next_file: u32,
+}
+
+impl Easy68K {
+    pub fn new() -> Self {
+        Self {
+            files: HashMap::new(),
+            next_file: 1,
+        }
+    }
+
+    // Performs the task numbered in D0
+    pub fn task(&mut self, cpu: &mut CPU) -> TrapAction {
+        let r = &cpu.registers;
+        let (task, d1, d2, a1) = (r.D0 & 0xFF, r.D1, r.D2, r.A1);
+        let mem = &mut cpu.memory_bus;
+        let mut out = io::stdout();
+
+        match task {
+            DISPLAY_LINE_LENGTH | DISPLAY_STRING_LENGTH => {
+                let text = read_bytes(mem, a1, d1 & 0xFFFF).unwrap_or_default();
+                let _ = out.write_all(&text);
+                if task == DISPLAY_LINE_LENGTH {
+                    let _ = writeln!(out);
+                }
+            }
+            READ_STRING => {
+                // Cut by bytes: the guest sees bytes, and byte MAX_LINE
+                // need not be a char boundary
+                let line = read_line();
+                let mut line = line.as_bytes()[..line.len().min(MAX_LINE)].to_vec();
+                set_word(&mut cpu.registers.D1, line.len() as u32);
+                line.push(0);
+                let _ = write_bytes(mem, a1, &line);
+            }
+            DISPLAY_NUMBER => {
+                let _ = write!(out, "{}", d1 as i32);
+            }
+            READ_NUMBER => cpu.registers.D1 = read_line().trim().parse::<i32>().unwrap_or(0) as u32,
+            READ_CHAR => {
+                let mut byte = [0];
+                let _ = io::stdin().read(&mut byte);
+                cpu.registers.D1 = (d1 & !0xFF) | byte[0] as u32;
+            }
+            DISPLAY_CHAR => {
+                let _ = out.write_all(&[d1 as u8]);
+            }
+            // No way to peek at the host keyboard without blocking
+            INPUT_PENDING => cpu.registers.D1 &= !0xFF,
+            TIME => {
+                let now = SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .unwrap_or_default();
+                cpu.registers.D1 = ((now.as_millis() % 86_400_000) / 10) as u32;
+            }
+            TERMINATE => {
+                let _ = out.flush();
+                return TrapAction::Exit(0);
+            }
+            CURSOR if d1 & 0xFFFF == CLEAR_SCREEN => {
+                let _ = write!(out, "\x1B[2J\x1B[H");
+            }
+            CURSOR => {
+                // D1.W holds the column in its high byte and the row in its low
+                let _ = write!(out, "\x1B[{};{}H", (d1 & 0xFF) + 1, (d1 >> 8 & 0xFF) + 1);
+            }
+            DISPLAY_LINE | DISPLAY_STRING => {
+                let _ = out.write_all(read_string(mem, a1).unwrap_or_default().as_bytes());
+                if task == DISPLAY_LINE {
+                    let _ = writeln!(out);
+                }
+            }
+            DISPLAY_UNSIGNED => {
+                let _ = write!(out, "{}", radix(d1, d2 & 0xFF));
+            }
+            DISPLAY_STRING_NUMBER => {
+                let _ = write!(
+                    out,
+                    "{}{}",
+                    read_string(mem, a1).unwrap_or_default(),
+                    d1 as i32
+                );
+            }
+            PROMPT_NUMBER => {
+                let _ = write!(out, "{}", read_string(mem, a1).unwrap_or_default());
+                let _ = out.flush();
+                cpu.registers.D1 = read_line().trim().parse::<i32>().unwrap_or(0) as u32;
+            }
+            DISPLAY_FIELD => {
+                let width = (d2 & 0xFF) as usize;
+                let _ = write!(out, "{:>width$}", d1 as i32, width = width);
+            }
+            DELAY => thread::sleep(Duration::from_millis(d1 as u64 * 10)),
+            CLOSE_ALL => {
+                self.files.clear();
+                set_word(&mut cpu.registers.D0, SUCCESS);
+            }
+            OPEN_EXISTING | OPEN_NEW => {
+                let name = read_string(mem, a1).unwrap_or_default();
+                let file = if task == OPEN_EXISTING {
+                    OpenOptions::new()
+                        .read(true)
+                        .write(true)
+                        .open(&name)
+                        .or_else(|_| File::open(&name))
+                } else {
+                    File::create(&name)
+                        .and_then(|_| OpenOptions::new().read(true).write(true).open(&name))
+                };
+                match file {
+                    Ok(file) => {
+                        let id = self.next_file;
+                        self.next_file += 1;
+                        self.files.insert(id, file);
+                        cpu.registers.D1 = id;
+                        set_word(&mut cpu.registers.D0, SUCCESS);
+                    }
+                    Err(_) => set_word(&mut cpu.registers.D0, ERROR),
+                }
+            }
+            READ_FILE => {
+                let status = match self.files.get_mut(&d1) {
+                    Some(file) => {
+                        // No larger than the memory it is copied to; a read
+                        // that does not fit fails after filling what does
+                        let length = room(&mem.memory, a1, d2);
+                        let mut buffer = vec![0; length as usize];
+                        let mut filled = 0;
+                        while filled < buffer.len() {
+                            match file.read(&mut buffer[filled..]) {
+                                Ok(0) | Err(_) => break,
+                                Ok(count) => filled += count,
+                            }
+                        }
+                        cpu.registers.D2 = filled as u32;
+                        match write_bytes(mem, a1, &buffer[..filled]) {
+                            Err(_) => ERROR,
+                            Ok(()) if filled < buffer.len() => END_OF_FILE,
+                            Ok(()) if length < d2 => ERROR,
+                            Ok(()) => SUCCESS,
+                        }
+                    }
+                    None => ERROR,
+                };
+                set_word(&mut cpu.registers.D0, status);
+            }
+            WRITE_FILE => {
+                let written = match (self.files.get_mut(&d1), read_bytes(mem, a1, d2)) {
+                    (Some(file), Ok(data)) => file.write_all(&data).is_ok(),
+                    _ => false,
+                };
+                let status = if written { SUCCESS } else { ERROR };
+                set_word(&mut cpu.registers.D0, status);
+            }
+            POSITION_FILE => {
+                let positioned = match self.files.get_mut(&d1) {
+                    Some(file) => file.seek(SeekFrom::Start(d2 as u64)).is_ok(),
+                    None => false,
+                };
+                let status = if positioned { SUCCESS } else { ERROR };
+                set_word(&mut cpu.registers.D0, status);
+            }
+            CLOSE_FILE => {
+                let status = match self.files.remove(&d1) {
+                    Some(_) => SUCCESS,
+                    None => ERROR,
+                };
+                set_word(&mut cpu.registers.D0, status);
+            }
+            DELETE_FILE => {
+                let status = match fs::remove_file(read_string(mem, a1).unwrap_or_default()) {
+                    Ok(()) => SUCCESS,
+                    Err(_) => ERROR,
+                };
+                set_word(&mut cpu.registers.D0, status);
+            }
+            // Keyboard echo, graphics, sound, network and dialog tasks have no
+            // console equivalent and are ignored
+            _ => {}
+        }
+        let _ = out.flush();
+        TrapAction::Handled
+    }
+}
+
+impl Default for Easy68K {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl TrapHandler for Easy68K {
+    fn trap(&mut self, cpu: &mut CPU, vector: u8) -> TrapAction {
+        match vector {
+            15 => self.task(cpu),
+            _ => TrapAction::Exception,
+        }
+    }
+}
+
+fn set_word(register: &mut u32, value: u32) {
+    *register = (*register & 0xFFFF_0000) | (value & 0xFFFF);
+}
+
+// One line of host input without its line ending
+fn read_line() -> String {
+    let mut line = String::new();
+    let _ = io::stdin().lock().read_line(&mut line);
+    line.trim_end_matches(['\r', '\n']).to_string()
+}
+
+// Unsigned number in base 2 to 36; other bases print in decimal
+fn radix(mut value: u32, base: u32) -> String {
+    let base = if (2..=36).contains(&base) { base } else { 10 };
+    let mut digits = Vec::new();
+    loop {
+        digits.push(
+            std::char::from_digit(value % base, base)
+                .unwrap()
+                .to_ascii_uppercase(),
+        );
+        value /= base;
+        if value == 0 {
+            break;
+        }
+    }
+    digits.iter().rev().collect()
+}
@@ -0,0 +1,493 @@
+use std::collections::HashMap;
+use std::fs::OpenOptions;
+use std::io::{self, Read, Seek, SeekFrom};
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+
+use crate::cpu::{MemoryBus, CPU};
+use crate::loader::elf::{self, Elf};
+use crate::loader::write_bytes;
+use crate::memory::Memory;
+use crate::trap::{read_bytes, read_string, room, Descriptor, TrapAction, TrapHandler};
+
+// m68k system call numbers
+const SYS_EXIT: u32 = 1;
+const SYS_READ: u32 = 3;
+const SYS_WRITE: u32 = 4;
+const SYS_OPEN: u32 = 5;
+const SYS_CLOSE: u32 = 6;
+const SYS_LSEEK: u32 = 19;
+const SYS_GETPID: u32 = 20;
+const SYS_BRK: u32 = 45;
+const SYS_IOCTL: u32 = 54;
+const SYS_MMAP: u32 = 90;
+const SYS_MUNMAP: u32 = 91;
+const SYS_UNAME: u32 = 122;
+const SYS_WRITEV: u32 = 146;
+const SYS_MMAP2: u32 = 192;
+const SYS_EXIT_GROUP: u32 = 252;
+const SYS_SET_TID_ADDRESS: u32 = 253;
+const SYS_CLOCK_GETTIME: u32 = 263;
+const SYS_OPENAT: u32 = 288;
+const SYS_GET_THREAD_AREA: u32 = 333;
+const SYS_SET_THREAD_AREA: u32 = 334;
+const SYS_CLOCK_GETTIME64: u32 = 403;
+
+const EIO: u32 = 5;
+const EBADF: u32 = 9;
+const ENOMEM: u32 = 12;
+const EFAULT: u32 = 14;
+const EINVAL: u32 = 22;
+const ENOTTY: u32 = 25;
+const ENOSYS: u32 = 38;
+
+const O_ACCMODE: u32 = 0o3;
+const O_WRONLY: u32 = 0o1;
+const O_RDWR: u32 = 0o2;
+const O_CREAT: u32 = 0o100;
+const O_EXCL: u32 = 0o200;
+const O_TRUNC: u32 = 0o1000;
+const O_APPEND: u32 = 0o2000;
+const AT_FDCWD: u32 = -100i32 as u32;
+
+const TCGETS: u32 = 0x5401;
+const TIOCGWINSZ: u32 = 0x5413;
+// Size of the kernel struct termios on m68k
+const TERMIOS_SIZE: u32 = 36;
+
+// Most buffers one writev may gather
+const IOV_MAX: u32 = 1024;
+
+const MAP_FIXED: u32 = 0x10;
+const MAP_ANONYMOUS: u32 = 0x20;
+
+const CLOCK_REALTIME: u32 = 0;
+
+// Auxiliary vector entry types
+const AT_NULL: u32 = 0;
+const AT_PHDR: u32 = 3;
+const AT_PHENT: u32 = 4;
+const AT_PHNUM: u32 = 5;
+const AT_PAGESZ: u32 = 6;
+const AT_ENTRY: u32 = 9;
+const AT_UID: u32 = 11;
+const AT_EUID: u32 = 12;
+const AT_GID: u32 = 13;
+const AT_EGID: u32 = 14;
+const AT_HWCAP: u32 = 16;
+const AT_CLKTCK: u32 = 17;
+const AT_RANDOM: u32 = 25;
+const AT_EXECFN: u32 = 31;
+
+const PAGE_SIZE: u32 = 4096;
+const STACK_SIZE: u32 = 0x2_0000;
+const PID: u32 = 1000;
+
+// User-mode Linux personality: TRAP #0 system calls serviced on the host, with
+// the program break and mmap regions carved out of emulated memory
+pub struct Linux {
+    descriptors: HashMap<u32, Descriptor>,
+    break_start: u32,
+    break_end: u32,
+    // mmap allocations grow down from just below the stack
+    mmap_next: u32,
+    thread_pointer: u32,
+    started: Instant,
+}
+
+impl Linux {
+    // Loads a static ELF executable and lays out its initial stack with argv,
+    // envp and the auxiliary vector at the top of memory, as execve would
+    pub fn load(
+        data: &[u8],
+        cpu: &mut CPU,
+        args: &[&str],
+        env: &[&str],
+    ) -> Result<(Self, Elf), String> {
+        let elf = elf::load_cpu(data, cpu)?;
+        let end = elf
+            .segments
+            .iter()
+            .map(|s| s.address.wrapping_add(s.memory_size))
+            .max()
+            .unwrap_or(0);
+        let break_start = page_align(end);
+        let top = cpu.memory_bus.memory.len() as u32;
+        let sp = setup_stack(&mut cpu.memory_bus.memory, top, &elf, args, env)?;
+        cpu.registers.SP = sp;
+
+        let linux = Linux {
+            descriptors: Descriptor::standard(),
+            break_start,
+            break_end: break_start,
+            mmap_next: top
+                .checked_sub(STACK_SIZE)
+                .ok_or("Not enough memory for a stack")?
+                & !(PAGE_SIZE - 1),
+            thread_pointer: 0,
+            started: Instant::now(),
+        };
+        Ok((linux, elf))
+    }
+
+    // Performs the system call numbered in D0 with arguments in D1-D5 and A0,
+    // leaving the result or a negated errno in D0
+    pub fn syscall(&mut self, cpu: &mut CPU) -> TrapAction {
+        let r = &cpu.registers;
+        let number = r.D0;
+        let args = [r.D1, r.D2, r.D3, r.D4, r.D5, r.A0];
+        if let SYS_EXIT | SYS_EXIT_GROUP = number {
+            return TrapAction::Exit(args[0] as i32);
+        }
+        cpu.registers.D0 = match self.call(&mut cpu.memory_bus, number, args) {
+            Ok(value) => value,
+            Err(errno) => errno.wrapping_neg(),
+        };
+        TrapAction::Handled
+    }
+
+    fn call(
+        &mut self,
+        mem: &mut MemoryBus<Box<[u8]>>,
+        number: u32,
+        args: [u32; 6],
+    ) -> Result<u32, u32> {
+        match number {
+            SYS_READ => {
+                // No larger than the memory it is copied to
+                let length = room(&mem.memory, args[1], args[2]);
+                if length == 0 && args[2] != 0 {
+                    return Err(EFAULT);
+                }
+                let mut buffer = vec![0; length as usize];
+                let count = self
+                    .descriptor(args[0])?
+                    .read(&mut buffer)
+                    .ok_or(EBADF)?
+                    .map_err(errno)?;
+                write_bytes(mem, args[1], &buffer[..count]).map_err(|_| EFAULT)?;
+                Ok(count as u32)
+            }
+            SYS_WRITE => {
+                let buffer = read_bytes(mem, args[1], args[2]).map_err(|_| EFAULT)?;
+                self.write(args[0], &buffer)
+            }
+            SYS_WRITEV => {
+                if args[2] > IOV_MAX {
+                    return Err(EINVAL);
+                }
+                let mut total: u32 = 0;
+                for i in 0..args[2] {
+                    let iov = args[1].wrapping_add(i * 8);
+                    let base = mem.read_at_address_long(iov).ok_or(EFAULT)?;
+                    let length = mem
+                        .read_at_address_long(iov.wrapping_add(4))
+                        .ok_or(EFAULT)?;
+                    let buffer = read_bytes(mem, base, length).map_err(|_| EFAULT)?;
+                    let written = self.write(args[0], &buffer)?;
+                    total = total.checked_add(written).ok_or(EINVAL)?;
+                }
+                Ok(total)
+            }
+            SYS_OPEN => self.open(mem, args[0], args[1], args[2]),
+            SYS_OPENAT if args[0] == AT_FDCWD => self.open(mem, args[1], args[2], args[3]),
+            SYS_OPENAT => Err(EINVAL),
+            SYS_CLOSE => self.descriptors.remove(&args[0]).map(|_| 0).ok_or(EBADF),
+            SYS_LSEEK => {
+                let position = match args[2] {
+                    0 => SeekFrom::Start(args[1] as u64),
+                    1 => SeekFrom::Current(args[1] as i32 as i64),
+                    2 => SeekFrom::End(args[1] as i32 as i64),
+                    _ => return Err(EINVAL),
+                };
+                match self.descriptor(args[0])? {
+                    Descriptor::File(file) => file.seek(position).map(|p| p as u32).map_err(errno),
+                    _ => Err(EINVAL),
+                }
+            }
+            SYS_GETPID => Ok(PID),
+            SYS_BRK => {
+                let requested = args[0];
+                if requested >= self.break_start && requested <= self.mmap_next {
+                    if requested > self.break_end {
+                        let grown = vec![0; (requested - self.break_end) as usize];
+                        write_bytes(mem, self.break_end, &grown).map_err(|_| ENOMEM)?;
+                    }
+                    self.break_end = requested;
+                }
+                Ok(self.break_end)
+            }
+            SYS_IOCTL => {
+                let terminal = self.descriptor(args[0])?.is_terminal();
+                match args[1] {
+                    _ if !terminal => Err(ENOTTY),
+                    TCGETS => {
+                        let termios = vec![0; TERMIOS_SIZE as usize];
+                        write_bytes(mem, args[2], &termios).map_err(|_| EFAULT)?;
+                        Ok(0)
+                    }
+                    TIOCGWINSZ => {
+                        let winsize = [0, 24, 0, 80, 0, 0, 0, 0];
+                        write_bytes(mem, args[2], &winsize).map_err(|_| EFAULT)?;
+                        Ok(0)
+                    }
+                    _ => Err(EINVAL),
+                }
+            }
+            SYS_MMAP => {
+                // The old m68k mmap takes its six arguments in a block
+                let mut block = [0; 6];
+                for (i, arg) in block.iter_mut().enumerate() {
+                    *arg = mem
+                        .read_at_address_long(args[0].wrapping_add(4 * i as u32))
+                        .ok_or(EFAULT)?;
+                }
+                self.mmap(mem, block)
+            }
+            SYS_MMAP2 => {
+                let mut args = args;
+                args[5] = args[5].wrapping_mul(PAGE_SIZE);
+                self.mmap(mem, args)
+            }
+            SYS_MUNMAP => Ok(0),
+            SYS_UNAME => {
+                let fields = ["Linux", "rs68000", "6.1.0", "#1", "m68k", ""];
+                let mut utsname = vec![0; 65 * fields.len()];
+                for (i, field) in fields.iter().enumerate() {
+                    utsname[i * 65..i * 65 + field.len()].copy_from_slice(field.as_bytes());
+                }
+                write_bytes(mem, args[0], &utsname).map_err(|_| EFAULT)?;
+                Ok(0)
+            }
+            SYS_SET_TID_ADDRESS => Ok(PID),
+            SYS_CLOCK_GETTIME | SYS_CLOCK_GETTIME64 => {
+                let elapsed = if args[0] == CLOCK_REALTIME {
+                    SystemTime::now()
+                        .duration_since(UNIX_EPOCH)
+                        .unwrap_or_default()
+                } else {
+                    self.started.elapsed()
+                };
+                let mut timespec = Vec::new();
+                if number == SYS_CLOCK_GETTIME64 {
+                    timespec.extend_from_slice(&elapsed.as_secs().to_be_bytes());
+                    timespec.extend_from_slice(&(elapsed.subsec_nanos() as u64).to_be_bytes());
+                } else {
+                    timespec.extend_from_slice(&(elapsed.as_secs() as u32).to_be_bytes());
+                    timespec.extend_from_slice(&elapsed.subsec_nanos().to_be_bytes());
+                }
+                write_bytes(mem, args[1], &timespec).map_err(|_| EFAULT)?;
+                Ok(0)
+            }
+            SYS_GET_THREAD_AREA => Ok(self.thread_pointer),
+            SYS_SET_THREAD_AREA => {
+                self.thread_pointer = args[0];
+                Ok(0)
+            }
+            _ => Err(ENOSYS),
+        }
+    }
+
+    fn descriptor(&mut self, fd: u32) -> Result<&mut Descriptor, u32> {
+        self.descriptors.get_mut(&fd).ok_or(EBADF)
+    }
+
+    fn write(&mut self, fd: u32, buffer: &[u8]) -> Result<u32, u32> {
+        self.descriptor(fd)?
+            .write(buffer)
+            .ok_or(EBADF)?
+            .map_err(errno)?;
+        Ok(buffer.len() as u32)
+    }
+
+    fn open<M: Memory + ?Sized>(
+        &mut self,
+        mem: &M,
+        path: u32,
+        flags: u32,
+        _mode: u32,
+    ) -> Result<u32, u32> {
+        let path = read_string(mem, path).map_err(|_| EFAULT)?;
+        let access = flags & O_ACCMODE;
+        let file = OpenOptions::new()
+            .read(access != O_WRONLY)
+            .write(access == O_WRONLY || access == O_RDWR)
+            .append(flags & O_APPEND != 0)
+            .truncate(flags & O_TRUNC != 0)
+            .create(flags & O_CREAT != 0 && flags & O_EXCL == 0)
+            .create_new(flags & O_CREAT != 0 && flags & O_EXCL != 0)
+            .open(path)
+            .map_err(errno)?;
+        // Lowest free descriptor, as POSIX requires
+        let fd = (0..)
+            .find(|fd| !self.descriptors.contains_key(fd))
+            .unwrap_or_default();
+        self.descriptors.insert(fd, Descriptor::File(file));
+        Ok(fd)
+    }
+
+    // Private mappings only: anonymous memory is zeroed and file mappings are
+    // copied in, so writes never reach the file
+    fn mmap(&mut self, mem: &mut MemoryBus<Box<[u8]>>, args: [u32; 6]) -> Result<u32, u32> {
+        let [address, length, _prot, flags, fd, offset] = args;
+        if length == 0 {
+            return Err(EINVAL);
+        }
+        let length = page_align(length);
+        if length == 0 {
+            return Err(ENOMEM);
+        }
+        let address = if flags & MAP_FIXED != 0 {
+            address
+        } else {
+            let next = self.mmap_next.checked_sub(length).ok_or(ENOMEM)?;
+            if next < self.break_end {
+                return Err(ENOMEM);
+            }
+            self.mmap_next = next;
+            next
+        };
+        // A fixed mapping may name any address, but must land in memory
+        if room(&mem.memory, address, length) < length {
+            return Err(ENOMEM);
+        }
+
+        let mut contents = vec![0; length as usize];
+        if flags & MAP_ANONYMOUS == 0 {
+            match self.descriptor(fd)? {
+                Descriptor::File(file) => {
+                    file.seek(SeekFrom::Start(offset as u64)).map_err(errno)?;
+                    let mut filled = 0;
+                    while filled < contents.len() {
+                        match file.read(&mut contents[filled..]).map_err(errno)? {
+                            0 => break,
+                            count => filled += count,
+                        }
+                    }
+                }
+                _ => return Err(EBADF),
+            }
+        }
+        write_bytes(mem, address, &contents).map_err(|_| ENOMEM)?;
+        Ok(address)
+    }
+}
+
+impl TrapHandler for Linux {
+    fn trap(&mut self, cpu: &mut CPU, vector: u8) -> TrapAction {
+        match vector {
+            0 => self.syscall(cpu),
+            _ => TrapAction::Exception,
+        }
+    }
+}
+
+// Builds the process entry stack below top: argc, argv, envp and the auxiliary
+// vector, with the strings they point to above them. Returns the new SP.
+fn setup_stack<M: Memory + ?Sized>(
+    mem: &mut M,
+    top: u32,
+    elf: &Elf,
+    args: &[&str],
+    env: &[&str],
+) -> Result<u32, String> {
+    let mut at = top;
+    let mut push = |mem: &mut M, bytes: &[u8]| -> Result<u32, String> {
+        at -= bytes.len() as u32;
+        write_bytes(mem, at, bytes)?;
+        Ok(at)
+    };
+
+    let mut strings = Vec::new();
+    for string in args.iter().chain(env) {
+        strings.push(push(mem, format!("{}\0", string).as_bytes())?);
+    }
+    let seed = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_nanos();
+    let random = push(mem, &seed.to_be_bytes())?;
+    let (argv, envp) = strings.split_at(args.len());
+
+    let mut auxv = vec![
+        (AT_PAGESZ, PAGE_SIZE),
+        (AT_ENTRY, elf.entry),
+        (AT_UID, 0),
+        (AT_EUID, 0),
+        (AT_GID, 0),
+        (AT_EGID, 0),
+        (AT_HWCAP, 0),
+        (AT_CLKTCK, 100),
+        (AT_RANDOM, random),
+    ];
+    if let Some(headers) = elf.program_headers {
+        auxv.push((AT_PHDR, headers));
+        auxv.push((AT_PHENT, elf.program_header_size as u32));
+        auxv.push((AT_PHNUM, elf.program_header_count as u32));
+    }
+    if let Some(name) = argv.first() {
+        auxv.push((AT_EXECFN, *name));
+    }
+    auxv.push((AT_NULL, 0));
+
+    let mut longs = vec![argv.len() as u32];
+    longs.extend(argv);
+    longs.push(0);
+    longs.extend(envp);
+    longs.push(0);
+    longs.extend(auxv.iter().flat_map(|(kind, value)| [*kind, *value]));
+
+    let sp = (at & !3) - 4 * longs.len() as u32;
+    let bytes: Vec<u8> = longs.iter().flat_map(|long| long.to_be_bytes()).collect();
+    write_bytes(mem, sp, &bytes)?;
+    Ok(sp)
+}
+
+fn page_align(address: u32) -> u32 {
+    address.wrapping_add(PAGE_SIZE - 1) & !(PAGE_SIZE - 1)
+}
+
+// Guest errno for a host I/O error; Linux hosts share the m68k numbering
+fn errno(error: io::Error) -> u32 {
+    match error.kind() {
+        io::ErrorKind::NotFound => 2,
+        io::ErrorKind::PermissionDenied => 13,
+        io::ErrorKind::AlreadyExists => 17,
+        _ => error.raw_os_error().map_or(EIO, |code| code as u32),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Instant;
+
+    use super::*;
+
+    fn process(cpu: &CPU) -> Linux {
+        let top = cpu.memory_bus.memory.len() as u32;
+        Linux {
+            descriptors: Descriptor::standard(),
+            break_start: 0x1000,
+            break_end: 0x1000,
+            mmap_next: top - STACK_SIZE,
+            thread_pointer: 0,
+            started: Instant::now(),
+        }
+    }
+
+    #[test]
+    fn guest_sizes_are_bounded_by_memory() {
+        let mut cpu = CPU::with_memory(0x40000);
+        let mut linux = process(&cpu);
+        let mem = &mut cpu.memory_bus;
+        let fixed = [0x3F000, 0x8000_0000, 3, MAP_FIXED | MAP_ANONYMOUS, 0, 0];
+        assert_eq!(linux.mmap(mem, fixed), Err(ENOMEM));
+        let huge = [0, u32::MAX, 3, MAP_ANONYMOUS, 0, 0];
+        assert_eq!(linux.mmap(mem, huge), Err(ENOMEM));
+        let writev = [1, 0xFFFF_FFF8, u32::MAX, 0, 0, 0];
+        assert_eq!(linux.call(mem, SYS_WRITEV, writev), Err(EINVAL));
+        let writev = [1, 0xFFFF_FFF8, 2, 0, 0, 0];
+        assert_eq!(linux.call(mem, SYS_WRITEV, writev), Err(EFAULT));
+        let mmap = [0xFFFF_FFF0, 0, 0, 0, 0, 0];
+        assert_eq!(linux.call(mem, SYS_MMAP, mmap), Err(EFAULT));
+    }
+}
@@ -0,0 +1,280 @@
+use std::collections::HashMap;
+use std::fs::{self, OpenOptions};
+use std::io::{self, Seek, SeekFrom};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::cpu::CPU;
+use crate::loader::write_bytes;
+use crate::memory::Memory;
+use crate::trap::{read_bytes, read_string, room, Descriptor, TrapAction, TrapHandler};
+
+// Hosted call numbers passed in D0, as used by libgloss for m68k-elf
+const HOSTED_EXIT: u32 = 0;
+const HOSTED_OPEN: u32 = 2;
+const HOSTED_CLOSE: u32 = 3;
+const HOSTED_READ: u32 = 4;
+const HOSTED_WRITE: u32 = 5;
+const HOSTED_LSEEK: u32 = 6;
+const HOSTED_UNLINK: u32 = 8;
+const HOSTED_GETTIMEOFDAY: u32 = 11;
+const HOSTED_ISATTY: u32 = 12;
+
+// GDB File-I/O open flags
+const O_WRONLY: u32 = 0x1;
+const O_RDWR: u32 = 0x2;
+const O_APPEND: u32 = 0x8;
+const O_CREAT: u32 = 0x200;
+const O_TRUNC: u32 = 0x400;
+const O_EXCL: u32 = 0x800;
+
+// GDB File-I/O errno values
+const ENOENT: u32 = 2;
+const EBADF: u32 = 9;
+const EACCES: u32 = 13;
+const EFAULT: u32 = 14;
+const EEXIST: u32 = 17;
+const EINVAL: u32 = 22;
+const ENOSYS: u32 = 88;
+const EUNKNOWN: u32 = 9999;
+
+// Opcodes around a semihosting HALT: NOP before it, this marker long after it
+const NOP: u16 = 0x4E71;
+const HALT: u16 = 0x4AC8;
+const MARKER: u32 = 0x4E7B_F000;
+
+// Host services for bare-metal test programs. Guest code puts the call number
+// in D0 and the address of its argument block in D1; the result replaces the
+// first argument and, on failure (-1), the errno replaces the second. Exit
+// takes its status in D1 directly.
+pub struct Semihosting {
+    descriptors: HashMap<u32, Descriptor>,
+    // TRAP vector that makes a hosted call
+    vector: u8,
+}
+
+impl Semihosting {
+    pub fn new(vector: u8) -> Self {
+        Self {
+            descriptors: Descriptor::standard(),
+            vector,
+        }
+    }
+
+    // Performs the hosted call numbered in D0
+    pub fn call(&mut self, cpu: &mut CPU) -> TrapAction {
+        let (number, block) = (cpu.registers.D0, cpu.registers.D1);
+        if number == HOSTED_EXIT {
+            return TrapAction::Exit(block as i32);
+        }
+        let mut args = [0; 4];
+        for (i, arg) in args.iter_mut().enumerate() {
+            *arg = cpu
+                .memory_bus
+                .read_at_address_long(block.wrapping_add(4 * i as u32))
+                .unwrap_or(0);
+        }
+
+        let result = match number {
+            // The offset and result are 64-bit, split over two arguments
+            HOSTED_LSEEK => match self.lseek(args) {
+                Ok(position) => [(position >> 32) as u32, position as u32, 0],
+                Err(errno) => [u32::MAX, u32::MAX, errno],
+            },
+            _ => match self.hosted(cpu, number, args) {
+                Ok(value) => [value, 0, 0],
+                Err(errno) => [u32::MAX, errno, 0],
+            },
+        };
+        let written = if number == HOSTED_LSEEK { 3 } else { 2 };
+        let bytes: Vec<u8> = result[..written]
+            .iter()
+            .flat_map(|long| long.to_be_bytes())
+            .collect();
+        let _ = write_bytes(&mut cpu.memory_bus, block, &bytes);
+        TrapAction::Handled
+    }
+
+    // Services the HALT based calling sequence used by QEMU and libgloss:
+    // NOP, HALT, then a marker long. pc points past the HALT; None if the
+    // words around it are not that sequence.
+    pub fn halt(&mut self, cpu: &mut CPU) -> Option<TrapAction> {
+        let pc = cpu.registers.PC;
+        let mem = &cpu.memory_bus.memory;
+        if mem.read_at_address_word(pc.wrapping_sub(4)) != Some(NOP)
+            || mem.read_at_address_word(pc.wrapping_sub(2)) != Some(HALT)
+            || mem.read_at_address_long(pc) != Some(MARKER)
+        {
+            return None;
+        }
+        cpu.registers.PC = pc.wrapping_add(4);
+        Some(self.call(cpu))
+    }
+
+    fn hosted(&mut self, cpu: &mut CPU, number: u32, args: [u32; 4]) -> Result<u32, u32> {
+        let mem = &mut cpu.memory_bus;
+        match number {
+            HOSTED_OPEN => {
+                // args[1] holds the name length, but the name is NUL terminated too
+                let name = read_string(mem, args[0]).map_err(|_| EFAULT)?;
+                let flags = args[2];
+                let access = flags & (O_WRONLY | O_RDWR);
+                let file = OpenOptions::new()
+                    .read(access != O_WRONLY)
+                    .write(access != 0)
+                    .append(flags & O_APPEND != 0)
+                    .truncate(flags & O_TRUNC != 0)
+                    .create(flags & O_CREAT != 0 && flags & O_EXCL == 0)
+                    .create_new(flags & O_CREAT != 0 && flags & O_EXCL != 0)
+                    .open(name)
+                    .map_err(errno)?;
+                let fd = (0..)
+                    .find(|fd| !self.descriptors.contains_key(fd))
+                    .unwrap_or_default();
+                self.descriptors.insert(fd, Descriptor::File(file));
+                Ok(fd)
+            }
+            HOSTED_CLOSE => self.descriptors.remove(&args[0]).map(|_| 0).ok_or(EBADF),
+            HOSTED_READ => {
+                // No larger than the memory it is copied to
+                let length = room(&mem.memory, args[1], args[2]);
+                if length == 0 && args[2] != 0 {
+                    return Err(EFAULT);
+                }
+                let mut buffer = vec![0; length as usize];
+                let count = self
+                    .descriptor(args[0])?
+                    .read(&mut buffer)
+                    .ok_or(EBADF)?
+                    .map_err(errno)?;
+                write_bytes(mem, args[1], &buffer[..count]).map_err(|_| EFAULT)?;
+                Ok(count as u32)
+            }
+            HOSTED_WRITE => {
+                let buffer = read_bytes(mem, args[1], args[2]).map_err(|_| EFAULT)?;
+                self.descriptor(args[0])?
+                    .write(&buffer)
+                    .ok_or(EBADF)?
+                    .map_err(errno)?;
+                Ok(args[2])
+            }
+            HOSTED_UNLINK => {
+                let name = read_string(mem, args[0]).map_err(|_| EFAULT)?;
+                fs::remove_file(name).map(|_| 0).map_err(errno)
+            }
+            HOSTED_GETTIMEOFDAY => {
+                // struct timeval as GDB File-I/O lays it out: 32-bit seconds
+                // then 64-bit microseconds
+                let now = SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .unwrap_or_default();
+                let mut timeval = (now.as_secs() as u32).to_be_bytes().to_vec();
+                timeval.extend_from_slice(&(now.subsec_micros() as u64).to_be_bytes());
+                write_bytes(mem, args[0], &timeval).map_err(|_| EFAULT)?;
+                Ok(0)
+            }
+            HOSTED_ISATTY => Ok(self.descriptor(args[0])?.is_terminal() as u32),
+            _ => Err(ENOSYS),
+        }
+    }
+
+    fn lseek(&mut self, args: [u32; 4]) -> Result<u64, u32> {
+        let offset = ((args[1] as u64) << 32 | args[2] as u64) as i64;
+        let position = match args[3] {
+            0 => SeekFrom::Start(offset as u64),
+            1 => SeekFrom::Current(offset),
+            2 => SeekFrom::End(offset),
+            _ => return Err(EINVAL),
+        };
+        match self.descriptor(args[0])? {
+            Descriptor::File(file) => file.seek(position).map_err(errno),
+            _ => Err(EINVAL),
+        }
+    }
+
+    fn descriptor(&mut self, fd: u32) -> Result<&mut Descriptor, u32> {
+        self.descriptors.get_mut(&fd).ok_or(EBADF)
+    }
+}
+
+impl TrapHandler for Semihosting {
+    fn trap(&mut self, cpu: &mut CPU, vector: u8) -> TrapAction {
+        if vector == self.vector {
+            self.call(cpu)
+        } else {
+            TrapAction::Exception
+        }
+    }
+
+    fn illegal(&mut self, cpu: &mut CPU, opcode: u16) -> Option<TrapAction> {
+        match opcode {
+            HALT => self.halt(cpu),
+            _ => None,
+        }
+    }
+}
+
+// GDB File-I/O errno for a host I/O error
+fn errno(error: io::Error) -> u32 {
+    match error.kind() {
+        io::ErrorKind::NotFound => ENOENT,
+        io::ErrorKind::PermissionDenied => EACCES,
+        io::ErrorKind::AlreadyExists => EEXIST,
+        io::ErrorKind::InvalidInput => EINVAL,
+        _ => EUNKNOWN,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Semihosting, ENOSYS, HALT, MARKER, NOP};
+    use crate::cpu::CPU;
+    use crate::memory::Memory;
+    use crate::trap::{TrapAction, TrapHandler};
+
+    #[test]
+    fn exit_takes_its_status_from_d1() {
+        let mut cpu = CPU::with_memory(0x1000);
+        let mut semihosting = Semihosting::new(15);
+        cpu.registers.D0 = 0;
+        cpu.registers.D1 = 3;
+        assert_eq!(semihosting.trap(&mut cpu, 15), TrapAction::Exit(3));
+        assert_eq!(semihosting.trap(&mut cpu, 14), TrapAction::Exception);
+    }
+
+    #[test]
+    fn halt_sequence_makes_a_call() {
+        let mut cpu = CPU::with_memory(0x1000);
+        let mut semihosting = Semihosting::new(15);
+        let mem = &mut cpu.memory_bus.memory;
+        mem.write_at_address_word(0x100, NOP).unwrap();
+        mem.write_at_address_word(0x102, HALT).unwrap();
+        mem.write_at_address_long(0x104, MARKER).unwrap();
+
+        // An unknown call fails with ENOSYS in the argument block, and
+        // execution continues past the marker
+        cpu.registers.PC = 0x104;
+        cpu.registers.D0 = 99;
+        cpu.registers.D1 = 0x200;
+        assert_eq!(
+            semihosting.illegal(&mut cpu, HALT),
+            Some(TrapAction::Handled)
+        );
+        assert_eq!(cpu.registers.PC, 0x108);
+        let mem = &cpu.memory_bus.memory;
+        assert_eq!(mem.read_at_address_long(0x200), Some(u32::MAX));
+        assert_eq!(mem.read_at_address_long(0x204), Some(ENOSYS));
+
+        // Exit through the same sequence
+        cpu.registers.PC = 0x104;
+        cpu.registers.D0 = 0;
+        cpu.registers.D1 = 1;
+        assert_eq!(
+            semihosting.illegal(&mut cpu, HALT),
+            Some(TrapAction::Exit(1))
+        );
+
+        // A HALT without the NOP and marker is an illegal instruction
+        cpu.registers.PC = 0x106;
+        assert_eq!(semihosting.illegal(&mut cpu, HALT), None);
+    }
+}